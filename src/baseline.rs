@@ -0,0 +1,298 @@
+//! The comparison of the failures of a run with those of a previous run, see `--baseline`. During
+//! large refactorings, a known set of failures can be accepted while newly broken tests still
+//! fail the run, see `--fail-on`.
+
+use std::collections::{BTreeMap, HashSet};
+use std::io::Write;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::{TestResult, TestResultKind};
+
+/// The outcomes of the JSON report that count as failures, see [`crate::report::TestEvent`].
+const FAILED_OUTCOMES: [&str; 3] = ["failed", "crashed", "unexpected_success"];
+
+/// The failures that fail the run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum FailOn {
+    /// Every failure.
+    Any,
+    /// Only the failures of tests that did not fail in the `--baseline`.
+    New,
+}
+
+/// The part of a JSON report written with `--format json` that the baseline is read from.
+#[derive(Deserialize)]
+struct Report {
+    tests: Vec<ReportedTest>,
+}
+
+#[derive(Deserialize)]
+struct ReportedTest {
+    name: String,
+    outcome: String,
+}
+
+/// Whether every test of the previous run failed, by the name it was reported with.
+pub(crate) struct Baseline {
+    failed: BTreeMap<String, bool>,
+}
+
+/// The failures of a run classified against a [`Baseline`].
+pub(crate) struct Comparison<'a> {
+    /// Tests that fail now, but did not fail or did not exist in the baseline.
+    pub(crate) new: Vec<&'a TestResult>,
+    /// Tests that fail now and failed in the baseline.
+    pub(crate) pre_existing: Vec<&'a TestResult>,
+    /// Tests that failed in the baseline and succeed now.
+    pub(crate) fixed: Vec<&'a TestResult>,
+    /// The number of tests of the baseline that were not part of the run, e.g. because they were
+    /// removed.
+    pub(crate) missing: usize,
+}
+
+impl Baseline {
+    /// Reads the results of a JSON report written with `--format json`.
+    pub(crate) fn read(path: &Path) -> anyhow::Result<Self> {
+        let read_error = |error: &dyn std::fmt::Display| {
+            anyhow::anyhow!("unable to read the baseline {}: {error}", path.display())
+        };
+        let contents = std::fs::read_to_string(path).map_err(|error| read_error(&error))?;
+        let report: Report = serde_json::from_str(&contents).map_err(|error| read_error(&error))?;
+        Ok(Self {
+            failed: report
+                .tests
+                .into_iter()
+                .map(|test| {
+                    let failed = FAILED_OUTCOMES.contains(&test.outcome.as_str());
+                    (test.name, failed)
+                })
+                .collect(),
+        })
+    }
+
+    /// Classifies the failures of the results. Tests are matched by the name they are reported
+    /// with, see `--name-format`.
+    pub(crate) fn compare<'a>(&self, results: &'a [TestResult]) -> Comparison<'a> {
+        let mut comparison = Comparison {
+            new: Vec::new(),
+            pre_existing: Vec::new(),
+            fixed: Vec::new(),
+            missing: 0,
+        };
+        for result in results {
+            let failed_before = self.failed.get(&result.report_name).copied();
+            match (result.kind.is_failure(), failed_before) {
+                (true, Some(true)) => comparison.pre_existing.push(result),
+                (true, _) => comparison.new.push(result),
+                (false, Some(true)) if result.kind == TestResultKind::Success => {
+                    comparison.fixed.push(result)
+                }
+                (false, _) => {}
+            }
+        }
+        let run: HashSet<&str> = results
+            .iter()
+            .map(|result| result.report_name.as_str())
+            .collect();
+        comparison.missing = self
+            .failed
+            .keys()
+            .filter(|name| !run.contains(name.as_str()))
+            .count();
+        comparison
+    }
+}
+
+impl Comparison<'_> {
+    /// Lists the new, pre-existing and fixed failures.
+    pub(crate) fn render(&self, out: &mut impl Write, path: &Path) -> std::io::Result<()> {
+        writeln!(out, "Compared to the baseline {}:", path.display())?;
+        let lists = [
+            ("New failures", &self.new),
+            ("Pre-existing failures", &self.pre_existing),
+            ("Fixed", &self.fixed),
+        ];
+        for (label, results) in lists {
+            writeln!(out, "  {label}: {}", results.len())?;
+            for result in results {
+                writeln!(out, "\t{}", result.filename)?;
+            }
+        }
+        if self.missing > 0 {
+            writeln!(
+                out,
+                "  {} test(s) of the baseline were not run and are ignored",
+                self.missing
+            )?;
+        }
+        out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Baseline;
+    use crate::discovery::TestFile;
+    use crate::failure_code::FailureCode;
+    use crate::scratch::ScratchDir;
+    use crate::test_log::TestLog;
+    use crate::{TestResult, TestResultKind};
+
+    fn result(name: &str, kind: TestResultKind) -> TestResult {
+        TestResult {
+            filename: format!("{name}.bs"),
+            report_name: name.to_string(),
+            test: TestFile {
+                name: format!("{name}.bs"),
+                root: 0,
+                path: format!("{name}.bs").into(),
+                source_path: format!("{name}.bs").into(),
+                quarantined: false,
+                overrides: Default::default(),
+            },
+            configuration: None,
+            kind,
+            duration: Duration::ZERO,
+            log: TestLog::default(),
+            log_error: None,
+        }
+    }
+
+    fn failure() -> TestResultKind {
+        TestResultKind::Failure(FailureCode::WrongMessage, String::new())
+    }
+
+    /// Reads a baseline from a report of the tests with the given outcomes.
+    fn baseline(tests: &[(&str, &str)]) -> Baseline {
+        let tests: Vec<_> = tests
+            .iter()
+            .map(|(name, outcome)| serde_json::json!({ "name": name, "outcome": outcome }))
+            .collect();
+        let scratch = ScratchDir::create(None, "baseline").unwrap();
+        let path = scratch.path().join("report.json");
+        let report = serde_json::json!({ "tests": tests, "summary": {} });
+        std::fs::write(&path, report.to_string()).unwrap();
+        let baseline = Baseline::read(&path).unwrap();
+        scratch.finish(false).unwrap();
+        baseline
+    }
+
+    fn names(results: &[&TestResult]) -> Vec<String> {
+        results
+            .iter()
+            .map(|result| result.report_name.clone())
+            .collect()
+    }
+
+    #[test]
+    fn failures_are_new_unless_they_failed_in_the_baseline() {
+        let baseline = baseline(&[
+            ("still_failing", "failed"),
+            ("crashing", "crashed"),
+            ("now_failing", "passed"),
+            ("skipped_before", "skipped"),
+        ]);
+        let results = [
+            result("still_failing", failure()),
+            result("crashing", TestResultKind::Crash(String::new())),
+            result("now_failing", failure()),
+            result(
+                "skipped_before",
+                TestResultKind::UnexpectedSuccess(String::new()),
+            ),
+            result("added", failure()),
+        ];
+        let comparison = baseline.compare(&results);
+        assert_eq!(
+            names(&comparison.pre_existing),
+            ["still_failing", "crashing"]
+        );
+        assert_eq!(
+            names(&comparison.new),
+            ["now_failing", "skipped_before", "added"]
+        );
+        assert!(comparison.fixed.is_empty());
+        assert_eq!(comparison.missing, 0);
+    }
+
+    #[test]
+    fn only_successes_fix_failures_of_the_baseline() {
+        let baseline = baseline(&[
+            ("fixed", "failed"),
+            ("skipped", "unexpected_success"),
+            ("quarantined", "failed"),
+        ]);
+        let results = [
+            result("fixed", TestResultKind::Success),
+            result("skipped", TestResultKind::Skipped(String::new())),
+            result(
+                "quarantined",
+                TestResultKind::Quarantined(FailureCode::WrongMessage, String::new()),
+            ),
+        ];
+        let comparison = baseline.compare(&results);
+        assert_eq!(names(&comparison.fixed), ["fixed"]);
+        assert!(comparison.new.is_empty());
+        assert!(comparison.pre_existing.is_empty());
+    }
+
+    #[test]
+    fn tests_of_the_baseline_that_were_not_run_are_counted() {
+        let baseline = baseline(&[
+            ("removed", "failed"),
+            ("renamed", "passed"),
+            ("kept", "passed"),
+        ]);
+        let results = [result("kept", TestResultKind::Success)];
+        assert_eq!(baseline.compare(&results).missing, 2);
+    }
+
+    #[test]
+    fn tests_are_matched_by_their_report_name() {
+        let baseline = baseline(&[("a.bs [release]", "failed")]);
+        let mut result = result("a.bs [release]", failure());
+        result.filename = "a.bs".to_string();
+        let results = [result];
+        let comparison = baseline.compare(&results);
+        assert_eq!(names(&comparison.pre_existing), ["a.bs [release]"]);
+    }
+
+    #[test]
+    fn comparisons_list_the_failures() {
+        let baseline = baseline(&[("old", "failed"), ("fixed", "failed"), ("gone", "failed")]);
+        let results = [
+            result("old", failure()),
+            result("new", failure()),
+            result("fixed", TestResultKind::Success),
+        ];
+        let mut out = Vec::new();
+        baseline
+            .compare(&results)
+            .render(&mut out, "baseline.json".as_ref())
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Compared to the baseline baseline.json:\n  \
+            New failures: 1\n\tnew.bs\n  \
+            Pre-existing failures: 1\n\told.bs\n  \
+            Fixed: 1\n\tfixed.bs\n  \
+            1 test(s) of the baseline were not run and are ignored\n"
+        );
+    }
+
+    #[test]
+    fn unreadable_baselines_are_reported() {
+        let scratch = ScratchDir::create(None, "baseline").unwrap();
+        let path = scratch.path().join("report.json");
+        let error = Baseline::read(&path).err().unwrap().to_string();
+        assert!(error.starts_with("unable to read the baseline "), "{error}");
+        std::fs::write(&path, "{\"tests\": [{\"name\": \"a\"}]}").unwrap();
+        let error = Baseline::read(&path).err().unwrap().to_string();
+        assert!(error.contains("missing field `outcome`"), "{error}");
+        scratch.finish(false).unwrap();
+    }
+}
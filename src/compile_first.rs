@@ -0,0 +1,109 @@
+//! Compiling all tests before running any, see `--phases compile-first`. A broken toolchain or
+//! standard library fails every compilation with the same error, which is reported once instead of
+//! interleaved with the results of the run phase. The compilations are reused by the run phase.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::process::ProcessOutput;
+use crate::text::strip_ansi;
+
+/// The output of the compiler process of a test and how long it took, or why the test could not
+/// be compiled, which fails it with [`crate::failure_code::FailureCode::Infra`] when it is run.
+type Compilation = anyhow::Result<(ProcessOutput, Duration)>;
+
+/// The compilations of the compile phase by the source file and the configuration of the test.
+#[derive(Default)]
+pub(crate) struct Compilations(Mutex<HashMap<(PathBuf, Option<String>), Compilation>>);
+
+impl Compilations {
+    pub(crate) fn insert(
+        &self,
+        source_path: &Path,
+        configuration: Option<&str>,
+        compilation: Compilation,
+    ) {
+        self.0
+            .lock()
+            .expect("the compilations are never poisoned")
+            .insert(key(source_path, configuration), compilation);
+    }
+
+    /// Removes the compilation of the test, so the run phase uses every compilation only once.
+    pub(crate) fn take(
+        &self,
+        source_path: &Path,
+        configuration: Option<&str>,
+    ) -> Option<Compilation> {
+        self.0
+            .lock()
+            .expect("the compilations are never poisoned")
+            .remove(&key(source_path, configuration))
+    }
+}
+
+fn key(source_path: &Path, configuration: Option<&str>) -> (PathBuf, Option<String>) {
+    (source_path.to_path_buf(), configuration.map(String::from))
+}
+
+/// The outcome of the compilation of a single test in the compile phase.
+pub(crate) struct CompileOutcome {
+    pub(crate) filename: String,
+    pub(crate) source_path: PathBuf,
+    /// Whether the test has neither a failure directive nor a snapshot, so its compilation has to
+    /// succeed.
+    pub(crate) must_compile: bool,
+    pub(crate) succeeded: bool,
+    pub(crate) stderr: Vec<u8>,
+}
+
+/// The same error most tests that have to compile failed with.
+pub(crate) struct Breakage<'a> {
+    /// The leading error with the path of the test replaced by `<test>`.
+    pub(crate) error: String,
+    /// The first test that failed with the error.
+    pub(crate) example: &'a CompileOutcome,
+    pub(crate) failed: usize,
+    pub(crate) total: usize,
+}
+
+/// Returns the most common leading error if more than `threshold` of the tests that have to compile
+/// failed with it.
+pub(crate) fn find_breakage(outcomes: &[CompileOutcome], threshold: f64) -> Option<Breakage<'_>> {
+    let must_compile: Vec<_> = outcomes
+        .iter()
+        .filter(|outcome| outcome.must_compile)
+        .collect();
+    let mut errors: Vec<(String, Vec<&CompileOutcome>)> = Vec::new();
+    for outcome in must_compile.iter().filter(|outcome| !outcome.succeeded) {
+        let error = leading_error(outcome);
+        match errors.iter_mut().find(|(known, _)| *known == error) {
+            Some((_, failed)) => failed.push(outcome),
+            None => errors.push((error, vec![outcome])),
+        }
+    }
+    // the first of the most common errors
+    let (error, failed) = errors
+        .into_iter()
+        .rev()
+        .max_by_key(|(_, failed)| failed.len())?;
+    (failed.len() as f64 > threshold * must_compile.len() as f64).then(|| Breakage {
+        error,
+        example: failed[0],
+        failed: failed.len(),
+        total: must_compile.len(),
+    })
+}
+
+/// The first non-empty line of the compiler stderr with the path of the test replaced by `<test>`.
+fn leading_error(outcome: &CompileOutcome) -> String {
+    let stderr = String::from_utf8_lossy(&strip_ansi(&outcome.stderr)).into_owned();
+    stderr
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .unwrap_or("<no output>")
+        .replace(&*outcome.source_path.to_string_lossy(), "<test>")
+}
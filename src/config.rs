@@ -0,0 +1,204 @@
+//! The settings that determine how a single test is compiled, run and evaluated, separated from
+//! the command line so the per-test code does not depend on how the settings were specified.
+
+use std::borrow::Cow;
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use crate::compile_first::Compilations;
+use crate::directory_config::Overrides;
+use crate::name_format::NameFormat;
+use crate::normalization::Normalization;
+use crate::process::ResourceLimits;
+use crate::replay::Recording;
+use crate::sandbox::Sandbox;
+use crate::seed::SeedArg;
+use crate::shell_words;
+use crate::stderr_noise::NoisePattern;
+use crate::test_log::Stage;
+use crate::toolchain::ToolchainVersions;
+use crate::{ChildColor, Cli, HaltMode, StdinEncoding};
+
+#[derive(Clone)]
+pub(crate) struct Config {
+    pub(crate) seatbelt_path: PathBuf,
+    pub(crate) backseater_path: PathBuf,
+    pub(crate) lib_path: PathBuf,
+    pub(crate) log_dir: Option<PathBuf>,
+    /// The directory the scratch directories of the tests are created in, see `--work-dir`.
+    pub(crate) work_dir: Option<PathBuf>,
+    pub(crate) keep_failed_tmp: bool,
+    pub(crate) stream_output: bool,
+    pub(crate) no_source_context: bool,
+    /// The name of the test that is run with this config, if any.
+    pub(crate) test_name: Option<String>,
+    /// The scratch directory of the test that is run with this config, if any.
+    pub(crate) test_tmpdir: Option<PathBuf>,
+    /// The compilations of the compile phase, see `--phases compile-first`.
+    pub(crate) compilations: Option<Arc<Compilations>>,
+    /// The trees the tests must not change, see `--sandbox`.
+    pub(crate) sandbox: Option<Arc<Sandbox>>,
+    /// The seed derived for the test that is run with this config, if seeds are passed.
+    pub(crate) test_seed: Option<u64>,
+    pub(crate) keep_ansi: bool,
+    pub(crate) child_color: ChildColor,
+    pub(crate) halt_mode: HaltMode,
+    pub(crate) stdin_encoding: StdinEncoding,
+    /// How the seed of every test is passed to the Backseater, `None` if it is not passed.
+    pub(crate) seed_arg: Option<SeedArg>,
+    /// The seed of the run the seeds of the tests are derived from.
+    pub(crate) run_seed: u64,
+    pub(crate) name_format: Option<NameFormat>,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) compile_timeout: Option<Duration>,
+    pub(crate) no_normalize: bool,
+    /// The lines removed from the stderr of the toolchain, see `--ignore-stderr-pattern`.
+    pub(crate) ignore_stderr_patterns: Vec<NoisePattern>,
+    /// Applied to outputs before they are compared with the expected ones.
+    pub(crate) normalization: Normalization,
+    pub(crate) bless: bool,
+    pub(crate) paranoid: bool,
+    pub(crate) deny_warnings: bool,
+    /// The stage tests with a failure directive have to abort in, see `--expect-stage`.
+    pub(crate) expect_stage: Option<Stage>,
+    pub(crate) limits: ResourceLimits,
+    /// The configuration of the matrix the tests are run with, if `--config-set` is given.
+    pub(crate) configuration: Option<Configuration>,
+    pub(crate) recording: Option<Recording>,
+    /// The settings of the `test-runner.toml` files that apply to the test.
+    pub(crate) overrides: Arc<Overrides>,
+    /// Queried when the first test requires a minimum version, shared by all tests.
+    pub(crate) versions: Arc<OnceLock<ToolchainVersions>>,
+}
+
+/// A named set of extra toolchain arguments every test is run with, see `--config-set`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Configuration {
+    pub(crate) name: String,
+    /// Appended to the arguments of the Seatbelt compiler.
+    pub(crate) seatbelt_args: Vec<String>,
+    /// Appended to the arguments of the Backseater, before the arguments of the test program.
+    pub(crate) backseater_args: Vec<String>,
+}
+
+impl Config {
+    /// The config of a test the given `test-runner.toml` settings apply to. Their timeouts take
+    /// precedence over the ones given on the command line.
+    pub(crate) fn with_overrides(&self, overrides: &Arc<Overrides>) -> Cow<'_, Config> {
+        if overrides.sources.is_empty() {
+            return Cow::Borrowed(self);
+        }
+        Cow::Owned(Config {
+            timeout: overrides.timeout.or(self.timeout),
+            compile_timeout: overrides.compile_timeout.or(self.compile_timeout),
+            overrides: overrides.clone(),
+            ..self.clone()
+        })
+    }
+
+    /// The versions of the toolchain binaries, which are queried on the first call.
+    pub(crate) fn versions(&self) -> &ToolchainVersions {
+        self.versions.get_or_init(|| ToolchainVersions::query(self))
+    }
+
+    /// The extra arguments of the Seatbelt compiler: the ones of the configuration followed by the
+    /// ones of the `test-runner.toml` files.
+    pub(crate) fn seatbelt_args(&self) -> impl Iterator<Item = &String> {
+        let configuration = self.configuration.iter();
+        configuration
+            .flat_map(|configuration| &configuration.seatbelt_args)
+            .chain(&self.overrides.seatbelt_args)
+    }
+
+    /// The patterns of the lines removed from the stderr of the toolchain: the ones given on the
+    /// command line followed by the ones of the `test-runner.toml` files.
+    pub(crate) fn noise_patterns(&self) -> impl Iterator<Item = &NoisePattern> + Clone {
+        (self.ignore_stderr_patterns.iter()).chain(&self.overrides.ignore_stderr_patterns)
+    }
+
+    /// The extra arguments of the Backseater: the ones of the configuration followed by the ones of
+    /// the `test-runner.toml` files.
+    pub(crate) fn backseater_args(&self) -> impl Iterator<Item = &String> {
+        let configuration = self.configuration.iter();
+        configuration
+            .flat_map(|configuration| &configuration.backseater_args)
+            .chain(&self.overrides.backseater_args)
+    }
+}
+
+impl Configuration {
+    /// Parses `NAME:SEATBELT_ARGS[:BACKSEATER_ARGS]`, where the arguments are split like by a
+    /// shell, see [`shell_words::split`].
+    pub(crate) fn parse(value: &str) -> Result<Self, String> {
+        let mut parts = value.splitn(3, ':');
+        let name = parts.next().unwrap_or_default().trim();
+        let is_valid_name = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+        if !is_valid_name {
+            return Err(format!(
+                "expected NAME:SEATBELT_ARGS[:BACKSEATER_ARGS] with an alphanumeric name: {value}"
+            ));
+        }
+        let args = |part: Option<&str>| shell_words::split(part.unwrap_or_default());
+        Ok(Self {
+            name: name.to_string(),
+            seatbelt_args: args(parts.next())?,
+            backseater_args: args(parts.next())?,
+        })
+    }
+}
+
+impl From<&Cli> for Config {
+    fn from(cli: &Cli) -> Self {
+        Self {
+            seatbelt_path: cli.seatbelt_path.clone(),
+            backseater_path: cli.backseater_path.clone(),
+            lib_path: cli.lib_path.clone(),
+            log_dir: cli.log_dir.clone(),
+            work_dir: cli.work_dir.clone(),
+            keep_failed_tmp: cli.keep_failed_tmp,
+            stream_output: cli.stream_output,
+            no_source_context: cli.no_source_context,
+            test_name: None,
+            test_tmpdir: None,
+            test_seed: None,
+            sandbox: None,
+            compilations: None,
+            keep_ansi: cli.keep_ansi,
+            child_color: cli.child_color,
+            halt_mode: cli.halt_mode,
+            stdin_encoding: cli.stdin_encoding,
+            seed_arg: cli.seed_arg.clone(),
+            run_seed: cli.seed.unwrap_or_default(),
+            name_format: cli.name_format.clone(),
+            timeout: cli.timeout,
+            compile_timeout: cli.compile_timeout,
+            no_normalize: cli.no_normalize,
+            ignore_stderr_patterns: cli.ignore_stderr_pattern.clone(),
+            normalization: Normalization {
+                trailing_whitespace: cli.trim_trailing_whitespace,
+                line_endings: cli.normalize_line_endings,
+                final_newline: cli.normalize_final_newline,
+            },
+            bless: cli.bless,
+            paranoid: cli.paranoid,
+            deny_warnings: cli.deny_warnings,
+            expect_stage: cli.expect_stage,
+            limits: ResourceLimits {
+                memory_mb: cli.memory_limit,
+                cpu_seconds: cli.cpu_limit,
+            },
+            configuration: None,
+            overrides: Arc::default(),
+            versions: Arc::default(),
+            recording: match (&cli.record, &cli.replay) {
+                (_, Some(directory)) => Some(Recording::Replay(directory.clone())),
+                (Some(directory), None) => Some(Recording::Record(directory.clone())),
+                (None, None) => None,
+            },
+        }
+    }
+}
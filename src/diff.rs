@@ -0,0 +1,210 @@
+//! Rendering of the differences between an expected and an actual text. The lines of the diffs
+//! are prefixed with `-` and `+`, and highlighted parts are marked with `^` on the following line,
+//! which the [`crate::output::Printer`] colors unless colors are disabled.
+
+/// The number of unchanged lines shown around every change in a unified diff.
+const CONTEXT_LINES: usize = 3;
+
+/// Renders a line-by-line diff from `old` to `new`. Every line is prefixed with `-` if it was
+/// removed, `+` if it was added and a space if it is unchanged.
+pub(crate) fn line_diff(old: &str, new: &str) -> String {
+    let mut diff = String::new();
+    for change in similar::TextDiff::from_lines(old, new).iter_all_changes() {
+        let sign = match change.tag() {
+            similar::ChangeTag::Delete => '-',
+            similar::ChangeTag::Insert => '+',
+            similar::ChangeTag::Equal => ' ',
+        };
+        diff.push(sign);
+        diff += change.as_str().unwrap_or_default().trim_end_matches('\n');
+        diff.push('\n');
+    }
+    diff
+}
+
+/// Renders a unified diff from `expected` to `actual` with [`CONTEXT_LINES`] lines of context.
+/// The header names where the expected text comes from and what the actual text is. Every line is
+/// indented with a tab, so the diff can be embedded into a failure message.
+pub(crate) fn unified_diff(
+    expected: &str,
+    actual: &str,
+    expected_source: &str,
+    actual_source: &str,
+) -> String {
+    let diff = similar::TextDiff::from_lines(expected, actual)
+        .unified_diff()
+        .context_radius(CONTEXT_LINES)
+        .missing_newline_hint(false)
+        .header(expected_source, actual_source)
+        .to_string();
+    let mut indented = String::new();
+    for line in diff.lines() {
+        indented += &format!("\t{line}\n");
+    }
+    indented
+}
+
+/// Renders a diff from `expected` to `actual` with [`CONTEXT_LINES`] lines of context, in which the
+/// expected lines are numbered starting with `first_line`, so they can be found in the file they
+/// are specified in. Every line is indented with a tab, so the diff can be embedded into a failure
+/// message.
+pub(crate) fn numbered_diff(expected: &str, actual: &str, first_line: usize) -> String {
+    let diff = similar::TextDiff::from_lines(expected, actual);
+    let mut rendered = String::new();
+    for (index, group) in diff.grouped_ops(CONTEXT_LINES).iter().enumerate() {
+        if index > 0 {
+            rendered += "\t  ...\n";
+        }
+        for change in group.iter().flat_map(|op| diff.iter_changes(op)) {
+            let sign = match change.tag() {
+                similar::ChangeTag::Delete => '-',
+                similar::ChangeTag::Insert => '+',
+                similar::ChangeTag::Equal => ' ',
+            };
+            let line = match change.old_index() {
+                Some(index) => format!("{:>4}", first_line + index),
+                None => " ".repeat(4),
+            };
+            let text = change.as_str().unwrap_or_default();
+            let hint = match change.missing_newline() {
+                true => " (no newline at the end)",
+                false => "",
+            };
+            rendered += &format!("\t{sign}{line} | {}{hint}\n", text.trim_end_matches('\n'));
+        }
+    }
+    rendered
+}
+
+/// Parts of the actual text shorter than this many characters are not highlighted by
+/// [`highlight_overlaps`].
+const MIN_OVERLAP: usize = 4;
+
+/// Renders `actual` line by line and marks the longest part every `missing` text shares with one
+/// of the lines, so near-matches of expected messages are easy to spot. Every line is indented
+/// with a tab and two spaces.
+pub(crate) fn highlight_overlaps(actual: &str, missing: &[&str]) -> String {
+    let lines: Vec<&str> = actual.lines().collect();
+    // the line, the column and the length of the overlap of every missing text
+    let mut overlaps = Vec::new();
+    for text in missing {
+        let mut best: Option<(usize, usize, usize)> = None;
+        for (index, line) in lines.iter().enumerate() {
+            let Some((column, length)) = longest_common_substring(line, text) else {
+                continue;
+            };
+            if length >= MIN_OVERLAP && best.is_none_or(|(_, _, best)| length > best) {
+                best = Some((index, column, length));
+            }
+        }
+        if let Some(overlap) = best {
+            overlaps.push((overlap, text));
+        }
+    }
+    let mut rendered = String::new();
+    for (index, line) in lines.iter().enumerate() {
+        rendered += &format!("\t  {line}\n");
+        for ((_, column, length), text) in overlaps.iter().filter(|(overlap, _)| overlap.0 == index)
+        {
+            rendered += &format!(
+                "\t  {}{} partially matches {text:?}\n",
+                " ".repeat(*column),
+                "^".repeat(*length)
+            );
+        }
+    }
+    rendered
+}
+
+/// Returns the column and the length in characters of the longest substring of `text` that is
+/// also a substring of `other`, or `None` if they have no character in common.
+fn longest_common_substring(text: &str, other: &str) -> Option<(usize, usize)> {
+    let other: Vec<char> = other.chars().collect();
+    // the length of the common suffix of the prefixes of both texts ending at every position of
+    // `other`, for the previous and the current character of `text`
+    let mut previous = vec![0; other.len() + 1];
+    let mut current = vec![0; other.len() + 1];
+    let mut best = (0, 0);
+    for (index, c) in text.chars().enumerate() {
+        for (other_index, other_c) in other.iter().enumerate() {
+            current[other_index + 1] = match c == *other_c {
+                true => previous[other_index] + 1,
+                false => 0,
+            };
+            if current[other_index + 1] > best.1 {
+                best = (
+                    index + 1 - current[other_index + 1],
+                    current[other_index + 1],
+                );
+            }
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    (best.1 > 0).then_some(best)
+}
+
+/// The number of bytes of the hex dump of [`hex_diff`].
+const HEX_WINDOW: usize = 64;
+
+/// The number of bytes per line of the hex dump of [`hex_diff`].
+const HEX_LINE: usize = 16;
+
+/// Renders the lengths of `expected` and `actual` and a side-by-side hex dump of the
+/// [`HEX_WINDOW`] bytes around their first difference, with the differing bytes marked below
+/// each line. Every line is indented with a tab.
+pub(crate) fn hex_diff(expected: &[u8], actual: &[u8]) -> String {
+    let first_difference = expected
+        .iter()
+        .zip(actual)
+        .position(|(expected, actual)| expected != actual)
+        .unwrap_or(expected.len().min(actual.len()));
+    let start = first_difference / HEX_WINDOW * HEX_WINDOW;
+    let end = (start + HEX_WINDOW).min(expected.len().max(actual.len()));
+    let column_width = HEX_LINE * 3 - 1;
+    let mut diff = format!(
+        "\texpected {} bytes, got {} bytes, first difference at offset {first_difference:#x}\n",
+        expected.len(),
+        actual.len()
+    );
+    diff += &format!(
+        "\t{:8}  {:<column_width$}  |  {}\n",
+        "offset", "expected", "actual"
+    );
+    for line_start in (start..end).step_by(HEX_LINE) {
+        let range = line_start..(line_start + HEX_LINE);
+        let (expected_hex, expected_marks) = hex_line(expected, actual, range.clone());
+        let (actual_hex, actual_marks) = hex_line(actual, expected, range);
+        diff += &format!("\t{line_start:08x}  {expected_hex:<column_width$}  |  {actual_hex}\n");
+        if expected_marks.contains('^') || actual_marks.contains('^') {
+            diff += &format!(
+                "\t{:8}  {expected_marks:<column_width$}     {}\n",
+                "",
+                actual_marks.trim_end()
+            );
+        }
+    }
+    diff
+}
+
+/// Renders the bytes of `bytes` within `range` as hex and a line marking the ones that differ from
+/// `other` with `^^`. Bytes beyond the end of `bytes` are left blank.
+fn hex_line(bytes: &[u8], other: &[u8], range: std::ops::Range<usize>) -> (String, String) {
+    let mut hex = Vec::new();
+    let mut marks = Vec::new();
+    for index in range {
+        match bytes.get(index) {
+            Some(byte) => {
+                hex.push(format!("{byte:02x}"));
+                marks.push(match other.get(index) == Some(byte) {
+                    true => "  ",
+                    false => "^^",
+                });
+            }
+            None => {
+                hex.push("  ".to_string());
+                marks.push("  ");
+            }
+        }
+    }
+    (hex.join(" ").trim_end().to_string(), marks.join(" "))
+}
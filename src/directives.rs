@@ -0,0 +1,1783 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use log::debug;
+use serde::Serialize;
+
+use crate::discovery;
+use crate::normalization::Normalization;
+use crate::test_log::Stage;
+use crate::timings::{self, Phase};
+use crate::version::Version;
+
+/// The keys that are recognized in the leading comment block of a test.
+pub(crate) const DIRECTIVE_KEYS: &[&str] = &[
+    "fails_with",
+    "fails_with_exact",
+    "fails_with_file",
+    "skip",
+    "program_args",
+    "allow_empty",
+    "allow_empty_program",
+    "skip_configs",
+    "exit_code",
+    "requires_seatbelt",
+    "requires_backseater",
+    "seed",
+    "warns_with",
+    "lib",
+    "expects_output",
+    "not_a_test",
+    "match_stdout",
+    "timeout",
+    "normalize",
+    "stage",
+];
+
+/// The prefix that marks a comment line as a directive, e.g. `// test-runner: skip`, so ordinary
+/// comments are never mistaken for directives. Lines with a known key but without the prefix are
+/// still directives, but that syntax is deprecated, see [`syntax_issues`].
+pub(crate) const DIRECTIVE_PREFIX: &str = "test-runner:";
+
+/// The keys of directives whose value is the block of indented comment lines below them, e.g.
+/// `// expects_output:`.
+const BLOCK_KEYS: &[&str] = &["expects_output"];
+
+/// How a test is expected to end, rendered as e.g. `abort with the error message "x"`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub(crate) enum TestOutcome {
+    Finished,
+    Aborted {
+        error_messages: Vec<String>,
+    },
+    /// The test has to abort with exactly the given stderr, apart from leading and trailing
+    /// whitespace.
+    AbortedExactly {
+        expected_stderr: ExpectedStderr,
+    },
+}
+
+impl fmt::Display for TestOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TestOutcome::Finished => write!(f, "finish"),
+            TestOutcome::Aborted { error_messages } => {
+                let messages: Vec<_> = error_messages
+                    .iter()
+                    .map(|message| format!("{message:?}"))
+                    .collect();
+                match messages.len() {
+                    1 => write!(f, "abort with the error message {}", messages[0]),
+                    _ => write!(f, "abort with the error messages {}", messages.join(", ")),
+                }
+            }
+            TestOutcome::AbortedExactly { expected_stderr } => {
+                write!(f, "abort with the stderr {expected_stderr}")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ExpectedStderr {
+    Inline(String),
+    /// A file containing the expected stderr, relative to the directory of the test.
+    File(PathBuf),
+}
+
+impl ExpectedStderr {
+    /// Returns the expected stderr, reading it from its file if necessary.
+    pub(crate) fn load(&self, source_file: &Path) -> std::io::Result<String> {
+        match self {
+            ExpectedStderr::Inline(stderr) => Ok(stderr.clone()),
+            ExpectedStderr::File(path) => {
+                let directory = source_file.parent().unwrap_or(Path::new("."));
+                std::fs::read_to_string(directory.join(path))
+            }
+        }
+    }
+
+    /// The file the expected stderr is specified in: the test itself or the referenced file.
+    pub(crate) fn source(&self, source_file: &Path) -> PathBuf {
+        match self {
+            ExpectedStderr::Inline(_) => source_file.to_path_buf(),
+            ExpectedStderr::File(path) => source_file.parent().unwrap_or(Path::new(".")).join(path),
+        }
+    }
+}
+
+impl fmt::Display for ExpectedStderr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExpectedStderr::Inline(stderr) => write!(f, "{stderr:?}"),
+            ExpectedStderr::File(path) => write!(f, "in {}", path.display()),
+        }
+    }
+}
+
+/// The library a test is compiled against instead of the one given via `--lib`.
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Library {
+    /// A directory relative to the directory of the test.
+    Path(PathBuf),
+    /// No library at all, so the compiler is started without `--lib`.
+    None,
+}
+
+impl Library {
+    /// The path of the library, `None` if the test is compiled without a library.
+    pub(crate) fn resolve(&self, source_file: &Path) -> Option<PathBuf> {
+        match self {
+            Library::Path(path) => Some(source_file.parent().unwrap_or(Path::new(".")).join(path)),
+            Library::None => None,
+        }
+    }
+}
+
+/// The limit of the run time of a test in the VM given by a `timeout` directive, replacing
+/// `--timeout`.
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum TestTimeout {
+    #[serde(rename = "limit_ms", serialize_with = "crate::stats::milliseconds")]
+    Limit(Duration),
+    /// The test may run as long as it takes.
+    None,
+}
+
+/// The stdout a finished test has to write, given as a block of comment lines in the test itself.
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct ExpectedOutput {
+    /// The lines of the block with their common indentation removed, each ending with a newline.
+    pub(crate) text: String,
+    /// The 1-based line number within the test file of the first line of `text`, or within the
+    /// `expects_output` value of the sidecar. Every further line of `text` is on the next line.
+    pub(crate) first_line: usize,
+    /// The sidecar file the block was read from, `None` if it is part of the test file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) sidecar: Option<PathBuf>,
+}
+
+impl ExpectedOutput {
+    /// The 1-based line number within the test file of the last line of the block.
+    pub(crate) fn last_line(&self) -> usize {
+        self.first_line + self.text.lines().count().max(1) - 1
+    }
+}
+
+/// Everything the leading comment block of a test specifies about how it has to be run.
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct TestDirectives {
+    pub(crate) expected_outcome: TestOutcome,
+    /// If set, the test is not run. Contains the reason for skipping, which may be empty.
+    pub(crate) skip: Option<String>,
+    /// The arguments passed to the test program after a `--` on the command line of the VM.
+    pub(crate) program_args: Vec<String>,
+    /// Whether the test is legitimately trivial and must not be reported as suspiciously empty.
+    pub(crate) allow_empty: bool,
+    /// Whether the compiler may write an empty program, which otherwise fails the test.
+    pub(crate) allow_empty_program: bool,
+    /// The names of the configurations of the matrix the test is not run with.
+    pub(crate) skip_configs: Vec<String>,
+    /// The exit code the test program has to finish with, see `--halt-mode`.
+    pub(crate) exit_code: Option<i32>,
+    /// The minimum version of the Seatbelt compiler the test needs.
+    pub(crate) requires_seatbelt: Option<Version>,
+    /// The minimum version of the Backseater the test needs.
+    pub(crate) requires_backseater: Option<Version>,
+    /// The seed of the random number generator of the Backseater, replacing the derived one, see
+    /// `--seed-arg`.
+    pub(crate) seed: Option<u64>,
+    /// The messages the compiler stderr has to contain although the compilation succeeds.
+    #[serde(rename = "warns_with")]
+    pub(crate) warnings: Vec<String>,
+    /// The library the test is compiled against instead of the one given via `--lib`.
+    pub(crate) lib: Option<Library>,
+    /// The stdout the test has to write instead of the one in its `.expected.bin` file.
+    #[serde(rename = "expects_output")]
+    pub(crate) expected_output: Option<ExpectedOutput>,
+    /// Whether the expected error messages of a failing compilation are matched against the
+    /// compiler stdout as well, which is appended to the compiler stderr for matching.
+    pub(crate) match_stdout: bool,
+    pub(crate) timeout: Option<TestTimeout>,
+    /// The normalizations applied to the outputs in addition to the ones given on the command
+    /// line.
+    pub(crate) normalize: Option<Normalization>,
+    /// The stage the test has to abort in, replacing `--expect-stage`.
+    pub(crate) stage: Option<Stage>,
+}
+
+impl Default for TestDirectives {
+    fn default() -> Self {
+        Self {
+            expected_outcome: TestOutcome::Finished,
+            skip: None,
+            program_args: Vec::new(),
+            allow_empty: false,
+            allow_empty_program: false,
+            skip_configs: Vec::new(),
+            exit_code: None,
+            requires_seatbelt: None,
+            requires_backseater: None,
+            seed: None,
+            warnings: Vec::new(),
+            lib: None,
+            expected_output: None,
+            match_stdout: false,
+            timeout: None,
+            normalize: None,
+            stage: None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct DirectiveError {
+    pub(crate) location: Location,
+    pub(crate) message: String,
+}
+
+impl fmt::Display for DirectiveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.location, self.message)
+    }
+}
+
+/// Where a directive was read from.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Location {
+    /// The 1-based line number within the test file.
+    Line(usize),
+    /// The key of the directive in the sidecar file with the given file name.
+    Sidecar { file: String, key: String },
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Location::Line(line) => write!(f, "line {line}"),
+            Location::Sidecar { file, key } => write!(f, "`{key}` in {file}"),
+        }
+    }
+}
+
+/// The leading comment block of a test with the directives of its sidecar merged into it, see
+/// [`read_leading_comments`]. It dereferences to its lines.
+#[derive(Debug, Default)]
+pub(crate) struct LeadingComments {
+    /// The lines of the block within the test file, followed by the lines rendered from the
+    /// sidecar. Every line of the file keeps its line number: the directives of the file that the
+    /// sidecar overrides are replaced by [`OVERRIDDEN`] lines.
+    lines: Vec<String>,
+    /// The number of lines from the test file.
+    file_lines: usize,
+    sidecar: Option<Sidecar>,
+}
+
+/// The comment replacing a directive of the test file that its sidecar overrides. It is neither a
+/// directive nor part of the block of a block directive.
+const OVERRIDDEN: &str = "// (overridden by the sidecar)";
+
+impl LeadingComments {
+    /// Merges the directives of the sidecar into the leading comment block of a file. The sidecar
+    /// wins: the directives of the file that the sidecar specifies as well, even as `false`, are
+    /// replaced, the others are kept.
+    fn merge(mut lines: Vec<String>, sidecar: Sidecar) -> Self {
+        let file_lines = lines.len();
+        if sidecar.keys.is_empty() {
+            return Self::from(lines);
+        }
+        let overridden: Vec<Range<usize>> = directive_groups(&lines)
+            .into_iter()
+            .filter(|(key, _)| {
+                sidecar
+                    .keys
+                    .iter()
+                    .any(|(sidecar_key, _)| group_key(sidecar_key) == *key)
+            })
+            .map(|(_, lines)| lines)
+            .collect();
+        for range in overridden {
+            for line in &mut lines[range] {
+                *line = OVERRIDDEN.to_string();
+            }
+        }
+        lines.extend(sidecar.lines.iter().cloned());
+        Self {
+            lines,
+            file_lines,
+            sidecar: Some(sidecar),
+        }
+    }
+
+    /// Where the directive on the 1-based line of the merged lines was read from.
+    fn location(&self, line: usize) -> Location {
+        let sidecar_key = self.sidecar.as_ref().and_then(|sidecar| {
+            let index = line.checked_sub(self.file_lines + 1)?;
+            let (key, _) = sidecar
+                .keys
+                .iter()
+                .find(|(_, lines)| lines.contains(&index))?;
+            Some((sidecar, key))
+        });
+        match sidecar_key {
+            Some((sidecar, key)) => Location::Sidecar {
+                file: sidecar.path.file_name().map_or_else(
+                    || sidecar.path.display().to_string(),
+                    |name| name.to_string_lossy().into_owned(),
+                ),
+                key: key.clone(),
+            },
+            None => Location::Line(line),
+        }
+    }
+}
+
+impl From<Vec<String>> for LeadingComments {
+    /// The leading comment block of a test without a sidecar.
+    fn from(lines: Vec<String>) -> Self {
+        Self {
+            file_lines: lines.len(),
+            lines,
+            sidecar: None,
+        }
+    }
+}
+
+impl std::ops::Deref for LeadingComments {
+    type Target = [String];
+
+    fn deref(&self) -> &[String] {
+        &self.lines
+    }
+}
+
+/// The directives of a sidecar file, rendered as the lines of a leading comment block.
+#[derive(Debug, Default)]
+struct Sidecar {
+    path: PathBuf,
+    lines: Vec<String>,
+    /// Every key of the sidecar with its lines in `lines`, which are none for `false`.
+    keys: Vec<(String, Range<usize>)>,
+}
+
+impl std::error::Error for DirectiveError {}
+
+/// Reads the leading comment block of a source file, i.e. all lines up to the first one that is
+/// not a `//` comment. A shebang-style `#!` first line is part of the block, so directives may
+/// follow it. The rest of the file is not read. The directives of the sidecar file of the test
+/// are merged into the block, see [`LeadingComments::merge`]. Precompiled tests have no comments,
+/// their directives are only read from their sidecar file, see [`sidecar_path`].
+pub(crate) fn read_leading_comments(path: &Path) -> std::io::Result<LeadingComments> {
+    timings::measure(Phase::Directives, || {
+        let comments = match discovery::is_precompiled(path) {
+            true => Vec::new(),
+            false => read_comment_block(path)?,
+        };
+        let sidecar = read_sidecar(&sidecar_path(path))?;
+        Ok(LeadingComments::merge(comments, sidecar))
+    })
+}
+
+/// Reads the leading comment block of a source file without the directives of its sidecar.
+fn read_comment_block(path: &Path) -> std::io::Result<Vec<String>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut comments = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let is_shebang = index == 0 && line.starts_with("#!");
+        if !is_shebang && !line.trim().starts_with("//") {
+            break;
+        }
+        comments.push(line);
+    }
+    Ok(comments)
+}
+
+/// The TOML file next to a test that contains its directives, e.g. `test_foo.bs.toml` for
+/// `test_foo.bs` or `test_foo.bsm.toml` for `test_foo.bsm`. It is optional for both.
+pub(crate) fn sidecar_path(path: &Path) -> PathBuf {
+    let mut sidecar = path.as_os_str().to_os_string();
+    sidecar.push(".toml");
+    PathBuf::from(sidecar)
+}
+
+/// The error of a sidecar file that is no valid TOML or contains unsupported values.
+#[derive(Debug)]
+struct InvalidSidecar(String);
+
+impl fmt::Display for InvalidSidecar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidSidecar {}
+
+/// Whether reading the leading comments of a test failed because its sidecar is no valid TOML or
+/// contains unsupported values, which is reported like invalid directives.
+pub(crate) fn is_invalid_sidecar(error: &std::io::Error) -> bool {
+    error
+        .get_ref()
+        .is_some_and(|error| error.is::<InvalidSidecar>())
+}
+
+/// Reads the directives of a test from its sidecar file and renders them as the lines of a
+/// leading comment block, so they are parsed and validated like those of source files. Every key
+/// is the name of a directive: `true` stands for a directive without a value, strings and arrays
+/// of strings for quoted values and `expects_output` for the block of lines below it. `false`
+/// stands for no directive, overriding the one of the test file. A missing sidecar means that
+/// there are no directives.
+fn read_sidecar(path: &Path) -> std::io::Result<Sidecar> {
+    let invalid = |message: String| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            InvalidSidecar(format!("{}: {message}", path.display())),
+        )
+    };
+    let contents = match std::fs::read_to_string(path) {
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(Sidecar::default()),
+        Err(error) if error.kind() == std::io::ErrorKind::InvalidData => {
+            return Err(invalid(error.to_string()))
+        }
+        contents => contents?,
+    };
+    let table: toml::Table = contents
+        .parse()
+        .map_err(|error: toml::de::Error| invalid(error.message().replace('\n', ", ")))?;
+    let mut comments = Vec::new();
+    let mut keys = Vec::new();
+    for (key, value) in &table {
+        let start = comments.len();
+        let quoted = |value: &str| {
+            let escaped = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n")
+                .replace('\t', "\\t");
+            format!("\"{escaped}\"")
+        };
+        let value = match (key.as_str(), value) {
+            (_, toml::Value::Boolean(false)) => None,
+            (_, toml::Value::Boolean(true)) => {
+                comments.push(format!("// {DIRECTIVE_PREFIX} {key}"));
+                None
+            }
+            ("expects_output", toml::Value::String(output)) => {
+                comments.push(format!("// {DIRECTIVE_PREFIX} {key}:"));
+                comments.extend(output.lines().map(|line| match line.is_empty() {
+                    true => "//".to_string(),
+                    false => format!("//   {line}"),
+                }));
+                None
+            }
+            (_, toml::Value::String(version)) if key.starts_with("requires_") => {
+                comments.push(format!(
+                    "// {DIRECTIVE_PREFIX} {key} >= {}",
+                    quoted(version)
+                ));
+                None
+            }
+            // keywords and durations are not quoted
+            ("timeout", toml::Value::String(value)) => Some(value.clone()),
+            ("lib", toml::Value::String(value)) if value == "none" => Some(value.clone()),
+            (_, toml::Value::String(value)) => Some(quoted(value)),
+            (_, toml::Value::Integer(number)) => Some(number.to_string()),
+            (_, toml::Value::Float(number)) => Some(number.to_string()),
+            (_, toml::Value::Array(values)) => {
+                let values = values
+                    .iter()
+                    .map(|value| value.as_str().map(quoted))
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| invalid(format!("`{key}` requires an array of strings")))?;
+                Some(match key.as_str() {
+                    "program_args" => values.join(" "),
+                    _ => values.join(", "),
+                })
+            }
+            _ => return Err(invalid(format!("unsupported value for `{key}`"))),
+        };
+        if let Some(value) = value {
+            comments.push(format!("// {DIRECTIVE_PREFIX} {key} = {value}"));
+        }
+        keys.push((key.clone(), start..comments.len()));
+    }
+    Ok(Sidecar {
+        path: path.to_path_buf(),
+        lines: comments,
+        keys,
+    })
+}
+
+/// The directives of a leading comment block with all their lines, including the block of a block
+/// directive, by the key that decides which of them wins when the in-file directives are merged
+/// with those of the sidecar. The `fails_with` directives share a key, as a test can only have one
+/// of them.
+fn directive_groups(leading_comments: &[String]) -> Vec<(&str, Range<usize>)> {
+    let mut groups = Vec::new();
+    let mut block_end = 0;
+    for (index, line) in leading_comments.iter().enumerate() {
+        if index < block_end {
+            continue;
+        }
+        let Some(key) = directive_key(line) else {
+            continue;
+        };
+        let length = match BLOCK_KEYS.contains(&key) {
+            true => 1 + parse_block(&leading_comments[index..]).1,
+            false => 1,
+        };
+        block_end = index + length;
+        groups.push((group_key(key), index..block_end));
+    }
+    groups
+}
+
+/// The key that decides which directive wins when the directives of a file are merged with those
+/// of its sidecar, see [`directive_groups`].
+fn group_key(key: &str) -> &str {
+    match key.starts_with("fails_with") {
+        true => "fails_with",
+        false => key,
+    }
+}
+
+/// Finds the directives that the leading comment block of a source file and its sidecar both
+/// specify, but with different values, see `--check`. The sidecar wins when the test is run.
+pub(crate) fn sidecar_conflicts(path: &Path) -> std::io::Result<Vec<String>> {
+    if discovery::is_precompiled(path) {
+        return Ok(Vec::new());
+    }
+    let sidecar = read_sidecar(&sidecar_path(path))?;
+    if sidecar.keys.is_empty() {
+        return Ok(Vec::new());
+    }
+    let comments = read_comment_block(path)?;
+    let in_file = directive_groups(&comments);
+    let mut conflicts = Vec::new();
+    for (key, sidecar_lines) in &sidecar.keys {
+        // `false` specifies no directive, which differs from every directive of the file
+        let value = (!sidecar_lines.is_empty())
+            .then(|| normalized_value(&sidecar.lines[sidecar_lines.clone()]));
+        let differs = in_file.iter().any(|(in_file_key, lines)| {
+            *in_file_key == group_key(key)
+                && Some(normalized_value(&comments[lines.clone()])) != value
+        });
+        if differs {
+            conflicts.push(format!(
+                "the file and its sidecar {} specify `{key}` with different values, the sidecar wins",
+                sidecar.path.display()
+            ));
+        }
+    }
+    Ok(conflicts)
+}
+
+/// The key and the value of a directive without the whitespace outside of quotes, or the text of
+/// the block of a block directive, so equal values written differently compare equal.
+fn normalized_value(lines: &[String]) -> String {
+    let Some((comment, _)) = split_comment(&lines[0]) else {
+        return String::new();
+    };
+    if lines.len() > 1 || comment.ends_with(':') {
+        return format!("{comment}{}", parse_block(lines).0);
+    }
+    let mut normalized = String::with_capacity(comment.len());
+    let mut in_quotes = false;
+    let mut escaped = false;
+    for c in comment.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_quotes => escaped = true,
+            '"' => in_quotes = !in_quotes,
+            c if c.is_whitespace() && !in_quotes => continue,
+            _ => {}
+        }
+        normalized.push(c);
+    }
+    normalized
+}
+
+/// Whether the sidecar of a source file specifies how the test has to fail, so the failure
+/// directive cannot be removed from the source file, see `--fix-stale`.
+pub(crate) fn sidecar_specifies_failure(path: &Path) -> bool {
+    read_sidecar(&sidecar_path(path)).is_ok_and(|sidecar| {
+        sidecar
+            .keys
+            .iter()
+            .any(|(key, _)| group_key(key) == "fails_with")
+    })
+}
+
+/// Whether the leading comment block of a source file marks it as no test, e.g. because it is a
+/// helper module of other tests that happens to match the test file pattern.
+pub(crate) fn is_not_a_test<S: AsRef<str>>(leading_comments: &[S]) -> bool {
+    leading_comments
+        .iter()
+        .any(|line| directive_key(line.as_ref()) == Some("not_a_test"))
+}
+
+/// Whether a source file defines a `main` function and can therefore be run.
+pub(crate) fn has_main_function(source: &str) -> bool {
+    source.lines().any(|line| {
+        line.trim_start()
+            .strip_prefix("function")
+            .filter(|rest| rest.starts_with(char::is_whitespace))
+            .and_then(|rest| rest.trim_start().strip_prefix("main"))
+            .is_some_and(|rest| rest.trim_start().starts_with('('))
+    })
+}
+
+/// Returns the key of a comment line if the line specifies a directive. Every line starting with
+/// the [`DIRECTIVE_PREFIX`] is a directive, so its key is reported as unknown if it is none. Lines
+/// without the prefix are only directives if their key is a known one. Every other comment line
+/// is an ordinary comment.
+pub(crate) fn directive_key(line: &str) -> Option<&str> {
+    let (comment, prefixed) = split_comment(line)?;
+    match prefixed {
+        true => Some(attempted_key(comment).unwrap_or(comment)),
+        false => attempted_key(comment).filter(|key| DIRECTIVE_KEYS.contains(key)),
+    }
+}
+
+/// The text of a comment line after the `//` and the [`DIRECTIVE_PREFIX`], and whether the
+/// prefix is present.
+fn split_comment(line: &str) -> Option<(&str, bool)> {
+    let comment = line.trim().strip_prefix("//")?.trim();
+    Some(match comment.strip_prefix(DIRECTIVE_PREFIX) {
+        Some(directive) => (directive.trim(), true),
+        None => (comment, false),
+    })
+}
+
+/// Returns the key of a comment if it has the shape of a directive: `key = value` or
+/// `key >= value` with an identifier-like key, a bare known key like `skip` or a known block key
+/// followed by a colon like `expects_output:`.
+fn attempted_key(comment: &str) -> Option<&str> {
+    let key = match comment.split_once('=') {
+        Some((key, _)) => {
+            let key = key.trim();
+            key.strip_suffix('>').map_or(key, str::trim_end)
+        }
+        None => match comment.strip_suffix(':').map(str::trim_end) {
+            Some(key) if BLOCK_KEYS.contains(&key) => key,
+            _ if DIRECTIVE_KEYS.contains(&comment) => comment,
+            _ => return None,
+        },
+    };
+    let is_identifier =
+        !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    is_identifier.then_some(key)
+}
+
+/// A line of the leading comment block whose meaning depends on whether the deprecated syntax
+/// without the [`DIRECTIVE_PREFIX`] is supported, see `--check`.
+#[derive(Debug, PartialEq)]
+pub(crate) enum SyntaxIssue {
+    /// A directive with a known key, but without the prefix.
+    Deprecated { line: usize, key: String },
+    /// An ordinary comment of the form `key = value` with an unknown key, e.g. a misspelled
+    /// directive, which is ignored.
+    Ambiguous { line: usize, key: String },
+}
+
+impl fmt::Display for SyntaxIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyntaxIssue::Deprecated { line, key } => write!(
+                f,
+                "line {line}: `{key}` without the `{DIRECTIVE_PREFIX}` prefix is deprecated, write `// {DIRECTIVE_PREFIX} {key} ...`"
+            ),
+            SyntaxIssue::Ambiguous { line, key } => write!(
+                f,
+                "line {line}: looks like a directive, but `{key}` is no known one, so the line is ignored"
+            ),
+        }
+    }
+}
+
+/// Finds the lines of the leading comment block that are directives without the
+/// [`DIRECTIVE_PREFIX`] or look like directives, but are ordinary comments. The lines of the
+/// blocks of block directives are ordinary comments.
+pub(crate) fn syntax_issues<S: AsRef<str>>(leading_comments: &[S]) -> Vec<SyntaxIssue> {
+    let mut issues = Vec::new();
+    let mut block_end = 0;
+    for (index, line) in leading_comments.iter().enumerate() {
+        let line_number = index + 1;
+        let line = line.as_ref();
+        let Some((comment, false)) = split_comment(line).filter(|_| line_number > block_end) else {
+            continue;
+        };
+        match directive_key(line) {
+            Some(key) => {
+                if BLOCK_KEYS.contains(&key) {
+                    block_end = line_number + parse_block(&leading_comments[index..]).1;
+                }
+                issues.push(SyntaxIssue::Deprecated {
+                    line: line_number,
+                    key: key.to_string(),
+                });
+            }
+            None => {
+                if let Some(key) = attempted_key(comment) {
+                    issues.push(SyntaxIssue::Ambiguous {
+                        line: line_number,
+                        key: key.to_string(),
+                    });
+                }
+            }
+        }
+    }
+    issues
+}
+
+/// Parses the directives within the leading comment block of a test. Unknown keys, malformed
+/// values and conflicting directives are reported as errors, at the line of the test file or the
+/// key of the sidecar they were read from.
+pub(crate) fn parse_directives(
+    leading_comments: &LeadingComments,
+) -> Result<TestDirectives, DirectiveError> {
+    let mut directives = TestDirectives::default();
+    // the key and line of every directive, to check them for conflicts
+    let mut keys: Vec<(&str, usize)> = Vec::new();
+    let mut skip_line = None;
+    let mut program_args_line = None;
+    let mut exit_code_line = None;
+    // the line number of the last line of the latest block, whose lines are no directives
+    let mut block_end = 0;
+
+    for (index, line) in leading_comments.iter().enumerate() {
+        let line_number = index + 1;
+        if line_number <= block_end {
+            continue;
+        }
+        let error = |message: String| DirectiveError {
+            location: leading_comments.location(line_number),
+            message,
+        };
+        let Some(key) = directive_key(line) else {
+            continue;
+        };
+        let value = line.split_once('=').map(|(_, value)| value.trim());
+        let is_minimum = line
+            .split_once('=')
+            .is_some_and(|(key, _)| key.trim_end().ends_with('>'));
+        let is_requirement = key.starts_with("requires_") && DIRECTIVE_KEYS.contains(&key);
+        if is_minimum != is_requirement {
+            return Err(error(match is_requirement {
+                true => format!("expected `{key} >= \"VERSION\"`"),
+                false => format!("`>=` is only supported by `requires_` directives, not `{key}`"),
+            }));
+        }
+
+        if key.starts_with("fails_with") && keys.iter().any(|(previous, _)| *previous == key) {
+            return Err(error(format!("duplicate directive `{key}`")));
+        }
+        keys.push((key, line_number));
+        debug!("found the directive `{key}` on line {line_number}");
+
+        match key {
+            "fails_with" => {
+                let value = value.unwrap_or_default();
+                let error_messages = parse_strings(value, Some(',')).map_err(error)?;
+                if error_messages.is_empty() {
+                    return Err(error(
+                        "`fails_with` requires at least one error message".to_string(),
+                    ));
+                }
+                directives.expected_outcome = TestOutcome::Aborted { error_messages };
+            }
+            "fails_with_exact" => {
+                let expected_stderr = parse_string(value.unwrap_or_default()).map_err(error)?;
+                directives.expected_outcome = TestOutcome::AbortedExactly {
+                    expected_stderr: ExpectedStderr::Inline(expected_stderr),
+                };
+            }
+            "fails_with_file" => {
+                let path = parse_string(value.unwrap_or_default()).map_err(error)?;
+                if path.is_empty() {
+                    return Err(error("`fails_with_file` requires a file name".to_string()));
+                }
+                directives.expected_outcome = TestOutcome::AbortedExactly {
+                    expected_stderr: ExpectedStderr::File(PathBuf::from(path)),
+                };
+            }
+            "skip" => {
+                if skip_line.replace(line_number).is_some() {
+                    return Err(error("duplicate directive `skip`".to_string()));
+                }
+                let reason = match value {
+                    Some(value) => parse_string(value).map_err(error)?,
+                    None => String::new(),
+                };
+                directives.skip = Some(reason);
+            }
+            "program_args" => {
+                if program_args_line.replace(line_number).is_some() {
+                    return Err(error("duplicate directive `program_args`".to_string()));
+                }
+                directives.program_args =
+                    parse_strings(value.unwrap_or_default(), None).map_err(error)?;
+            }
+            "allow_empty" => {
+                if directives.allow_empty {
+                    return Err(error("duplicate directive `allow_empty`".to_string()));
+                }
+                if value.is_some() {
+                    return Err(error("`allow_empty` does not take a value".to_string()));
+                }
+                directives.allow_empty = true;
+            }
+            "allow_empty_program" => {
+                if directives.allow_empty_program {
+                    return Err(error(
+                        "duplicate directive `allow_empty_program`".to_string(),
+                    ));
+                }
+                if value.is_some() {
+                    return Err(error(
+                        "`allow_empty_program` does not take a value".to_string(),
+                    ));
+                }
+                directives.allow_empty_program = true;
+            }
+            "timeout" => {
+                let timeout = match value.unwrap_or_default() {
+                    "none" => TestTimeout::None,
+                    value => TestTimeout::Limit(parse_duration(value).ok_or_else(|| {
+                        error(format!(
+                            "`timeout` requires a positive number of seconds like `120`, `1.5s` or `500ms`, or `none`, got `{value}`"
+                        ))
+                    })?),
+                };
+                if directives.timeout.replace(timeout).is_some() {
+                    return Err(error("duplicate directive `timeout`".to_string()));
+                }
+            }
+            "normalize" => {
+                let names = parse_strings(value.unwrap_or_default(), Some(','))
+                    .map_err(error)?
+                    .join(",");
+                let normalization = Normalization::parse(&names).map_err(error)?;
+                if directives.normalize.replace(normalization).is_some() {
+                    return Err(error("duplicate directive `normalize`".to_string()));
+                }
+            }
+            "match_stdout" => {
+                if directives.match_stdout {
+                    return Err(error("duplicate directive `match_stdout`".to_string()));
+                }
+                if value.is_some() {
+                    return Err(error("`match_stdout` does not take a value".to_string()));
+                }
+                directives.match_stdout = true;
+            }
+            // files with this directive are excluded by the discovery, see `is_not_a_test`
+            "not_a_test" => {
+                if value.is_some() {
+                    return Err(error("`not_a_test` does not take a value".to_string()));
+                }
+            }
+            "skip_configs" => {
+                if !directives.skip_configs.is_empty() {
+                    return Err(error("duplicate directive `skip_configs`".to_string()));
+                }
+                directives.skip_configs =
+                    parse_strings(value.unwrap_or_default(), Some(',')).map_err(error)?;
+                if directives.skip_configs.is_empty() {
+                    return Err(error(
+                        "`skip_configs` requires at least one configuration name".to_string(),
+                    ));
+                }
+            }
+            "exit_code" => {
+                if exit_code_line.replace(line_number).is_some() {
+                    return Err(error("duplicate directive `exit_code`".to_string()));
+                }
+                let value = value.unwrap_or_default();
+                let exit_code = value
+                    .parse::<i32>()
+                    .ok()
+                    .filter(|exit_code| (0..crate::VM_ABORT_STATUS).contains(exit_code))
+                    .ok_or_else(|| {
+                        error(format!(
+                            "`exit_code` requires a number from 0 to {}, got `{value}`",
+                            crate::VM_ABORT_STATUS - 1
+                        ))
+                    })?;
+                directives.exit_code = Some(exit_code);
+            }
+            "requires_seatbelt" | "requires_backseater" => {
+                let version = parse_string(value.unwrap_or_default()).map_err(error)?;
+                let version = Version::parse(&version).ok_or_else(|| {
+                    error(format!(
+                        "`{key}` requires a version like \"0.4\", got {version:?}"
+                    ))
+                })?;
+                let requirement = match key {
+                    "requires_seatbelt" => &mut directives.requires_seatbelt,
+                    _ => &mut directives.requires_backseater,
+                };
+                if requirement.replace(version).is_some() {
+                    return Err(error(format!("duplicate directive `{key}`")));
+                }
+            }
+            "warns_with" => {
+                if !directives.warnings.is_empty() {
+                    return Err(error("duplicate directive `warns_with`".to_string()));
+                }
+                directives.warnings =
+                    parse_strings(value.unwrap_or_default(), Some(',')).map_err(error)?;
+                if directives.warnings.is_empty() {
+                    return Err(error(
+                        "`warns_with` requires at least one warning".to_string(),
+                    ));
+                }
+            }
+            "lib" => {
+                let lib = match value.unwrap_or_default() {
+                    "none" => Library::None,
+                    value => {
+                        let path = parse_string(value).map_err(error)?;
+                        if path.is_empty() {
+                            return Err(error("`lib` requires a path or `none`".to_string()));
+                        }
+                        Library::Path(PathBuf::from(path))
+                    }
+                };
+                if directives.lib.replace(lib).is_some() {
+                    return Err(error("duplicate directive `lib`".to_string()));
+                }
+            }
+            "expects_output" => {
+                if value.is_some() || !line.trim_end().ends_with(':') {
+                    return Err(error(
+                        "expected `// expects_output:` followed by the indented lines of the stdout"
+                            .to_string(),
+                    ));
+                }
+                let (text, lines) = parse_block(&leading_comments[index..]);
+                if text.is_empty() {
+                    return Err(error(
+                        "`expects_output:` requires at least one line indented further below it"
+                            .to_string(),
+                    ));
+                }
+                let expected_output = match leading_comments.location(line_number) {
+                    Location::Line(_) => ExpectedOutput {
+                        text,
+                        first_line: line_number + 1,
+                        sidecar: None,
+                    },
+                    Location::Sidecar { .. } => ExpectedOutput {
+                        text,
+                        first_line: 1,
+                        sidecar: leading_comments
+                            .sidecar
+                            .as_ref()
+                            .map(|sidecar| sidecar.path.clone()),
+                    },
+                };
+                if directives
+                    .expected_output
+                    .replace(expected_output)
+                    .is_some()
+                {
+                    return Err(error("duplicate directive `expects_output`".to_string()));
+                }
+                block_end = line_number + lines;
+            }
+            "seed" => {
+                let value = value.unwrap_or_default();
+                let seed = crate::seed::parse_seed(value).map_err(|_| {
+                    error(format!(
+                        "`seed` requires a non-negative integer below 2^64, got `{value}`"
+                    ))
+                })?;
+                if directives.seed.replace(seed).is_some() {
+                    return Err(error("duplicate directive `seed`".to_string()));
+                }
+            }
+            "stage" => {
+                let stage = match parse_string(value.unwrap_or_default())
+                    .map_err(error)?
+                    .as_str()
+                {
+                    "compile" => Stage::Compile,
+                    "run" => Stage::Run,
+                    stage => {
+                        return Err(error(format!(
+                            "`stage` requires \"compile\" or \"run\", got \"{stage}\""
+                        )))
+                    }
+                };
+                if directives.stage.replace(stage).is_some() {
+                    return Err(error("duplicate directive `stage`".to_string()));
+                }
+            }
+            _ => return Err(error(format!("unknown directive `{key}`"))),
+        }
+    }
+
+    check_interactions(&keys, leading_comments)?;
+    Ok(directives)
+}
+
+/// Parses the block below the block directive on the first of the given lines: the following
+/// comment lines that are indented further than the directive, and empty comment lines in
+/// between. Returns the lines with their common indentation removed, each ending with a newline,
+/// and the number of lines of the block. Empty comment lines at the end of the block are not part
+/// of it.
+fn parse_block<S: AsRef<str>>(lines: &[S]) -> (String, usize) {
+    let indentation = |comment: &str| comment.len() - comment.trim_start_matches(' ').len();
+    let directive_indentation = lines
+        .first()
+        .and_then(|line| line.as_ref().trim_start().strip_prefix("//"))
+        .map_or(0, indentation);
+    let mut block = Vec::new();
+    for line in &lines[1..] {
+        match line.as_ref().trim_start().strip_prefix("//") {
+            Some(comment) if comment.trim().is_empty() => block.push(None),
+            Some(comment) if indentation(comment) > directive_indentation => {
+                block.push(Some(comment));
+            }
+            _ => break,
+        }
+    }
+    while block.last() == Some(&None) {
+        block.pop();
+    }
+    let common_indentation = block
+        .iter()
+        .flatten()
+        .map(|comment| indentation(comment))
+        .min();
+    let mut text = String::new();
+    for comment in &block {
+        if let (Some(comment), Some(common_indentation)) = (comment, common_indentation) {
+            text += &comment[common_indentation..];
+        }
+        text.push('\n');
+    }
+    (text, block.len())
+}
+
+/// The directives specifying how a test has to fail, of which a test can only have one.
+const FAILURE_KEYS: &[&str] = &["fails_with", "fails_with_exact", "fails_with_file"];
+
+/// How a directive depends on other directives, see [`INTERACTIONS`].
+enum Interaction {
+    /// The directive cannot be combined with any of the directives.
+    Excludes(&'static [&'static str]),
+    /// The directive needs one of the directives.
+    Requires(&'static [&'static str]),
+}
+
+/// How the directives depend on each other, with the reason. Every directive that interacts with
+/// another one has to be listed here.
+const INTERACTIONS: &[(&str, Interaction, &str)] = &[
+    (
+        "fails_with_exact",
+        Interaction::Excludes(&["fails_with"]),
+        "a test can only fail in one way",
+    ),
+    (
+        "fails_with_file",
+        Interaction::Excludes(&["fails_with", "fails_with_exact"]),
+        "a test can only fail in one way",
+    ),
+    (
+        "skip",
+        Interaction::Excludes(FAILURE_KEYS),
+        "a skipped test is not run at all",
+    ),
+    (
+        "exit_code",
+        Interaction::Excludes(FAILURE_KEYS),
+        "an aborting program never exits with an exit code of its own",
+    ),
+    (
+        "expects_output",
+        Interaction::Excludes(FAILURE_KEYS),
+        "only tests that finish write an output that is compared",
+    ),
+    (
+        "allow_empty",
+        Interaction::Excludes(FAILURE_KEYS),
+        "only tests that finish can look suspiciously empty",
+    ),
+    (
+        "match_stdout",
+        Interaction::Requires(FAILURE_KEYS),
+        "the compiler stdout is only matched against the expected error messages",
+    ),
+    (
+        "stage",
+        Interaction::Requires(FAILURE_KEYS),
+        "only a test that aborts aborts in a stage",
+    ),
+];
+
+/// Reports the first directive of [`INTERACTIONS`] that is combined with a directive it excludes
+/// or lacks the directives it requires, given the directives with the lines of the leading
+/// comments they are on. The error is reported at the location of that directive.
+fn check_interactions(
+    keys: &[(&str, usize)],
+    leading_comments: &LeadingComments,
+) -> Result<(), DirectiveError> {
+    let line = |key: &str| {
+        keys.iter()
+            .find(|(other, _)| *other == key)
+            .map(|(_, line)| *line)
+    };
+    for (key, interaction, reason) in INTERACTIONS {
+        let Some(key_line) = line(key) else {
+            continue;
+        };
+        let message = match interaction {
+            Interaction::Excludes(others) => others.iter().find_map(|other| {
+                let other_location = match leading_comments.location(line(other)?) {
+                    Location::Line(line) => format!("on line {line}"),
+                    Location::Sidecar { file, .. } => format!("in {file}"),
+                };
+                Some(format!(
+                    "`{key}` cannot be combined with `{other}` {other_location}: {reason}"
+                ))
+            }),
+            Interaction::Requires(others) if others.iter().all(|other| line(other).is_none()) => {
+                let others: Vec<String> = others.iter().map(|other| format!("`{other}`")).collect();
+                let (last, others) = others.split_last().expect("a directive requires others");
+                let others = match others.is_empty() {
+                    true => last.clone(),
+                    false => format!("{} or {last}", others.join(", ")),
+                };
+                Some(format!("`{key}` requires a {others} directive: {reason}"))
+            }
+            Interaction::Requires(_) => None,
+        };
+        if let Some(message) = message {
+            return Err(DirectiveError {
+                location: leading_comments.location(key_line),
+                message,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// A filter selecting the tests that specify a directive, optionally with a given value, see
+/// `--only-directives`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct DirectiveFilter {
+    key: String,
+    value: Option<String>,
+}
+
+impl DirectiveFilter {
+    /// Parses `KEY` or `KEY=VALUE` with a known directive key.
+    pub(crate) fn parse(filter: &str) -> Result<Self, String> {
+        let (key, value) = match filter.split_once('=') {
+            Some((key, value)) => (key.trim(), Some(value.trim().to_string())),
+            None => (filter.trim(), None),
+        };
+        if !DIRECTIVE_KEYS.contains(&key) {
+            return Err(format!(
+                "unknown directive `{key}`, expected one of {}",
+                DIRECTIVE_KEYS.join(", ")
+            ));
+        }
+        Ok(Self {
+            key: key.to_string(),
+            value,
+        })
+    }
+
+    /// Whether the leading comment block of a test contains the directive. A value matches if it
+    /// is the whole value of the directive or one of its quoted strings.
+    pub(crate) fn matches<S: AsRef<str>>(&self, leading_comments: &[S]) -> bool {
+        leading_comments.iter().any(|line| {
+            let line = line.as_ref();
+            if directive_key(line) != Some(self.key.as_str()) {
+                return false;
+            }
+            let Some(expected) = &self.value else {
+                return true;
+            };
+            let value = line.split_once('=').map_or("", |(_, value)| value.trim());
+            value == expected
+                || [Some(','), None].into_iter().any(|separator| {
+                    parse_strings(value, separator)
+                        .is_ok_and(|strings| strings.iter().any(|string| string == expected))
+                })
+        })
+    }
+}
+
+impl fmt::Display for DirectiveFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.value {
+            Some(value) => write!(f, "{}={value}", self.key),
+            None => write!(f, "{}", self.key),
+        }
+    }
+}
+
+/// Removes the lines specifying how the test has to fail from the leading comment block of a
+/// source file. Returns `None` if there are no such lines.
+pub(crate) fn remove_failure_directives(contents: &str) -> Option<String> {
+    let mut fixed = String::with_capacity(contents.len());
+    let mut in_leading_comments = true;
+    let mut removed = false;
+    for line in contents.split_inclusive('\n') {
+        in_leading_comments &= line.trim().starts_with("//");
+        let is_failure_directive = in_leading_comments
+            && directive_key(line)
+                .is_some_and(|key| key.starts_with("fails_with") && DIRECTIVE_KEYS.contains(&key));
+        if is_failure_directive {
+            removed = true;
+        } else {
+            fixed.push_str(line);
+        }
+    }
+    removed.then_some(fixed)
+}
+
+/// Parses a positive duration in seconds, optionally with an `s` or `ms` suffix.
+fn parse_duration(value: &str) -> Option<Duration> {
+    let (number, unit) = match value.strip_suffix("ms") {
+        Some(number) => (number, 0.001),
+        None => (value.strip_suffix('s').unwrap_or(value), 1.0),
+    };
+    let seconds = number.trim_end().parse::<f64>().ok()? * unit;
+    (seconds.is_finite() && seconds > 0.0).then(|| Duration::from_secs_f64(seconds))
+}
+
+/// Parses a single quoted string.
+fn parse_string(value: &str) -> Result<String, String> {
+    let mut strings = parse_strings(value, Some(','))?;
+    match strings.len() {
+        1 => Ok(strings.remove(0)),
+        _ => Err(format!("expected a single quoted string, got {value}")),
+    }
+}
+
+/// Parses a list of quoted strings that are separated by `separator` or, if it is `None`, by
+/// whitespace. Within the quotes, `\"` and `\\` escape a quote and a backslash, `\n` and `\t` a
+/// newline and a tab. Other backslashes are kept as is.
+fn parse_strings(value: &str, separator: Option<char>) -> Result<Vec<String>, String> {
+    let mut strings = Vec::new();
+    let mut chars = value.trim().chars().peekable();
+    while chars.peek().is_some() {
+        if chars.next() != Some('"') {
+            return Err(format!("\" prefix not found in {value}"));
+        }
+        let mut string = String::new();
+        loop {
+            match chars.next() {
+                Some('"') => break,
+                Some('\\') => match chars.next() {
+                    Some('"') => string.push('"'),
+                    Some('\\') => string.push('\\'),
+                    Some('n') => string.push('\n'),
+                    Some('t') => string.push('\t'),
+                    Some(other) => {
+                        string.push('\\');
+                        string.push(other);
+                    }
+                    None => return Err(format!("\" suffix not found in {value}")),
+                },
+                Some(c) => string.push(c),
+                None => return Err(format!("\" suffix not found in {value}")),
+            }
+        }
+        strings.push(string);
+
+        let mut separated = false;
+        while chars.next_if(|c| c.is_whitespace()).is_some() {
+            separated = true;
+        }
+        if separator.is_none() {
+            match chars.peek() {
+                Some(other) if !separated => {
+                    return Err(format!("unexpected '{other}' after string in {value}"))
+                }
+                _ => continue,
+            }
+        }
+        match chars.next() {
+            None => break,
+            Some(c) if Some(c) == separator => {
+                while chars.next_if(|c| c.is_whitespace()).is_some() {}
+                if chars.peek().is_none() {
+                    return Err(format!("trailing comma in {value}"));
+                }
+            }
+            Some(other) => return Err(format!("unexpected '{other}' after string in {value}")),
+        }
+    }
+    Ok(strings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::ScratchDir;
+
+    /// The leading comments of a test with the given source and sidecar, read from a scratch
+    /// directory.
+    fn read_with_sidecar(source: &str, sidecar: &str) -> std::io::Result<LeadingComments> {
+        let scratch = ScratchDir::create(None, "directives").unwrap();
+        let path = scratch.path().join("test_foo.bs");
+        std::fs::write(&path, source).unwrap();
+        std::fs::write(sidecar_path(&path), sidecar).unwrap();
+        let leading_comments = read_leading_comments(&path);
+        scratch.finish(false).unwrap();
+        leading_comments
+    }
+
+    fn sidecar_location(key: &str) -> Location {
+        Location::Sidecar {
+            file: "test_foo.bs.toml".to_string(),
+            key: key.to_string(),
+        }
+    }
+
+    #[test]
+    fn overridden_directives_keep_the_line_numbers_of_the_file() {
+        let leading_comments = read_with_sidecar(
+            "// test-runner: seed = 1\n// test-runner: exit_code = 2\n// test-runner: bogus\n",
+            "seed = 3\n",
+        )
+        .unwrap();
+        assert_eq!(leading_comments.len(), 4);
+        assert_eq!(leading_comments[0], OVERRIDDEN);
+        let error = parse_directives(&leading_comments).unwrap_err();
+        assert_eq!(error.location, Location::Line(3));
+    }
+
+    #[test]
+    fn expected_output_blocks_of_the_file_keep_their_line_numbers() {
+        let leading_comments = read_with_sidecar(
+            "// test-runner: seed = 1\n// test-runner: expects_output:\n//   a\n//   b\n",
+            "seed = 3\n",
+        )
+        .unwrap();
+        let directives = parse_directives(&leading_comments).unwrap();
+        let expected_output = directives.expected_output.unwrap();
+        assert_eq!(
+            (expected_output.first_line, expected_output.last_line()),
+            (3, 4)
+        );
+        assert_eq!(expected_output.sidecar, None);
+        assert_eq!(directives.seed, Some(3));
+    }
+
+    #[test]
+    fn overridden_directives_end_blocks() {
+        let leading_comments = read_with_sidecar(
+            "// test-runner: expects_output:\n//   a\n// test-runner: seed = 1\n//   b\n",
+            "seed = 3\n",
+        )
+        .unwrap();
+        let directives = parse_directives(&leading_comments).unwrap();
+        assert_eq!(directives.expected_output.unwrap().text, "a\n");
+    }
+
+    #[test]
+    fn expected_output_of_the_sidecar_is_numbered_within_its_value() {
+        let leading_comments =
+            read_with_sidecar("// a comment\n", "expects_output = \"a\\nb\\n\"\n").unwrap();
+        let expected_output = parse_directives(&leading_comments)
+            .unwrap()
+            .expected_output
+            .unwrap();
+        assert_eq!(expected_output.text, "a\nb\n");
+        assert_eq!(
+            (expected_output.first_line, expected_output.last_line()),
+            (1, 2)
+        );
+        assert!(expected_output
+            .sidecar
+            .is_some_and(|sidecar| sidecar.ends_with("test_foo.bs.toml")));
+    }
+
+    #[test]
+    fn errors_of_the_sidecar_are_attributed_to_its_key() {
+        let leading_comments =
+            read_with_sidecar("// test-runner: seed = 1\n", "timeout = \"soon\"\n").unwrap();
+        let error = parse_directives(&leading_comments).unwrap_err();
+        assert_eq!(error.location, sidecar_location("timeout"));
+        assert!(error
+            .to_string()
+            .starts_with("`timeout` in test_foo.bs.toml: `timeout` requires"));
+    }
+
+    #[test]
+    fn conflicts_with_the_sidecar_name_it() {
+        let leading_comments =
+            read_with_sidecar("// test-runner: exit_code = 1\n", "fails_with = \"boom\"\n")
+                .unwrap();
+        let error = parse_directives(&leading_comments).unwrap_err();
+        assert_eq!(error.location, Location::Line(1));
+        assert!(
+            error
+                .message
+                .contains("cannot be combined with `fails_with` in test_foo.bs.toml"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn false_in_the_sidecar_overrides_the_file() {
+        let leading_comments = read_with_sidecar(
+            "// test-runner: allow_empty\n// test-runner: skip\n",
+            "allow_empty = false\nskip = false\n",
+        )
+        .unwrap();
+        let directives = parse_directives(&leading_comments).unwrap();
+        assert!(!directives.allow_empty);
+        assert_eq!(directives.skip, None);
+    }
+
+    #[test]
+    fn false_in_the_sidecar_conflicts_with_the_file() {
+        let scratch = ScratchDir::create(None, "directives").unwrap();
+        let path = scratch.path().join("test_foo.bs");
+        std::fs::write(
+            &path,
+            "// test-runner: allow_empty\n// test-runner: seed = 1\n",
+        )
+        .unwrap();
+        std::fs::write(sidecar_path(&path), "allow_empty = false\nseed = 1\n").unwrap();
+        let conflicts = sidecar_conflicts(&path).unwrap();
+        scratch.finish(false).unwrap();
+        assert_eq!(conflicts.len(), 1);
+        assert!(conflicts[0].contains("`allow_empty`"), "{conflicts:?}");
+    }
+
+    #[test]
+    fn failure_directives_of_the_sidecar_override_every_one_of_the_file() {
+        let leading_comments = read_with_sidecar(
+            "// test-runner: fails_with = \"boom\"\n",
+            "fails_with_exact = \"bust\"\n",
+        )
+        .unwrap();
+        let directives = parse_directives(&leading_comments).unwrap();
+        assert_eq!(
+            directives.expected_outcome,
+            TestOutcome::AbortedExactly {
+                expected_stderr: ExpectedStderr::Inline("bust".to_string())
+            }
+        );
+    }
+
+    /// A valid directive with the key, as the lines of a leading comment block.
+    fn directive(key: &str) -> Vec<String> {
+        let lines: &[&str] = match key {
+            "fails_with" => &["// test-runner: fails_with = \"boom\""],
+            "fails_with_exact" => &["// test-runner: fails_with_exact = \"boom\""],
+            "fails_with_file" => &["// test-runner: fails_with_file = \"boom.stderr\""],
+            "skip" => &["// test-runner: skip"],
+            "exit_code" => &["// test-runner: exit_code = 1"],
+            "expects_output" => &["// test-runner: expects_output:", "//   a"],
+            "allow_empty" => &["// test-runner: allow_empty"],
+            "match_stdout" => &["// test-runner: match_stdout"],
+            "stage" => &["// test-runner: stage = \"run\""],
+            _ => unreachable!("no sample of `{key}`"),
+        };
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    fn parse(keys: &[&str]) -> Result<TestDirectives, DirectiveError> {
+        let lines: Vec<String> = keys.iter().flat_map(|key| directive(key)).collect();
+        parse_directives(&LeadingComments::from(lines))
+    }
+
+    const FAILURES: [&str; 3] = ["fails_with", "fails_with_exact", "fails_with_file"];
+
+    #[test]
+    fn every_excluded_pair_is_rejected_in_either_order() {
+        let mut pairs = vec![
+            ("fails_with_exact", "fails_with"),
+            ("fails_with_file", "fails_with"),
+            ("fails_with_file", "fails_with_exact"),
+        ];
+        for key in ["skip", "exit_code", "expects_output", "allow_empty"] {
+            pairs.extend(FAILURES.map(|failure| (key, failure)));
+        }
+        for (key, other) in pairs {
+            for order in [[key, other], [other, key]] {
+                let error = parse(&order).unwrap_err();
+                let key_line = match order[0] == key {
+                    true => 1,
+                    false => directive(other).len() + 1,
+                };
+                let other_line = match order[0] == other {
+                    true => 1,
+                    false => directive(key).len() + 1,
+                };
+                assert_eq!(error.location, Location::Line(key_line), "{order:?}");
+                let expected =
+                    format!("`{key}` cannot be combined with `{other}` on line {other_line}: ");
+                assert!(error.message.starts_with(&expected), "{order:?}: {error}");
+            }
+        }
+    }
+
+    #[test]
+    fn directives_for_finishing_tests_combine() {
+        parse(&["skip", "exit_code", "expects_output", "allow_empty"]).unwrap();
+    }
+
+    #[test]
+    fn directives_for_aborting_tests_require_a_failure_directive() {
+        for key in ["match_stdout", "stage"] {
+            let error = parse(&[key]).unwrap_err();
+            assert_eq!(error.location, Location::Line(1));
+            assert!(error.message.starts_with(&format!(
+                "`{key}` requires a `fails_with`, `fails_with_exact` or `fails_with_file` directive: "
+            )));
+            for failure in FAILURES {
+                parse(&[key, failure]).unwrap();
+                parse(&[failure, key]).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn exclusions_are_reported_before_missing_requirements() {
+        let error = parse(&["stage", "skip", "fails_with"]).unwrap_err();
+        assert!(error
+            .message
+            .starts_with("`skip` cannot be combined with `fails_with`"));
+    }
+
+    /// Parses the directives of a leading comment block given as text.
+    fn parse_block_text(text: &str) -> Result<TestDirectives, DirectiveError> {
+        let lines: Vec<String> = text.lines().map(str::to_string).collect();
+        parse_directives(&LeadingComments::from(lines))
+    }
+
+    fn error_of(text: &str) -> String {
+        parse_block_text(text).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn the_prefix_is_optional_around_whitespace() {
+        for line in [
+            "// test-runner: seed = 7",
+            "//test-runner:seed=7",
+            "   //   test-runner:   seed   =   7   ",
+        ] {
+            assert_eq!(parse_block_text(line).unwrap().seed, Some(7), "{line}");
+        }
+    }
+
+    #[test]
+    fn prefixed_unknown_keys_are_errors() {
+        assert_eq!(
+            error_of("// test-runner: sead = 7"),
+            "line 1: unknown directive `sead`"
+        );
+        assert_eq!(
+            error_of("// test-runner: skip it"),
+            "line 1: unknown directive `skip it`"
+        );
+    }
+
+    #[test]
+    fn unprefixed_unknown_keys_are_ordinary_comments() {
+        let directives = parse_block_text("// sead = 7\n// skipping this is fine\n").unwrap();
+        assert_eq!(directives, TestDirectives::default());
+        assert_eq!(
+            syntax_issues(&["// sead = 7", "// skipping this is fine"]),
+            vec![SyntaxIssue::Ambiguous {
+                line: 1,
+                key: "sead".to_string()
+            }]
+        );
+    }
+
+    #[test]
+    fn deprecated_bare_keys_are_still_directives() {
+        let text = "#!/usr/bin/env backseat\n// skip\n// fails_with = \"boom\"\n";
+        assert_eq!(
+            error_of(text),
+            "line 2: `skip` cannot be combined with `fails_with` on line 3: a skipped test is not run at all"
+        );
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(
+            syntax_issues(&lines),
+            vec![
+                SyntaxIssue::Deprecated {
+                    line: 2,
+                    key: "skip".to_string()
+                },
+                SyntaxIssue::Deprecated {
+                    line: 3,
+                    key: "fails_with".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn prefixed_directives_have_no_syntax_issues() {
+        assert_eq!(
+            syntax_issues(&["// test-runner: skip", "// test-runner: seed = 1"]),
+            Vec::new()
+        );
+    }
+
+    #[test]
+    fn the_lines_of_blocks_are_no_directives() {
+        let text = "// expects_output:\n//   skip\n//   seed = 1\n// test-runner: exit_code = 3\n";
+        let directives = parse_block_text(text).unwrap();
+        assert_eq!(directives.expected_output.unwrap().text, "skip\nseed = 1\n");
+        assert_eq!(directives.skip, None);
+        assert_eq!(directives.exit_code, Some(3));
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(syntax_issues(&lines).len(), 1);
+    }
+
+    #[test]
+    fn duplicate_directives_are_errors() {
+        for (text, key) in [
+            ("// test-runner: skip\n// test-runner: skip", "skip"),
+            (
+                "// test-runner: fails_with = \"a\"\n// test-runner: fails_with = \"b\"",
+                "fails_with",
+            ),
+            (
+                "// test-runner: fails_with_exact = \"a\"\n// fails_with_exact = \"b\"",
+                "fails_with_exact",
+            ),
+            ("// test-runner: seed = 1\n// test-runner: seed = 1", "seed"),
+            (
+                "// test-runner: exit_code = 1\n// test-runner: exit_code = 2",
+                "exit_code",
+            ),
+            (
+                "// test-runner: program_args = \"a\"\n// test-runner: program_args = \"b\"",
+                "program_args",
+            ),
+            ("// test-runner: allow_empty\n// allow_empty", "allow_empty"),
+            (
+                "// test-runner: timeout = 1\n// test-runner: timeout = none",
+                "timeout",
+            ),
+            (
+                "// test-runner: stage = \"run\"\n// test-runner: stage = \"run\"",
+                "stage",
+            ),
+        ] {
+            assert_eq!(
+                error_of(text),
+                format!("line 2: duplicate directive `{key}`"),
+                "{text}"
+            );
+        }
+    }
+
+    #[test]
+    fn quoted_values_support_escapes() {
+        let directives =
+            parse_block_text(r#"// test-runner: fails_with = "a \"b\"", "c\\d\ne\tf", "g\h""#)
+                .unwrap();
+        assert_eq!(
+            directives.expected_outcome,
+            TestOutcome::Aborted {
+                error_messages: vec![
+                    "a \"b\"".to_string(),
+                    "c\\d\ne\tf".to_string(),
+                    "g\\h".to_string()
+                ]
+            }
+        );
+        let directives =
+            parse_block_text(r#"// test-runner: program_args = "a b" "" "c,d""#).unwrap();
+        assert_eq!(directives.program_args, ["a b", "", "c,d"]);
+    }
+
+    #[test]
+    fn malformed_quoting_is_an_error() {
+        for (value, message) in [
+            ("boom", "\" prefix not found in boom"),
+            ("\"boom", "\" suffix not found in \"boom"),
+            ("\"boom\\\"", "\" suffix not found in \"boom\\\""),
+            ("\"a\" \"b\"", "unexpected '\"' after string in \"a\" \"b\""),
+            ("\"a\",", "trailing comma in \"a\","),
+        ] {
+            let error = error_of(&format!("// test-runner: fails_with = {value}"));
+            assert_eq!(error, format!("line 1: {message}"), "{value}");
+        }
+        assert_eq!(
+            error_of("// test-runner: fails_with ="),
+            "line 1: `fails_with` requires at least one error message"
+        );
+        assert_eq!(
+            error_of(r#"// test-runner: program_args = "a""b""#),
+            r#"line 1: unexpected '"' after string in "a""b""#
+        );
+    }
+
+    #[test]
+    fn single_strings_reject_lists() {
+        assert_eq!(
+            error_of(r#"// test-runner: skip = "a", "b""#),
+            r#"line 1: expected a single quoted string, got "a", "b""#
+        );
+    }
+
+    #[test]
+    fn minimum_versions_require_requires_directives() {
+        assert_eq!(
+            error_of("// test-runner: seed >= 1"),
+            "line 1: `>=` is only supported by `requires_` directives, not `seed`"
+        );
+        assert_eq!(
+            error_of("// test-runner: requires_seatbelt = \"1.0\""),
+            "line 1: expected `requires_seatbelt >= \"VERSION\"`"
+        );
+    }
+
+    #[test]
+    fn directives_follow_ordinary_comments() {
+        for text in [
+            "// AUTOGENERATED - DO NOT EDIT\n// test-runner: fails_with = \"boom\"\n",
+            "#!/usr/bin/env backseat\n// Copyright the authors\n// test-runner: fails_with = \"boom\"\n",
+        ] {
+            assert_eq!(
+                parse_block_text(text).unwrap().expected_outcome,
+                TestOutcome::Aborted {
+                    error_messages: vec!["boom".to_string()]
+                },
+                "{text}"
+            );
+        }
+    }
+
+    #[test]
+    fn the_comment_block_ends_at_the_first_line_of_code() {
+        let scratch = ScratchDir::create(None, "directives").unwrap();
+        let path = scratch.path().join("test_foo.bs");
+        std::fs::write(
+            &path,
+            "#!/usr/bin/env backseat\n// a comment\n// test-runner: skip\nfunction main() {}\n// test-runner: seed = 1\n",
+        )
+        .unwrap();
+        let leading_comments = read_leading_comments(&path).unwrap();
+        scratch.finish(false).unwrap();
+        assert_eq!(leading_comments.len(), 3);
+        let directives = parse_directives(&leading_comments).unwrap();
+        assert_eq!(directives.skip, Some(String::new()));
+        assert_eq!(directives.seed, None);
+    }
+}
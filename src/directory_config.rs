@@ -0,0 +1,143 @@
+//! Settings for the tests below a directory, read from a `test-runner.toml` in that directory.
+//! The files of all directories between a tests path and a test apply to the test, with the
+//! settings of the innermost file winning.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context};
+use serde::Deserialize;
+
+use crate::stderr_noise::NoisePattern;
+
+/// The name of the file specifying the settings of a directory.
+pub(crate) const DIRECTORY_CONFIG_FILE: &str = "test-runner.toml";
+
+/// The contents of a single `test-runner.toml`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DirectoryConfig {
+    /// In seconds, replacing `--timeout`.
+    timeout: Option<f64>,
+    /// In seconds, replacing `--compile-timeout`.
+    compile_timeout: Option<f64>,
+    #[serde(default)]
+    seatbelt_args: Vec<String>,
+    #[serde(default)]
+    backseater_args: Vec<String>,
+    /// Environment variables of the compiler and the VM.
+    #[serde(default)]
+    env: BTreeMap<String, String>,
+    /// Added to `--ignore-stderr-pattern`.
+    #[serde(default)]
+    ignore_stderr_patterns: Vec<String>,
+}
+
+/// The merged settings of all `test-runner.toml` files that apply to a test.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub(crate) struct Overrides {
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) compile_timeout: Option<Duration>,
+    /// The arguments of all files, the ones of outer directories first.
+    pub(crate) seatbelt_args: Vec<String>,
+    pub(crate) backseater_args: Vec<String>,
+    pub(crate) env: BTreeMap<String, String>,
+    /// The patterns of all files, the ones of outer directories first.
+    pub(crate) ignore_stderr_patterns: Vec<NoisePattern>,
+    /// The files the settings were read from, outermost first.
+    pub(crate) sources: Vec<PathBuf>,
+}
+
+/// Returns the outermost directory containing a `test-runner.toml` among the current working
+/// directory and its ancestors. The outermost one is used because the inner ones may belong to
+/// test directories.
+pub(crate) fn find_project_root() -> Option<PathBuf> {
+    let current_dir = std::env::current_dir().ok()?;
+    current_dir
+        .ancestors()
+        .filter(|directory| directory.join(DIRECTORY_CONFIG_FILE).is_file())
+        .last()
+        .map(Path::to_path_buf)
+}
+
+/// Resolves the overrides of directories, reading every `test-runner.toml` only once.
+#[derive(Default)]
+pub(crate) struct Resolver {
+    /// The file of every directory that has been looked at, `None` if there is none.
+    files: HashMap<PathBuf, Option<Arc<DirectoryConfig>>>,
+    /// The resolved overrides of every directory.
+    overrides: HashMap<PathBuf, Arc<Overrides>>,
+}
+
+impl Resolver {
+    /// Returns the merged settings of the files in `root` and in all directories between it and
+    /// `directory`, including `directory` itself.
+    pub(crate) fn resolve(
+        &mut self,
+        root: &Path,
+        directory: &Path,
+    ) -> anyhow::Result<Arc<Overrides>> {
+        if let Some(overrides) = self.overrides.get(directory) {
+            return Ok(overrides.clone());
+        }
+        let mut overrides = Overrides::default();
+        let relative_path = directory.strip_prefix(root).unwrap_or(Path::new(""));
+        let mut current = root.to_path_buf();
+        let components = std::iter::once(None).chain(relative_path.components().map(Some));
+        for component in components {
+            if let Some(component) = component {
+                current.push(component);
+            }
+            if let Some(file) = self.read(&current)? {
+                let path = current.join(DIRECTORY_CONFIG_FILE);
+                let seconds = |seconds: Option<f64>| match seconds {
+                    Some(seconds) if !seconds.is_finite() || seconds <= 0.0 => Err(anyhow!(
+                        "{}: the number of seconds must be positive: {seconds}",
+                        path.display()
+                    )),
+                    seconds => Ok(seconds.map(Duration::from_secs_f64)),
+                };
+                overrides.timeout = seconds(file.timeout)?.or(overrides.timeout);
+                overrides.compile_timeout =
+                    seconds(file.compile_timeout)?.or(overrides.compile_timeout);
+                overrides
+                    .seatbelt_args
+                    .extend(file.seatbelt_args.iter().cloned());
+                overrides
+                    .backseater_args
+                    .extend(file.backseater_args.iter().cloned());
+                overrides.env.extend(file.env.clone());
+                for pattern in &file.ignore_stderr_patterns {
+                    let pattern = NoisePattern::parse(pattern)
+                        .map_err(|error| anyhow!("{}: {error}", path.display()))?;
+                    overrides.ignore_stderr_patterns.push(pattern);
+                }
+                overrides.sources.push(path);
+            }
+        }
+        let overrides = Arc::new(overrides);
+        self.overrides
+            .insert(directory.to_path_buf(), overrides.clone());
+        Ok(overrides)
+    }
+
+    fn read(&mut self, directory: &Path) -> anyhow::Result<Option<Arc<DirectoryConfig>>> {
+        if let Some(file) = self.files.get(directory) {
+            return Ok(file.clone());
+        }
+        let path = directory.join(DIRECTORY_CONFIG_FILE);
+        let file = match std::fs::read_to_string(&path) {
+            Ok(contents) => Some(Arc::new(
+                toml::from_str(&contents).with_context(|| format!("invalid {}", path.display()))?,
+            )),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => None,
+            Err(error) => {
+                return Err(error).with_context(|| format!("unable to read {}", path.display()))
+            }
+        };
+        self.files.insert(directory.to_path_buf(), file.clone());
+        Ok(file)
+    }
+}
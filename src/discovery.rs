@@ -0,0 +1,227 @@
+//! Discovery of the tests below the tests paths. A test is either a single source file matching
+//! [`TEST_FILE_PATTERN`] or a test directory: a directory whose name starts with `test_` and that
+//! contains a `main.bs`. All other source files within a test directory are auxiliary modules of
+//! that test. With `--include-precompiled`, files matching [`PRECOMPILED_TEST_FILE_PATTERN`] are
+//! tests as well, which are run without compiling them.
+
+use std::collections::HashSet;
+use std::io::stderr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::directory_config::{self, Overrides};
+use crate::output::print_warning;
+
+/// The pattern a source file name has to match to be considered a test.
+pub(crate) const TEST_FILE_PATTERN: &str = "test*.bs";
+
+/// The pattern the file name of a precompiled Backseater program has to match to be considered a
+/// test, see `--include-precompiled`.
+pub(crate) const PRECOMPILED_TEST_FILE_PATTERN: &str = "test*.bsm";
+
+/// The extension of precompiled Backseater programs.
+const PRECOMPILED_EXTENSION: &str = "bsm";
+
+/// The prefix of the name of a test directory.
+const TEST_DIRECTORY_PREFIX: &str = "test_";
+
+/// The source file a test directory is compiled from.
+const TEST_DIRECTORY_ENTRY_POINT: &str = "main.bs";
+
+/// One of the directories given via `--tests-path`.
+pub(crate) struct TestRoot {
+    /// The prefix of the names of the tests below this root, which is empty if there is only one
+    /// root.
+    pub(crate) label: String,
+    /// The canonicalized path.
+    pub(crate) path: PathBuf,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct TestFile {
+    /// The name the test is reported with, see [`test_name`].
+    pub(crate) name: String,
+    /// The index of the root the test was discovered in.
+    pub(crate) root: usize,
+    /// The path the test is named after: the source file or the test directory.
+    pub(crate) path: PathBuf,
+    /// The source file that is compiled, which is `main.bs` for test directories. For precompiled
+    /// tests, this is the program passed to the Backseater.
+    pub(crate) source_path: PathBuf,
+    /// Whether the test is listed in the quarantine file.
+    pub(crate) quarantined: bool,
+    /// The settings of the `test-runner.toml` files between the root and the test.
+    pub(crate) overrides: Arc<Overrides>,
+}
+
+/// The Backseat source files below the tests paths.
+pub(crate) struct Discovery {
+    pub(crate) tests: Vec<TestFile>,
+    /// All source files that are neither a test nor part of a test directory.
+    pub(crate) other_files: Vec<PathBuf>,
+}
+
+/// Walks all roots and sorts the Backseat source files into tests and other files. Files that are
+/// found below multiple overlapping roots or, if `follow_symlinks` is set, via multiple links are
+/// only included once, for the first path they are found at. Symlink cycles are reported as
+/// warnings and not descended into. The `test-runner.toml` files that apply to every test are
+/// resolved as well. Precompiled tests are only included if `include_precompiled` is set.
+pub(crate) fn discover(
+    roots: &[TestRoot],
+    follow_symlinks: bool,
+    include_precompiled: bool,
+) -> anyhow::Result<Discovery> {
+    let mut discovery = Discovery {
+        tests: Vec::new(),
+        other_files: Vec::new(),
+    };
+    let mut seen = HashSet::new();
+    let mut cycles = HashSet::new();
+    let mut resolver = directory_config::Resolver::default();
+    for (index, root) in roots.iter().enumerate() {
+        let patterns = match include_precompiled {
+            true => ["*.bs", PRECOMPILED_TEST_FILE_PATTERN].as_slice(),
+            false => &["*.bs"],
+        };
+        let walker = globwalk::GlobWalkerBuilder::from_patterns(&root.path, patterns)
+            .follow_links(follow_symlinks)
+            .build()?;
+        for entry in walker {
+            let path = match entry {
+                Ok(entry) => entry.into_path(),
+                Err(error) => match (error.loop_ancestor(), error.path()) {
+                    (Some(ancestor), Some(link)) => {
+                        let cycle = ancestor.canonicalize().unwrap_or(ancestor.to_path_buf());
+                        if cycles.insert(cycle) {
+                            print_warning(
+                                &mut stderr(),
+                                &format!(
+                                    "not following the symlink cycle {} -> {}\n",
+                                    link.display(),
+                                    ancestor.display()
+                                ),
+                            );
+                        }
+                        continue;
+                    }
+                    _ => return Err(error.into()),
+                },
+            };
+            let canonical_path = match follow_symlinks {
+                true => path.canonicalize()?,
+                false => path.clone(),
+            };
+            if !seen.insert(canonical_path) {
+                continue;
+            }
+            let mut test = |path: PathBuf, source_path: PathBuf| -> anyhow::Result<TestFile> {
+                let directory = source_path.parent().unwrap_or(&root.path);
+                Ok(TestFile {
+                    name: test_name(root, &path),
+                    root: index,
+                    overrides: resolver.resolve(&root.path, directory)?,
+                    path,
+                    source_path,
+                    quarantined: false,
+                })
+            };
+            match test_directory(&root.path, &path) {
+                // precompiled programs within test directories are opaque files like any other
+                Some(_) if is_precompiled(&path) => {}
+                Some(directory) => {
+                    if path == directory.join(TEST_DIRECTORY_ENTRY_POINT) {
+                        discovery.tests.push(test(directory, path)?);
+                    }
+                }
+                None if is_test_file_name(&path) => discovery.tests.push(test(path.clone(), path)?),
+                None => discovery.other_files.push(path),
+            }
+        }
+    }
+    Ok(discovery)
+}
+
+/// Selects the listed tests in the order they are listed in, see `--tests-from`. An entry is the
+/// name of a test or its path relative to one of the roots. Entries that match no test become
+/// tests whose source file does not exist, so they fail instead of being silently dropped.
+pub(crate) fn select(tests: Vec<TestFile>, roots: &[TestRoot], entries: &[&str]) -> Vec<TestFile> {
+    let mut selected: Vec<TestFile> = Vec::new();
+    for entry in entries {
+        // test names always use forward slashes, also on Windows
+        let name = entry.replace(std::path::MAIN_SEPARATOR, "/");
+        let paths: Vec<_> = roots
+            .iter()
+            .map(|root| {
+                let path = root.path.join(entry);
+                path.canonicalize().unwrap_or(path)
+            })
+            .collect();
+        let test = tests.iter().find(|test| {
+            test.name == name || paths.contains(&test.path) || paths.contains(&test.source_path)
+        });
+        let test = match test {
+            Some(test) => test.clone(),
+            None => TestFile {
+                name,
+                root: 0,
+                path: paths[0].clone(),
+                source_path: paths[0].clone(),
+                quarantined: false,
+                overrides: Arc::default(),
+            },
+        };
+        if !selected.iter().any(|other| other.path == test.path) {
+            selected.push(test);
+        }
+    }
+    selected
+}
+
+/// The name a test is reported with: its path relative to its root, prefixed with the label of
+/// the root, using forward slashes as separators on all platforms.
+pub(crate) fn test_name(root: &TestRoot, path: &Path) -> String {
+    let relative_path = path.strip_prefix(&root.path).unwrap_or(path);
+    let mut components = Vec::new();
+    if !root.label.is_empty() {
+        components.push(root.label.clone());
+    }
+    components.extend(
+        relative_path
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy().into_owned()),
+    );
+    components.join("/")
+}
+
+/// Returns the outermost test directory below `tests_root` that contains `path`.
+fn test_directory(tests_root: &Path, path: &Path) -> Option<PathBuf> {
+    let relative_path = path.strip_prefix(tests_root).ok()?;
+    let mut directory = tests_root.to_path_buf();
+    for component in relative_path.parent()?.components() {
+        directory.push(component);
+        let is_test_directory = component
+            .as_os_str()
+            .to_string_lossy()
+            .starts_with(TEST_DIRECTORY_PREFIX)
+            && directory.join(TEST_DIRECTORY_ENTRY_POINT).is_file();
+        if is_test_directory {
+            return Some(directory);
+        }
+    }
+    None
+}
+
+/// Whether the file name matches [`TEST_FILE_PATTERN`] or [`PRECOMPILED_TEST_FILE_PATTERN`].
+fn is_test_file_name(path: &Path) -> bool {
+    path.file_name()
+        .map(|name| name.to_string_lossy())
+        .is_some_and(|name| {
+            name.starts_with("test") && (name.ends_with(".bs") || name.ends_with(".bsm"))
+        })
+}
+
+/// Whether the file is a precompiled Backseater program, which is run without compiling it.
+pub(crate) fn is_precompiled(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == PRECOMPILED_EXTENSION)
+}
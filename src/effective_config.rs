@@ -0,0 +1,297 @@
+//! The fully resolved settings of a run, see `--print-config`. They are printed as TOML and
+//! embedded into the JSON report, so every report records the settings it was produced with.
+
+use std::path::Path;
+use std::time::Duration;
+
+use clap::ValueEnum;
+use serde::ser::SerializeMap;
+use serde::{Serialize, Serializer};
+use serde_json::{json, Value};
+
+use crate::directory_config::Overrides;
+use crate::seed::SeedArg;
+use crate::stderr_noise::NoisePattern;
+use crate::Cli;
+
+/// A single resolved setting.
+struct Setting {
+    key: &'static str,
+    /// `Value::Null` if the setting is not set.
+    value: Value,
+    /// Where the value comes from if it is not the command line or a default.
+    source: Option<String>,
+}
+
+/// The resolved settings in the order they are printed.
+pub(crate) struct EffectiveConfig {
+    settings: Vec<Setting>,
+}
+
+impl EffectiveConfig {
+    pub(crate) fn resolve(cli: &Cli) -> Self {
+        let path = |path: &Path| {
+            let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+            Value::from(path.display().to_string())
+        };
+        let optional_path = |value: &Option<_>| value.as_deref().map_or(Value::Null, path);
+        let seconds = |duration: Option<Duration>| {
+            duration.map_or(Value::Null, |duration| json!(duration.as_secs_f64()))
+        };
+        let name = |value: Option<clap::PossibleValue>| {
+            value.map_or(Value::Null, |value| Value::from(value.get_name()))
+        };
+        let jobs = match cli.no_parallel {
+            true => 1,
+            false => rayon::current_num_threads(),
+        };
+        let color = std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty());
+        let setting = |key, value| Setting {
+            key,
+            value,
+            source: None,
+        };
+        let settings = vec![
+            setting("project_root", optional_path(&cli.project_root)),
+            setting("seatbelt_path", path(&cli.seatbelt_path)),
+            setting("backseater_path", path(&cli.backseater_path)),
+            setting("lib_path", path(&cli.lib_path)),
+            setting("strict_paths", cli.strict_paths.into()),
+            setting(
+                "tests_path",
+                cli.tests_path
+                    .iter()
+                    .map(|tests_path| path(tests_path))
+                    .collect(),
+            ),
+            setting("follow_symlinks", cli.follow_symlinks.into()),
+            setting("include_precompiled", cli.include_precompiled.into()),
+            setting("strict_discovery", cli.strict_discovery.into()),
+            setting(
+                "only_directives",
+                cli.only_directives
+                    .iter()
+                    .map(|filter| Value::from(filter.to_string()))
+                    .collect(),
+            ),
+            setting("tests_from", optional_path(&cli.tests_from)),
+            setting("explain_selection", optional_path(&cli.explain_selection)),
+            setting("quarantine", optional_path(&cli.quarantine)),
+            setting("work_dir", optional_path(&cli.work_dir)),
+            setting("sandbox", cli.sandbox.into()),
+            setting("deny_warnings", cli.deny_warnings.into()),
+            setting(
+                "expect_stage",
+                name(cli.expect_stage.and_then(|stage| stage.to_possible_value())),
+            ),
+            setting("report_stage", cli.report_stage.into()),
+            setting("stats", cli.stats.into()),
+            setting("max_repeated_warnings", cli.max_repeated_warnings.into()),
+            setting("keep_failed_tmp", cli.keep_failed_tmp.into()),
+            setting("stream_output", cli.stream_output.into()),
+            setting("no_status", cli.no_status.into()),
+            setting(
+                "config_sets",
+                cli.config_set
+                    .iter()
+                    .map(|set| {
+                        json!({
+                            "name": set.name,
+                            "seatbelt_args": set.seatbelt_args,
+                            "backseater_args": set.backseater_args,
+                        })
+                    })
+                    .collect(),
+            ),
+            setting("jobs", jobs.into()),
+            setting("fail_fast", cli.fail_fast.into()),
+            setting("warm_up", cli.warm_up.into()),
+            setting("total_timeout", seconds(cli.total_timeout)),
+            setting("timeout", seconds(cli.timeout)),
+            setting("compile_timeout", seconds(cli.compile_timeout)),
+            setting("memory_limit_mb", cli.memory_limit.into()),
+            setting("cpu_limit_seconds", cli.cpu_limit.into()),
+            setting("format", name(cli.format.to_possible_value())),
+            setting(
+                "symbols",
+                name(cli.symbols.and_then(|symbols| symbols.to_possible_value())),
+            ),
+            Setting {
+                key: "color",
+                value: color.into(),
+                source: Some("the NO_COLOR environment variable".to_string()),
+            },
+            setting("halt_mode", name(cli.halt_mode.to_possible_value())),
+            setting("phases", name(cli.phases.to_possible_value())),
+            setting("breakage_threshold", cli.breakage_threshold.into()),
+            setting("seed", cli.seed.into()),
+            setting(
+                "seed_arg",
+                match &cli.seed_arg {
+                    Some(SeedArg::Args(args)) => args.join(" ").into(),
+                    Some(SeedArg::Env(name)) => format!("env:{name}").into(),
+                    None => Value::Null,
+                },
+            ),
+            setting(
+                "name_format",
+                cli.name_format
+                    .as_ref()
+                    .map_or(Value::Null, |name_format| name_format.template().into()),
+            ),
+            setting(
+                "stdin_encoding",
+                name(cli.stdin_encoding.to_possible_value()),
+            ),
+            setting("keep_ansi", cli.keep_ansi.into()),
+            setting("child_color", name(cli.child_color.to_possible_value())),
+            setting(
+                "error_context",
+                match cli.full_output {
+                    true => Value::Null,
+                    false => cli.error_context.into(),
+                },
+            ),
+            setting("no_heuristics", cli.no_heuristics.into()),
+            setting("mismatch_threshold", cli.mismatch_threshold.into()),
+            setting("no_source_context", cli.no_source_context.into()),
+            setting("no_normalize", cli.no_normalize.into()),
+            setting(
+                "ignore_stderr_patterns",
+                json!(cli
+                    .ignore_stderr_pattern
+                    .iter()
+                    .map(NoisePattern::as_str)
+                    .collect::<Vec<_>>()),
+            ),
+            setting(
+                "trim_trailing_whitespace",
+                cli.trim_trailing_whitespace.into(),
+            ),
+            setting("normalize_line_endings", cli.normalize_line_endings.into()),
+            setting(
+                "normalize_final_newline",
+                cli.normalize_final_newline.into(),
+            ),
+            setting("paranoid", cli.paranoid.into()),
+            setting("bless", cli.bless.into()),
+            setting("fix_stale", cli.fix_stale.into()),
+            setting("log_dir", optional_path(&cli.log_dir)),
+            setting("no_prune", cli.no_prune.into()),
+            setting("csv", optional_path(&cli.csv)),
+            setting("baseline", optional_path(&cli.baseline)),
+            setting("fail_on", name(cli.fail_on.to_possible_value())),
+            setting("strict_reports", cli.strict_reports.into()),
+            setting("if_locked", name(cli.if_locked.to_possible_value())),
+            setting("lock_grace_period", seconds(Some(cli.lock_grace_period))),
+            setting("record", optional_path(&cli.record)),
+            setting("replay", optional_path(&cli.replay)),
+            setting("on_test_result", cli.on_test_result.clone().into()),
+        ];
+        Self { settings }
+    }
+
+    /// Applies the settings of the `test-runner.toml` files of a test: the timeouts they specify
+    /// replace the global ones, their arguments and environment variables are added.
+    pub(crate) fn apply_overrides(&mut self, overrides: &Overrides) {
+        if overrides.sources.is_empty() {
+            return;
+        }
+        let sources: Vec<_> = overrides
+            .sources
+            .iter()
+            .map(|source| source.display().to_string())
+            .collect();
+        let source = Some(sources.join(", "));
+        let timeouts = [
+            ("timeout", overrides.timeout),
+            ("compile_timeout", overrides.compile_timeout),
+        ];
+        for (key, timeout) in timeouts {
+            let Some(timeout) = timeout else {
+                continue;
+            };
+            if let Some(setting) = self.settings.iter_mut().find(|setting| setting.key == key) {
+                setting.value = json!(timeout.as_secs_f64());
+                setting.source = source.clone();
+            }
+        }
+        let additions = [
+            ("seatbelt_args", json!(overrides.seatbelt_args)),
+            ("backseater_args", json!(overrides.backseater_args)),
+            ("env", json!(overrides.env)),
+            (
+                "ignore_stderr_patterns",
+                json!(overrides
+                    .ignore_stderr_patterns
+                    .iter()
+                    .map(NoisePattern::as_str)
+                    .collect::<Vec<_>>()),
+            ),
+        ];
+        for (key, value) in additions {
+            self.settings.push(Setting {
+                key,
+                value,
+                source: source.clone(),
+            });
+        }
+    }
+
+    /// Renders the settings as a TOML document. Settings that are not set are commented out,
+    /// since TOML has no null value.
+    pub(crate) fn to_toml(&self) -> String {
+        let mut document = String::new();
+        for setting in &self.settings {
+            if let Some(source) = &setting.source {
+                document += &format!("# from {source}\n");
+            }
+            match &setting.value {
+                Value::Null => document += &format!("# {} is not set\n", setting.key),
+                value => document += &format!("{} = {}\n", setting.key, toml_value(value)),
+            }
+        }
+        document
+    }
+}
+
+impl Serialize for EffectiveConfig {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.settings.len()))?;
+        for setting in &self.settings {
+            map.serialize_entry(setting.key, &setting.value)?;
+        }
+        map.end()
+    }
+}
+
+/// Renders a JSON value as a TOML value. JSON strings are valid TOML basic strings, objects are
+/// rendered as inline tables.
+fn toml_value(value: &Value) -> String {
+    match value {
+        Value::Array(values) => {
+            let values: Vec<_> = values.iter().map(toml_value).collect();
+            format!("[{}]", values.join(", "))
+        }
+        Value::Object(entries) => {
+            let entries: Vec<_> = entries
+                .iter()
+                .map(|(key, value)| format!("{} = {}", toml_key(key), toml_value(value)))
+                .collect();
+            format!("{{ {} }}", entries.join(", "))
+        }
+        value => value.to_string(),
+    }
+}
+
+/// Quotes a key of an inline table unless it is a valid bare key.
+fn toml_key(key: &str) -> String {
+    let is_bare = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    match is_bare {
+        true => key.to_string(),
+        false => Value::from(key).to_string(),
+    }
+}
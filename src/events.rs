@@ -0,0 +1,153 @@
+//! The lifecycle events of a run, which the console output, the NDJSON output and the
+//! `--on-test-result` hook are driven by. The events are delivered on the thread that started the
+//! run, however many workers run the tests, so handlers need neither be `Send` nor `Sync`.
+
+use std::sync::mpsc;
+
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
+
+use crate::config::Config;
+use crate::discovery::TestFile;
+use crate::summary::Summary;
+use crate::timings::{self, Phase};
+use crate::{process, total_timeout, TestResult};
+
+pub(crate) enum RunEvent<'a> {
+    /// The tests were discovered and selected, none of them was started yet. `runs` is the number
+    /// of tests times the number of configurations.
+    Discovered { tests: &'a [TestFile], runs: usize },
+    /// The worker with the given index started running the test.
+    Started {
+        worker: usize,
+        test: &'a TestFile,
+        configuration: Option<&'a str>,
+    },
+    /// The worker with the given index finished running a test. Tests that were killed because
+    /// the run was aborted do not finish.
+    Finished {
+        worker: usize,
+        result: &'a TestResult,
+    },
+    /// All tests finished or the run was stopped early.
+    RunFinished { summary: &'a Summary },
+}
+
+pub(crate) trait EventHandler {
+    fn on_event(&mut self, event: &RunEvent);
+}
+
+impl<F: FnMut(&RunEvent)> EventHandler for F {
+    fn on_event(&mut self, event: &RunEvent) {
+        self(event)
+    }
+}
+
+/// Delivers the event to every handler, in order.
+pub(crate) fn dispatch(handlers: &mut [&mut dyn EventHandler], event: &RunEvent) {
+    for handler in handlers {
+        handler.on_event(event);
+    }
+}
+
+/// The messages of the workers to the thread delivering the events.
+enum Message {
+    Started {
+        index: usize,
+        worker: usize,
+    },
+    Finished {
+        index: usize,
+        worker: usize,
+        result: Option<Box<TestResult>>,
+    },
+}
+
+/// Runs every test in every configuration with `run`, on the rayon workers unless `parallel` is
+/// false, and delivers the events to the handlers on the current thread. No further test is
+/// started once the run is aborted or winding down. Returns the results in the order of `runs`,
+/// without the tests that were not started or did not finish.
+pub(crate) fn run_all(
+    tests: &[TestFile],
+    runs: &[(&Config, &TestFile)],
+    parallel: bool,
+    handlers: &mut [&mut dyn EventHandler],
+    run: impl Fn(&Config, &TestFile) -> Option<TestResult> + Sync,
+) -> Vec<TestResult> {
+    dispatch(
+        handlers,
+        &RunEvent::Discovered {
+            tests,
+            runs: runs.len(),
+        },
+    );
+    let mut results: Vec<Option<TestResult>> = runs.iter().map(|_| None).collect();
+    let (sender, receiver) = mpsc::channel();
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            let execute =
+                |sender: &mut mpsc::Sender<Message>,
+                 (index, &(config, test)): (usize, &(&Config, &TestFile))| {
+                    if process::is_aborted() || total_timeout::is_winding_down() {
+                        return;
+                    }
+                    let worker = rayon::current_thread_index().unwrap_or_default();
+                    // the receiver only stops receiving once every sender is dropped
+                    sender.send(Message::Started { index, worker }).ok();
+                    let result = run(config, test).map(Box::new);
+                    sender
+                        .send(Message::Finished {
+                            index,
+                            worker,
+                            result,
+                        })
+                        .ok();
+                };
+            match parallel {
+                true => runs.par_iter().enumerate().for_each_with(sender, execute),
+                false => {
+                    let mut sender = sender;
+                    runs.iter()
+                        .enumerate()
+                        .for_each(|run| execute(&mut sender, run));
+                }
+            }
+        });
+        for message in receiver {
+            match message {
+                Message::Started { index, worker } => {
+                    let (config, test) = runs[index];
+                    let configuration = config
+                        .configuration
+                        .as_ref()
+                        .map(|configuration| configuration.name.as_str());
+                    dispatch(
+                        handlers,
+                        &RunEvent::Started {
+                            worker,
+                            test,
+                            configuration,
+                        },
+                    );
+                }
+                Message::Finished {
+                    index,
+                    worker,
+                    result: Some(result),
+                } => {
+                    timings::measure(Phase::Reporting, || {
+                        dispatch(
+                            handlers,
+                            &RunEvent::Finished {
+                                worker,
+                                result: &result,
+                            },
+                        )
+                    });
+                    results[index] = Some(*result);
+                }
+                Message::Finished { result: None, .. } => {}
+            }
+        }
+    });
+    results.into_iter().flatten().collect()
+}
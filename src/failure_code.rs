@@ -0,0 +1,122 @@
+//! The stable codes failures are reported with, so automation can group failures without matching
+//! on the failure messages. The codes are part of the compatibility contract of the output: they
+//! must never be changed or reused for a different kind of failure.
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FailureCode {
+    /// The test directives are malformed.
+    Directives,
+    /// The compilation of a test that was expected to compile failed.
+    CompileUnexpected,
+    /// The execution of a test that was expected to finish aborted.
+    RuntimeUnexpected,
+    /// The test aborted as expected, but with a different error message or stderr.
+    WrongMessage,
+    /// The test aborted with the expected error, but in a different stage than the expected one,
+    /// see `--expect-stage`.
+    WrongStage,
+    /// The test finished, but its stdout differs from the expected one.
+    WrongOutput,
+    /// The test finished with a different exit code than the expected one.
+    WrongExitCode,
+    /// The test finished although it was expected to abort.
+    UnexpectedSuccess,
+    /// The compilation succeeded with warnings that are not expected, see `--deny-warnings`.
+    UnexpectedWarning,
+    /// Files below the tests paths changed while the test ran.
+    Sandbox,
+    /// The compiler or the VM did not finish within its timeout.
+    Timeout,
+    /// The VM exceeded its memory or CPU time limit.
+    LimitExceeded,
+    /// The compiler or the VM was killed by a signal.
+    Crash,
+    /// The test passed, but looks like it did not test anything, see `--paranoid`.
+    Suspicious,
+    /// The compilation succeeded, but the compiler wrote no program, so the VM would run an empty
+    /// one.
+    EmptyProgram,
+    /// The test could not be run properly, e.g. because an expectation file is unreadable or the
+    /// VM did not accept the compiled program.
+    Infra,
+}
+
+impl FailureCode {
+    pub(crate) const ALL: [FailureCode; 16] = [
+        FailureCode::Directives,
+        FailureCode::CompileUnexpected,
+        FailureCode::RuntimeUnexpected,
+        FailureCode::WrongMessage,
+        FailureCode::WrongStage,
+        FailureCode::WrongOutput,
+        FailureCode::WrongExitCode,
+        FailureCode::UnexpectedSuccess,
+        FailureCode::UnexpectedWarning,
+        FailureCode::Sandbox,
+        FailureCode::Timeout,
+        FailureCode::LimitExceeded,
+        FailureCode::Crash,
+        FailureCode::Suspicious,
+        FailureCode::EmptyProgram,
+        FailureCode::Infra,
+    ];
+
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            FailureCode::Directives => "E-DIRECTIVES",
+            FailureCode::CompileUnexpected => "E-COMPILE-UNEXPECTED",
+            FailureCode::RuntimeUnexpected => "E-RUNTIME-UNEXPECTED",
+            FailureCode::WrongMessage => "E-WRONG-MESSAGE",
+            FailureCode::WrongStage => "E-WRONG-STAGE",
+            FailureCode::WrongOutput => "E-WRONG-OUTPUT",
+            FailureCode::WrongExitCode => "E-EXIT-CODE",
+            FailureCode::UnexpectedWarning => "E-WARNING",
+            FailureCode::Sandbox => "E-SANDBOX",
+            FailureCode::UnexpectedSuccess => "E-UNEXPECTED-SUCCESS",
+            FailureCode::Timeout => "E-TIMEOUT",
+            FailureCode::LimitExceeded => "E-LIMIT",
+            FailureCode::Crash => "E-CRASH",
+            FailureCode::Suspicious => "E-SUSPICIOUS",
+            FailureCode::EmptyProgram => "E-EMPTY-PROGRAM",
+            FailureCode::Infra => "E-INFRA",
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            FailureCode::Directives => "the test directives are malformed",
+            FailureCode::CompileUnexpected => "the compilation failed unexpectedly",
+            FailureCode::RuntimeUnexpected => "the execution aborted unexpectedly",
+            FailureCode::WrongMessage => "the test aborted with a different error message",
+            FailureCode::WrongStage => "the test aborted as expected, but in a different stage",
+            FailureCode::WrongOutput => "the test finished with a different stdout",
+            FailureCode::WrongExitCode => "the test finished with a different exit code",
+            FailureCode::UnexpectedWarning => "the compilation succeeded with unexpected warnings",
+            FailureCode::Sandbox => "files below the tests paths changed while the test ran",
+            FailureCode::UnexpectedSuccess => "the test finished but was expected to abort",
+            FailureCode::Timeout => "the compiler or the VM timed out",
+            FailureCode::LimitExceeded => "the VM exceeded its memory or CPU time limit",
+            FailureCode::Crash => "the compiler or the VM was killed by a signal",
+            FailureCode::Suspicious => "the test looks suspiciously empty (--paranoid)",
+            FailureCode::EmptyProgram => "the compiler succeeded without writing a program",
+            FailureCode::Infra => "the test could not be run properly",
+        }
+    }
+
+    /// The section of the help listing all codes.
+    pub(crate) fn help() -> String {
+        let mut help = String::from("FAILURE CODES:\n");
+        for code in Self::ALL {
+            help += &format!("    {:<22} {}\n", code.as_str(), code.description());
+        }
+        help
+    }
+}
+
+impl fmt::Display for FailureCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
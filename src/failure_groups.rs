@@ -0,0 +1,56 @@
+//! Groups failed tests by their failure message, so a broken toolchain that fails hundreds of
+//! tests with the same error is reported once instead of once per test, see `--no-dedup`.
+
+use crate::{TestResult, TestResultKind};
+
+/// The failed tests that share a fingerprint, in the order they were reported.
+pub(crate) struct FailureGroup<'a> {
+    /// The status of the failures, e.g. `FAILED [E-COMPILE-UNEXPECTED]`.
+    pub(crate) status: String,
+    /// The failure message of the first test of the group.
+    pub(crate) message: &'a str,
+    pub(crate) filenames: Vec<&'a str>,
+}
+
+/// Groups the failed results by their status and the first line of their failure message, with the
+/// path of the respective test replaced by a placeholder. The groups are ordered by their first
+/// test.
+pub(crate) fn group_failures(results: &[TestResult]) -> Vec<FailureGroup<'_>> {
+    let mut groups: Vec<(String, FailureGroup)> = Vec::new();
+    for result in results {
+        let (TestResultKind::Failure(_, message)
+        | TestResultKind::UnexpectedSuccess(message)
+        | TestResultKind::Crash(message)) = &result.kind
+        else {
+            continue;
+        };
+        let status = result.kind.status();
+        let fingerprint = format!("{status}\n{}", fingerprint(result, message));
+        match groups.iter_mut().find(|(key, _)| *key == fingerprint) {
+            Some((_, group)) => group.filenames.push(&result.filename),
+            None => groups.push((
+                fingerprint,
+                FailureGroup {
+                    status,
+                    message,
+                    filenames: vec![&result.filename],
+                },
+            )),
+        }
+    }
+    groups.into_iter().map(|(_, group)| group).collect()
+}
+
+/// The first non-empty line of the failure message with the source path and the names of the test
+/// replaced by `<test>`, so messages that differ only in the test they mention are equal.
+fn fingerprint(result: &TestResult, message: &str) -> String {
+    let first_line = message
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .unwrap_or_default();
+    first_line
+        .replace(&*result.test.source_path.to_string_lossy(), "<test>")
+        .replace(&result.filename, "<test>")
+        .replace(&result.report_name, "<test>")
+}
@@ -0,0 +1,41 @@
+//! The command that is invoked for every finished test, see `--on-test-result`.
+
+use std::process::Command;
+
+use log::{debug, warn};
+
+use crate::process;
+use crate::report::{self, Event};
+
+/// Runs the hook command through the shell with `event` as JSON on its stdin and waits for it to
+/// exit. Failures are only logged. Since the hook runs on the thread that finished the test, no
+/// more hooks run at once than tests do.
+pub(crate) fn invoke(hook: &str, event: &Event) {
+    let mut command = shell_command(hook);
+    let input = report::ndjson_line(event).into_bytes();
+    debug!("spawning hook {:?}", command);
+    match process::run(&mut command, Some(input), None) {
+        Ok(output) if output.output.status.success() => {}
+        Ok(output) => warn!(
+            "hook `{}` failed ({}): {}",
+            hook,
+            output.output.status,
+            String::from_utf8_lossy(&output.output.stderr).trim()
+        ),
+        Err(error) => warn!("unable to run hook `{}`: {}", hook, error),
+    }
+}
+
+#[cfg(unix)]
+fn shell_command(hook: &str) -> Command {
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(hook);
+    command
+}
+
+#[cfg(windows)]
+fn shell_command(hook: &str) -> Command {
+    let mut command = Command::new("cmd");
+    command.arg("/C").arg(hook);
+    command
+}
@@ -0,0 +1,195 @@
+//! The prompt to triage the failed tests after a run, see `--interactive`.
+
+use std::io::{stdin, stdout, BufRead, Write};
+use std::path::Path;
+
+use crossterm::execute;
+use crossterm::style::{Color, Print, ResetColor, SetForegroundColor};
+
+use crate::config::Config;
+use crate::discovery;
+use crate::output::Printer;
+use crate::text::{shell_env, shell_executable, shell_quote};
+use crate::{
+    directives, library_path, process, run_single_test, seed_env, vm_arguments, TestResult,
+    TestResultKind,
+};
+
+const HELP: &str = "commands:
+  list        list the failed tests
+  show <N>    print the captured output of test N
+  rerun <N>   run test N again
+  repro <N>   print a shell pipeline reproducing test N
+  quit        leave the interactive mode
+";
+
+/// Runs the prompt loop on the failed tests of a run until the user quits. Every test is rerun and
+/// reproduced with the one of the `configs` it failed with. Returns whether all of the tests
+/// succeeded on their latest rerun.
+pub(crate) fn triage(
+    configs: &[Config],
+    printer: &Printer,
+    mut failures: Vec<TestResult>,
+) -> anyhow::Result<bool> {
+    failures.sort_by(|a, b| a.filename.cmp(&b.filename));
+    // the run may have been aborted after the first failure, but all of its tests are done now
+    process::resume_run();
+
+    println!();
+    print_list(&failures)?;
+    print!("{HELP}");
+    let mut lines = stdin().lock().lines();
+    loop {
+        print!("> ");
+        stdout().flush()?;
+        let Some(line) = lines.next() else {
+            println!();
+            break;
+        };
+        let line = line?;
+        let mut words = line.split_whitespace();
+        let (command, argument) = (words.next(), words.next());
+        let index = match command {
+            None => continue,
+            Some("quit" | "q" | "exit") => break,
+            Some("list" | "l") => {
+                print_list(&failures)?;
+                continue;
+            }
+            Some("help" | "h" | "?") => {
+                print!("{HELP}");
+                continue;
+            }
+            Some("show" | "s" | "rerun" | "r" | "repro" | "p") => {
+                match parse_index(argument, failures.len()) {
+                    Ok(index) => index,
+                    Err(message) => {
+                        println!("{message}");
+                        continue;
+                    }
+                }
+            }
+            Some(_) => {
+                print!("unknown command\n{HELP}");
+                continue;
+            }
+        };
+
+        let failure = &mut failures[index];
+        let config = configs
+            .iter()
+            .find(|config| {
+                let name = config.configuration.as_ref().map(|set| set.name.as_str());
+                name == failure.configuration.as_deref()
+            })
+            .unwrap_or(&configs[0]);
+        match command {
+            Some("show" | "s") => {
+                print!("{}", failure.log.render(failure.duration, &failure.kind));
+            }
+            Some("rerun" | "r") => {
+                *failure = run_single_test(config, &failure.test);
+                match &failure.kind {
+                    TestResultKind::Success => {
+                        printer.print_success(&failure.filename, failure.log.seed, None, None)
+                    }
+                    TestResultKind::Failure(_, message)
+                    | TestResultKind::UnexpectedSuccess(message)
+                    | TestResultKind::Crash(message) => printer.print_fail(
+                        &failure.filename,
+                        &failure.kind.status(),
+                        failure.log.seed,
+                        message,
+                    ),
+                    TestResultKind::Skipped(reason) => {
+                        printer.print_skipped(&failure.filename, reason)
+                    }
+                    TestResultKind::Quarantined(_, message) => {
+                        printer.print_quarantined(&failure.filename, failure.log.seed, message)
+                    }
+                }
+            }
+            _ => {
+                let config = config.with_overrides(&failure.test.overrides);
+                let command = repro_command(&config, &failure.test.source_path, failure.log.seed);
+                println!("{command}")
+            }
+        }
+    }
+
+    Ok(failures.iter().all(|failure| !failure.kind.is_failure()))
+}
+
+/// Prints the numbered list of failed tests together with their latest result.
+fn print_list(failures: &[TestResult]) -> std::io::Result<()> {
+    for (index, failure) in failures.iter().enumerate() {
+        let color = match failure.kind {
+            TestResultKind::Success => Color::DarkGreen,
+            TestResultKind::Failure(..)
+            | TestResultKind::UnexpectedSuccess(_)
+            | TestResultKind::Crash(_) => Color::DarkRed,
+            TestResultKind::Skipped(_) | TestResultKind::Quarantined(..) => Color::DarkYellow,
+        };
+        execute!(
+            stdout(),
+            Print(format!("{:>3}) {} ", index + 1, failure.filename)),
+            SetForegroundColor(color),
+            Print(failure.kind.status()),
+            ResetColor,
+            Print("\n")
+        )?;
+    }
+    Ok(())
+}
+
+/// Parses the 1-based number of a test into an index into the list of failures.
+fn parse_index(argument: Option<&str>, count: usize) -> Result<usize, String> {
+    let argument = argument.ok_or("the command requires the number of a test")?;
+    match argument.parse::<usize>() {
+        Ok(number) if (1..=count).contains(&number) => Ok(number - 1),
+        _ => Err(format!("expected a number between 1 and {count}")),
+    }
+}
+
+/// The shell pipeline that compiles and runs the test the same way the test runner does.
+fn repro_command(config: &Config, path: &Path, seed: Option<u64>) -> String {
+    let directives = directives::read_leading_comments(path)
+        .ok()
+        .and_then(|leading_comments| directives::parse_directives(&leading_comments).ok())
+        .unwrap_or_default();
+    let mut compiler_args = vec![path.display().to_string()];
+    if let Some(lib_path) = library_path(config, path, directives.lib.as_ref()) {
+        compiler_args.extend(["--lib".to_string(), lib_path.display().to_string()]);
+    }
+    compiler_args.extend(config.seatbelt_args().cloned());
+    let env: Vec<_> = (config.overrides.env.iter())
+        .map(|(name, value)| (name.as_str(), value.clone()))
+        .collect();
+    let vm_env: Vec<_> = env.iter().cloned().chain(seed_env(config, seed)).collect();
+    let command_line = |executable: &Path, args: &[String]| {
+        let mut words = vec![shell_executable(executable)];
+        words.extend(args.iter().map(|arg| shell_quote(arg)));
+        words.join(" ")
+    };
+    let compiler = command_line(&config.seatbelt_path, &compiler_args);
+    let vm = command_line(
+        &config.backseater_path,
+        &vm_arguments(config, &directives.program_args, seed),
+    );
+    // precompiled tests are passed to the VM as they are
+    if discovery::is_precompiled(path) {
+        let program = shell_quote(&path.display().to_string());
+        return match cfg!(windows) {
+            true => format!(
+                "{}Get-Content -AsByteStream {program} | {vm}",
+                shell_env(&vm_env)
+            ),
+            false => format!("{}{vm} < {program}", shell_env(&vm_env)),
+        };
+    }
+    match cfg!(windows) {
+        // the assignments are statements of their own in PowerShell, see `shell_env`
+        true => format!("{}{compiler} | {vm}", shell_env(&vm_env)),
+        false => format!("{}{compiler} | {}{vm}", shell_env(&env), shell_env(&vm_env)),
+    }
+}
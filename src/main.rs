@@ -1,20 +1,108 @@
+use std::collections::{BTreeMap, HashSet};
 use std::error::Error;
 use std::ffi::OsStr;
-use std::io::{stdout, Write};
+use std::fs::File;
+use std::io::{stderr, stdin, stdout, ErrorKind, IsTerminal, Write};
 use std::path::{Path, PathBuf};
-use std::process::{Command, Stdio};
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::process::{Command, ExitStatus, Output};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use anyhow::anyhow;
-use clap::Parser;
+use anyhow::{anyhow, Context};
+use clap::{CommandFactory, FromArgMatches, Parser};
 use crossterm::execute;
 use crossterm::style::{Color, Print, ResetColor, SetForegroundColor};
+use log::{debug, LevelFilter};
 use rayon::iter::ParallelIterator;
 use rayon::prelude::IntoParallelRefIterator;
 
+mod baseline;
+mod compile_first;
+mod config;
+mod diff;
+mod directives;
+mod directory_config;
+mod discovery;
+mod effective_config;
+mod events;
+mod failure_code;
+mod failure_groups;
+mod hook;
+mod interactive;
+mod mismatch;
+mod name_format;
+mod normalization;
+mod notify;
+mod output;
+mod process;
+mod quarantine;
+mod replay;
+mod report;
+mod run_lock;
+mod sandbox;
+mod scratch;
+mod seed;
+mod selection;
+mod self_check;
+mod shell_words;
+mod source_context;
+mod stats;
+mod status_board;
+mod stderr_noise;
+mod stream;
+mod summary;
+mod test_log;
+mod text;
+mod timings;
+mod toolchain;
+mod total_timeout;
+mod version;
+mod warnings;
+
+use baseline::{Baseline, FailOn};
+use compile_first::{Compilations, CompileOutcome};
+use config::{Config, Configuration};
+use directives::{
+    DirectiveFilter, ExpectedOutput, LeadingComments, Library, SyntaxIssue, TestDirectives,
+    TestOutcome, TestTimeout,
+};
+use directory_config::Overrides;
+use discovery::{Discovery, TestFile, TestRoot, TEST_FILE_PATTERN};
+use effective_config::EffectiveConfig;
+use events::{EventHandler, RunEvent};
+use failure_code::FailureCode;
+use mismatch::Mismatch;
+use name_format::NameFormat;
+use normalization::Normalization;
+use output::{print_warning, use_color, ConsoleReporter, Printer, Symbols};
+use process::{LineSink, ProcessOutput};
+use replay::Tape;
+use report::{Event, Format, ManifestEntry, NdjsonWriter, ReportErrors, SummaryEvent, TestEvent};
+use run_lock::IfLocked;
+use sandbox::Sandbox;
+use scratch::ScratchDir;
+use seed::SeedArg;
+use selection::{Explanation, Selection};
+use stats::Stats;
+use status_board::StatusBoard;
+use stderr_noise::NoisePattern;
+use stream::StreamSlot;
+use summary::Summary;
+use test_log::{Stage, StageRecord, TestLog};
+use text::strip_ansi;
+use timings::{Phase, RunnerTimings};
+use toolchain::Toolchain;
+
 #[derive(Parser)]
 #[clap(author, version, about)]
 struct Cli {
+    /// The directory all relative paths given as options, including the defaults, are resolved
+    /// against. Defaults to the outermost directory containing a `test-runner.toml` among the
+    /// working directory and its ancestors, or the working directory if there is none.
+    #[clap(long, value_name = "PATH")]
+    project_root: Option<PathBuf>,
+
     /// The path to the Seatbelt compiler executable.
     #[clap(short, long, value_parser, default_value = "./Seatbelt")]
     seatbelt_path: PathBuf,
@@ -23,260 +111,3035 @@ struct Cli {
     #[clap(short, long, value_parser, default_value = "./backseat_safe_system_2k")]
     backseater_path: PathBuf,
 
-    /// The path to the standard library for the Backseat language. The path must specify the
-    /// parent directory of the std-folder.
-    #[clap(short, long, value_parser, default_value = ".")]
-    lib_path: PathBuf,
+    /// The path to the standard library for the Backseat language. The path must specify the
+    /// parent directory of the std-folder, which is checked before the tests run, see
+    /// `--strict-paths`.
+    #[clap(short, long, value_parser, default_value = ".")]
+    lib_path: PathBuf,
+
+    /// Fail before any test runs if the `--lib-path` contains no `std` directory instead of only
+    /// warning about it.
+    #[clap(long)]
+    strict_paths: bool,
+
+    /// The paths of the Backseat source files to test, which may be given multiple times or as a
+    /// comma-separated list. The source files have to start with 'test_' and end with '.bs' to be
+    /// tested. A directory starting with 'test_' that contains a 'main.bs' is a single test
+    /// compiled from 'main.bs', the other files in it are helper modules.
+    #[clap(short, long, value_parser, default_value = ".", value_delimiter = ',')]
+    tests_path: Vec<PathBuf>,
+
+    /// Follow symbolic links while discovering the tests. Tests that are reachable via multiple
+    /// links are only run once.
+    #[clap(long)]
+    follow_symlinks: bool,
+
+    /// Also run the precompiled Backseater programs matching `test*.bsm` outside test
+    /// directories, which are passed to the Backseater without compiling them. Their directives
+    /// are read from a TOML file next to them, e.g. `test_foo.bsm.toml` containing
+    /// `fails_with = ["error"]`.
+    #[clap(long)]
+    include_precompiled: bool,
+
+    /// A file listing known-flaky tests by name, one per line, with `#` starting a comment. The
+    /// failures of these tests are reported, but do not fail the run.
+    #[clap(long, value_name = "PATH")]
+    quarantine: Option<PathBuf>,
+
+    /// Compile and run a few built-in programs to verify that the toolchain works instead of
+    /// running the tests.
+    #[clap(long)]
+    self_check: bool,
+
+    /// Compile and run a tiny built-in program once before the tests, so the time the OS needs to
+    /// load the toolchain binaries is not attributed to the first tests. The warm-up is not
+    /// included in the reported time of the run.
+    #[clap(long)]
+    warm_up: bool,
+
+    /// How compiling and running the tests is scheduled. With `interleaved`, every test is run
+    /// right after it is compiled. With `compile-first`, all tests are compiled before any is run,
+    /// and the run is stopped if more than `--breakage-threshold` of the tests that have to compile
+    /// fail with the same leading error, which usually means that the toolchain or the standard
+    /// library is broken. The compilations are reused when the tests are run.
+    #[clap(
+        long,
+        value_enum,
+        default_value = "interleaved",
+        conflicts_with_all = &["record", "replay"]
+    )]
+    phases: Phases,
+
+    /// The fraction of the tests that have to compile, i.e. that have neither a failure directive
+    /// nor a snapshot, that has to fail with the same error to stop the run, see `--phases`.
+    #[clap(long, value_name = "FRACTION", value_parser = parse_fraction, default_value = "0.5")]
+    breakage_threshold: f64,
+
+    /// Do not check whether most tests failed with the same usage error of the toolchain, which
+    /// usually means that the toolchain expects different arguments than the test runner passes.
+    /// Such a run is reported as a toolchain mismatch with exit status 3 instead of as individual
+    /// test failures.
+    #[clap(long)]
+    no_heuristics: bool,
+
+    /// The fraction of the run tests that has to fail with the same usage error in the same stage
+    /// to report a toolchain mismatch.
+    #[clap(long, value_name = "FRACTION", value_parser = parse_fraction, default_value = "0.5")]
+    mismatch_threshold: f64,
+
+    /// Print the min, median, p90 and max of the compile and run durations, the total, median and
+    /// largest size of the compiled programs and the CPU time spent in the toolchain after the
+    /// summary. The CPU time is only available on Unix.
+    #[clap(long)]
+    stats: bool,
+
+    /// Fail the run instead of only warning if multiple tests share the same file name.
+    #[clap(long)]
+    deny_duplicates: bool,
+
+    /// Fail tests whose compiler stderr contains lines that are not expected by a `warns_with`
+    /// directive although the compilation succeeds.
+    #[clap(long)]
+    deny_warnings: bool,
+
+    /// Fail tests with a failure directive that abort with the expected error, but in another
+    /// stage than the given one, e.g. at compile time although the test is meant to check a
+    /// runtime error. A `stage` directive replaces this for a single test.
+    #[clap(long, value_enum, value_name = "STAGE")]
+    expect_stage: Option<Stage>,
+
+    /// Show the stage in which every successful test with a failure directive aborted.
+    #[clap(long)]
+    report_stage: bool,
+
+    /// Fail the run instead of only warning if Backseat source files that contain test directives
+    /// do not match the test file pattern.
+    #[clap(long)]
+    deny_unmatched: bool,
+
+    /// Fail if a file matching the test file pattern neither defines a `main` function nor has a
+    /// `not_a_test` directive, which excludes helper modules of other tests from the run.
+    #[clap(long)]
+    strict_discovery: bool,
+
+    /// Only check the directives of all tests for errors instead of running the tests. Directives
+    /// without the `test-runner:` prefix and ordinary comments that look like directives are
+    /// reported as well, and so are directives that a test and its sidecar file, e.g.
+    /// `test_foo.bs.toml`, both specify with different values.
+    #[clap(long)]
+    check: bool,
+
+    /// List the discovered tests instead of running them: their names, or with a machine-readable
+    /// `--format` their paths, sizes, modification times and parsed directives, including how
+    /// they are expected to end. Only the leading comment blocks of the tests are read.
+    #[clap(long, conflicts_with = "check")]
+    list: bool,
+
+    /// Only run the tests whose leading comment block contains the given directive, e.g.
+    /// `fails_with`, or the directive with the given value, e.g. `lib=none`. A value also matches
+    /// a single quoted string of the directive. If given multiple times, tests have to match all
+    /// filters.
+    #[clap(long, value_name = "KEY[=VALUE]", value_parser = DirectiveFilter::parse)]
+    only_directives: Vec<DirectiveFilter>,
+
+    /// Only run the tests listed in the given file, or in stdin if it is `-`, in the order they
+    /// are listed in. Every line contains the name of a test or its path relative to a tests path.
+    /// Blank lines and lines starting with `#` are ignored. Listed tests that do not exist fail.
+    #[clap(long, value_name = "PATH")]
+    tests_from: Option<PathBuf>,
+
+    /// Print every selection rule that applied to the test at the given path, e.g. its
+    /// `not_a_test` directive or `--tests-from`, and whether it is selected, instead of running
+    /// the tests.
+    #[clap(long, value_name = "PATH", conflicts_with_all = &["check", "list"])]
+    explain_selection: Option<PathBuf>,
+
+    /// The directory the scratch directories of the tests are created in instead of the system
+    /// temp directory. Every test gets a directory of its own, passed to the toolchain in the
+    /// `TEST_TMPDIR` environment variable.
+    #[clap(long, value_name = "PATH")]
+    work_dir: Option<PathBuf>,
+
+    /// Run the Backseater in the scratch directory of the test and fail every test during which a
+    /// file below the tests paths was created, modified or deleted. When tests run in parallel,
+    /// all tests running at the time of a change fail.
+    #[clap(long)]
+    sandbox: bool,
+
+    /// Keep the scratch directories of failed tests instead of removing them after the test.
+    #[clap(long)]
+    keep_failed_tmp: bool,
+
+    /// Print the stdout of the Backseater to stderr line by line while a test runs, prefixed with
+    /// the name of the test, e.g. to watch the progress of long-running tests. Only one test
+    /// streams at a time, the output of tests running concurrently is only captured, so this is
+    /// most useful with `--no-parallel`.
+    #[clap(long)]
+    stream_output: bool,
+
+    /// Do not show the live status lines below the results, one per worker with the test it is
+    /// running and for how long. They are only shown if the results are written to a terminal and
+    /// never with `--stream-output`.
+    #[clap(long)]
+    no_status: bool,
+
+    /// A directory to write a log file with the commands and outputs of every test to. The
+    /// directory structure mirrors the one of the tests.
+    #[clap(long, value_parser)]
+    log_dir: Option<PathBuf>,
+
+    /// Keep the logs in `--log-dir` of tests that no longer exist. By default, they are removed
+    /// after runs that are not restricted to some of the tests.
+    #[clap(long)]
+    no_prune: bool,
+
+    /// Fail the run whenever a log or a report cannot be written, aborting the tests that are
+    /// still running. Without it, failures to write logs and reports are listed after the results
+    /// and only fail a run whose tests all succeeded.
+    #[clap(long)]
+    strict_reports: bool,
+
+    /// What to do if another run still writes to the `--log-dir` or the `--record` directory
+    /// after `--lock-grace-period`: 'wait' until it is done, 'skip-writes' to run the tests without
+    /// writing to the locked directory, or 'error' to fail.
+    #[clap(long, value_enum, default_value = "wait")]
+    if_locked: IfLocked,
+
+    /// The number of seconds to wait for another run that writes to the same directories before
+    /// `--if-locked` applies.
+    #[clap(long, value_name = "SECONDS", value_parser = parse_seconds, default_value = "10")]
+    lock_grace_period: Duration,
+
+    /// A file to write one CSV row per test with its outcome, timings and first line of its error
+    /// to. The file is replaced atomically once the run is done.
+    #[clap(long, value_name = "PATH")]
+    csv: Option<PathBuf>,
+
+    /// The level of the diagnostic messages about the test runner itself that are written to
+    /// stderr. One of 'off', 'error', 'warn', 'info', 'debug' and 'trace'.
+    #[clap(long, value_parser, default_value = "warn")]
+    log_level: LevelFilter,
+
+    /// A JSON report of a previous run written with `--format json`. The failures of this run are
+    /// listed as new, pre-existing or fixed compared to it; tests that are not in the baseline
+    /// count as new.
+    #[clap(long, value_name = "PATH")]
+    baseline: Option<PathBuf>,
+
+    /// The failures that fail the run: 'any' failure, or only the 'new' ones compared to the
+    /// `--baseline`.
+    #[clap(long, value_enum, default_value = "any", requires = "baseline")]
+    fail_on: FailOn,
+
+    /// The markers to prefix the result of every test with. Defaults to 'unicode' if the output
+    /// is a terminal and to 'ascii' otherwise.
+    #[clap(long, value_enum)]
+    symbols: Option<Symbols>,
+
+    /// The format of the results written to stdout. With the machine-readable formats, the
+    /// human-readable results are written to stderr instead.
+    #[clap(long, value_enum, default_value = "human")]
+    format: Format,
+
+    /// Keep ANSI escape sequences in the toolchain output that is included in failure messages
+    /// and log files. Expected error messages are always matched against the stripped output.
+    #[clap(long)]
+    keep_ansi: bool,
+
+    /// How the Backseater reports the end of a program. With `exit-on-halt`, the VM exits with 0
+    /// when the program halts and any other status is an abort. With `propagate-exit-code`, the
+    /// VM exits with the exit code set by the program, which has to match the `exit_code`
+    /// directive of the test (0 by default), and reports aborts with the reserved status 255.
+    /// Tests with a `fails_with` directive have to abort in both modes, whatever exit code they
+    /// would have finished with.
+    #[clap(long, value_enum, default_value = "exit-on-halt")]
+    halt_mode: HaltMode,
+
+    /// How the compiled program that is passed to the Backseater on stdin is shown in the logs: as
+    /// a hex dump of its first bytes, or as text if it is valid UTF-8. The program is always
+    /// passed to the Backseater unchanged.
+    #[clap(long, value_enum, default_value = "hex")]
+    stdin_encoding: StdinEncoding,
+
+    /// The seed of the run, from which the seeds of the random number generator of the Backseater
+    /// are derived for every test, see `--seed-arg`. A random seed is chosen and printed if it is
+    /// not given, so runs can be reproduced.
+    #[clap(long, value_name = "N", value_parser = seed::parse_seed)]
+    seed: Option<u64>,
+
+    /// How the seed of a test is passed to the Backseater: `env:NAME` sets the environment
+    /// variable NAME, anything else is split into arguments like by a shell that are passed after
+    /// the other arguments of the Backseater, with `{seed}` replaced by the seed, e.g.
+    /// `--seed={seed}`. Seeds are only passed with this option. A `seed` directive replaces the
+    /// derived seed of a test.
+    #[clap(long, value_name = "TEMPLATE", value_parser = SeedArg::parse)]
+    seed_arg: Option<SeedArg>,
+
+    /// The name tests are reported with in the JSON, NDJSON and CSV reports, the hook events and
+    /// the duplicate check: `{path}` is the name of the test, e.g. `vm/stack/test_overflow.bs`,
+    /// `{dir}` its directories joined with dots, e.g. `vm.stack`, `{file}` the file or directory
+    /// name, `{stem}` the file name without the extension and `{config}` the name of the
+    /// configuration of the matrix. A dot after `{dir}` is dropped for tests at the top level. `{{`
+    /// and `}}` stand for literal braces. The console output always shows the name of the test.
+    #[clap(long, value_name = "TEMPLATE", value_parser = NameFormat::parse)]
+    name_format: Option<NameFormat>,
+
+    /// Whether the toolchain is asked not to colorize its output by setting `NO_COLOR=1` and
+    /// `CLICOLOR=0` in its environment, or inherits the environment as is.
+    #[clap(long, value_enum, default_value = "never")]
+    child_color: ChildColor,
+
+    /// The number of lines kept at the start and at the end of long failure messages on the
+    /// console. The lines in between are elided, but still included in the logs and the
+    /// machine-readable reports.
+    #[clap(long, value_name = "N", default_value = "30")]
+    error_context: usize,
+
+    /// Print failure messages in full instead of eliding the middle of long ones.
+    #[clap(long)]
+    full_output: bool,
+
+    /// Do not append an excerpt of the test source to failures whose stderr refers to a line of
+    /// the test, e.g. `line 42` or `test_foo.bs:42:`.
+    #[clap(long)]
+    no_source_context: bool,
+
+    /// Stop the run after the first failing test, killing all tests that are still running.
+    #[clap(long)]
+    fail_fast: bool,
+
+    /// Print the failure message of every failed test right after its result line. By default,
+    /// the messages are printed after the run, once for every group of tests that failed with the
+    /// same message. The machine-readable reports always contain the message of every test.
+    #[clap(long)]
+    no_dedup: bool,
+
+    /// Run the tests one after another on the main thread instead of in parallel.
+    #[clap(long)]
+    no_parallel: bool,
+
+    /// The maximum number of seconds the whole run may take. Shortly before, no further tests are
+    /// started; the tests still running at the deadline are killed and fail. The summary and the
+    /// reports are written nevertheless and the test runner exits with status 124.
+    #[clap(long, value_name = "SECONDS", value_parser = parse_seconds)]
+    total_timeout: Option<Duration>,
+
+    /// The maximum number of seconds a test program may run in the Backseater before it is killed.
+    #[clap(long, value_name = "SECONDS", value_parser = parse_seconds)]
+    timeout: Option<Duration>,
+
+    /// The maximum number of seconds the Seatbelt compiler may take to compile a test before it is
+    /// killed.
+    #[clap(long, value_name = "SECONDS", value_parser = parse_seconds)]
+    compile_timeout: Option<Duration>,
+
+    /// Match expected error messages against the stderr as is instead of replacing the paths of
+    /// the test and of the standard library with `$FILE` and `$LIB` first.
+    #[clap(long)]
+    no_normalize: bool,
+
+    /// A regular expression matching lines of the compiler and VM stderr that are removed before
+    /// the stderr is matched against the expectations and shown in failure messages, e.g. a
+    /// banner. The logs keep these lines. Can be repeated, and is added to the
+    /// `ignore_stderr_patterns` of the `test-runner.toml` files.
+    #[clap(long, value_name = "REGEX", value_parser = NoisePattern::parse)]
+    ignore_stderr_pattern: Vec<NoisePattern>,
+
+    /// Remove spaces and tabs at the end of every line of the stdout of a test and of its expected
+    /// stdout before comparing them. The same applies to the stderr of a compilation and its
+    /// snapshot. Tests can enable it with `normalize = "trailing-ws"`.
+    #[clap(long)]
+    trim_trailing_whitespace: bool,
+
+    /// Convert CRLF line endings to LF before comparing outputs, see `--trim-trailing-whitespace`.
+    /// Tests can enable it with `normalize = "eol"`.
+    #[clap(long)]
+    normalize_line_endings: bool,
+
+    /// Ignore missing or additional newlines at the end of outputs when comparing them, see
+    /// `--trim-trailing-whitespace`. Tests can enable it with `normalize = "final-newline"`.
+    #[clap(long)]
+    normalize_final_newline: bool,
+
+    /// The maximum address space in megabytes a test program may use in the Backseater. Only
+    /// supported on Unix.
+    #[clap(long, value_name = "MB")]
+    memory_limit: Option<u64>,
+
+    /// The maximum CPU time in seconds a test program may use in the Backseater. Only supported on
+    /// Unix.
+    #[clap(long, value_name = "SECONDS")]
+    cpu_limit: Option<u64>,
+
+    /// Record the compiler stderr of tests without failure directives whose compilation fails in
+    /// '<test>.stderr' snapshots instead of comparing it, and remove the snapshots of tests that
+    /// compile now.
+    #[clap(long)]
+    bless: bool,
+
+    /// Fail tests that look like they did not test anything instead of only listing them at the
+    /// end: tests whose compiled program is almost empty or whose VM run finished instantly
+    /// without any output. Tests with an `allow_empty` directive are exempt.
+    #[clap(long)]
+    paranoid: bool,
+
+    /// Remove the failure directives of tests that finished although they were expected to abort.
+    #[clap(long)]
+    fix_stale: bool,
+
+    /// Print the effective settings of the run, with all defaults resolved and all paths
+    /// canonicalized, as TOML and exit. If a test path is given, the settings of the
+    /// 'test-runner.toml' files that apply to it are included. The global settings are included
+    /// in the JSON report.
+    #[clap(long, value_name = "TEST_PATH", min_values = 0, max_values = 1)]
+    print_config: Option<Option<PathBuf>>,
+
+    /// Print the paths, sizes, modification times and hashes of the toolchain binaries before
+    /// running the tests, how every failed test was expected to end and the size of the compiled
+    /// program of every test. With `--list`, the expected outcome is listed after every test.
+    /// Every warning is printed, regardless of `--max-repeated-warnings`.
+    #[clap(long)]
+    verbose: bool,
+
+    /// How often the same warning is printed, e.g. one caused by a systemic problem for every
+    /// test. Followed by the number of times it was left out at the end of the run. Every warning
+    /// is written to `warnings.txt` in the `--log-dir`.
+    #[clap(long, value_name = "N", default_value_t = warnings::DEFAULT_MAX_REPEATED)]
+    max_repeated_warnings: usize,
+
+    /// A shell command to invoke after every test with its result as JSON on stdin, using the
+    /// schema of the 'ndjson' format. It is invoked once more with the summary at the end of the
+    /// run. Failures of the command are logged, but do not affect the result of the run.
+    #[clap(long, value_name = "CMD")]
+    on_test_result: Option<String>,
+
+    /// Run every test once per configuration of a matrix, given as NAME:SEATBELT_ARGS or
+    /// NAME:SEATBELT_ARGS:BACKSEATER_ARGS with arguments split like by a shell, so quotes keep
+    /// whitespace within an argument, e.g. 'optimized:--optimize'. Can be repeated. The results
+    /// are reported as `<test> [NAME]`.
+    #[clap(long, value_name = "SET", value_parser = Configuration::parse)]
+    config_set: Vec<Configuration>,
+
+    /// Record the command lines, inputs and outputs of the processes of every test into a
+    /// directory, so the run can be replayed with `--replay`.
+    #[clap(long, value_name = "DIR", conflicts_with = "replay")]
+    record: Option<PathBuf>,
+
+    /// Replay a run recorded with `--record` instead of starting the toolchain. The test files
+    /// are read and evaluated as usual, but the processes have to match the recorded ones.
+    #[clap(long, value_name = "DIR")]
+    replay: Option<PathBuf>,
+
+    /// Ring the terminal bell when the run is done.
+    #[clap(long)]
+    bell: bool,
+
+    /// Send a desktop notification with the summary when the run is done. Requires the test
+    /// runner to be built with the `notify` feature.
+    #[clap(long)]
+    notify: bool,
+
+    /// After a run with failures, open a prompt to inspect, rerun and reproduce the failed tests.
+    /// Ignored if stdin is not a terminal or a machine-readable format is used.
+    #[clap(long)]
+    interactive: bool,
+}
+
+impl Cli {
+    /// Resolves all relative paths of the options against `project_root`, see `--project-root`.
+    fn resolve_relative_paths(&mut self, project_root: &Path) {
+        let resolve = |path: &mut PathBuf| {
+            if path.is_relative() {
+                let components = path
+                    .components()
+                    .filter(|component| *component != std::path::Component::CurDir);
+                let mut resolved = project_root.to_path_buf();
+                resolved.extend(components);
+                *path = resolved;
+            }
+        };
+        resolve(&mut self.seatbelt_path);
+        resolve(&mut self.backseater_path);
+        resolve(&mut self.lib_path);
+        self.tests_path.iter_mut().for_each(resolve);
+        let optional_paths = [
+            &mut self.quarantine,
+            &mut self.log_dir,
+            &mut self.csv,
+            &mut self.baseline,
+            &mut self.work_dir,
+            &mut self.record,
+            &mut self.replay,
+            &mut self.explain_selection,
+        ];
+        for path in optional_paths.into_iter().flatten() {
+            resolve(path);
+        }
+        if let Some(Some(path)) = &mut self.print_config {
+            resolve(path);
+        }
+        if let Some(path) = self
+            .tests_from
+            .as_mut()
+            .filter(|path| *path != Path::new("-"))
+        {
+            resolve(path);
+        }
+    }
+
+    /// Renders the project root and the paths resolved against it, one per line.
+    fn paths_header(&self) -> String {
+        let mut header = match &self.project_root {
+            Some(project_root) => format!("project root: {}\n", project_root.display()),
+            None => "project root: none, paths are relative to the working directory\n".to_string(),
+        };
+        header += &format!("seatbelt: {}\n", self.seatbelt_path.display());
+        header += &format!("backseater: {}\n", self.backseater_path.display());
+        header += &format!("lib: {}\n", self.lib_path.display());
+        for tests_path in &self.tests_path {
+            header += &format!("tests: {}\n", tests_path.display());
+        }
+        header += &format!(
+            "scratch directories: {}\n",
+            scratch::run_directory(self.work_dir.as_deref()).display()
+        );
+        header
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+enum ChildColor {
+    Never,
+    Inherit,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+enum Phases {
+    Interleaved,
+    CompileFirst,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+enum HaltMode {
+    ExitOnHalt,
+    PropagateExitCode,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+enum StdinEncoding {
+    Hex,
+    Text,
+}
+
+/// The number of bytes of the compiled program that are shown in the logs.
+const COMPILED_PROGRAM_PREVIEW: usize = 1024;
+
+impl StdinEncoding {
+    /// Renders the start of a compiled program for the logs. Programs that are not valid UTF-8
+    /// are always rendered as hex, so the rendering never loses bytes.
+    fn render(self, program: &[u8]) -> String {
+        let preview = &program[..program.len().min(COMPILED_PROGRAM_PREVIEW)];
+        let text = match std::str::from_utf8(preview) {
+            Ok(text) => Some(text),
+            // the preview may end within a character
+            Err(error) if error.error_len().is_none() => {
+                std::str::from_utf8(&preview[..error.valid_up_to()]).ok()
+            }
+            Err(_) => None,
+        };
+        match (self, text) {
+            (StdinEncoding::Text, Some(text)) => {
+                let mut rendered = text.to_string();
+                if !rendered.ends_with('\n') {
+                    rendered.push('\n');
+                }
+                if text.len() < program.len() {
+                    rendered += &format!("... {} more bytes\n", program.len() - text.len());
+                }
+                rendered
+            }
+            _ => text::hex_dump(program, COMPILED_PROGRAM_PREVIEW),
+        }
+    }
+}
+
+/// The exit status with which the Backseater reports an aborted program in the
+/// `propagate-exit-code` mode. Programs cannot exit with it themselves.
+pub(crate) const VM_ABORT_STATUS: i32 = 255;
+
+/// How a program ended according to the exit status of the Backseater.
+enum VmExit {
+    Finished { exit_code: i32 },
+    Aborted,
+}
+
+impl HaltMode {
+    /// The arguments of the Backseater to run a compiled program that is passed via stdin.
+    fn vm_args(self) -> [&'static str; 2] {
+        match self {
+            HaltMode::ExitOnHalt => ["run", "--exit-on-halt"],
+            HaltMode::PropagateExitCode => ["run", "--propagate-exit-code"],
+        }
+    }
+
+    /// Interprets the exit status of a Backseater that was not killed by a signal.
+    fn vm_exit(self, status: ExitStatus) -> VmExit {
+        match (self, status.code()) {
+            (HaltMode::ExitOnHalt, Some(0)) => VmExit::Finished { exit_code: 0 },
+            (HaltMode::PropagateExitCode, Some(exit_code)) if exit_code != VM_ABORT_STATUS => {
+                VmExit::Finished { exit_code }
+            }
+            _ => VmExit::Aborted,
+        }
+    }
+}
+
+/// Set when the run is interrupted via Ctrl-C.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+struct TestResult {
+    filename: String,
+    /// The name of the test in the machine-readable reports, see `--name-format`.
+    report_name: String,
+    test: TestFile,
+    /// The name of the configuration of the matrix the test was run with, see `--config-set`.
+    configuration: Option<String>,
+    kind: TestResultKind,
+    duration: Duration,
+    log: TestLog,
+    /// Why the log of the test could not be written, see `--log-dir`.
+    log_error: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+enum TestResultKind {
+    Success,
+    Failure(FailureCode, String),
+    Skipped(String),
+    /// A failure of a quarantined test, which does not fail the run.
+    Quarantined(FailureCode, String),
+    /// A failure because the test finished although it was expected to abort, which usually
+    /// means that its failure directive is stale.
+    UnexpectedSuccess(String),
+    /// A failure because the compiler or the VM was killed by a signal. Crashes never satisfy an
+    /// expected failure.
+    Crash(String),
+}
+
+impl TestResultKind {
+    /// Whether the result fails the run.
+    fn is_failure(&self) -> bool {
+        matches!(
+            self,
+            TestResultKind::Failure(..)
+                | TestResultKind::UnexpectedSuccess(_)
+                | TestResultKind::Crash(_)
+        )
+    }
+
+    /// The code of the failure, if the result is one.
+    fn code(&self) -> Option<FailureCode> {
+        match self {
+            TestResultKind::Failure(code, _) | TestResultKind::Quarantined(code, _) => Some(*code),
+            TestResultKind::UnexpectedSuccess(_) => Some(FailureCode::UnexpectedSuccess),
+            TestResultKind::Crash(_) => Some(FailureCode::Crash),
+            TestResultKind::Success | TestResultKind::Skipped(_) => None,
+        }
+    }
+
+    /// The label followed by the code of the failure, e.g. `FAILED [E-TIMEOUT]`.
+    fn status(&self) -> String {
+        match self.code() {
+            Some(code) => format!("{} [{code}]", self.label()),
+            None => self.label().to_string(),
+        }
+    }
+
+    /// The label the result is reported with.
+    fn label(&self) -> &'static str {
+        match self {
+            TestResultKind::Success => "OK",
+            TestResultKind::Failure(..) | TestResultKind::UnexpectedSuccess(_) => "FAILED",
+            TestResultKind::Crash(_) => "CRASHED",
+            TestResultKind::Skipped(_) => "SKIPPED",
+            TestResultKind::Quarantined(..) => "FAILED (quarantined)",
+        }
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let run_started = Instant::now();
+    eprintln!("test runner started");
+    let failure_codes = FailureCode::help();
+    let matches = Cli::command().after_help(&*failure_codes).get_matches();
+    let mut cli = Cli::from_arg_matches(&matches)?;
+    env_logger::Builder::new()
+        .filter_module(env!("CARGO_CRATE_NAME"), cli.log_level)
+        .init();
+
+    let default_panic_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        process::abort_run();
+        default_panic_hook(info);
+    }));
+    ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            std::process::exit(130);
+        }
+        eprintln!(
+            "interrupted, stopping the running tests (press Ctrl-C again to exit immediately)"
+        );
+        process::abort_run();
+    })?;
+    if let Some(timeout) = cli.total_timeout {
+        total_timeout::start(timeout);
+    }
+
+    cli.project_root = cli
+        .project_root
+        .take()
+        .or_else(directory_config::find_project_root);
+    if let Some(project_root) = cli.project_root.clone() {
+        cli.resolve_relative_paths(&project_root);
+    }
+    cli.seatbelt_path = process::resolve_executable(cli.seatbelt_path);
+    cli.backseater_path = process::resolve_executable(cli.backseater_path);
+    if cli.verbose {
+        eprint!("{}", cli.paths_header());
+    }
+    warnings::set_limit((!cli.verbose).then_some(cli.max_repeated_warnings));
+    let _print_suppressed_warnings = warnings::PrintSuppressedOnDrop;
+
+    cli.seed = Some(cli.seed.unwrap_or_else(seed::random_run_seed));
+
+    // held until the end of the run
+    let mut locks = Vec::new();
+    if cli.print_config.is_none() && !cli.check && !cli.list {
+        for directory in [&mut cli.log_dir, &mut cli.record] {
+            let Some(path) = directory else {
+                continue;
+            };
+            if let Err(error) = std::fs::create_dir_all(&*path) {
+                // the tests are run nevertheless, every file that cannot be written is reported
+                print_warning(
+                    &mut stderr(),
+                    &format!("unable to create {}: {error}\n", path.display()),
+                );
+                continue;
+            }
+            match run_lock::acquire(path, cli.if_locked, cli.lock_grace_period)? {
+                Some(lock) => locks.push(lock),
+                None => *directory = None,
+            }
+        }
+    }
+
+    let mut effective_config = EffectiveConfig::resolve(&cli);
+    if let Some(test_path) = &cli.print_config {
+        if let Some(test_path) = test_path {
+            effective_config.apply_overrides(&*overrides_for_path(&cli.tests_path, test_path)?);
+        }
+        print!("{}", effective_config.to_toml());
+        return Ok(());
+    }
+    let config = Config::from(&cli);
+    if !cfg!(unix) && !config.limits.is_empty() {
+        print_warning(
+            &mut stderr(),
+            "resource limits are only supported on Unix and are ignored\n",
+        );
+    }
+
+    let mut configurations: Vec<&str> = Vec::new();
+    for configuration in &cli.config_set {
+        if configurations.contains(&configuration.name.as_str()) {
+            return Err(format!("duplicate configuration {}", configuration.name).into());
+        }
+        configurations.push(&configuration.name);
+    }
+    let mut configs: Vec<Config> = match cli.config_set.is_empty() {
+        true => vec![config.clone()],
+        false => cli
+            .config_set
+            .iter()
+            .map(|configuration| Config {
+                configuration: Some(configuration.clone()),
+                ..config.clone()
+            })
+            .collect(),
+    };
+
+    let toolchain = Toolchain::inspect(&config);
+    if cli.verbose {
+        eprint!("{}", toolchain.header());
+    }
+    if !cli.check && !cli.list && cli.explain_selection.is_none() {
+        if let Some(problem) = toolchain::check_lib_path(&cli.lib_path) {
+            if cli.strict_paths {
+                return Err(problem.trim_end().into());
+            }
+            print_warning(&mut stderr(), &problem);
+        }
+    }
+
+    if cli.self_check {
+        self_check::run_self_check(&config)?;
+        println!("self-check succeeded");
+        return Ok(());
+    }
+
+    let discovery_started = Instant::now();
+    let mut roots = Vec::new();
+    for tests_path in &cli.tests_path {
+        let path = tests_path.canonicalize().map_err(|error| {
+            format!(
+                "unable to resolve the tests path {}: {}",
+                tests_path.display(),
+                error
+            )
+        })?;
+        let label = match cli.tests_path.len() {
+            1 => String::new(),
+            _ => root_label(tests_path),
+        };
+        roots.push(TestRoot { label, path });
+    }
+    if cli.sandbox {
+        let excluded = [&cli.log_dir, &cli.work_dir, &cli.record]
+            .into_iter()
+            .flatten()
+            .filter_map(|path| {
+                path.canonicalize()
+                    .or_else(|_| std::path::absolute(path))
+                    .ok()
+            })
+            .collect();
+        let sandbox = Arc::new(Sandbox {
+            roots: roots.iter().map(|root| root.path.clone()).collect(),
+            excluded,
+            parallel: !cli.no_parallel,
+        });
+        for config in &mut configs {
+            config.sandbox = Some(sandbox.clone());
+        }
+    }
+    let Discovery {
+        mut tests,
+        other_files,
+    } = timings::measure(Phase::Discovery, || {
+        discovery::discover(&roots, cli.follow_symlinks, cli.include_precompiled)
+    })?;
+    for test in &tests {
+        debug!("discovered {}", test.path.display());
+    }
+    debug!(
+        "discovered {} tests in {:?}",
+        tests.len(),
+        discovery_started.elapsed()
+    );
+    let mut selection = Selection {
+        discovered: tests.len(),
+        ..Selection::default()
+    };
+    let mut explanation = Explanation::new(cli.explain_selection.as_deref());
+    explanation.discovered(&tests, &other_files);
+
+    let mut not_runnable = Vec::new();
+    let mut deprecated_syntax = 0;
+    let mut runnable_tests = Vec::with_capacity(tests.len());
+    for test in tests {
+        // unreadable or not valid UTF-8, the test fails with the error when it is run
+        let leading_comments =
+            directives::read_leading_comments(&test.source_path).unwrap_or_default();
+        let not_a_test = directives::is_not_a_test(&leading_comments);
+        if explanation.is_for(&test) {
+            let reason = match not_a_test {
+                true => "the leading comment block contains the directive",
+                false => "the leading comment block does not contain the directive",
+            };
+            explanation.applied("`not_a_test`", !not_a_test, reason.to_string());
+        }
+        if not_a_test {
+            debug!("excluded {} by directive", test.path.display());
+            selection.not_a_test += 1;
+            continue;
+        }
+        if directives::syntax_issues(&leading_comments)
+            .iter()
+            .any(|issue| matches!(issue, SyntaxIssue::Deprecated { .. }))
+        {
+            deprecated_syntax += 1;
+        }
+        if cli.strict_discovery
+            && !discovery::is_precompiled(&test.source_path)
+            && std::fs::read(&test.source_path).is_ok_and(|source| {
+                !directives::has_main_function(&String::from_utf8_lossy(&source))
+            })
+        {
+            not_runnable.push(test.source_path.clone());
+        }
+        runnable_tests.push(test);
+    }
+    tests = runnable_tests;
+    if deprecated_syntax > 0 && !cli.check {
+        print_warning(&mut stderr(), &format!(
+            "{deprecated_syntax} test(s) specify directives without the `{}` prefix, which is deprecated and will stop working in the next release, see --check\n",
+            directives::DIRECTIVE_PREFIX
+        ));
+    }
+    if !not_runnable.is_empty() {
+        let mut message = format!(
+            "{} file(s) match the pattern \"{TEST_FILE_PATTERN}\" but neither define a `main` function nor have a `not_a_test` directive:\n",
+            not_runnable.len()
+        );
+        for path in &not_runnable {
+            message += &format!("\t{}\n", path.display());
+        }
+        print_warning(&mut stderr(), &message);
+        return Err("test files without a `main` function found".into());
+    }
+
+    if let Some(quarantine_path) = &cli.quarantine {
+        let quarantine = quarantine::read_quarantine(quarantine_path)?;
+        for test in &mut tests {
+            test.quarantined = quarantine.contains(&test.name);
+            if test.quarantined && explanation.is_for(test) {
+                explanation.applied(
+                    "--quarantine",
+                    true,
+                    format!(
+                        "listed in {}, so it is run but its failures are not counted",
+                        quarantine_path.display()
+                    ),
+                );
+            }
+        }
+        for name in &quarantine {
+            if !tests.iter().any(|test| &test.name == name) {
+                print_warning(
+                    &mut stderr(),
+                    &format!("the quarantined test \"{name}\" does not exist\n"),
+                );
+            }
+        }
+    }
+
+    if let Some(path) = &cli.tests_from {
+        let list = match path == Path::new("-") {
+            true => std::io::read_to_string(stdin()),
+            false => std::fs::read_to_string(path),
+        }
+        .map_err(|error| format!("unable to read {}: {error}", path.display()))?;
+        let entries: Vec<_> = list
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .collect();
+        let discovered: HashSet<PathBuf> = tests.iter().map(|test| test.path.clone()).collect();
+        let was_discovered = explanation.is_among(&tests);
+        tests = discovery::select(tests, &roots, &entries);
+        let listed = tests
+            .iter()
+            .filter(|test| discovered.contains(&test.path))
+            .count();
+        selection.not_listed = discovered.len() - listed;
+        selection.listed_missing = tests.len() - listed;
+        let is_listed = explanation.is_among(&tests);
+        if was_discovered || is_listed {
+            let reason = match (is_listed, was_discovered) {
+                (true, true) => format!("listed in {}", path.display()),
+                (true, false) => format!(
+                    "listed in {} although it was not discovered, so it fails",
+                    path.display()
+                ),
+                (false, _) => format!("not listed in {}", path.display()),
+            };
+            explanation.applied("--tests-from", is_listed, reason);
+        }
+        if tests.is_empty() && !explanation.is_active() {
+            return Err(format!("{} lists no tests", path.display()).into());
+        }
+    }
+    if !cli.only_directives.is_empty() {
+        let tests_before_filter = tests.len();
+        let mut selected = Vec::new();
+        for test in tests {
+            // listed tests that do not exist and unreadable tests are kept, so they fail
+            let leading_comments = match directives::read_leading_comments(&test.source_path) {
+                Ok(leading_comments) => leading_comments,
+                Err(error) if directives::is_invalid_sidecar(&error) => LeadingComments::default(),
+                Err(_) => {
+                    if explanation.is_for(&test) {
+                        explanation.applied(
+                            "--only-directives",
+                            true,
+                            "unreadable, so it is kept and fails".to_string(),
+                        );
+                    }
+                    selected.push(test);
+                    continue;
+                }
+            };
+            if explanation.is_for(&test) {
+                for filter in &cli.only_directives {
+                    let matches = filter.matches(&leading_comments);
+                    let reason = match matches {
+                        true => "the leading comment block contains the directive",
+                        false => "the leading comment block does not contain the directive",
+                    };
+                    explanation.applied(
+                        &format!("--only-directives {filter}"),
+                        matches,
+                        reason.to_string(),
+                    );
+                }
+            }
+            if cli
+                .only_directives
+                .iter()
+                .all(|filter| filter.matches(&leading_comments))
+            {
+                selected.push(test);
+            }
+        }
+        selection.not_matching_directives = tests_before_filter - selected.len();
+        if selected.is_empty() && !explanation.is_active() {
+            let filters: Vec<_> = cli
+                .only_directives
+                .iter()
+                .map(ToString::to_string)
+                .collect();
+            return Err(format!("no tests match --only-directives {}", filters.join(", ")).into());
+        }
+        tests = selected;
+    }
+    selection.selected = tests.len();
+
+    if explanation.is_active() {
+        let skipped = tests
+            .iter()
+            .find(|test| explanation.is_for(test))
+            .map(|test| explanation.skipped(test, &configurations));
+        let selected = skipped == Some(false);
+        explanation.render(&mut stdout().lock(), selected)?;
+        return Ok(());
+    }
+
+    let duplicates = find_duplicate_names(&tests, cli.name_format.as_ref());
+    for (name, paths) in &duplicates {
+        let mut message = format!("multiple tests are named \"{name}\":\n");
+        for path in paths {
+            message += &format!("\t{}\n", path.display());
+        }
+        print_warning(&mut stderr(), &message);
+    }
+    if cli.deny_duplicates && !duplicates.is_empty() {
+        return Err("duplicate test names found".into());
+    }
+
+    let unmatched = find_unmatched_test_files(&other_files);
+    if !unmatched.is_empty() {
+        let mut message = format!(
+            "{} file(s) contain test directives but do not match the pattern \"{}\":\n",
+            unmatched.len(),
+            TEST_FILE_PATTERN
+        );
+        for path in &unmatched {
+            message += &format!("\t{}\n", path.display());
+        }
+        print_warning(&mut stderr(), &message);
+        if cli.deny_unmatched {
+            return Err("test files not matching the test file pattern found".into());
+        }
+    }
+
+    if cli.check {
+        return check_directives(&tests);
+    }
+    if cli.list {
+        return list_tests(&tests, cli.format, cli.verbose);
+    }
+
+    selection.skipped_by_directive = tests
+        .iter()
+        .filter(|test| selection::is_skipped_by_directive(test, &configurations))
+        .count();
+    match cli.format.is_machine_readable() {
+        true => selection.render(&mut stderr().lock()),
+        false => selection.render(&mut stdout().lock()),
+    }?;
+
+    let baseline = cli.baseline.as_deref().map(Baseline::read).transpose()?;
+
+    let symbols = cli
+        .symbols
+        .unwrap_or(if stdout().is_terminal() && stderr().is_terminal() {
+            Symbols::Unicode
+        } else {
+            Symbols::Ascii
+        });
+    let longest_configuration = configurations
+        .iter()
+        .map(|name| " [] ".len() + name.chars().count())
+        .max()
+        .unwrap_or_default();
+    let name_width = tests
+        .iter()
+        .map(|test| test.name.chars().count() + longest_configuration)
+        .max()
+        .unwrap_or_default();
+    let human_output_is_terminal = if cli.format.is_machine_readable() {
+        stderr().is_terminal()
+    } else {
+        stdout().is_terminal()
+    };
+    let terminal_width = match human_output_is_terminal {
+        true => crossterm::terminal::size()
+            .ok()
+            .map(|(columns, _)| usize::from(columns)),
+        false => None,
+    };
+    let status_board =
+        (human_output_is_terminal && !cli.no_status && !cli.stream_output).then(|| {
+            let workers = match cli.no_parallel {
+                true => 1,
+                false => rayon::current_num_threads(),
+            };
+            StatusBoard::start(
+                workers,
+                cli.format.is_machine_readable(),
+                std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty()),
+            )
+        });
+    let printer = Printer::new(
+        symbols,
+        name_width,
+        terminal_width,
+        cli.format.is_machine_readable(),
+        (!cli.full_output).then_some(cli.error_context),
+        // streamed lines would be written before the batched results of earlier tests
+        !cli.stream_output,
+        status_board.clone(),
+    );
+
+    let warm_up_duration = match cli.warm_up {
+        true => {
+            let warm_up_started = Instant::now();
+            self_check::warm_up(&config);
+            warm_up_started.elapsed()
+        }
+        false => Duration::ZERO,
+    };
+
+    if cli.phases == Phases::CompileFirst {
+        let compilations = Arc::new(Compilations::default());
+        let precompile = |&(config, test): &(&Config, &TestFile)| match process::is_aborted()
+            || total_timeout::is_winding_down()
+        {
+            true => None,
+            false => precompile_test(config, test, &compilations),
+        };
+        let compiles: Vec<(&Config, &TestFile)> = tests
+            .iter()
+            .flat_map(|test| configs.iter().map(move |config| (config, test)))
+            .collect();
+        let outcomes: Vec<CompileOutcome> = if cli.no_parallel {
+            compiles.iter().filter_map(precompile).collect()
+        } else {
+            compiles.par_iter().filter_map(precompile).collect()
+        };
+        debug!("compiled {} tests", outcomes.len());
+        if let Some(breakage) = compile_first::find_breakage(&outcomes, cli.breakage_threshold) {
+            eprintln!(
+                "suspected toolchain/stdlib breakage: {} of {} tests that have to compile failed with the same error:\n\t{}",
+                breakage.failed, breakage.total, breakage.error
+            );
+            eprintln!("the compiler stderr of {}:", breakage.example.filename);
+            let stderr =
+                String::from_utf8_lossy(&strip_ansi(&breakage.example.stderr)).into_owned();
+            for line in stderr.lines() {
+                eprintln!("\t{line}");
+            }
+            return Err("suspected toolchain/stdlib breakage, no tests were run".into());
+        }
+        for config in &mut configs {
+            config.compilations = Some(Arc::clone(&compilations));
+        }
+    }
+
+    let execution_started = Instant::now();
+    let report_errors = ReportErrors::new(cli.strict_reports);
+
+    let runs: Vec<(&Config, &TestFile)> = tests
+        .iter()
+        .flat_map(|test| configs.iter().map(move |config| (config, test)))
+        .collect();
+    let run = |config: &Config, test: &TestFile| -> Option<TestResult> {
+        std::io::stdout().flush().expect("unable to flush stdout");
+        let result = run_single_test(config, test);
+        if process::is_aborted() && !total_timeout::is_expired() {
+            // the test was most likely killed while running
+            return None;
+        }
+        if let Some(hook) = &cli.on_test_result {
+            hook::invoke(hook, &Event::Test(TestEvent::from(&result)));
+        }
+        Some(result)
+    };
+    let mut console = ConsoleReporter::new(&printer, cli.report_stage, cli.verbose, cli.no_dedup);
+    let mut ndjson = NdjsonWriter {
+        errors: &report_errors,
+    };
+    let mut run_control = |event: &RunEvent| match event {
+        RunEvent::Discovered { tests, runs } => {
+            debug!("running {} tests in {runs} runs", tests.len());
+        }
+        RunEvent::Finished { result, .. } => {
+            if let Some(error) = &result.log_error {
+                report_errors.push(error.clone());
+            }
+            if cli.fail_fast && result.kind.is_failure() {
+                process::abort_run();
+            }
+        }
+        RunEvent::Started { .. } | RunEvent::RunFinished { .. } => {}
+    };
+    let mut handlers: Vec<&mut dyn EventHandler> = vec![&mut console];
+    if cli.format == Format::Ndjson {
+        handlers.push(&mut ndjson);
+    }
+    handlers.push(&mut run_control);
+    let results = events::run_all(&tests, &runs, !cli.no_parallel, &mut handlers, run);
+
+    total_timeout::finish();
+    printer.flush();
+    debug!("executed all tests in {:?}", execution_started.elapsed());
+    let not_run = runs.len() - results.len();
+
+    let mut summary = Summary {
+        not_run,
+        elapsed: run_started.elapsed() - warm_up_duration,
+        partial: process::is_aborted() || not_run > 0,
+        stats: cli.stats.then(|| Stats::collect(&results)),
+        selection: Some(selection),
+        ..Summary::from_results(&results)
+    };
+    let mismatch = match cli.no_heuristics {
+        true => None,
+        false => mismatch::detect(&results, cli.mismatch_threshold),
+    };
+    if let Some(mismatch) = &mismatch {
+        print_mismatch(mismatch);
+    } else if !cli.no_dedup {
+        let groups = failure_groups::group_failures(&results);
+        if !groups.is_empty() {
+            timings::measure(Phase::Reporting, || printer.print_failure_groups(&groups));
+        }
+    }
+    summary.runner_timings = (cli.verbose || cli.stats).then(RunnerTimings::collect);
+    let mut breakdown = Vec::new();
+    if roots.len() > 1 {
+        breakdown.extend(roots.iter().enumerate().map(|(index, root)| {
+            let results = results.iter().filter(|result| result.test.root == index);
+            (root.label.as_str(), Summary::from_results(results))
+        }));
+    }
+    if configurations.len() > 1 {
+        breakdown.extend(configurations.iter().map(|&name| {
+            let results = results
+                .iter()
+                .filter(|result| result.configuration.as_deref() == Some(name));
+            (name, Summary::from_results(results))
+        }));
+    }
+    if !breakdown.is_empty() {
+        match cli.format {
+            Format::Human => summary::render_breakdown(&mut stdout(), &breakdown, use_color()),
+            _ => summary::render_breakdown(&mut stderr(), &breakdown, use_color()),
+        }
+        .expect("unable to print output");
+    }
+    events::dispatch(&mut handlers, &RunEvent::RunFinished { summary: &summary });
+    drop(handlers);
+    let comparison = baseline.as_ref().map(|baseline| baseline.compare(&results));
+    if let (Some(comparison), Some(path)) = (&comparison, &cli.baseline) {
+        match cli.format {
+            Format::Human => comparison.render(&mut stdout(), path),
+            _ => comparison.render(&mut stderr(), path),
+        }
+        .expect("unable to print output");
+    }
+    let machine_report = match cli.format {
+        Format::Human => None,
+        Format::Json => Some((
+            "JSON",
+            report::json_report(&results, &summary, &toolchain, &effective_config),
+        )),
+        // written by the `NdjsonWriter`
+        Format::Ndjson => None,
+    };
+    if let Some((name, document)) = machine_report {
+        if let Err(error) = stdout().write_all(document.as_bytes()) {
+            report_errors.push(format!("unable to write the {name} report: {error}"));
+        }
+    }
+    if let Some(stats) = &summary.stats {
+        match cli.format {
+            Format::Human => stats.render(&mut stdout(), summary.elapsed),
+            _ => stats.render(&mut stderr(), summary.elapsed),
+        }
+        .expect("unable to print output");
+    }
+    if let Some(runner_timings) = &summary.runner_timings {
+        match cli.format {
+            Format::Human => runner_timings.render(&mut stdout()),
+            _ => runner_timings.render(&mut stderr()),
+        }
+        .expect("unable to print output");
+    }
+    if cli.seed_arg.is_some() {
+        let seed = cli.seed.unwrap_or_default();
+        let line = format!("Seed: {seed}, rerun with --seed {seed} to reproduce the seeds\n");
+        match cli.format {
+            Format::Human => stdout().write_all(line.as_bytes()),
+            _ => stderr().write_all(line.as_bytes()),
+        }
+        .expect("unable to print output");
+    }
+    if let Some(path) = &cli.csv {
+        if let Err(error) = write_atomic(path, report::csv_report(&results, &roots).as_bytes()) {
+            report_errors.push(format!("unable to write {}: {error}", path.display()));
+        }
+    }
+    if let Some(hook) = &cli.on_test_result {
+        hook::invoke(
+            hook,
+            &Event::Summary(Box::new(SummaryEvent::from(&summary))),
+        );
+    }
+    let stale: Vec<_> = results
+        .iter()
+        .filter(|result| matches!(result.kind, TestResultKind::UnexpectedSuccess(_)))
+        .collect();
+    if !stale.is_empty() {
+        eprintln!(
+            "The following tests finished although they were expected to fail, their failure directives may be stale:"
+        );
+        for result in &stale {
+            eprintln!("\t{}", result.filename);
+        }
+        if cli.fix_stale {
+            for result in &stale {
+                fix_stale_directive(&result.test.source_path)?;
+            }
+        } else {
+            eprintln!("Run with --fix-stale to remove these directives.");
+        }
+    }
+    let passed_quarantined: Vec<_> = results
+        .iter()
+        .filter(|result| result.test.quarantined && result.kind == TestResultKind::Success)
+        .collect();
+    if !passed_quarantined.is_empty() {
+        eprintln!("The following quarantined tests passed and may be removed from the quarantine:");
+        for result in passed_quarantined {
+            eprintln!("\t{}", result.filename);
+        }
+    }
+    let kept_scratch: Vec<_> = results
+        .iter()
+        .filter(|result| cli.keep_failed_tmp && result.kind.is_failure())
+        .filter_map(|result| Some((&result.filename, result.log.tmp_dir.as_ref()?)))
+        .collect();
+    if !kept_scratch.is_empty() {
+        eprintln!("The scratch directories of the failed tests were kept:");
+        for (filename, tmp_dir) in kept_scratch {
+            eprintln!("\t{filename}: {}", tmp_dir.display());
+        }
+    }
+    scratch::remove_run_directory(cli.work_dir.as_deref());
+    let suspicious: Vec<_> = results
+        .iter()
+        .filter(|result| result.kind == TestResultKind::Success)
+        .filter_map(|result| Some((&result.filename, result.log.suspicion.as_ref()?)))
+        .collect();
+    if !suspicious.is_empty() {
+        print_warning(
+            &mut stderr(),
+            "the following tests passed, but look suspiciously empty:\n",
+        );
+        for (filename, suspicion) in suspicious {
+            eprintln!("\t{filename}: {suspicion}");
+        }
+        eprintln!(
+            "Check the toolchain configuration, or add an `allow_empty` directive to trivial tests."
+        );
+    }
+    if let Some(log_dir) = &cli.log_dir {
+        // logs of tests that were not selected must not be mistaken for orphans
+        if !cli.no_prune && cli.only_directives.is_empty() && cli.tests_from.is_none() {
+            let logs: HashSet<_> = tests
+                .iter()
+                .flat_map(|test| {
+                    configs.iter().map(|config| {
+                        let stem = file_stem(&test.name, configuration_name(config));
+                        test_log::log_path(log_dir, &stem)
+                    })
+                })
+                .collect();
+            match test_log::prune(log_dir, &logs) {
+                Ok(0) => {}
+                Ok(pruned) => eprintln!(
+                    "Removed {pruned} log(s) of tests that no longer exist from {}",
+                    log_dir.display()
+                ),
+                Err(error) => report_errors.push(format!(
+                    "unable to remove the logs of tests that no longer exist from {}: {error}",
+                    log_dir.display()
+                )),
+            }
+        }
+        if let Err(error) = warnings::write_all(log_dir) {
+            report_errors.push(format!(
+                "unable to write the warnings to {}: {error}",
+                log_dir.display()
+            ));
+        }
+        if results.iter().all(|result| result.log_error.is_none()) {
+            eprintln!("Logs written to {}", log_dir.display());
+        }
+    }
+    if cli.bell {
+        notify::bell();
+    }
+    if cli.notify {
+        notify::notify(&summary);
+    }
+    let report_errors = report_errors.finish();
+    if INTERRUPTED.load(Ordering::SeqCst) {
+        return Err("the run has been interrupted".into());
+    }
+    if mismatch.is_some() {
+        eprintln!("the toolchain does not seem to match the test runner, see above");
+        std::process::exit(mismatch::EXIT_STATUS);
+    }
+    if cli.strict_reports && !report_errors.is_empty() {
+        return Err("not all logs and reports could be written".into());
+    }
+    if summary.partial && total_timeout::is_winding_down() {
+        eprintln!(
+            "the run exceeded its total timeout, {} test(s) were not run",
+            summary.not_run
+        );
+        std::process::exit(total_timeout::EXIT_STATUS);
+    }
+    if process::is_aborted() {
+        eprintln!("the run has been aborted after the first failure");
+    }
+    if cli.interactive && !summary.succeeded() {
+        if !stdin().is_terminal() {
+            print_warning(
+                &mut stderr(),
+                "stdin is not a terminal, not starting the interactive mode\n",
+            );
+        } else if cli.format.is_machine_readable() {
+            print_warning(
+                &mut stderr(),
+                "the interactive mode is not available with machine-readable formats\n",
+            );
+        } else {
+            let failures = results
+                .into_iter()
+                .filter(|result| result.kind.is_failure())
+                .collect();
+            return match interactive::triage(&configs, &printer, failures)? {
+                true => Ok(()),
+                false => Err("not all tests succeeded".into()),
+            };
+        }
+    }
+    let succeeded = match (cli.fail_on, &comparison) {
+        (FailOn::New, Some(comparison)) => comparison.new.is_empty(),
+        _ => summary.succeeded(),
+    };
+    if !succeeded {
+        Err("not all tests succeeded".into())
+    } else if !report_errors.is_empty() {
+        Err("all tests succeeded, but not all logs and reports could be written".into())
+    } else {
+        Ok(())
+    }
+}
+
+/// Prints a suspected mismatch between the toolchain and the test runner in place of the failures
+/// of the individual tests.
+fn print_mismatch(mismatch: &Mismatch) {
+    let message = mismatch.message();
+    execute!(
+        stderr().lock(),
+        SetForegroundColor(Color::DarkRed),
+        Print("error: "),
+        ResetColor,
+        Print(message)
+    )
+    .expect("unable to print output");
+}
 
-    /// The path of the Backseat source files to test. The source files have to start with
-    /// 'test_' and end with '.bs' to be tested.
-    #[clap(short, long, value_parser, default_value = ".")]
-    tests_path: PathBuf,
+/// The name of the files of a test that is run with the given configuration, e.g. its log.
+fn file_stem(test_name: &str, configuration: Option<&str>) -> String {
+    match configuration {
+        Some(configuration) => format!("{test_name}.{configuration}"),
+        None => test_name.to_string(),
+    }
 }
 
-#[derive(Debug, PartialEq)]
-enum TestOutcome {
-    Finished,
-    Aborted { error_messages: Vec<String> },
+/// The name of the test in the machine-readable reports: its name rendered with `--name-format`,
+/// or its name followed by the configuration in brackets.
+fn report_name(
+    name_format: Option<&NameFormat>,
+    name: &str,
+    configuration: Option<&str>,
+) -> String {
+    match (name_format, configuration) {
+        (Some(name_format), configuration) => name_format.render(name, configuration),
+        (None, Some(configuration)) => format!("{name} [{configuration}]"),
+        (None, None) => name.to_string(),
+    }
 }
 
-struct TestResult {
-    filename: String,
-    kind: TestResultKind,
+/// Runs a single test and writes its log if requested. Errors of the test runner itself, e.g. an
+/// uncreatable scratch directory or a toolchain that cannot be started, fail only this test with
+/// [`FailureCode::Infra`].
+fn run_single_test(config: &Config, test: &TestFile) -> TestResult {
+    let config = &*config.with_overrides(&test.overrides);
+    let configuration = config
+        .configuration
+        .as_ref()
+        .map(|configuration| configuration.name.clone());
+    let filename = match &configuration {
+        Some(name) => format!("{} [{name}]", test.name),
+        None => test.name.clone(),
+    };
+    let file_stem = file_stem(&test.name, configuration.as_deref());
+    let report_name = report_name(
+        config.name_format.as_ref(),
+        &test.name,
+        configuration.as_deref(),
+    );
+    let started = Instant::now();
+    let mut log = TestLog::default();
+    let (kind, scratch) = match ScratchDir::create(config.work_dir.as_deref(), &file_stem) {
+        Ok(scratch) => {
+            let config = &Config {
+                test_name: Some(filename.clone()),
+                test_tmpdir: Some(scratch.path().to_path_buf()),
+                test_seed: config
+                    .seed_arg
+                    .is_some()
+                    .then(|| seed::test_seed(config.run_seed, &file_stem)),
+                ..config.clone()
+            };
+            log.tmp_dir = Some(scratch.path().to_path_buf());
+            let kind = evaluate_test(config, test, &file_stem, &mut log)
+                .unwrap_or_else(|error| infra_failure(&error));
+            (kind, Some(scratch))
+        }
+        Err(error) => (
+            infra_failure(
+                &anyhow::Error::new(error).context("unable to create the scratch directory"),
+            ),
+            None,
+        ),
+    };
+    let mut kind = match kind {
+        TestResultKind::Failure(code, message) if test.quarantined => {
+            TestResultKind::Quarantined(code, message)
+        }
+        TestResultKind::UnexpectedSuccess(message) if test.quarantined => {
+            TestResultKind::Quarantined(FailureCode::UnexpectedSuccess, message)
+        }
+        TestResultKind::Success if config.paranoid && log.suspicion.is_some() => {
+            let suspicion = log.suspicion.as_deref().unwrap_or_default();
+            TestResultKind::Failure(
+                FailureCode::Suspicious,
+                format!("\tsuspiciously empty: {suspicion}\n"),
+            )
+        }
+        kind => kind,
+    };
+    if let Some(scratch) = scratch {
+        let keep = config.keep_failed_tmp && kind.is_failure();
+        if let Err(error) = scratch.finish(keep) {
+            let error = anyhow::Error::new(error).context("unable to remove the scratch directory");
+            kind = infra_failure(&error);
+        }
+    }
+    let duration = started.elapsed();
+    let log_error = config.log_dir.as_ref().and_then(|log_dir| {
+        let path = test_log::log_path(log_dir, &file_stem);
+        timings::measure(Phase::Reporting, || log.write(&path, duration, &kind))
+            .err()
+            .map(|error| format!("unable to write the log {}: {error}", path.display()))
+    });
+    TestResult {
+        filename,
+        report_name,
+        test: test.clone(),
+        configuration,
+        kind,
+        duration,
+        log,
+        log_error,
+    }
 }
 
-#[derive(Debug, PartialEq)]
-enum TestResultKind {
-    Success,
-    Failure(String),
+/// The failure of a test because of an error of the test runner itself.
+fn infra_failure(error: &anyhow::Error) -> TestResultKind {
+    TestResultKind::Failure(FailureCode::Infra, format!("\t{error:#}\n"))
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    println!("test runner started");
-    let cli = Cli::parse();
+/// Runs the test in its scratch directory and checks whether it changed the tests paths.
+fn evaluate_test(
+    config: &Config,
+    test: &TestFile,
+    file_stem: &str,
+    log: &mut TestLog,
+) -> anyhow::Result<TestResultKind> {
+    let mut tape = Tape::new(config.recording.as_ref(), file_stem)?;
+    let before = config.sandbox.as_ref().map(|sandbox| sandbox.snapshot());
+    let mut kind = match test.source_path.exists() {
+        true => match execute_test(config, &test.source_path, log, &mut tape) {
+            // killed at the deadline, possibly while its processes were spawned
+            _ if total_timeout::is_expired() => TestResultKind::Failure(
+                FailureCode::Timeout,
+                "\tnot finished: run timed out\n".to_string(),
+            ),
+            kind => kind?,
+        },
+        // only tests listed via `--tests-from` may not exist
+        false => TestResultKind::Failure(
+            FailureCode::Infra,
+            format!("\tthe test {} does not exist\n", test.source_path.display()),
+        ),
+    };
+    tape.finish()?;
+    if let TestResultKind::Failure(
+        FailureCode::CompileUnexpected | FailureCode::RuntimeUnexpected | FailureCode::WrongMessage,
+        message,
+    ) = &mut kind
+    {
+        let stderr = log.vm_stderr.as_ref().or(log.compiler_stderr.as_ref());
+        let excerpt = stderr
+            .filter(|_| !config.no_source_context)
+            .and_then(|stderr| source_context::excerpt(&test.source_path, stderr));
+        if let Some(excerpt) = excerpt {
+            *message += &excerpt;
+        }
+    }
+    if let (Some(sandbox), Some(before)) = (&config.sandbox, before) {
+        // the snapshots are written by the test runner itself when blessing
+        let ignored = match config.bless {
+            true => vec![
+                snapshot_path(&test.source_path),
+                expected_stdout_path(&test.source_path),
+            ],
+            false => Vec::new(),
+        };
+        let changes = sandbox.changes(&before, &sandbox.snapshot(), &ignored);
+        if !changes.is_empty() {
+            let mut message =
+                "\tthe following files below the tests paths changed while the test ran:\n"
+                    .to_string();
+            for change in changes {
+                message += &format!("\t\t{change}\n");
+            }
+            if sandbox.parallel {
+                message += "\tother tests ran at the same time, rerun with --no-parallel to find the test that changed them\n";
+            }
+            kind = TestResultKind::Failure(FailureCode::Sandbox, message);
+        }
+    }
+    Ok(kind)
+}
 
-    let globwalker = globwalk::GlobWalkerBuilder::new(cli.tests_path.as_path(), "test*.bs")
-        .build()
-        .expect("unable to create glob walker");
+/// Resolves the `test-runner.toml` settings that apply to the test at `test_path`, which has to be
+/// below one of the `tests_paths`.
+fn overrides_for_path(
+    tests_paths: &[PathBuf],
+    test_path: &Path,
+) -> Result<Arc<Overrides>, Box<dyn Error>> {
+    let test_path = test_path
+        .canonicalize()
+        .map_err(|error| format!("unable to resolve {}: {error}", test_path.display()))?;
+    let root = tests_paths
+        .iter()
+        .filter_map(|tests_path| tests_path.canonicalize().ok())
+        .find(|root| test_path.starts_with(root))
+        .ok_or_else(|| format!("{} is not below a tests path", test_path.display()))?;
+    let directory = match test_path.is_dir() {
+        true => test_path.as_path(),
+        false => test_path.parent().unwrap_or(&root),
+    };
+    Ok(directory_config::Resolver::default().resolve(&root, directory)?)
+}
 
-    let source_files: Vec<_> = globwalker.collect::<Result<_, _>>()?;
+/// The label the names of the tests below a tests path are prefixed with if there are multiple
+/// tests paths: the path as given on the command line with forward slashes as separators.
+fn root_label(tests_path: &Path) -> String {
+    let label = tests_path
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+    label.strip_prefix("./").unwrap_or(&label).to_string()
+}
 
-    let tests_run = AtomicUsize::new(0);
-    let tests_failed = AtomicUsize::new(0);
+/// Compiles and runs a single test and evaluates the result against the test's directives. The
+/// commands and outputs of the toolchain are recorded in `log`, the processes are started via
+/// `tape`.
+fn execute_test(
+    config: &Config,
+    source_file: &Path,
+    log: &mut TestLog,
+    tape: &mut Tape,
+) -> anyhow::Result<TestResultKind> {
+    let TestDirectives {
+        expected_outcome,
+        program_args,
+        allow_empty,
+        allow_empty_program,
+        exit_code,
+        seed,
+        warnings,
+        lib,
+        expected_output,
+        match_stdout,
+        timeout,
+        normalize,
+        stage,
+        ..
+    } = match test_directives(config, source_file)? {
+        Ok(directives) => directives,
+        Err(kind) => return Ok(kind),
+    };
+    log.seed = config
+        .test_seed
+        .map(|derived_seed| seed.unwrap_or(derived_seed));
+    log.expected_outcome = Some(expected_outcome.clone());
+    let expected_exit_code = exit_code.unwrap_or_default();
+    let normalization = config.normalization.union(normalize.unwrap_or_default());
+    let expected_stage = stage.or(config.expect_stage);
+    let vm_timeout = match timeout {
+        Some(TestTimeout::Limit(timeout)) => Some(timeout),
+        Some(TestTimeout::None) => None,
+        None => config.timeout,
+    };
+    log.timeout = vm_timeout;
+    debug!(
+        "{} runs with the timeout {vm_timeout:?}",
+        source_file.display()
+    );
 
-    source_files.par_iter().map(|source_file| -> anyhow::Result<TestResult> {
-        std::io::stdout().flush().expect("unable to flush stdout");
-        let expected_outcome = determine_expected_outcome(source_file.path())?;
-        let filename = source_file.path().display().to_string();
-
-        let command_result = Command::new(cli.seatbelt_path.as_os_str())
-            .arg(&source_file.path().as_os_str())
-            .arg("--lib")
-            .arg(cli.lib_path.as_os_str())
-            .stderr(Stdio::piped())
-            .output()?;
-        match command_result.status.success() {
-            true => {
-                let compiler_output = command_result.stdout;
-                let backseater_result = child_with_pipe_args(
-                    &cli.backseater_path,
-                    compiler_output,
-                    ["run", "--exit-on-halt"],
-                )?;
-                match backseater_result.status.success() {
-                    true => {
-                        if let TestOutcome::Aborted { error_messages } = expected_outcome {
-                            let mut error_message = "\ttest execution finished, but the following error messages were expected:".to_string();
-                            for message in error_messages {
-                                error_message += &format!("\t\t\"{}\"", message);
-                            }
-                            Ok(TestResult { filename, kind: TestResultKind::Failure(error_message) })
-                        } else {
-                            Ok(TestResult{ filename, kind: TestResultKind::Success})
-                        }
+    let lib_path = library_path(config, source_file, lib.as_ref());
+    let (compile_command_line, mut command) =
+        compile_command(config, source_file, lib_path.as_deref());
+    let is_precompiled = discovery::is_precompiled(source_file);
+    let precompiled = config
+        .compilations
+        .as_ref()
+        .and_then(|compilations| compilations.take(source_file, configuration_name(config)));
+    let (
+        ProcessOutput {
+            output: command_result,
+            timed_out,
+            ..
+        },
+        compile_duration,
+    ) = match precompiled {
+        // the program is passed to the Backseater as if the compiler had written it
+        None if is_precompiled => (
+            ProcessOutput {
+                output: Output {
+                    status: ExitStatus::default(),
+                    stdout: match std::fs::read(source_file) {
+                        Ok(program) => program,
+                        Err(error) => return Ok(unreadable_test(source_file, &error)),
+                    },
+                    stderr: Vec::new(),
+                },
+                timed_out: false,
+                stdin_error: None,
+            },
+            Duration::ZERO,
+        ),
+        Some(compilation) => compilation?,
+        None => {
+            debug!("spawning {:?}", command);
+            let compilation = tape.run(&compile_command_line, None, |stdin| {
+                Ok(process::run(&mut command, stdin, config.compile_timeout)?)
+            });
+            match compilation {
+                Err(error) if process::is_spawn_exhausted(&error) => {
+                    return Ok(spawn_failure("compiler", &error));
+                }
+                compilation => compilation.with_context(|| {
+                    format!(
+                        "unable to run the compiler {}",
+                        config.seatbelt_path.display()
+                    )
+                })?,
+            }
+        }
+    };
+    if !is_precompiled {
+        log.commands.push(compile_command_line);
+        log.compile_duration = Some(compile_duration);
+        log.stages.push(StageRecord {
+            stage: Stage::Compile,
+            duration: compile_duration,
+            exit_code: command_result.status.code(),
+        });
+        debug!(
+            "{:?} exited with {} after {:?}",
+            config.seatbelt_path, command_result.status, log.compile_duration
+        );
+    }
+    let compiler_stderr_raw = stderr_noise::remove(&command_result.stderr, config.noise_patterns());
+    let compiler_stderr = strip_ansi(&compiler_stderr_raw);
+    let reported = |raw: &[u8], stripped: &[u8]| match config.keep_ansi {
+        true => raw.to_vec(),
+        false => stripped.to_vec(),
+    };
+    let matched = |stripped: &[u8]| match config.no_normalize {
+        true => stripped.to_vec(),
+        false => normalize_stderr(config, source_file, stripped),
+    };
+    if !is_precompiled {
+        log.compiler_stderr = Some(reported(
+            &command_result.stderr,
+            &strip_ansi(&command_result.stderr),
+        ));
+    }
+    let compiler_stdout = strip_ansi(&command_result.stdout);
+    if !command_result.status.success() && !compiler_stdout.is_empty() {
+        log.compiler_stdout = Some(reported(&command_result.stdout, &compiler_stdout));
+    }
+    if timed_out {
+        return Ok(TestResultKind::Failure(
+            FailureCode::Timeout,
+            format!(
+                "\tcompilation timed out after {}s\n{}",
+                config.compile_timeout.unwrap_or_default().as_secs_f64(),
+                String::from_utf8_lossy(&reported(&compiler_stderr_raw, &compiler_stderr))
+            ),
+        ));
+    }
+    if let Some(signal) = process::crash_signal(command_result.status) {
+        return Ok(TestResultKind::Crash(crash_message(
+            "compiler",
+            &signal,
+            &[],
+            &reported(&compiler_stderr_raw, &compiler_stderr),
+        )));
+    }
+    // tests without failure directives may record their expected compiler stderr in a snapshot
+    let snapshot = match expected_outcome {
+        TestOutcome::Finished => Some(snapshot_path(source_file)),
+        _ => None,
+    };
+    match command_result.status.success() {
+        true => {
+            if let Some(snapshot) = snapshot.filter(|snapshot| snapshot.exists()) {
+                if !config.bless {
+                    return Ok(TestResultKind::Failure(
+                        FailureCode::UnexpectedSuccess,
+                        format!(
+                            "\texpected compile error as recorded in {}, but the compilation succeeded\n",
+                            snapshot.display()
+                        ),
+                    ));
+                }
+                std::fs::remove_file(&snapshot).with_context(|| {
+                    format!("unable to remove the stale snapshot {}", snapshot.display())
+                })?;
+            }
+            let compiler_warnings = matched(&compiler_stderr);
+            if !warnings.is_empty() {
+                if let Err(error) = validate_warnings(&compiler_warnings, &warnings) {
+                    return Ok(TestResultKind::Failure(
+                        FailureCode::WrongMessage,
+                        error.to_string(),
+                    ));
+                }
+            }
+            if config.deny_warnings {
+                let compiler_warnings = String::from_utf8_lossy(&compiler_warnings);
+                let unexpected: Vec<_> = compiler_warnings
+                    .lines()
+                    .filter(|line| !line.trim().is_empty())
+                    .filter(|line| !warnings.iter().any(|warning| line.contains(warning)))
+                    .collect();
+                if !unexpected.is_empty() {
+                    let mut message =
+                        "\tcompilation succeeded, but with unexpected warnings:\n".to_string();
+                    for line in unexpected {
+                        message += &format!("\t\t{line}\n");
+                    }
+                    return Ok(TestResultKind::Failure(
+                        FailureCode::UnexpectedWarning,
+                        message,
+                    ));
+                }
+            }
+            let compiler_output = command_result.stdout;
+            if compiler_output.is_empty() && !allow_empty_program {
+                let mut message = match is_precompiled {
+                    true => format!(
+                        "\tthe precompiled program {} is empty\n",
+                        source_file.display()
+                    ),
+                    false => "\tthe compilation succeeded, but the compiler wrote no program\n"
+                        .to_string(),
+                };
+                let stderr = reported(&compiler_stderr_raw, &compiler_stderr);
+                if !stderr.is_empty() {
+                    message += "\tcompiler stderr:\n";
+                    for line in String::from_utf8_lossy(&stderr).lines() {
+                        message += &format!("\t{line}\n");
+                    }
+                }
+                return Ok(TestResultKind::Failure(FailureCode::EmptyProgram, message));
+            }
+            let compiled_size = compiler_output.len();
+            log.compiled_program = Some((
+                compiled_size,
+                config.stdin_encoding.render(&compiler_output),
+            ));
+            let mut backseater_command = toolchain_command(config, &config.backseater_path);
+            let limits = config.limits;
+            limits.apply(&mut backseater_command);
+            if config.sandbox.is_some() {
+                if let Some(test_tmpdir) = &config.test_tmpdir {
+                    backseater_command.current_dir(test_tmpdir);
+                }
+            }
+            let vm_args = vm_arguments(config, &program_args, log.seed);
+            let mut vm_command_line = shell_command_line(&config.backseater_path, &vm_args);
+            if let Some((name, value)) = seed_env(config, log.seed) {
+                backseater_command.env(name, &value);
+                vm_command_line = format!("{name}={value} {vm_command_line}");
+            }
+            log.commands.push(vm_command_line);
+            let execution = tape.run(
+                log.commands.last().expect("the VM command was just pushed"),
+                Some(compiler_output),
+                |stdin| {
+                    let stdin = stdin.unwrap_or_default();
+                    let stdout_sink = config
+                        .test_name
+                        .as_deref()
+                        .filter(|_| config.stream_output)
+                        .and_then(|name| Some(StreamSlot::claim()?.sink(name)));
+                    child_with_pipe_args(
+                        backseater_command,
+                        stdin,
+                        vm_args,
+                        vm_timeout,
+                        stdout_sink,
+                    )
+                },
+            );
+            let (
+                ProcessOutput {
+                    output: backseater_result,
+                    timed_out,
+                    stdin_error,
+                },
+                run_duration,
+            ) = match execution {
+                Err(error) if process::is_spawn_exhausted(&error) => {
+                    return Ok(spawn_failure("VM", &error));
+                }
+                execution => execution.with_context(|| {
+                    format!("unable to run the VM {}", config.backseater_path.display())
+                })?,
+            };
+            log.run_duration = Some(run_duration);
+            log.stages.push(StageRecord {
+                stage: Stage::Run,
+                duration: run_duration,
+                exit_code: backseater_result.status.code(),
+            });
+            let vm_stderr_raw =
+                stderr_noise::remove(&backseater_result.stderr, config.noise_patterns());
+            let vm_stderr = strip_ansi(&vm_stderr_raw);
+            log.vm_stdout = Some(reported(
+                &backseater_result.stdout,
+                &strip_ansi(&backseater_result.stdout),
+            ));
+            log.vm_stderr = Some(reported(
+                &backseater_result.stderr,
+                &strip_ansi(&backseater_result.stderr),
+            ));
+            if timed_out {
+                return Ok(TestResultKind::Failure(
+                    FailureCode::Timeout,
+                    format!(
+                        "\texecution {} {}s\n{}",
+                        match timeout {
+                            Some(_) => "exceeded the per-test timeout of",
+                            None => "timed out after",
+                        },
+                        vm_timeout.unwrap_or_default().as_secs_f64(),
+                        String::from_utf8_lossy(&reported(&vm_stderr_raw, &vm_stderr))
+                    ),
+                ));
+            }
+            if let Some(error) = stdin_error {
+                return Ok(TestResultKind::Failure(
+                    FailureCode::Infra,
+                    format!(
+                        "\tunable to pass the compiled program to the VM: {error}\n{}",
+                        String::from_utf8_lossy(&reported(&vm_stderr_raw, &vm_stderr))
+                    ),
+                ));
+            }
+            if let Some(exceeded) = limits.exceeded(backseater_result.status, &vm_stderr) {
+                return Ok(TestResultKind::Failure(
+                    FailureCode::LimitExceeded,
+                    format!(
+                        "\texecution {exceeded}\n{}",
+                        String::from_utf8_lossy(&reported(&vm_stderr_raw, &vm_stderr))
+                    ),
+                ));
+            }
+            if let Some(signal) = process::crash_signal(backseater_result.status) {
+                return Ok(TestResultKind::Crash(crash_message(
+                    "VM",
+                    &signal,
+                    log.vm_stdout.as_deref().unwrap_or_default(),
+                    &reported(&vm_stderr_raw, &vm_stderr),
+                )));
+            }
+            match config.halt_mode.vm_exit(backseater_result.status) {
+                VmExit::Finished { exit_code } => match expected_outcome {
+                    TestOutcome::Finished if exit_code != expected_exit_code => {
+                        Ok(TestResultKind::Failure(
+                            FailureCode::WrongExitCode,
+                            format!(
+                                "\ttest finished with exit code {exit_code}, but the exit code {expected_exit_code} was expected\n{}",
+                                String::from_utf8_lossy(&reported(&vm_stderr_raw, &vm_stderr))
+                            ),
+                        ))
                     }
-                    false => {
-                        if let TestOutcome::Aborted { ref error_messages } = expected_outcome {
-                            match validate_error_messages(
-                                &backseater_result,
-                                error_messages,
-                            ) {
-                                Ok(_) => Ok(TestResult { filename, kind: TestResultKind::Success }),
-                                Err(error) => Ok(TestResult { filename, kind: TestResultKind::Failure(error.to_string()) }),
-                            }
-                        } else {
-                            Ok(TestResult{filename, kind: TestResultKind::Failure(String::from_utf8(backseater_result.stderr)?)})
+                    TestOutcome::Finished => {
+                        if !allow_empty {
+                            log.suspicion = suspicion(
+                                compiled_size,
+                                run_duration,
+                                &backseater_result.stdout,
+                            );
                         }
+                        match &expected_output {
+                            Some(expected_output) => check_expected_output(
+                                source_file,
+                                expected_output,
+                                &backseater_result.stdout,
+                                normalization,
+                            ),
+                            None => check_expected_stdout(
+                                config,
+                                source_file,
+                                &backseater_result.stdout,
+                                normalization,
+                            ),
+                        }
+                    }
+                    TestOutcome::Aborted { .. } | TestOutcome::AbortedExactly { .. } => {
+                        Ok(TestResultKind::UnexpectedSuccess(format!(
+                            "\ttest execution finished, but it was expected to {expected_outcome}\n"
+                        )))
                     }
+                },
+                VmExit::Aborted => {
+                    log.aborted_stage = Some(Stage::Run);
+                    evaluate_abort(
+                        &expected_outcome,
+                        (Stage::Run, expected_stage),
+                        source_file,
+                        &matched(&vm_stderr),
+                        reported(&vm_stderr_raw, &vm_stderr),
+                    )
                 }
             }
-            false => {
-                if let TestOutcome::Aborted { ref error_messages } = expected_outcome {
-                    match validate_error_messages(
-                        &command_result,
-                        error_messages,
-                    ) {
-                        Ok(_) => Ok(TestResult { filename, kind: TestResultKind::Success }),
-                        Err(error) => Ok(TestResult { filename, kind: TestResultKind::Failure(error.to_string()) }),
+        }
+        false => {
+            log.aborted_stage = Some(Stage::Compile);
+            let result = match snapshot {
+                Some(snapshot) => check_snapshot(
+                    config,
+                    source_file,
+                    &snapshot,
+                    &matched(&compiler_stderr),
+                    reported(&compiler_stderr_raw, &compiler_stderr),
+                    normalization,
+                ),
+                None => {
+                    let compiler_output = match match_stdout {
+                        true => [compiler_stderr.as_slice(), b"\n", &compiler_stdout].concat(),
+                        false => compiler_stderr.clone(),
+                    };
+                    evaluate_abort(
+                        &expected_outcome,
+                        (Stage::Compile, expected_stage),
+                        source_file,
+                        &matched(&compiler_output),
+                        reported(&compiler_stderr_raw, &compiler_stderr),
+                    )
+                }
+            };
+            Ok(match (result?, &log.compiler_stdout) {
+                (TestResultKind::Failure(code, mut message), Some(stdout)) => {
+                    message += "\tcompiler stdout:\n";
+                    for line in String::from_utf8_lossy(stdout).lines() {
+                        message += &format!("\t{line}\n");
                     }
-                } else {
-                    Ok(TestResult{filename, kind: TestResultKind::Failure(String::from_utf8(command_result.stderr)?)})
+                    TestResultKind::Failure(code, message)
                 }
+                (kind, _) => kind,
+            })
+        }
+    }
+}
+
+/// The failure of a test whose source file cannot be read or is not valid UTF-8, e.g. because of
+/// its permissions.
+fn unreadable_test(source_file: &Path, error: &std::io::Error) -> TestResultKind {
+    TestResultKind::Failure(
+        FailureCode::Infra,
+        format!("\tunable to read {}: {error}\n", source_file.display()),
+    )
+}
+
+/// Parses the directives of a test and checks whether it can be run with the given config. Returns
+/// the result of the test instead of the directives if it is skipped or its directives are invalid.
+fn test_directives(
+    config: &Config,
+    source_file: &Path,
+) -> anyhow::Result<Result<TestDirectives, TestResultKind>> {
+    let leading_comments = match directives::read_leading_comments(source_file) {
+        Err(error) if directives::is_invalid_sidecar(&error) => {
+            return Ok(Err(TestResultKind::Failure(
+                FailureCode::Directives,
+                format!("\tinvalid test directives: {error}\n"),
+            )))
+        }
+        Err(error) => return Ok(Err(unreadable_test(source_file, &error))),
+        Ok(leading_comments) => leading_comments,
+    };
+    let directives = directives::parse_directives(&leading_comments);
+    debug!("{}: {:?}", source_file.display(), directives);
+    let directives = match directives {
+        Ok(directives) => directives,
+        Err(error) => {
+            return Ok(Err(TestResultKind::Failure(
+                FailureCode::Directives,
+                format!("\tinvalid test directives: {error}\n"),
+            )))
+        }
+    };
+    if let Some(reason) = &directives.skip {
+        return Ok(Err(TestResultKind::Skipped(reason.clone())));
+    }
+    if let Some(configuration) = &config.configuration {
+        if directives.skip_configs.contains(&configuration.name) {
+            return Ok(Err(TestResultKind::Skipped(format!(
+                "excluded from the configuration {}",
+                configuration.name
+            ))));
+        }
+    }
+    if directives.requires_seatbelt.is_some() || directives.requires_backseater.is_some() {
+        let versions = config.versions();
+        let requirements = [
+            (
+                "Seatbelt",
+                &directives.requires_seatbelt,
+                &versions.seatbelt,
+            ),
+            (
+                "Backseater",
+                &directives.requires_backseater,
+                &versions.backseater,
+            ),
+        ];
+        for (name, required, available) in requirements {
+            if let Some(reason) = required
+                .as_ref()
+                .and_then(|required| required.unmet_by(name, available.as_ref()))
+            {
+                return Ok(Err(TestResultKind::Skipped(reason)));
             }
         }
-    }).for_each(|result| {
-        match result {
-            Ok(result) => {
-                tests_run.fetch_add(1, Ordering::SeqCst);
+    }
+    if let Some(lib_path) = directives
+        .lib
+        .as_ref()
+        .and_then(|lib| lib.resolve(source_file))
+    {
+        if !lib_path.is_dir() {
+            return Ok(Err(TestResultKind::Failure(
+                FailureCode::Directives,
+                format!(
+                    "\tinvalid test directives: the library {} of the `lib` directive does not exist\n",
+                    lib_path.display()
+                ),
+            )));
+        }
+    }
+    if directives.exit_code.is_some_and(|exit_code| exit_code != 0)
+        && config.halt_mode != HaltMode::PropagateExitCode
+    {
+        return Ok(Err(TestResultKind::Failure(
+            FailureCode::Directives,
+            "\tinvalid test directives: a nonzero `exit_code` requires `--halt-mode propagate-exit-code`\n"
+                .to_string(),
+        )));
+    }
+    Ok(Ok(directives))
+}
+
+/// The library a test is compiled against: the one given via `--lib`, unless the `lib` directive
+/// of the test overrides it. `None` if the test is compiled without a library.
+fn library_path(config: &Config, source_file: &Path, lib: Option<&Library>) -> Option<PathBuf> {
+    match lib {
+        Some(lib) => lib.resolve(source_file),
+        None => Some(config.lib_path.clone()),
+    }
+}
+
+/// The command line shown in the log and the command that compiles the test against the given
+/// library.
+fn compile_command(
+    config: &Config,
+    source_file: &Path,
+    lib_path: Option<&Path>,
+) -> (String, Command) {
+    let mut command_line = format!(
+        "{} {}",
+        config.seatbelt_path.display(),
+        source_file.display()
+    );
+    let mut command = toolchain_command(config, &config.seatbelt_path);
+    command.arg(source_file.as_os_str());
+    if let Some(lib_path) = lib_path {
+        command_line += &format!(" --lib {}", lib_path.display());
+        command.arg("--lib").arg(lib_path.as_os_str());
+    }
+    for arg in config.seatbelt_args() {
+        command_line.push(' ');
+        command_line += &text::shell_quote(arg);
+    }
+    command.args(config.seatbelt_args());
+    (command_line, command)
+}
+
+fn configuration_name(config: &Config) -> Option<&str> {
+    config
+        .configuration
+        .as_ref()
+        .map(|configuration| configuration.name.as_str())
+}
+
+/// Compiles a test in the compile phase of `--phases compile-first` and stores the compilation
+/// for the run phase. Tests that are skipped or have invalid directives are not compiled. Errors of
+/// the test runner itself are stored as well, so they fail only this test when it is run.
+fn precompile_test(
+    config: &Config,
+    test: &TestFile,
+    compilations: &Compilations,
+) -> Option<CompileOutcome> {
+    let config = &*config.with_overrides(&test.overrides);
+    if discovery::is_precompiled(&test.source_path) {
+        return None;
+    }
+    let configuration = configuration_name(config);
+    match compile_ahead(config, test) {
+        Ok(Some((outcome, compilation))) => {
+            compilations.insert(&test.source_path, configuration, Ok(compilation));
+            Some(outcome)
+        }
+        Ok(None) => None,
+        Err(error) => {
+            compilations.insert(&test.source_path, configuration, Err(error));
+            None
+        }
+    }
+}
 
-                match result.kind {
-                    TestResultKind::Success => {
-                        print_success(&result.filename);
-                    },
-                    TestResultKind::Failure(error_message) => {
-                        print_fail(&result.filename, &error_message);
-                        tests_failed.fetch_add(1, Ordering::SeqCst);
-                    },
+/// Compiles a test for [`precompile_test`]. Returns `None` if the test is not compiled ahead,
+/// e.g. because its compiler could not be started yet, so it is compiled when it is run.
+fn compile_ahead(
+    config: &Config,
+    test: &TestFile,
+) -> anyhow::Result<Option<(CompileOutcome, (ProcessOutput, Duration))>> {
+    let Ok(directives) = test_directives(config, &test.source_path)? else {
+        return Ok(None);
+    };
+    let file_stem = file_stem(&test.name, configuration_name(config));
+    let scratch = ScratchDir::create(config.work_dir.as_deref(), &file_stem)
+        .context("unable to create the scratch directory")?;
+    let config = &Config {
+        test_tmpdir: Some(scratch.path().to_path_buf()),
+        ..config.clone()
+    };
+    let lib_path = library_path(config, &test.source_path, directives.lib.as_ref());
+    let (_, mut command) = compile_command(config, &test.source_path, lib_path.as_deref());
+    debug!("spawning {:?}", command);
+    let started = Instant::now();
+    let output =
+        process::run(&mut command, None, config.compile_timeout).map_err(anyhow::Error::from);
+    let duration = started.elapsed();
+    scratch
+        .finish(false)
+        .context("unable to remove the scratch directory")?;
+    let output = match output {
+        // the test is compiled again when it is run
+        Err(error) if process::is_spawn_exhausted(&error) => return Ok(None),
+        output => output.with_context(|| {
+            format!(
+                "unable to run the compiler {}",
+                config.seatbelt_path.display()
+            )
+        })?,
+    };
+    let outcome = CompileOutcome {
+        filename: match configuration_name(config) {
+            Some(name) => format!("{} [{name}]", test.name),
+            None => test.name.clone(),
+        },
+        source_path: test.source_path.clone(),
+        must_compile: directives.expected_outcome == TestOutcome::Finished
+            && !snapshot_path(&test.source_path).exists(),
+        succeeded: output.output.status.success(),
+        stderr: output.output.stderr.clone(),
+    };
+    Ok(Some((outcome, (output, duration))))
+}
+
+/// The failure of a test whose compiler or VM could not be started because the OS was still out of
+/// resources after all attempts.
+fn spawn_failure(name: &str, error: &anyhow::Error) -> TestResultKind {
+    TestResultKind::Failure(
+        FailureCode::Infra,
+        format!(
+            "\tunable to start the {name} after {} attempts: {error}\n",
+            process::SPAWN_ATTEMPTS
+        ),
+    )
+}
+
+/// Compiled programs smaller than this many bytes are considered suspiciously empty.
+const SUSPICIOUS_PROGRAM_SIZE: usize = 8;
+
+/// VM runs without any output that take less than this are considered suspiciously empty.
+const SUSPICIOUS_RUN_DURATION: Duration = Duration::from_millis(1);
+
+/// Returns why a successful test looks like it did not test anything, e.g. because a
+/// misconfigured toolchain compiled it to an empty program. `None` if the test looks fine.
+fn suspicion(compiled_size: usize, run_duration: Duration, vm_stdout: &[u8]) -> Option<String> {
+    if compiled_size < SUSPICIOUS_PROGRAM_SIZE {
+        Some(format!(
+            "the compiled program has only {compiled_size} bytes"
+        ))
+    } else if run_duration < SUSPICIOUS_RUN_DURATION && vm_stdout.is_empty() {
+        Some(format!(
+            "the VM finished after {}µs without any output",
+            run_duration.as_micros()
+        ))
+    } else {
+        None
+    }
+}
+
+/// The failure message of a toolchain process that was killed by a signal, including the output
+/// it produced before.
+fn crash_message(process: &str, signal: &str, stdout: &[u8], stderr: &[u8]) -> String {
+    let mut message = format!("\t{process} crashed with {signal}\n");
+    for (label, output) in [("stdout", stdout), ("stderr", stderr)] {
+        if !output.is_empty() {
+            message += &format!("\t{label}:\n{}", String::from_utf8_lossy(output));
+            if !output.ends_with(b"\n") {
+                message.push('\n');
+            }
+        }
+    }
+    message
+}
+
+/// Removes the failure directive from a test that finished although it was expected to abort and
+/// prints the change as a diff.
+fn fix_stale_directive(source_file: &Path) -> anyhow::Result<()> {
+    if discovery::is_precompiled(source_file) || directives::sidecar_specifies_failure(source_file)
+    {
+        print_warning(
+            &mut stderr(),
+            &format!(
+                "not removing the failure directive from {}, edit it manually\n",
+                directives::sidecar_path(source_file).display()
+            ),
+        );
+        return Ok(());
+    }
+    let contents = std::fs::read_to_string(source_file)?;
+    let Some(fixed) = directives::remove_failure_directives(&contents) else {
+        return Ok(());
+    };
+    write_atomic(source_file, fixed.as_bytes())?;
+    let mut message = format!(
+        "removed the failure directive from {}:\n",
+        source_file.display()
+    );
+    for line in diff::line_diff(&contents, &fixed).lines() {
+        if !line.starts_with(' ') {
+            message += &format!("\t{line}\n");
+        }
+    }
+    eprint!("{message}");
+    Ok(())
+}
+
+/// Writes `contents` to `<path>.tmp`, syncs it to disk and renames it to `path`, so `path` either
+/// keeps its previous contents or has the new ones, but is never left half-written. Every file
+/// the test runner produces is written this way.
+fn write_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    write_atomic_with(path, |file| file.write_all(contents))
+}
+
+/// Like [`write_atomic`], but the contents are written by `write`.
+fn write_atomic_with(
+    path: &Path,
+    write: impl FnOnce(&mut File) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    let mut temporary_path = path.as_os_str().to_os_string();
+    temporary_path.push(".tmp");
+    let temporary_path = PathBuf::from(temporary_path);
+    let result = File::create(&temporary_path)
+        .and_then(|mut file| {
+            write(&mut file)?;
+            file.sync_all()
+        })
+        .and_then(|()| std::fs::rename(&temporary_path, path));
+    if result.is_err() {
+        std::fs::remove_file(&temporary_path).ok();
+    }
+    result
+}
+
+/// The path of the file the compiler stderr of a test is recorded in, see `--bless`.
+fn snapshot_path(source_file: &Path) -> PathBuf {
+    let mut path = source_file.as_os_str().to_os_string();
+    path.push(".stderr");
+    PathBuf::from(path)
+}
+
+/// The path of the file containing the exact bytes a test has to write to stdout.
+fn expected_stdout_path(source_file: &Path) -> PathBuf {
+    let mut path = source_file.as_os_str().to_os_string();
+    path.push(".expected.bin");
+    PathBuf::from(path)
+}
+
+/// Compares the stdout of a finished test byte by byte with its expected stdout if the test has
+/// one, or replaces the expected stdout if `--bless` is given.
+fn check_expected_stdout(
+    config: &Config,
+    source_file: &Path,
+    stdout: &[u8],
+    normalization: Normalization,
+) -> anyhow::Result<TestResultKind> {
+    let path = expected_stdout_path(source_file);
+    let expected_stdout = match std::fs::read(&path) {
+        Ok(expected_stdout) => normalization.apply(&expected_stdout),
+        Err(error) if error.kind() == ErrorKind::NotFound => return Ok(TestResultKind::Success),
+        Err(error) => {
+            return Err(
+                anyhow::Error::new(error).context(format!("unable to read {}", path.display()))
+            )
+        }
+    };
+    let normalized_stdout = normalization.apply(stdout);
+    if expected_stdout == normalized_stdout {
+        return Ok(TestResultKind::Success);
+    }
+    if config.bless {
+        write_atomic(&path, stdout)
+            .with_context(|| format!("unable to bless {}", path.display()))?;
+        return Ok(TestResultKind::Success);
+    }
+    Ok(TestResultKind::Failure(
+        FailureCode::WrongOutput,
+        format!(
+            "\ttest finished, but its stdout differs from {}:\n{}{}",
+            path.display(),
+            normalization.note(),
+            diff::hex_diff(&expected_stdout, &normalized_stdout)
+        ),
+    ))
+}
+
+/// Compares the stdout of a finished test with the `expects_output` block of the test. The block
+/// is not updated by `--bless`, and a test must not have an `.expected.bin` file as well.
+fn check_expected_output(
+    source_file: &Path,
+    expected_output: &ExpectedOutput,
+    stdout: &[u8],
+    normalization: Normalization,
+) -> anyhow::Result<TestResultKind> {
+    let path = expected_stdout_path(source_file);
+    if path.exists() {
+        return Ok(TestResultKind::Failure(
+            FailureCode::Directives,
+            format!(
+                "\tthe test has both an `expects_output` block and {}, remove one of them\n",
+                path.display()
+            ),
+        ));
+    }
+    let stdout = normalization.apply(stdout);
+    let stdout = String::from_utf8_lossy(&stdout);
+    let expected = normalization.apply(expected_output.text.as_bytes());
+    let expected = String::from_utf8_lossy(&expected);
+    if stdout == expected {
+        return Ok(TestResultKind::Success);
+    }
+    let block = match &expected_output.sidecar {
+        Some(sidecar) => format!("`expects_output` in {}", sidecar.display()),
+        None => format!(
+            "the `expects_output` block on lines {}-{} of {}",
+            expected_output.first_line,
+            expected_output.last_line(),
+            source_file.display()
+        ),
+    };
+    Ok(TestResultKind::Failure(
+        FailureCode::WrongOutput,
+        format!(
+            "\ttest finished, but its stdout differs from {block} (-expected +actual):\n{}{}",
+            normalization.note(),
+            diff::numbered_diff(&expected, &stdout, expected_output.first_line)
+        ),
+    ))
+}
+
+/// Compares the stderr of a failed compilation with the snapshot of the test, or writes the
+/// snapshot if `--bless` is given. `stderr` is the normalized output, `reported_stderr` the one
+/// that is reported if there is no snapshot.
+fn check_snapshot(
+    config: &Config,
+    source_file: &Path,
+    snapshot: &Path,
+    stderr: &[u8],
+    reported_stderr: Vec<u8>,
+    normalization: Normalization,
+) -> anyhow::Result<TestResultKind> {
+    if config.bless {
+        let stderr = String::from_utf8_lossy(stderr);
+        write_atomic(snapshot, format!("{}\n", stderr.trim()).as_bytes())
+            .with_context(|| format!("unable to bless {}", snapshot.display()))?;
+        return Ok(TestResultKind::Success);
+    }
+    let expected_stderr = match std::fs::read_to_string(snapshot) {
+        Ok(expected_stderr) => expected_stderr,
+        Err(error) if error.kind() == ErrorKind::NotFound => {
+            return Ok(TestResultKind::Failure(
+                FailureCode::CompileUnexpected,
+                format!(
+                    "{}\tthe compilation failed and there is no snapshot {}, run with --bless to record the stderr\n",
+                    String::from_utf8_lossy(&reported_stderr),
+                    snapshot.display()
+                ),
+            ));
+        }
+        Err(error) => {
+            return Err(anyhow::Error::new(error).context(format!(
+                "unable to read the snapshot {}",
+                snapshot.display()
+            )))
+        }
+    };
+    let expected_stderr = normalization.apply(expected_stderr.as_bytes());
+    let stderr = normalization.apply(stderr);
+    match validate_exact_stderr(
+        &stderr,
+        &String::from_utf8_lossy(&expected_stderr),
+        snapshot,
+        source_file,
+    ) {
+        Ok(()) => Ok(TestResultKind::Success),
+        Err(error) => Ok(TestResultKind::Failure(
+            FailureCode::WrongMessage,
+            format!("{error}{}", normalization.note()),
+        )),
+    }
+}
+
+/// Evaluates the stderr of a failed compilation or execution against the expected outcome of a
+/// test. `stderr` is the stripped output that is matched, `reported_stderr` the one that is
+/// reported if the test was not expected to fail. `stages` are the stage the test aborted in and
+/// the one it has to abort in, if any.
+fn evaluate_abort(
+    expected_outcome: &TestOutcome,
+    stages: (Stage, Option<Stage>),
+    source_file: &Path,
+    stderr: &[u8],
+    reported_stderr: Vec<u8>,
+) -> anyhow::Result<TestResultKind> {
+    let result = match expected_outcome {
+        TestOutcome::Finished => {
+            return Ok(TestResultKind::Failure(
+                FailureCode::RuntimeUnexpected,
+                String::from_utf8_lossy(&reported_stderr).into_owned(),
+            ))
+        }
+        TestOutcome::Aborted { error_messages } => validate_error_messages(stderr, error_messages),
+        TestOutcome::AbortedExactly { expected_stderr } => {
+            match expected_stderr.load(source_file) {
+                Ok(expected) => validate_exact_stderr(
+                    stderr,
+                    &expected,
+                    &expected_stderr.source(source_file),
+                    source_file,
+                ),
+                Err(error) => {
+                    return Ok(TestResultKind::Failure(
+                        FailureCode::Infra,
+                        format!(
+                            "\tunable to read the expected stderr {}: {}\n",
+                            expected_stderr, error
+                        ),
+                    ))
                 }
-            },
-            Err(_) => panic!(),
+            }
         }
-    });
+    };
+    match (result, stages) {
+        (Ok(()), (stage, Some(expected_stage))) if stage != expected_stage => {
+            Ok(TestResultKind::Failure(
+                FailureCode::WrongStage,
+                format!("\tthe test aborted with the expected error in the {stage} stage, but has to abort in the {expected_stage} stage\n"),
+            ))
+        }
+        (Ok(()), _) => Ok(TestResultKind::Success),
+        (Err(error), _) => Ok(TestResultKind::Failure(
+            FailureCode::WrongMessage,
+            error.to_string(),
+        )),
+    }
+}
+
+/// Groups the paths of all tests by the name they are reported with, see [`report_name`], and
+/// returns the groups that contain more than one test.
+fn find_duplicate_names(
+    tests: &[TestFile],
+    name_format: Option<&NameFormat>,
+) -> BTreeMap<String, Vec<PathBuf>> {
+    let mut paths_by_name: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    for test in tests {
+        // the configuration is appended to every name alike, so it cannot cause or resolve a clash
+        let name = report_name(name_format, &test.name, None);
+        paths_by_name
+            .entry(name)
+            .or_default()
+            .push(test.path.clone());
+    }
+    paths_by_name.retain(|_, paths| paths.len() > 1);
+    paths_by_name
+}
+
+/// Finds all source files among the ones that are not part of a test that contain a test
+/// directive within their first lines.
+fn find_unmatched_test_files(other_files: &[PathBuf]) -> Vec<PathBuf> {
+    let mut unmatched: Vec<PathBuf> = other_files
+        .iter()
+        .filter(|path| contains_directive(path))
+        .cloned()
+        .collect();
+    unmatched.sort();
+    unmatched
+}
 
-    let tests_run = tests_run.load(Ordering::Relaxed);
-    let tests_failed = tests_failed.load(Ordering::Relaxed);
+/// Checks whether one of the leading comment lines of the given file is a test directive. Only
+/// the head of the file is read. Files that cannot be read are skipped with a warning.
+fn contains_directive(path: &Path) -> bool {
+    let leading_comments = match directives::read_leading_comments(path) {
+        Ok(leading_comments) => leading_comments,
+        // not valid UTF-8, so this is not a Backseat source file anyway
+        Err(error) if error.kind() == ErrorKind::InvalidData => return false,
+        Err(error) => {
+            print_warning(
+                &mut stderr(),
+                &format!("unable to read {}: {error}, skipping it\n", path.display()),
+            );
+            return false;
+        }
+    };
+    leading_comments.iter().any(|line| {
+        directives::directive_key(line).is_some_and(|key| directives::DIRECTIVE_KEYS.contains(&key))
+    })
+}
+
+/// Prints the discovered tests, see `--list`. With `verbose`, the human-readable list shows how
+/// every test is expected to end.
+fn list_tests(tests: &[TestFile], format: Format, verbose: bool) -> Result<(), Box<dyn Error>> {
+    let entries: Vec<_> = tests.iter().map(ManifestEntry::read).collect();
+    let mut out = stdout().lock();
+    match format {
+        Format::Human => {
+            for entry in &entries {
+                match (entry.read_error(), entry.expected_outcome()) {
+                    (Some(error), _) => writeln!(out, "{} ({error})", entry.name())?,
+                    (None, Some(expected_outcome)) if verbose => {
+                        writeln!(out, "{} (expected to {expected_outcome})", entry.name())?
+                    }
+                    (None, _) => writeln!(out, "{}", entry.name())?,
+                }
+            }
+        }
+        Format::Json => {
+            serde_json::to_writer_pretty(&mut out, &entries)?;
+            writeln!(out)?;
+        }
+        Format::Ndjson => {
+            for entry in &entries {
+                serde_json::to_writer(&mut out, entry)?;
+                writeln!(out)?;
+            }
+        }
+    }
+    match entries.iter().any(|entry| entry.read_error().is_some()) {
+        true => Err("not all tests could be read".into()),
+        false => Ok(()),
+    }
+}
+
+/// Parses the directives of all tests and reports the ones that are invalid.
+fn check_directives(tests: &[TestFile]) -> Result<(), Box<dyn Error>> {
+    let mut invalid = 0;
+    let mut unreadable = 0;
+    let mut syntax_issues = 0;
+    for test in tests {
+        let path = &test.source_path;
+        let error = match directives::read_leading_comments(path) {
+            Ok(leading_comments) => {
+                for issue in directives::syntax_issues(&leading_comments) {
+                    syntax_issues += 1;
+                    let label = match issue {
+                        SyntaxIssue::Deprecated { .. } => "deprecated directive syntax",
+                        SyntaxIssue::Ambiguous { .. } => "ambiguous comment",
+                    };
+                    print_check_line(path, Color::DarkYellow, label, &format!(" ({issue})\n"));
+                }
+                directives::parse_directives(&leading_comments)
+                    .err()
+                    .map(|error| error.to_string())
+                    .or_else(|| {
+                        let conflicts = directives::sidecar_conflicts(path).ok()?;
+                        (!conflicts.is_empty()).then(|| conflicts.join(", "))
+                    })
+            }
+            Err(error) if directives::is_invalid_sidecar(&error) => Some(error.to_string()),
+            Err(error) => {
+                unreadable += 1;
+                print_check_line(
+                    path,
+                    Color::DarkRed,
+                    "unreadable",
+                    &format!(" (unable to read {}: {error})\n", path.display()),
+                );
+                continue;
+            }
+        };
+        if let Some(error) = error {
+            invalid += 1;
+            print_check_line(
+                path,
+                Color::DarkRed,
+                "invalid directives",
+                &format!(" ({error})\n"),
+            );
+        }
+    }
 
-    let message = format!(
-        "Tests run: {}, Tests successful: {}, Tests failed: {}\n",
-        tests_run,
-        tests_run - tests_failed,
-        tests_failed
+    let mut message = format!(
+        "Tests checked: {}, Tests with invalid directives: {}, ",
+        tests.len(),
+        invalid
     );
-    execute!(
-        stdout(),
-        SetForegroundColor(if tests_failed == 0 {
-            Color::DarkGreen
-        } else {
-            Color::DarkRed
-        }),
-        Print(message),
-        ResetColor
-    )
+    if unreadable > 0 {
+        message += &format!("Unreadable tests: {unreadable}, ");
+    }
+    message += &format!("Lines with deprecated or ambiguous syntax: {syntax_issues}\n");
+    match use_color() {
+        true => execute!(
+            stdout(),
+            SetForegroundColor(if invalid + unreadable == 0 {
+                Color::DarkGreen
+            } else {
+                Color::DarkRed
+            }),
+            Print(message),
+            ResetColor
+        ),
+        false => execute!(stdout(), Print(message)),
+    }
     .expect("unable to print output");
-    if tests_failed == 0 {
-        Ok(())
+    if unreadable > 0 {
+        Err("not all tests could be read".into())
+    } else if invalid > 0 {
+        Err("not all tests have valid directives".into())
     } else {
-        Err("not all tests succeeded".into())
+        Ok(())
     }
 }
 
-fn print_success(filename: &str) {
-    execute!(
-        stdout().lock(),
-        Print(format!("test {filename} ... ")),
-        SetForegroundColor(Color::DarkGreen),
-        Print("OK\n"),
-        ResetColor
-    )
+/// Prints a line of `--check` about the test at `path`, with the label in `color`.
+fn print_check_line(path: &Path, color: Color, label: &str, details: &str) {
+    let mut out = stdout().lock();
+    match use_color() {
+        true => execute!(
+            out,
+            Print(format!("{}: ", path.display())),
+            SetForegroundColor(color),
+            Print(label),
+            ResetColor,
+            Print(details)
+        ),
+        false => execute!(out, Print(format!("{}: {label}{details}", path.display()))),
+    }
     .expect("unable to print output");
 }
 
-fn print_fail(filename: &str, error_message: &str) {
-    execute!(
-        stdout().lock(),
-        Print(format!("test {filename} ... ")),
-        SetForegroundColor(Color::DarkRed),
-        Print("FAILED\n"),
-        ResetColor,
-        Print(error_message)
+fn validate_error_messages(stderr: &[u8], error_messages: &[String]) -> anyhow::Result<()> {
+    validate_messages(
+        stderr,
+        error_messages,
+        "test aborted as expected, but with wrong error message",
     )
-    .expect("unable to print output");
 }
 
-fn validate_error_messages(
-    command_result: &std::process::Output,
-    error_messages: &[String],
-) -> anyhow::Result<()> {
-    let stderr_string = String::from_utf8_lossy(&command_result.stderr);
-    if error_messages
+/// Checks that the compiler stderr of a successful compilation contains all expected warnings.
+fn validate_warnings(stderr: &[u8], warnings: &[String]) -> anyhow::Result<()> {
+    validate_messages(
+        stderr,
+        warnings,
+        "compilation succeeded, but without the expected warnings",
+    )
+}
+
+/// Checks that `stderr` contains all `messages`. On a mismatch, the error starts with `heading`
+/// and lists the found and the missing messages.
+fn validate_messages(stderr: &[u8], messages: &[String], heading: &str) -> anyhow::Result<()> {
+    let stderr_string = String::from_utf8_lossy(stderr);
+    if messages
         .iter()
         .all(|message| stderr_string.contains(message))
     {
         Ok(())
     } else {
-        let mut error_message = format!(
-            "\ttest aborted as expected, but with wrong error message:\n\texpected: \"{}\"",
-            error_messages[0]
-        );
-        for message in &error_messages[1..] {
-            error_message += &format!("\t          and \"{}\"", &message);
+        let mut error_message = format!("\t{heading}:\n");
+        let (found, missing): (Vec<_>, Vec<_>) = messages
+            .iter()
+            .map(String::as_str)
+            .partition(|message| stderr_string.contains(message));
+        for message in &found {
+            error_message += &format!("\t+ found:   \"{message}\"\n");
+        }
+        for message in &missing {
+            error_message += &format!("\t- missing: \"{message}\"\n");
         }
-        error_message += &format!("\t     got: \"{}\"", stderr_string.trim());
+        error_message += "\tgot:\n";
+        error_message += &diff::highlight_overlaps(stderr_string.trim(), &missing);
         Err(anyhow!(error_message))
     }
 }
 
-fn determine_expected_outcome(source_file: &Path) -> anyhow::Result<TestOutcome> {
-    let input_file = std::fs::read_to_string(source_file.as_os_str())?;
-    let first_line = input_file.split('\n').next().unwrap().trim();
-    if first_line.starts_with("//") {
-        let test_runner_command = first_line.strip_prefix("//").unwrap().trim();
-        let mut parts = test_runner_command.split('=');
-        if let Some(lhs) = parts.next() {
-            if let Some(rhs) = parts.next() {
-                if lhs.trim() == "fails_with" {
-                    let messages = rhs.trim().split(',');
-                    let mut message_vector = Vec::new();
-                    for message in messages {
-                        let message = message.trim();
-                        let message = message
-                            .strip_prefix('"')
-                            .ok_or_else(|| {
-                                anyhow!("\" prefix not found in {}", source_file.display())
-                            })?
-                            .strip_suffix('"')
-                            .ok_or_else(|| {
-                                anyhow!("\" suffix not found in {}", source_file.display())
-                            })?;
-                        message_vector.push(String::from(message));
-                    }
-                    return Ok(TestOutcome::Aborted {
-                        error_messages: message_vector,
-                    });
-                }
-            }
-        }
+/// Replaces the paths of the test and of the standard library in `stderr` with `$FILE` and `$LIB`
+/// so expectations do not depend on where the tests are located.
+fn normalize_stderr(config: &Config, source_file: &Path, stderr: &[u8]) -> Vec<u8> {
+    let mut paths = vec![(source_file.to_path_buf(), "$FILE")];
+    if let Ok(source_file) = source_file.canonicalize() {
+        paths.push((source_file, "$FILE"));
+    }
+    if config.lib_path.is_absolute() {
+        paths.push((config.lib_path.clone(), "$LIB"));
+    }
+    if let Ok(lib_path) = config.lib_path.canonicalize() {
+        paths.push((lib_path, "$LIB"));
+    }
+    text::normalize_paths(stderr, &paths)
+}
+
+/// Checks that `stderr` equals `expected_stderr`, ignoring leading and trailing whitespace. On a
+/// mismatch, the error contains a line-by-line diff.
+fn validate_exact_stderr(
+    stderr: &[u8],
+    expected_stderr: &str,
+    expectation_file: &Path,
+    source_file: &Path,
+) -> anyhow::Result<()> {
+    let stderr_string = String::from_utf8_lossy(stderr);
+    let (actual, expected) = (stderr_string.trim(), expected_stderr.trim());
+    if actual == expected {
+        return Ok(());
+    }
+    let mut error_message =
+        "\ttest aborted as expected, but with a different stderr (-expected +actual):\n"
+            .to_string();
+    error_message += &diff::unified_diff(
+        &format!("{expected}\n"),
+        &format!("{actual}\n"),
+        &format!("expected {}", expectation_file.display()),
+        &format!("actual stderr of {}", source_file.display()),
+    );
+    Err(anyhow!(error_message))
+}
+
+/// The arguments of the VM to run a compiled program that is passed via stdin, including the extra
+/// arguments of the configuration and the ones passing the seed. The arguments for the program
+/// itself follow after a `--`.
+fn vm_arguments(config: &Config, program_args: &[String], seed: Option<u64>) -> Vec<String> {
+    let mut args: Vec<String> = config.halt_mode.vm_args().map(String::from).into();
+    args.extend(config.backseater_args().cloned());
+    if let (Some(seed_arg), Some(seed)) = (&config.seed_arg, seed) {
+        args.extend(seed_arg.args(seed));
+    }
+    if !program_args.is_empty() {
+        args.push("--".to_string());
+        args.extend(program_args.iter().cloned());
+    }
+    args
+}
+
+/// The environment variable of the VM passing the seed, if the seed is passed in one.
+fn seed_env(config: &Config, seed: Option<u64>) -> Option<(&str, String)> {
+    config.seed_arg.as_ref()?.env(seed?)
+}
+
+/// Renders an invocation of `executable` as a shell command line, quoting the words as necessary.
+fn shell_command_line(executable: &Path, args: &[String]) -> String {
+    let mut command_line = executable.display().to_string();
+    for arg in args {
+        command_line.push(' ');
+        command_line += &text::shell_quote(arg);
+    }
+    command_line
+}
+
+/// Creates the command to invoke one of the toolchain executables with the environment configured
+/// on the command line.
+fn toolchain_command(config: &Config, executable: &Path) -> Command {
+    let mut command = Command::new(executable.as_os_str());
+    if config.child_color == ChildColor::Never {
+        command.env("NO_COLOR", "1").env("CLICOLOR", "0");
+    }
+    command.envs(&config.overrides.env);
+    if let Some(test_tmpdir) = &config.test_tmpdir {
+        command.env(scratch::TEST_TMPDIR, test_tmpdir);
     }
-    Ok(TestOutcome::Finished)
+    command
 }
 
 fn child_with_pipe_args<S, I>(
-    path_of_executable: &Path,
+    mut command: Command,
     compiler_output: Vec<u8>,
     args: I,
-) -> anyhow::Result<std::process::Output>
+    timeout: Option<Duration>,
+    stdout_sink: Option<LineSink>,
+) -> anyhow::Result<ProcessOutput>
 where
     S: AsRef<OsStr>,
     I: IntoIterator<Item = S>,
 {
-    let child = Command::new(path_of_executable.as_os_str())
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .args(args)
-        .spawn()?;
-    spawn_child(child, compiler_output)
+    command.args(args);
+    debug!("spawning {:?}", command);
+    let started = Instant::now();
+    let output = process::run_streaming(&mut command, Some(compiler_output), timeout, stdout_sink)?;
+    debug!(
+        "{:?} exited with {} after {:?}",
+        command.get_program(),
+        output.output.status,
+        started.elapsed()
+    );
+    Ok(output)
 }
 
-fn spawn_child(
-    mut child: std::process::Child,
-    compiler_output: Vec<u8>,
-) -> anyhow::Result<std::process::Output> {
-    let mut stdin = child.stdin.take().expect("Failed to open stdin");
-    std::thread::spawn(move || {
-        stdin
-            .write_all(&compiler_output)
-            .expect("Failed to write to stdin");
-    });
-    Ok(child.wait_with_output()?)
+/// Parses a fraction from 0 to 1.
+fn parse_fraction(value: &str) -> Result<f64, String> {
+    let fraction: f64 = value
+        .parse()
+        .map_err(|_| format!("invalid fraction: {value}"))?;
+    if !(0.0..=1.0).contains(&fraction) {
+        return Err(format!("the fraction must be between 0 and 1: {value}"));
+    }
+    Ok(fraction)
+}
+
+/// Parses a positive number of seconds, which may have a fractional part.
+fn parse_seconds(value: &str) -> Result<Duration, String> {
+    let seconds: f64 = value
+        .parse()
+        .map_err(|_| format!("invalid number of seconds: {value}"))?;
+    if !seconds.is_finite() || seconds <= 0.0 {
+        return Err(format!("the number of seconds must be positive: {value}"));
+    }
+    Ok(Duration::from_secs_f64(seconds))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::{write_atomic, write_atomic_with};
+    use crate::scratch::ScratchDir;
+
+    #[test]
+    fn atomic_writes_replace_the_file() {
+        let scratch = ScratchDir::create(None, "write_atomic").unwrap();
+        let path = scratch.path().join("report.json");
+        std::fs::write(&path, "old").unwrap();
+        write_atomic(&path, b"new").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new");
+        assert!(!scratch.path().join("report.json.tmp").exists());
+        scratch.finish(false).unwrap();
+    }
+
+    #[test]
+    fn failed_writes_keep_the_previous_file() {
+        let scratch = ScratchDir::create(None, "write_atomic").unwrap();
+        let path = scratch.path().join("report.json");
+        std::fs::write(&path, "old").unwrap();
+        let error = write_atomic_with(&path, |file| {
+            file.write_all(b"half of the n")?;
+            Err(std::io::Error::other("disk full"))
+        })
+        .unwrap_err();
+        assert_eq!(error.to_string(), "disk full");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "old");
+        assert!(!scratch.path().join("report.json.tmp").exists());
+        scratch.finish(false).unwrap();
+    }
+
+    #[test]
+    fn failed_renames_remove_the_temporary_file() {
+        let scratch = ScratchDir::create(None, "write_atomic").unwrap();
+        // a directory that is not empty cannot be replaced by a file
+        let path = scratch.path().join("report.json");
+        std::fs::create_dir(&path).unwrap();
+        std::fs::write(path.join("kept"), "old").unwrap();
+        assert!(write_atomic(&path, b"new").is_err());
+        assert_eq!(std::fs::read_to_string(path.join("kept")).unwrap(), "old");
+        assert!(!scratch.path().join("report.json.tmp").exists());
+        scratch.finish(false).unwrap();
+    }
 }
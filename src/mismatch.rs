@@ -0,0 +1,252 @@
+//! Detection of a toolchain that does not speak the protocol of the test runner, e.g. a Backseater
+//! that expects different arguments after an upgrade. Instead of failing every test with the same
+//! usage message, the run is reported as broken, see `--no-heuristics`.
+
+use crate::text::strip_ansi;
+use crate::TestResult;
+
+/// The exit status of a run in which the toolchain is suspected not to match the test runner.
+pub(crate) const EXIT_STATUS: i32 = 3;
+
+/// The parts of the first line of a stderr that mark it as a usage or argument error, compared
+/// case-insensitively.
+const USAGE_ERROR_MARKERS: [&str; 2] = ["usage", "unrecognized option"];
+
+/// The same usage error most of the run tests failed with in the same stage.
+pub(crate) struct Mismatch<'a> {
+    /// `"compiling"` or `"running in the VM"`.
+    pub(crate) stage: &'static str,
+    /// The stderr of the stage with the path of the test replaced by `<test>`.
+    pub(crate) stderr: String,
+    /// The first test that failed with the error.
+    pub(crate) example: &'a TestResult,
+    pub(crate) failed: usize,
+    pub(crate) total: usize,
+}
+
+impl Mismatch<'_> {
+    /// The command line of the stage of the example test.
+    pub(crate) fn command_line(&self) -> &str {
+        self.example
+            .log
+            .commands
+            .last()
+            .map_or("<unknown>", String::as_str)
+    }
+
+    /// The message reporting the mismatch, without the `error: ` prefix.
+    pub(crate) fn message(&self) -> String {
+        let mut message = format!(
+            "suspected toolchain/runner mismatch: {} of {} tests failed while {} with the same usage error:\n",
+            self.failed, self.total, self.stage
+        );
+        for line in self.stderr.lines() {
+            message += &format!("\t{line}\n");
+        }
+        message += &format!(
+            "the command line of {} was:\n\t{}\n",
+            self.example.filename,
+            self.command_line()
+        );
+        message += "the Seatbelt compiler or the Backseater probably expects different arguments, e.g. after an upgrade; check the toolchain paths and versions, or pass --no-heuristics to report the failures of all tests\n";
+        message
+    }
+}
+
+/// Returns the most common usage error if more than `threshold` of the tests that were run failed
+/// with it in the same stage.
+pub(crate) fn detect(results: &[TestResult], threshold: f64) -> Option<Mismatch<'_>> {
+    let run: Vec<_> = results
+        .iter()
+        .filter(|result| !matches!(result.kind, crate::TestResultKind::Skipped(_)))
+        .collect();
+    let mut errors: Vec<((&'static str, String), Vec<&TestResult>)> = Vec::new();
+    for result in run.iter().filter(|result| result.kind.is_failure()) {
+        let Some(error) = usage_error(result) else {
+            continue;
+        };
+        match errors.iter_mut().find(|(known, _)| *known == error) {
+            Some((_, failed)) => failed.push(result),
+            None => errors.push((error, vec![result])),
+        }
+    }
+    // the first of the most common errors
+    let ((stage, stderr), failed) = errors
+        .into_iter()
+        .rev()
+        .max_by_key(|(_, failed)| failed.len())?;
+    (failed.len() as f64 > threshold * run.len() as f64).then(|| Mismatch {
+        stage,
+        stderr,
+        example: failed[0],
+        failed: failed.len(),
+        total: run.len(),
+    })
+}
+
+/// The stage and the normalized stderr of the stage a test failed in, if the first line of the
+/// stderr looks like a usage error.
+fn usage_error(result: &TestResult) -> Option<(&'static str, String)> {
+    let (stage, stderr) = match (&result.log.vm_stderr, &result.log.compiler_stderr) {
+        (Some(stderr), _) => ("running in the VM", stderr),
+        (None, Some(stderr)) => ("compiling", stderr),
+        (None, None) => return None,
+    };
+    let stderr = String::from_utf8_lossy(&strip_ansi(stderr))
+        .trim()
+        .replace(&*result.test.source_path.to_string_lossy(), "<test>");
+    let first_line = stderr.lines().next()?.to_lowercase();
+    USAGE_ERROR_MARKERS
+        .iter()
+        .any(|marker| first_line.contains(marker))
+        .then_some((stage, stderr))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::detect;
+    use crate::discovery::TestFile;
+    use crate::failure_code::FailureCode;
+    use crate::test_log::TestLog;
+    use crate::{TestResult, TestResultKind};
+
+    fn result(name: &str, kind: TestResultKind, log: TestLog) -> TestResult {
+        TestResult {
+            filename: name.to_string(),
+            report_name: name.to_string(),
+            test: TestFile {
+                name: name.to_string(),
+                root: 0,
+                path: format!("tests/{name}").into(),
+                source_path: format!("tests/{name}").into(),
+                quarantined: false,
+                overrides: Default::default(),
+            },
+            configuration: None,
+            kind,
+            duration: Duration::ZERO,
+            log,
+            log_error: None,
+        }
+    }
+
+    /// A test that failed while running in the VM with the stderr.
+    fn failed_in_vm(name: &str, stderr: &str) -> TestResult {
+        let log = TestLog {
+            commands: vec![
+                format!("./Seatbelt tests/{name}"),
+                format!("./backseat_safe_system_2k --run tests/{name}m"),
+            ],
+            vm_stderr: Some(
+                stderr
+                    .replace("{test}", &format!("tests/{name}"))
+                    .into_bytes(),
+            ),
+            ..TestLog::default()
+        };
+        let kind = TestResultKind::Failure(FailureCode::RuntimeUnexpected, String::new());
+        result(name, kind, log)
+    }
+
+    fn failed_compiling(name: &str, stderr: &str) -> TestResult {
+        let log = TestLog {
+            commands: vec![format!("./Seatbelt tests/{name}")],
+            compiler_stderr: Some(stderr.as_bytes().to_vec()),
+            ..TestLog::default()
+        };
+        let kind = TestResultKind::Failure(FailureCode::CompileUnexpected, String::new());
+        result(name, kind, log)
+    }
+
+    const USAGE: &str =
+        "\x1b[31mUsage\x1b[0m: backseater [OPTIONS] <FILE>\nunknown argument --run {test}m\n";
+
+    #[test]
+    fn mismatches_are_rendered_with_the_example_command_line() {
+        let results = [
+            failed_in_vm("test_a.bs", USAGE),
+            failed_in_vm("test_b.bs", USAGE),
+            result("test_c.bs", TestResultKind::Success, TestLog::default()),
+        ];
+        let mismatch = detect(&results, 0.5).unwrap();
+        assert_eq!(
+            mismatch.message(),
+            "suspected toolchain/runner mismatch: 2 of 3 tests failed while running in the VM with \
+            the same usage error:\n\
+            \tUsage: backseater [OPTIONS] <FILE>\n\
+            \tunknown argument --run <test>m\n\
+            the command line of test_a.bs was:\n\
+            \t./backseat_safe_system_2k --run tests/test_a.bsm\n\
+            the Seatbelt compiler or the Backseater probably expects different arguments, e.g. \
+            after an upgrade; check the toolchain paths and versions, or pass --no-heuristics to \
+            report the failures of all tests\n"
+        );
+    }
+
+    #[test]
+    fn compiler_usage_errors_are_detected() {
+        let results = [failed_compiling(
+            "test_a.bs",
+            "error: unrecognized option '-O'\n",
+        )];
+        let mismatch = detect(&results, 0.5).unwrap();
+        assert_eq!(mismatch.stage, "compiling");
+        assert_eq!(mismatch.stderr, "error: unrecognized option '-O'");
+        assert_eq!(mismatch.command_line(), "./Seatbelt tests/test_a.bs");
+    }
+
+    #[test]
+    fn mismatches_need_more_than_the_threshold_of_the_run_tests() {
+        let results = [
+            failed_in_vm("test_a.bs", USAGE),
+            failed_in_vm("test_b.bs", USAGE),
+            result("test_c.bs", TestResultKind::Success, TestLog::default()),
+            result("test_d.bs", TestResultKind::Success, TestLog::default()),
+        ];
+        assert!(detect(&results, 0.5).is_none());
+        assert_eq!(detect(&results, 0.49).unwrap().failed, 2);
+        // skipped tests were not run
+        let results = [
+            failed_in_vm("test_a.bs", USAGE),
+            failed_in_vm("test_b.bs", USAGE),
+            result("test_c.bs", TestResultKind::Success, TestLog::default()),
+            result(
+                "test_d.bs",
+                TestResultKind::Skipped(String::new()),
+                TestLog::default(),
+            ),
+        ];
+        let mismatch = detect(&results, 0.5).unwrap();
+        assert_eq!((mismatch.failed, mismatch.total), (2, 3));
+    }
+
+    #[test]
+    fn only_usage_errors_in_the_first_line_count() {
+        let results = [
+            failed_in_vm(
+                "test_a.bs",
+                "error: division by zero\nusage of a dangling pointer\n",
+            ),
+            failed_in_vm("test_b.bs", "panic\n"),
+            result("test_c.bs", TestResultKind::Success, TestLog::default()),
+        ];
+        assert!(detect(&results, 0.0).is_none());
+        assert!(detect(&[], 0.0).is_none());
+    }
+
+    #[test]
+    fn the_first_of_the_most_common_errors_is_reported() {
+        let results = [
+            failed_compiling("test_a.bs", "usage: seatbelt <FILE>"),
+            failed_in_vm("test_b.bs", USAGE),
+            failed_compiling("test_c.bs", "usage: seatbelt <FILE>"),
+            failed_in_vm("test_d.bs", USAGE),
+        ];
+        let mismatch = detect(&results, 0.25).unwrap();
+        assert_eq!(mismatch.stage, "compiling");
+        assert_eq!(mismatch.example.filename, "test_a.bs");
+        assert_eq!((mismatch.failed, mismatch.total), (2, 4));
+    }
+}
@@ -0,0 +1,174 @@
+//! The names tests are reported with in the machine-readable reports, see `--name-format`. The
+//! console output always uses the path of the test.
+
+/// A template of the report name of a test containing placeholders like `{stem}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct NameFormat(String);
+
+/// The placeholders a template may contain.
+const PLACEHOLDERS: [&str; 5] = ["{path}", "{dir}", "{file}", "{stem}", "{config}"];
+
+/// A part of a template: literal text, or a placeholder including its braces.
+#[derive(Debug, PartialEq)]
+enum Piece<'a> {
+    Text(&'a str),
+    Placeholder(&'a str),
+}
+
+/// Splits a template into its pieces. `{{` and `}}` stand for literal braces.
+fn pieces(template: &str) -> Result<Vec<Piece<'_>>, String> {
+    let mut pieces = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find(['{', '}']) {
+        pieces.push(Piece::Text(&rest[..start]));
+        let brace = &rest[start..];
+        if brace.starts_with("{{") || brace.starts_with("}}") {
+            pieces.push(Piece::Text(&brace[..1]));
+            rest = &brace[2..];
+            continue;
+        }
+        if brace.starts_with('}') {
+            return Err(format!(
+                "unmatched }} in {template:?}, a literal one is written as }}}}"
+            ));
+        }
+        let end = brace
+            .find('}')
+            .ok_or_else(|| format!("unclosed placeholder in {template:?}"))?;
+        let placeholder = &brace[..=end];
+        if !PLACEHOLDERS.contains(&placeholder) {
+            return Err(format!(
+                "unknown placeholder {placeholder}, expected one of {}",
+                PLACEHOLDERS.join(", ")
+            ));
+        }
+        pieces.push(Piece::Placeholder(placeholder));
+        rest = &brace[end + 1..];
+    }
+    pieces.push(Piece::Text(rest));
+    Ok(pieces)
+}
+
+impl NameFormat {
+    /// Parses a template, which has to contain at least one placeholder and no unknown ones.
+    pub(crate) fn parse(template: &str) -> Result<Self, String> {
+        let has_placeholder = pieces(template)?
+            .iter()
+            .any(|piece| matches!(piece, Piece::Placeholder(_)));
+        match has_placeholder {
+            true => Ok(Self(template.to_string())),
+            false => Err(format!("{template:?} contains no placeholder")),
+        }
+    }
+
+    pub(crate) fn template(&self) -> &str {
+        &self.0
+    }
+
+    /// The report name of the test with the given name that is run with the given configuration.
+    /// The name of the configuration is appended in brackets if the template does not contain
+    /// `{config}`, so the runs of a test with different configurations are told apart. Braces
+    /// in the name of the test are kept as they are.
+    pub(crate) fn render(&self, name: &str, configuration: Option<&str>) -> String {
+        let (dir, file) = name.rsplit_once('/').unwrap_or(("", name));
+        let stem = file.strip_suffix(".bs").unwrap_or(file);
+        let pieces = pieces(&self.0).expect("the template was parsed");
+        let mut rendered = String::new();
+        let mut after_empty_dir = false;
+        for piece in &pieces {
+            match *piece {
+                // tests at the top level have no directories, so `{dir}.{stem}` becomes `{stem}`
+                Piece::Text(text) if after_empty_dir => {
+                    rendered += text.strip_prefix('.').unwrap_or(text)
+                }
+                Piece::Text(text) => rendered += text,
+                Piece::Placeholder("{path}") => rendered += name,
+                Piece::Placeholder("{dir}") => rendered += &dir.replace('/', "."),
+                Piece::Placeholder("{file}") => rendered += file,
+                Piece::Placeholder("{stem}") => rendered += stem,
+                // `{config}`, since `pieces` rejects unknown placeholders
+                Piece::Placeholder(_) => rendered += configuration.unwrap_or_default(),
+            }
+            after_empty_dir = dir.is_empty() && *piece == Piece::Placeholder("{dir}");
+        }
+        let has_config = pieces.contains(&Piece::Placeholder("{config}"));
+        if let Some(configuration) = configuration.filter(|_| !has_config) {
+            rendered += &format!(" [{configuration}]");
+        }
+        rendered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NameFormat;
+
+    fn render(template: &str, name: &str, configuration: Option<&str>) -> String {
+        NameFormat::parse(template)
+            .unwrap()
+            .render(name, configuration)
+    }
+
+    #[test]
+    fn unknown_placeholders_are_rejected() {
+        let error = NameFormat::parse("{dir}/{name}").unwrap_err();
+        assert!(error.starts_with("unknown placeholder {name}"), "{error}");
+        assert!(NameFormat::parse("{stem")
+            .unwrap_err()
+            .starts_with("unclosed placeholder"));
+        assert!(NameFormat::parse("test")
+            .unwrap_err()
+            .ends_with("contains no placeholder"));
+    }
+
+    #[test]
+    fn doubled_braces_are_literal() {
+        assert_eq!(render("{{{stem}}}", "vm/test_a.bs", None), "{test_a}");
+        assert!(NameFormat::parse("{{stem}}")
+            .unwrap_err()
+            .ends_with("contains no placeholder"));
+        assert!(NameFormat::parse("{stem}}")
+            .unwrap_err()
+            .starts_with("unmatched }"));
+    }
+
+    #[test]
+    fn braces_in_test_names_are_not_expanded() {
+        assert_eq!(
+            render("{path}", "vm/test_{stem}.bs", None),
+            "vm/test_{stem}.bs"
+        );
+    }
+
+    #[test]
+    fn stem_and_path_name_different_parts() {
+        let name = "vm/stack/test_overflow.bs";
+        assert_eq!(render("{path}", name, None), name);
+        assert_eq!(render("{stem}", name, None), "test_overflow");
+        assert_eq!(render("{file}", name, None), "test_overflow.bs");
+        assert_eq!(render("{dir}.{stem}", name, None), "vm.stack.test_overflow");
+    }
+
+    #[test]
+    fn the_dot_after_an_empty_dir_is_dropped() {
+        assert_eq!(render("{dir}.{stem}", "test_a.bs", None), "test_a");
+        assert_eq!(render("{stem}.{dir}", "test_a.bs", None), "test_a.");
+    }
+
+    #[test]
+    fn the_configuration_is_appended_unless_the_template_contains_it() {
+        assert_eq!(
+            render("{stem}", "vm/test_a.bs", Some("fast")),
+            "test_a [fast]"
+        );
+        assert_eq!(
+            render("{config}/{stem}", "vm/test_a.bs", Some("fast")),
+            "fast/test_a"
+        );
+        assert_eq!(
+            render("{{config}}/{stem}", "vm/test_a.bs", Some("fast")),
+            "{config}/test_a [fast]"
+        );
+        assert_eq!(render("{config}/{stem}", "vm/test_a.bs", None), "/test_a");
+    }
+}
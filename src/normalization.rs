@@ -0,0 +1,190 @@
+//! The normalizations applied to the output of a test and to the expected one before they are
+//! compared, e.g. because the VM pads lines with invisible trailing spaces. They are enabled via
+//! `--trim-trailing-whitespace`, `--normalize-line-endings` and `--normalize-final-newline`, or
+//! for a single test via the `normalize` directive.
+
+use std::fmt;
+
+use serde::Serialize;
+
+/// The names of the normalizations in the `normalize` directive and in failure messages.
+const TRAILING_WHITESPACE: &str = "trailing-ws";
+const LINE_ENDINGS: &str = "eol";
+const FINAL_NEWLINE: &str = "final-newline";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub(crate) struct Normalization {
+    /// Removes spaces and tabs at the end of every line.
+    pub(crate) trailing_whitespace: bool,
+    /// Converts CRLF line endings to LF.
+    pub(crate) line_endings: bool,
+    /// Ends non-empty output with exactly one newline, so a missing or an additional newline at
+    /// the end does not matter.
+    pub(crate) final_newline: bool,
+}
+
+impl Normalization {
+    /// Parses the comma-separated names of the normalizations of a `normalize` directive.
+    pub(crate) fn parse(names: &str) -> Result<Self, String> {
+        let mut normalization = Self::default();
+        for name in names.split(',').map(str::trim) {
+            match name {
+                TRAILING_WHITESPACE => normalization.trailing_whitespace = true,
+                LINE_ENDINGS => normalization.line_endings = true,
+                FINAL_NEWLINE => normalization.final_newline = true,
+                _ => {
+                    return Err(format!(
+                        "unknown normalization `{name}`, expected `{TRAILING_WHITESPACE}`, `{LINE_ENDINGS}` or `{FINAL_NEWLINE}`"
+                    ))
+                }
+            }
+        }
+        Ok(normalization)
+    }
+
+    /// The normalizations enabled in either of both.
+    pub(crate) fn union(self, other: Self) -> Self {
+        Self {
+            trailing_whitespace: self.trailing_whitespace || other.trailing_whitespace,
+            line_endings: self.line_endings || other.line_endings,
+            final_newline: self.final_newline || other.final_newline,
+        }
+    }
+
+    pub(crate) fn is_empty(self) -> bool {
+        self == Self::default()
+    }
+
+    /// Applies the enabled normalizations to `output`, line endings first.
+    pub(crate) fn apply(self, output: &[u8]) -> Vec<u8> {
+        let mut output = output.to_vec();
+        if self.line_endings {
+            let mut converted = Vec::with_capacity(output.len());
+            let mut bytes = output.iter().peekable();
+            while let Some(&byte) = bytes.next() {
+                if byte != b'\r' || bytes.peek() != Some(&&b'\n') {
+                    converted.push(byte);
+                }
+            }
+            output = converted;
+        }
+        if self.trailing_whitespace {
+            let lines: Vec<&[u8]> = output
+                .split(|byte| *byte == b'\n')
+                .map(|line| {
+                    let length = line.len()
+                        - line
+                            .iter()
+                            .rev()
+                            .take_while(|byte| matches!(byte, b' ' | b'\t'))
+                            .count();
+                    &line[..length]
+                })
+                .collect();
+            output = lines.join(&b'\n');
+        }
+        if self.final_newline {
+            while output.last() == Some(&b'\n') {
+                output.pop();
+            }
+            if !output.is_empty() {
+                output.push(b'\n');
+            }
+        }
+        output
+    }
+
+    /// The note on the normalizations that is added to a failure message, empty if there are none.
+    pub(crate) fn note(self) -> String {
+        match self.is_empty() {
+            true => String::new(),
+            false => format!("\tcompared after normalizing: {self}\n"),
+        }
+    }
+}
+
+impl fmt::Display for Normalization {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<_> = [
+            (self.trailing_whitespace, TRAILING_WHITESPACE),
+            (self.line_endings, LINE_ENDINGS),
+            (self.final_newline, FINAL_NEWLINE),
+        ]
+        .into_iter()
+        .filter_map(|(enabled, name)| enabled.then_some(name))
+        .collect();
+        write!(f, "{}", names.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalize(names: &str, output: &str) -> String {
+        let normalization = Normalization::parse(names).unwrap();
+        String::from_utf8(normalization.apply(output.as_bytes())).unwrap()
+    }
+
+    #[test]
+    fn crlf_line_endings_become_lf() {
+        assert_eq!(normalize("eol", "a\r\nb\r\n"), "a\nb\n");
+        // carriage returns that do not end a line are kept
+        assert_eq!(normalize("eol", "a\rb\r\r\n\r"), "a\rb\r\n\r");
+        assert_eq!(normalize("trailing-ws", "a\r\n"), "a\r\n");
+    }
+
+    #[test]
+    fn trailing_whitespace_is_removed_from_every_line() {
+        assert_eq!(
+            normalize("trailing-ws", "a \t\n b  \n\t\n c"),
+            "a\n b\n\n c"
+        );
+        assert_eq!(normalize("trailing-ws", "a\u{a0}\n"), "a\u{a0}\n");
+    }
+
+    #[test]
+    fn line_endings_are_normalized_before_trailing_whitespace() {
+        assert_eq!(normalize("trailing-ws,eol", "a \r\nb\t\r\n"), "a\nb\n");
+    }
+
+    #[test]
+    fn non_empty_output_ends_with_exactly_one_newline() {
+        assert_eq!(normalize("final-newline", "a"), "a\n");
+        assert_eq!(normalize("final-newline", "a\n\n\n"), "a\n");
+        assert_eq!(normalize("final-newline", "\n\n"), "");
+        assert_eq!(normalize("final-newline", ""), "");
+        assert_eq!(normalize("final-newline,trailing-ws", "a\n \n"), "a\n");
+    }
+
+    #[test]
+    fn nothing_is_normalized_by_default() {
+        let output = b"a \r\n\n";
+        assert_eq!(Normalization::default().apply(output), output);
+        assert_eq!(Normalization::default().note(), "");
+    }
+
+    #[test]
+    fn names_are_parsed_and_displayed() {
+        let normalization = Normalization::parse(" final-newline , trailing-ws").unwrap();
+        assert_eq!(normalization.to_string(), "trailing-ws, final-newline");
+        assert_eq!(
+            normalization.note(),
+            "\tcompared after normalizing: trailing-ws, final-newline\n"
+        );
+        assert_eq!(
+            Normalization::parse("eol,whitespace"),
+            Err("unknown normalization `whitespace`, expected `trailing-ws`, `eol` or `final-newline`".to_string())
+        );
+        assert!(Normalization::parse("").is_err());
+    }
+
+    #[test]
+    fn unions_enable_the_normalizations_of_both() {
+        let union = Normalization::parse("eol")
+            .unwrap()
+            .union(Normalization::parse("trailing-ws").unwrap());
+        assert_eq!(union, Normalization::parse("trailing-ws,eol").unwrap());
+        assert!(!union.is_empty());
+    }
+}
@@ -0,0 +1,64 @@
+//! Signalling the end of a run to a user who is not watching the terminal, see `--bell` and
+//! `--notify`. Neither ever fails the run.
+
+use std::io::{stderr, IsTerminal, Write};
+
+use crate::summary::Summary;
+
+/// Rings the terminal bell if stderr is a terminal.
+pub(crate) fn bell() {
+    let mut stderr = stderr();
+    if stderr.is_terminal() {
+        stderr.write_all(b"\x07").and_then(|()| stderr.flush()).ok();
+    }
+}
+
+/// Sends a desktop notification with the summary of the run. Failures to deliver it are logged.
+#[cfg(feature = "notify")]
+pub(crate) fn notify(summary: &Summary) {
+    let (summary_line, icon) = match summary.succeeded() {
+        true => ("Tests succeeded", "dialog-information"),
+        false => ("Tests failed", "dialog-error"),
+    };
+    let mut notification = notify_rust::Notification::new();
+    notification
+        .appname("test-runner")
+        .summary(summary_line)
+        .body(&body(summary))
+        .icon(icon);
+    #[cfg(all(unix, not(target_os = "macos")))]
+    notification.urgency(match summary.succeeded() {
+        true => notify_rust::Urgency::Low,
+        false => notify_rust::Urgency::Critical,
+    });
+    if let Err(error) = notification.show() {
+        log::warn!("unable to send the desktop notification: {error}");
+    }
+}
+
+/// Without the `notify` feature there is no way to send desktop notifications.
+#[cfg(not(feature = "notify"))]
+pub(crate) fn notify(_summary: &Summary) {
+    log::warn!("not sending a desktop notification, the test runner was built without the `notify` feature");
+}
+
+/// The counts of the summary as a single line of plain text.
+#[cfg(feature = "notify")]
+fn body(summary: &Summary) -> String {
+    let mut body = format!(
+        "run: {}, successful: {}, failed: {}",
+        summary.run,
+        summary.successful(),
+        summary.failed
+    );
+    if summary.skipped > 0 {
+        body += &format!(", skipped: {}", summary.skipped);
+    }
+    if summary.not_run > 0 {
+        body += &format!(", not run: {}", summary.not_run);
+    }
+    if summary.partial {
+        body += " (stopped early)";
+    }
+    body
+}
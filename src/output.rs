@@ -0,0 +1,725 @@
+use std::borrow::Cow;
+use std::io::{stderr, stdout, Write};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crossterm::style::{Color, Print, ResetColor, SetForegroundColor};
+use crossterm::{execute, queue};
+
+use crate::events::{EventHandler, RunEvent};
+use crate::failure_groups::FailureGroup;
+use crate::status_board::StatusBoard;
+use crate::test_log::Stage;
+use crate::text;
+use crate::timings::{self, Phase};
+use crate::warnings;
+use crate::{TestResult, TestResultKind};
+
+/// The markers that prefix the result line of every test.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum Symbols {
+    /// `[PASS]`, `[FAIL]` and `[SKIP]`
+    Ascii,
+    /// `✓`, `✗` and `-`
+    Unicode,
+}
+
+impl Symbols {
+    fn success(self) -> &'static str {
+        match self {
+            Symbols::Ascii => "[PASS]",
+            Symbols::Unicode => "✓",
+        }
+    }
+
+    fn failure(self) -> &'static str {
+        match self {
+            Symbols::Ascii => "[FAIL]",
+            Symbols::Unicode => "✗",
+        }
+    }
+
+    fn skipped(self) -> &'static str {
+        match self {
+            Symbols::Ascii => "[SKIP]",
+            Symbols::Unicode => "-",
+        }
+    }
+}
+
+/// The number of tests listed below a failure message of several tests, the others are counted.
+const LISTED_GROUP_TESTS: usize = 5;
+
+/// The minimum width test names are truncated to, no matter how narrow the terminal is.
+const MIN_NAME_WIDTH: usize = 20;
+
+/// Whether the output is colored, which it is unless the `NO_COLOR` environment variable is set
+/// to a non-empty value.
+pub(crate) fn use_color() -> bool {
+    std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+}
+
+/// How long result lines are collected after the first one arrives before they are written
+/// together, so suites of many fast tests do not write to the terminal once per test.
+const BATCH_WINDOW: Duration = Duration::from_millis(20);
+
+/// Prints the result lines of the tests. The test names are padded to a common width so the
+/// status of all tests is printed in the same column.
+pub(crate) struct Printer {
+    symbols: Symbols,
+    /// The width the test names are padded to.
+    name_width: usize,
+    /// The width of the terminal the result lines are fitted into by truncating the test names.
+    terminal_width: Option<usize>,
+    to_stderr: bool,
+    /// Whether the markers, statuses and diff lines are colored.
+    color: bool,
+    /// The number of lines kept at the start and at the end of failure messages, or `None` if
+    /// they are printed in full.
+    error_context: Option<usize>,
+    /// The thread writing the batched result lines, `None` if they are written right away.
+    batch: Mutex<Option<Batch>>,
+    /// The status lines the result lines are written above, see `--no-status`.
+    status: Option<Arc<StatusBoard>>,
+    /// Writes the rendered result lines, in batches or right away.
+    sink: Sink,
+}
+
+/// Writes rendered result lines to the terminal.
+type Sink = Arc<dyn Fn(&[u8]) + Send + Sync>;
+
+/// The rendered result lines sent to the thread that writes them in batches.
+struct Batch {
+    sender: Sender<Vec<u8>>,
+    writer: JoinHandle<()>,
+}
+
+impl Batch {
+    /// Starts the thread writing the batches to the sink.
+    fn start(sink: Sink) -> Self {
+        let (sender, receiver) = mpsc::channel::<Vec<u8>>();
+        let writer = std::thread::spawn(move || {
+            while let Ok(mut batch) = receiver.recv() {
+                let deadline = Instant::now() + BATCH_WINDOW;
+                while let Ok(lines) =
+                    receiver.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                {
+                    batch.extend(lines);
+                }
+                timings::measure(Phase::Printing, || sink(&batch));
+            }
+        });
+        Self { sender, writer }
+    }
+}
+
+/// Writes rendered result lines to stdout, or to stderr if `to_stderr` is set, above the status
+/// lines if there are any.
+fn write_lines(to_stderr: bool, status: Option<&StatusBoard>, lines: &[u8]) {
+    let written = match (status, to_stderr) {
+        (Some(status), _) => {
+            status.write_above(lines);
+            Ok(())
+        }
+        (None, true) => write_flushed(&mut stderr().lock(), lines),
+        (None, false) => write_flushed(&mut stdout().lock(), lines),
+    };
+    written.expect("unable to print output");
+}
+
+fn write_flushed(out: &mut impl Write, bytes: &[u8]) -> std::io::Result<()> {
+    out.write_all(bytes)?;
+    out.flush()
+}
+
+impl Printer {
+    /// Creates a printer for test names of at most `longest_name` characters. If
+    /// `terminal_width` is given, longer names are truncated so the result lines fit into the
+    /// terminal. The result lines are written to stdout, or to stderr if `to_stderr` is set. They
+    /// are only colored if [`use_color`] allows it. Failure
+    /// messages are truncated to `error_context` lines at their start and end if it is given. If
+    /// `batched` is set, the result lines are written in batches until [`Printer::flush`]. They are
+    /// written above the `status` lines until then as well.
+    pub(crate) fn new(
+        symbols: Symbols,
+        longest_name: usize,
+        terminal_width: Option<usize>,
+        to_stderr: bool,
+        error_context: Option<usize>,
+        batched: bool,
+        status: Option<Arc<StatusBoard>>,
+    ) -> Self {
+        let sink: Sink = {
+            let status = status.clone();
+            Arc::new(move |lines| write_lines(to_stderr, status.as_deref(), lines))
+        };
+        Self {
+            symbols,
+            name_width: longest_name,
+            terminal_width,
+            to_stderr,
+            color: use_color(),
+            error_context,
+            batch: Mutex::new(batched.then(|| Batch::start(Arc::clone(&sink)))),
+            status,
+            sink,
+        }
+    }
+
+    /// Writes the result lines that are still batched and removes the status lines. All further
+    /// output is written right away.
+    pub(crate) fn flush(&self) {
+        let batch = self
+            .batch
+            .lock()
+            .expect("the batch is never poisoned")
+            .take();
+        if let Some(Batch { sender, writer }) = batch {
+            drop(sender);
+            writer.join().expect("the batch writer never panics");
+        }
+        if let Some(status) = &self.status {
+            status.stop();
+        }
+    }
+
+    /// Prints the result line of a successful test, with the seed passed to the VM and the stage
+    /// the test aborted in if they are given.
+    pub(crate) fn print_success(
+        &self,
+        filename: &str,
+        seed: Option<u64>,
+        aborted_stage: Option<Stage>,
+        program_size: Option<usize>,
+    ) {
+        let stage_note = match aborted_stage {
+            Some(stage) => format!(" (aborted in the {stage} stage)"),
+            None => String::new(),
+        };
+        let size_note = match program_size {
+            Some(size) => format!(" (program: {size} bytes)"),
+            None => String::new(),
+        };
+        self.print_result(
+            filename,
+            self.symbols.success(),
+            Color::DarkGreen,
+            "OK",
+            &format!("{}{stage_note}{size_note}\n", seed_note(seed)),
+        );
+    }
+
+    /// Prints the result line of a failed test with the given status, e.g. `FAILED`, followed by
+    /// the error message.
+    pub(crate) fn print_fail(
+        &self,
+        filename: &str,
+        status: &str,
+        seed: Option<u64>,
+        error_message: &str,
+    ) {
+        self.print_result(
+            filename,
+            self.symbols.failure(),
+            Color::DarkRed,
+            status,
+            &format!(
+                "{}\n{}",
+                seed_note(seed),
+                self.truncate_message(error_message)
+            ),
+        );
+    }
+
+    /// Prints the failure of a quarantined test, which does not fail the run.
+    pub(crate) fn print_quarantined(&self, filename: &str, seed: Option<u64>, error_message: &str) {
+        self.print_result(
+            filename,
+            self.symbols.failure(),
+            Color::DarkYellow,
+            "FAILED (quarantined)",
+            &format!(
+                "{}\n{}",
+                seed_note(seed),
+                self.truncate_message(error_message)
+            ),
+        );
+    }
+
+    pub(crate) fn print_skipped(&self, filename: &str, reason: &str) {
+        let reason = if reason.is_empty() {
+            String::from("\n")
+        } else {
+            format!(" ({reason})\n")
+        };
+        self.print_result(
+            filename,
+            self.symbols.skipped(),
+            Color::DarkYellow,
+            "SKIPPED",
+            &reason,
+        );
+    }
+
+    /// Prints the failure message of every group once, followed by the tests that failed with it.
+    pub(crate) fn print_failure_groups(&self, groups: &[FailureGroup]) {
+        let mut details = String::from("\nFailures:\n");
+        for group in groups {
+            let tests = match group.filenames.len() {
+                1 => "1 test".to_string(),
+                count => format!("{count} tests"),
+            };
+            details += &format!("\n---- {} in {tests} ----\n", group.status);
+            details += &self.truncate_message(group.message);
+            if !details.ends_with('\n') {
+                details.push('\n');
+            }
+            for filename in group.filenames.iter().take(LISTED_GROUP_TESTS) {
+                details += &format!("\t\t{filename}\n");
+            }
+            if group.filenames.len() > LISTED_GROUP_TESTS {
+                details += &format!(
+                    "\t\t... and {} more\n",
+                    group.filenames.len() - LISTED_GROUP_TESTS
+                );
+            }
+        }
+        details.push('\n');
+        let result = match self.to_stderr {
+            true => write_details(&mut stderr().lock(), self.color, &details),
+            false => write_details(&mut stdout().lock(), self.color, &details),
+        };
+        result.expect("unable to print output");
+    }
+
+    /// Elides the middle of a long failure message, see `--error-context`.
+    fn truncate_message<'a>(&self, error_message: &'a str) -> Cow<'a, str> {
+        match self.error_context {
+            Some(context) => text::elide_middle_lines(error_message, context),
+            None => Cow::Borrowed(error_message),
+        }
+    }
+
+    /// Prints the result line of a test. `details` are printed right after the status and have
+    /// to end with a line break.
+    fn print_result(
+        &self,
+        filename: &str,
+        symbol: &str,
+        color: Color,
+        status: &str,
+        details: &str,
+    ) {
+        let width = self.name_width(symbol, status, details);
+        let filename = match self.terminal_width {
+            Some(_) => truncate_middle(filename, width),
+            None => filename.to_string(),
+        };
+        let mut lines = Vec::new();
+        write_result(
+            &mut lines,
+            symbol,
+            self.color.then_some(color),
+            &filename,
+            width,
+            status,
+            details,
+        )
+        .expect("writing to a buffer never fails");
+        let batch = self.batch.lock().expect("the batch is never poisoned");
+        match &*batch {
+            // lost only if the writer panicked, which aborts the run anyway
+            Some(batch) => {
+                batch.sender.send(lines).ok();
+            }
+            None => (self.sink)(&lines),
+        }
+    }
+
+    /// The width the test name of a result line is padded or truncated to. If the line does not
+    /// fit into the terminal, the name is shortened by the width of everything else that is
+    /// printed on the line: the symbol, the status and the first line of the details.
+    fn name_width(&self, symbol: &str, status: &str, details: &str) -> usize {
+        let Some(terminal_width) = self.terminal_width else {
+            return self.name_width;
+        };
+        let fixed_width = symbol.chars().count()
+            + " test ".len()
+            + " ... ".len()
+            + status.chars().count()
+            + details.lines().next().unwrap_or_default().chars().count();
+        let available_width = terminal_width
+            .saturating_sub(fixed_width)
+            .max(MIN_NAME_WIDTH);
+        self.name_width.min(available_width)
+    }
+}
+
+/// The note on the seed passed to the VM after the status of a test, empty if there is none.
+fn seed_note(seed: Option<u64>) -> String {
+    match seed {
+        Some(seed) => format!(" (seed {seed})"),
+        None => String::new(),
+    }
+}
+
+/// Prints the result line of every finished test and the summary at the end of the run, and keeps
+/// the status lines up to date.
+pub(crate) struct ConsoleReporter<'a> {
+    printer: &'a Printer,
+    status: Option<Arc<StatusBoard>>,
+    /// Whether the stage successful tests aborted in is printed, see `--report-stage`.
+    report_stage: bool,
+    /// Whether the expected outcome and the program size are printed, see `--verbose`.
+    verbose: bool,
+    /// Whether failure messages are printed with every failed test instead of once per group
+    /// after the run, see `--no-dedup`.
+    no_dedup: bool,
+}
+
+impl<'a> ConsoleReporter<'a> {
+    pub(crate) fn new(
+        printer: &'a Printer,
+        report_stage: bool,
+        verbose: bool,
+        no_dedup: bool,
+    ) -> Self {
+        Self {
+            printer,
+            status: printer.status.clone(),
+            report_stage,
+            verbose,
+            no_dedup,
+        }
+    }
+
+    fn print_result(&self, result: &TestResult) {
+        let printer = self.printer;
+        match &result.kind {
+            TestResultKind::Success => {
+                printer.print_success(
+                    &result.filename,
+                    result.log.seed,
+                    result.log.aborted_stage.filter(|_| self.report_stage),
+                    result.log.program_size().filter(|_| self.verbose),
+                );
+            }
+            TestResultKind::Failure(_, error_message)
+            | TestResultKind::UnexpectedSuccess(error_message)
+            | TestResultKind::Crash(error_message) => {
+                let error_message = match self.no_dedup {
+                    true => error_message.as_str(),
+                    // printed once per group after the run
+                    false => "",
+                };
+                let mut expectation = match (&result.log.expected_outcome, self.verbose) {
+                    (Some(expected_outcome), true) => {
+                        format!("\texpected to {expected_outcome}\n")
+                    }
+                    _ => String::new(),
+                };
+                if let Some(size) = result.log.program_size().filter(|_| self.verbose) {
+                    expectation += &format!("\tcompiled program: {size} bytes\n");
+                }
+                let status = match result.log.failed_stage(&result.kind) {
+                    Some(stage) => format!("{} ({stage} stage)", result.kind.status()),
+                    None => result.kind.status(),
+                };
+                printer.print_fail(
+                    &result.filename,
+                    &status,
+                    result.log.seed,
+                    &(expectation + error_message),
+                );
+            }
+            TestResultKind::Skipped(reason) => {
+                printer.print_skipped(&result.filename, reason);
+            }
+            TestResultKind::Quarantined(_, error_message) => {
+                printer.print_quarantined(&result.filename, result.log.seed, error_message);
+            }
+        }
+    }
+}
+
+impl EventHandler for ConsoleReporter<'_> {
+    fn on_event(&mut self, event: &RunEvent) {
+        match event {
+            RunEvent::Discovered { .. } => {}
+            RunEvent::Started {
+                worker,
+                test,
+                configuration,
+            } => {
+                if let Some(status) = &self.status {
+                    status.started(
+                        *worker,
+                        &match configuration {
+                            Some(configuration) => format!("{} [{configuration}]", test.name),
+                            None => test.name.clone(),
+                        },
+                    );
+                }
+            }
+            RunEvent::Finished { worker, result } => {
+                if let Some(status) = &self.status {
+                    status.finished(*worker);
+                }
+                self.print_result(result);
+            }
+            RunEvent::RunFinished { summary } => {
+                match self.printer.to_stderr {
+                    true => summary.render(&mut stderr(), use_color()),
+                    false => summary.render(&mut stdout(), use_color()),
+                }
+                .expect("unable to print output");
+            }
+        }
+    }
+}
+
+/// Writes the result line of a test to `out`, colored with `color` unless it is `None`. Lines of a
+/// diff within the details, i.e. lines starting with `\t-` or `\t+`, and the `^` markers of
+/// highlighted parts are colored as well.
+fn write_result(
+    out: &mut impl Write,
+    symbol: &str,
+    color: Option<Color>,
+    filename: &str,
+    width: usize,
+    status: &str,
+    details: &str,
+) -> std::io::Result<()> {
+    let Some(color) = color else {
+        write!(
+            out,
+            "{symbol} test {filename:<width$} ... {status}{details}"
+        )?;
+        return out.flush();
+    };
+    execute!(
+        out,
+        SetForegroundColor(color),
+        Print(symbol),
+        ResetColor,
+        Print(format!(" test {filename:<width$} ... ")),
+        SetForegroundColor(color),
+        Print(status),
+        ResetColor
+    )?;
+    write_details(out, true, details)
+}
+
+/// Writes the details of a result line to `out`, coloring the lines of a diff if `color` is set.
+fn write_details(out: &mut impl Write, color: bool, details: &str) -> std::io::Result<()> {
+    for line in details.split_inclusive('\n') {
+        let color = if !color {
+            None
+        } else if line.starts_with("\t-") {
+            Some(Color::DarkRed)
+        } else if line.starts_with("\t+") {
+            Some(Color::DarkGreen)
+        } else if line.trim_start().starts_with('^') {
+            Some(Color::DarkYellow)
+        } else {
+            None
+        };
+        match color {
+            Some(color) => queue!(out, SetForegroundColor(color), Print(line), ResetColor)?,
+            None => queue!(out, Print(line))?,
+        }
+    }
+    out.flush()
+}
+
+/// Shortens `name` to at most `width` characters by replacing a part in the middle with an
+/// ellipsis. The end of the name, which contains the file name, is kept. The first path
+/// component is kept as well if there is enough room for it.
+fn truncate_middle(name: &str, width: usize) -> String {
+    let length = name.chars().count();
+    if length <= width {
+        return name.to_string();
+    }
+
+    let is_separator = |c: char| c == '/' || c == '\\';
+    let file_name_length = name
+        .rsplit(is_separator)
+        .next()
+        .map(|file_name| file_name.chars().count())
+        .unwrap_or_default();
+    let head_length = match name.find(is_separator) {
+        Some(index) if name[..=index].chars().count() + 1 + file_name_length < width => {
+            name[..=index].chars().count()
+        }
+        _ => 0,
+    };
+    let tail_length = width.saturating_sub(head_length + 1);
+
+    let head: String = name.chars().take(head_length).collect();
+    let tail: String = name.chars().skip(length - tail_length).collect();
+    format!("{head}…{tail}")
+}
+
+/// Writes the warning to `out`, which is stderr everywhere but in tests, unless the same warning
+/// was printed `--max-repeated-warnings` times.
+pub(crate) fn print_warning(out: &mut impl Write, message: &str) {
+    if !warnings::record(message) {
+        return;
+    }
+    match use_color() {
+        true => execute!(
+            out,
+            SetForegroundColor(Color::DarkYellow),
+            Print("warning: "),
+            ResetColor,
+            Print(message)
+        ),
+        false => execute!(out, Print("warning: "), Print(message)),
+    }
+    .expect("unable to print output");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Renders the result line the printer would print, without colors.
+    fn result_line(printer: &Printer, filename: &str, status: &str, details: &str) -> String {
+        let symbol = printer.symbols.failure();
+        let width = printer.name_width(symbol, status, details);
+        let mut line = Vec::new();
+        write_result(
+            &mut line,
+            symbol,
+            None,
+            &truncate_middle(filename, width),
+            width,
+            status,
+            details,
+        )
+        .unwrap();
+        String::from_utf8(line).unwrap()
+    }
+
+    #[test]
+    fn long_statuses_fit_into_the_terminal() {
+        let filename = "a_long_directory/another_long_directory/test_name.bs";
+        let printer = Printer::new(
+            Symbols::Ascii,
+            filename.len(),
+            Some(100),
+            false,
+            None,
+            false,
+            None,
+        );
+        for status in [
+            "OK",
+            "SKIPPED",
+            "FAILED [E-RUNTIME-UNEXPECTED] (run stage)",
+            "FAILED [E-COMPILE-UNEXPECTED] (compile stage)",
+        ] {
+            let line = result_line(&printer, filename, status, " (seed 42)\n");
+            assert!(line.trim_end().chars().count() <= 100, "{line:?}");
+            assert!(line.contains("/test_name.bs"), "{line:?}");
+        }
+    }
+
+    #[test]
+    fn short_names_are_not_truncated() {
+        let printer = Printer::new(Symbols::Unicode, 12, Some(80), false, None, false, None);
+        let status = "FAILED [E-RUNTIME-UNEXPECTED] (run stage)";
+        let line = result_line(&printer, "test_foo.bs", status, "\n");
+        assert_eq!(line, format!("✗ test test_foo.bs  ... {status}\n"));
+    }
+
+    #[test]
+    fn names_are_only_truncated_to_the_minimum_width() {
+        let filename = "directory/test_with_a_rather_long_name.bs";
+        let printer = Printer::new(
+            Symbols::Ascii,
+            filename.len(),
+            Some(30),
+            false,
+            None,
+            false,
+            None,
+        );
+        let width = printer.name_width("[FAIL]", "FAILED [E-DIRECTIVES]", "\n");
+        assert_eq!(width, MIN_NAME_WIDTH);
+    }
+
+    #[test]
+    fn warnings_are_written_to_the_given_writer() {
+        let mut out = Vec::new();
+        print_warning(&mut out, "the given writer gets this warning\n");
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("warning: "), "{out:?}");
+        assert!(
+            out.ends_with("the given writer gets this warning\n"),
+            "{out:?}"
+        );
+    }
+
+    /// A printer whose result lines are recorded, one entry per write, instead of written to the
+    /// terminal.
+    fn recording_printer(symbols: Symbols, batched: bool) -> (Printer, Arc<Mutex<Vec<Vec<u8>>>>) {
+        let writes = Arc::new(Mutex::new(Vec::new()));
+        let sink: Sink = {
+            let writes = Arc::clone(&writes);
+            Arc::new(move |lines| writes.lock().unwrap().push(lines.to_vec()))
+        };
+        let printer = Printer {
+            symbols,
+            name_width: 12,
+            terminal_width: None,
+            to_stderr: false,
+            color: true,
+            error_context: None,
+            batch: Mutex::new(batched.then(|| Batch::start(Arc::clone(&sink)))),
+            status: None,
+            sink,
+        };
+        (printer, writes)
+    }
+
+    /// Prints the same results with pauses of the given lengths before every result.
+    fn print_results(printer: &Printer, pauses: &[Duration]) {
+        for (index, pause) in pauses.iter().enumerate() {
+            std::thread::sleep(*pause);
+            let filename = format!("test_{index}.bs");
+            match index % 4 {
+                0 => printer.print_success(&filename, Some(7), None, None),
+                1 => printer.print_fail(&filename, "FAILED", None, "\t-expected\n\t+actual\n"),
+                2 => printer.print_skipped(&filename, "not on this platform"),
+                _ => printer.print_quarantined(&filename, None, "flaky\n"),
+            }
+        }
+        printer.flush();
+    }
+
+    #[test]
+    fn batched_results_are_written_byte_for_byte_like_unbatched_ones() {
+        // the fifth result arrives when the window of the first batch closes
+        let mut pauses = vec![Duration::ZERO; 4];
+        pauses.push(BATCH_WINDOW);
+        pauses.extend([Duration::ZERO, BATCH_WINDOW * 2, Duration::ZERO]);
+        for symbols in [Symbols::Ascii, Symbols::Unicode] {
+            let (unbatched, unbatched_writes) = recording_printer(symbols, false);
+            print_results(&unbatched, &pauses);
+            let (batched, batched_writes) = recording_printer(symbols, true);
+            print_results(&batched, &pauses);
+
+            let unbatched_writes = unbatched_writes.lock().unwrap();
+            let batched_writes = batched_writes.lock().unwrap();
+            assert_eq!(unbatched_writes.len(), pauses.len());
+            assert!(batched_writes.len() < unbatched_writes.len());
+            assert_eq!(
+                String::from_utf8(batched_writes.concat()).unwrap(),
+                String::from_utf8(unbatched_writes.concat()).unwrap()
+            );
+        }
+    }
+}
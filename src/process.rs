@@ -0,0 +1,673 @@
+//! Spawning of the toolchain processes. Every running child process is tracked in a registry so
+//! that all of them can be killed when the run is aborted.
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::PathBuf;
+use std::process::{Child, Command, ExitStatus, Output, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use log::debug;
+
+use crate::timings::{self, Phase};
+
+/// The longest pause between two checks whether a child process has exited.
+const MAX_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// How often spawning a process is attempted when the OS is temporarily out of resources.
+pub(crate) const SPAWN_ATTEMPTS: u32 = 5;
+
+/// The pause before the first retry of a spawn, which doubles with every further retry.
+const SPAWN_BACKOFF: Duration = Duration::from_millis(10);
+
+type SharedChild = Arc<Mutex<Child>>;
+
+static REGISTRY: Mutex<Option<HashMap<u64, SharedChild>>> = Mutex::new(None);
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+static ABORTED: AtomicBool = AtomicBool::new(false);
+static SPAWN_RETRIES: AtomicUsize = AtomicUsize::new(0);
+
+/// Aborts the run: no new processes are spawned anymore and all running ones are killed.
+pub(crate) fn abort_run() {
+    ABORTED.store(true, Ordering::SeqCst);
+    kill_all();
+}
+
+/// Allows spawning processes again after an aborted run. Must only be called when no process is
+/// running anymore.
+pub(crate) fn resume_run() {
+    ABORTED.store(false, Ordering::SeqCst);
+}
+
+/// Whether the run has been aborted.
+pub(crate) fn is_aborted() -> bool {
+    ABORTED.load(Ordering::SeqCst)
+}
+
+/// How often spawning a process has been retried so far, see [`spawn`].
+pub(crate) fn spawn_retries() -> usize {
+    SPAWN_RETRIES.load(Ordering::Relaxed)
+}
+
+/// Whether the error is the one returned by [`run`] when the OS was still out of resources after
+/// all attempts to spawn the process.
+pub(crate) fn is_spawn_exhausted(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<std::io::Error>()
+        .is_some_and(|error| error.kind() == std::io::ErrorKind::WouldBlock)
+}
+
+/// Spawns `command`, retrying with an exponential backoff while the OS is temporarily out of
+/// resources (`EAGAIN`), which happens when many processes are started at once.
+fn spawn(command: &mut Command) -> std::io::Result<Child> {
+    let mut backoff = SPAWN_BACKOFF;
+    for _ in 1..SPAWN_ATTEMPTS {
+        match command.spawn() {
+            Err(error) if error.kind() == std::io::ErrorKind::WouldBlock => {
+                debug!("unable to spawn {command:?}, retrying in {backoff:?}: {error}");
+                SPAWN_RETRIES.fetch_add(1, Ordering::Relaxed);
+                std::thread::sleep(backoff);
+                backoff *= 2;
+            }
+            result => return result,
+        }
+    }
+    command.spawn()
+}
+
+/// Resolves the path of a toolchain executable. On Windows, `.exe` is appended if the path does
+/// not exist as given but with the extension, so the defaults like `./Seatbelt` work there too.
+pub(crate) fn resolve_executable(path: PathBuf) -> PathBuf {
+    match cfg!(windows) {
+        true => with_missing_extension(path, ".exe"),
+        false => path,
+    }
+}
+
+/// Appends `extension` to `path` if the path does not exist, but a file with the extension does.
+fn with_missing_extension(path: PathBuf, extension: &str) -> PathBuf {
+    if path.exists() {
+        return path;
+    }
+    let mut extended = path.clone().into_os_string();
+    extended.push(extension);
+    let extended = PathBuf::from(extended);
+    match extended.is_file() {
+        true => extended,
+        false => path,
+    }
+}
+
+/// Kills and reaps all registered child processes.
+fn kill_all() {
+    let children: Vec<SharedChild> = match REGISTRY.lock() {
+        Ok(registry) => registry
+            .iter()
+            .flat_map(|map| map.values())
+            .cloned()
+            .collect(),
+        Err(_) => return,
+    };
+    for child in children {
+        if let Ok(mut child) = child.lock() {
+            if matches!(child.try_wait(), Ok(None)) {
+                debug!("killing process {}", child.id());
+                kill(&mut child);
+                child.wait().ok();
+            }
+        }
+    }
+}
+
+/// Kills a child process together with all processes it has spawned, so none of them keeps the
+/// output pipes open.
+fn kill(child: &mut Child) {
+    #[cfg(unix)]
+    // SAFETY: `kill` has no memory safety preconditions. Every child is the leader of its own
+    // process group, see `run`.
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+    child.kill().ok();
+}
+
+/// Kills the processes an exited child left behind in its process group, e.g. daemons started by
+/// a test, so they neither outlive the test nor keep its output pipes open. The child must not be
+/// reaped yet, so the ID of its process group cannot have been reused.
+#[cfg(unix)]
+fn kill_leftovers(child: &Child) {
+    // SAFETY: `waitid` only writes to the given struct and leaves the child waitable because of
+    // `WNOWAIT`. `kill` has no memory safety preconditions.
+    unsafe {
+        let mut info = std::mem::zeroed::<libc::siginfo_t>();
+        let options = libc::WEXITED | libc::WNOHANG | libc::WNOWAIT;
+        let exited =
+            libc::waitid(libc::P_PID, child.id(), &mut info, options) == 0 && info.si_pid() != 0;
+        if exited {
+            libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn kill_leftovers(_child: &Child) {}
+
+/// Keeps a child process registered while it runs. Dropping the guard kills the process if it is
+/// still running and removes it from the registry.
+struct ChildGuard {
+    id: u64,
+    child: SharedChild,
+}
+
+impl ChildGuard {
+    fn register(child: Child) -> Self {
+        let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+        let child = Arc::new(Mutex::new(child));
+        REGISTRY
+            .lock()
+            .expect("process registry poisoned")
+            .get_or_insert_with(HashMap::new)
+            .insert(id, Arc::clone(&child));
+        let guard = Self { id, child };
+        if is_aborted() {
+            // the run has been aborted while the process was spawned
+            kill(&mut guard.lock());
+        }
+        guard
+    }
+
+    /// Waits for the process to exit. If it is still running after `timeout`, it is killed and
+    /// `None` is returned.
+    fn wait(&self, timeout: Option<Duration>) -> std::io::Result<Option<ExitStatus>> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut interval = Duration::from_micros(50);
+        loop {
+            {
+                let mut child = self.lock();
+                kill_leftovers(&child);
+                if let Some(status) = child.try_wait()? {
+                    return Ok(Some(status));
+                }
+            }
+            if let Some(deadline) = deadline {
+                let now = Instant::now();
+                if now >= deadline {
+                    let mut child = self.lock();
+                    debug!("killing process {} after timeout", child.id());
+                    kill(&mut child);
+                    child.wait()?;
+                    return Ok(None);
+                }
+                interval = interval.min(deadline - now);
+            }
+            std::thread::sleep(interval);
+            interval = (interval * 2).min(MAX_POLL_INTERVAL);
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Child> {
+        self.child.lock().expect("child process poisoned")
+    }
+}
+
+impl Drop for ChildGuard {
+    fn drop(&mut self) {
+        if let Ok(mut registry) = REGISTRY.lock() {
+            if let Some(registry) = registry.as_mut() {
+                registry.remove(&self.id);
+            }
+        }
+        if let Ok(mut child) = self.child.lock() {
+            if matches!(child.try_wait(), Ok(None)) {
+                kill(&mut child);
+                child.wait().ok();
+            }
+        }
+    }
+}
+
+/// The messages, in lowercase, that a failed allocation usually causes on stderr.
+#[cfg(unix)]
+const ALLOCATION_FAILURES: [&str; 4] = [
+    "memory allocation",
+    "out of memory",
+    "bad_alloc",
+    "cannot allocate memory",
+];
+
+/// Resource limits a child process runs with. They are only enforced on Unix.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct ResourceLimits {
+    /// The maximum size of the address space in megabytes.
+    pub(crate) memory_mb: Option<u64>,
+    /// The maximum CPU time in seconds.
+    pub(crate) cpu_seconds: Option<u64>,
+}
+
+impl ResourceLimits {
+    pub(crate) fn is_empty(self) -> bool {
+        self.memory_mb.is_none() && self.cpu_seconds.is_none()
+    }
+
+    /// Makes the process spawned by `command` run with the limits.
+    #[cfg(unix)]
+    pub(crate) fn apply(self, command: &mut Command) {
+        use std::os::unix::process::CommandExt;
+
+        if self.is_empty() {
+            return;
+        }
+        let set_limit = |resource, soft: u64, hard: u64| {
+            let limit = libc::rlimit {
+                rlim_cur: soft as libc::rlim_t,
+                rlim_max: hard as libc::rlim_t,
+            };
+            // SAFETY: `limit` is a valid `rlimit` that outlives the call.
+            match unsafe { libc::setrlimit(resource, &limit) } {
+                0 => Ok(()),
+                _ => Err(std::io::Error::last_os_error()),
+            }
+        };
+        // SAFETY: the hook only calls `setrlimit`, which is async-signal-safe, and allocates
+        // nothing.
+        unsafe {
+            command.pre_exec(move || {
+                if let Some(memory_mb) = self.memory_mb {
+                    let bytes = memory_mb.saturating_mul(1024 * 1024);
+                    set_limit(libc::RLIMIT_AS, bytes, bytes)?;
+                }
+                if let Some(cpu_seconds) = self.cpu_seconds {
+                    // the soft limit sends SIGXCPU, the hard limit one second later SIGKILL
+                    set_limit(libc::RLIMIT_CPU, cpu_seconds, cpu_seconds + 1)?;
+                }
+                Ok(())
+            });
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub(crate) fn apply(self, _command: &mut Command) {}
+
+    /// Describes the limit a failed process has been stopped by, given its exit status and
+    /// stderr. A limit is only blamed with evidence: the CPU time limit if the process was killed
+    /// by `SIGXCPU`, the memory limit if its stderr reports a failed allocation. Other signals,
+    /// including the `SIGKILL` of the hard CPU time limit, are reported as crashes.
+    pub(crate) fn exceeded(self, status: ExitStatus, stderr: &[u8]) -> Option<String> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt;
+
+            if status.success() {
+                return None;
+            }
+            if let Some(cpu_seconds) = self.cpu_seconds {
+                if status.signal() == Some(libc::SIGXCPU) {
+                    return Some(format!("exceeded CPU time limit of {cpu_seconds}s"));
+                }
+            }
+            if let Some(memory_mb) = self.memory_mb {
+                let stderr = String::from_utf8_lossy(stderr).to_lowercase();
+                let allocation_failed = ALLOCATION_FAILURES
+                    .iter()
+                    .any(|message| stderr.contains(message));
+                if allocation_failed {
+                    return Some(format!("exceeded memory limit of {memory_mb} MB"));
+                }
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = (status, stderr);
+        None
+    }
+}
+
+/// Returns the name of the signal a process was killed by, or `None` if it exited regularly.
+/// Always `None` on platforms other than Unix.
+pub(crate) fn crash_signal(status: ExitStatus) -> Option<String> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+
+        let signal = status.signal()?;
+        let name = match signal {
+            libc::SIGSEGV => "SIGSEGV",
+            libc::SIGABRT => "SIGABRT",
+            libc::SIGBUS => "SIGBUS",
+            libc::SIGILL => "SIGILL",
+            libc::SIGFPE => "SIGFPE",
+            libc::SIGKILL => "SIGKILL",
+            libc::SIGTERM => "SIGTERM",
+            libc::SIGINT => "SIGINT",
+            libc::SIGTRAP => "SIGTRAP",
+            libc::SIGXCPU => "SIGXCPU",
+            libc::SIGPIPE => "SIGPIPE",
+            _ => return Some(format!("signal {signal}")),
+        };
+        Some(name.to_string())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = status;
+        None
+    }
+}
+
+/// The captured output of a process that has exited or has been killed after its timeout.
+pub(crate) struct ProcessOutput {
+    pub(crate) output: Output,
+    pub(crate) timed_out: bool,
+    /// Set if the input could not be written to the stdin of the process.
+    pub(crate) stdin_error: Option<StdinError>,
+}
+
+#[derive(Debug)]
+pub(crate) struct StdinError {
+    pub(crate) written: usize,
+    pub(crate) total: usize,
+    pub(crate) error: std::io::Error,
+}
+
+impl std::fmt::Display for StdinError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "wrote only {} of {} bytes: {}",
+            self.written, self.total, self.error
+        )
+    }
+}
+
+/// Receives every line of the stdout of a process while it runs, including the line break.
+pub(crate) type LineSink = Box<dyn FnMut(&[u8]) + Send>;
+
+/// Spawns `command` with piped stdout and stderr, writes `stdin` to its stdin (if given) and
+/// waits for it to exit. The process is killed if it does not exit within `timeout` or if the run
+/// is aborted in the meantime.
+pub(crate) fn run(
+    command: &mut Command,
+    stdin: Option<Vec<u8>>,
+    timeout: Option<Duration>,
+) -> std::io::Result<ProcessOutput> {
+    run_streaming(command, stdin, timeout, None)
+}
+
+/// Like [`run`], but passes every line of stdout to `stdout_sink` as soon as it is read. The whole
+/// stdout is captured nevertheless.
+pub(crate) fn run_streaming(
+    command: &mut Command,
+    stdin: Option<Vec<u8>>,
+    timeout: Option<Duration>,
+    stdout_sink: Option<LineSink>,
+) -> std::io::Result<ProcessOutput> {
+    if is_aborted() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Interrupted,
+            "the run has been aborted",
+        ));
+    }
+
+    command
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(command, 0);
+    let mut child = timings::measure(Phase::Spawning, || spawn(command))?;
+
+    let writer = match (stdin, child.stdin.take()) {
+        (Some(bytes), Some(pipe)) => Some(std::thread::spawn(move || write_input(pipe, &bytes))),
+        _ => None,
+    };
+    let stdout_reader = child.stdout.take().map(|pipe| match stdout_sink {
+        Some(sink) => read_lines_in_background(pipe, sink),
+        None => read_in_background(pipe),
+    });
+    let stderr_reader = child.stderr.take().map(read_in_background);
+
+    let guard = ChildGuard::register(child);
+    let status = guard.wait(timeout)?;
+    let timed_out = status.is_none();
+    let status = match status {
+        Some(status) => status,
+        None => guard.lock().wait()?,
+    };
+    drop(guard);
+
+    let stdin_error = match writer {
+        Some(writer) => writer.join().expect("input writer panicked").err(),
+        None => None,
+    };
+    Ok(ProcessOutput {
+        output: Output {
+            status,
+            stdout: join_reader(stdout_reader)?,
+            stderr: join_reader(stderr_reader)?,
+        },
+        timed_out,
+        stdin_error,
+    })
+}
+
+/// Writes `bytes` to the stdin of a process. A broken pipe is not an error since the process may
+/// exit without reading all of its input.
+fn write_input(mut pipe: impl Write, bytes: &[u8]) -> Result<(), StdinError> {
+    let mut written = 0;
+    while written < bytes.len() {
+        match pipe.write(&bytes[written..]) {
+            Ok(0) => {
+                return Err(StdinError {
+                    written,
+                    total: bytes.len(),
+                    error: std::io::ErrorKind::WriteZero.into(),
+                })
+            }
+            Ok(count) => written += count,
+            Err(error) if error.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(error) if error.kind() == std::io::ErrorKind::BrokenPipe => return Ok(()),
+            Err(error) => {
+                return Err(StdinError {
+                    written,
+                    total: bytes.len(),
+                    error,
+                })
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_in_background<R: Read + Send + 'static>(
+    mut pipe: R,
+) -> JoinHandle<std::io::Result<Vec<u8>>> {
+    std::thread::spawn(move || {
+        let mut buffer = Vec::new();
+        pipe.read_to_end(&mut buffer)?;
+        Ok(buffer)
+    })
+}
+
+fn read_lines_in_background<R: Read + Send + 'static>(
+    pipe: R,
+    mut sink: LineSink,
+) -> JoinHandle<std::io::Result<Vec<u8>>> {
+    std::thread::spawn(move || {
+        let mut reader = BufReader::new(pipe);
+        let mut buffer = Vec::new();
+        loop {
+            let start = buffer.len();
+            if reader.read_until(b'\n', &mut buffer)? == 0 {
+                return Ok(buffer);
+            }
+            sink(&buffer[start..]);
+        }
+    })
+}
+
+fn join_reader(reader: Option<JoinHandle<std::io::Result<Vec<u8>>>>) -> std::io::Result<Vec<u8>> {
+    match reader {
+        Some(reader) => reader.join().expect("output reader panicked"),
+        None => Ok(Vec::new()),
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::process::ExitStatusExt;
+
+    use super::*;
+
+    const BOTH_LIMITS: ResourceLimits = ResourceLimits {
+        memory_mb: Some(64),
+        cpu_seconds: Some(2),
+    };
+
+    fn killed_by(signal: libc::c_int) -> ExitStatus {
+        ExitStatus::from_raw(signal)
+    }
+
+    fn exited_with(code: i32) -> ExitStatus {
+        ExitStatus::from_raw(code << 8)
+    }
+
+    #[test]
+    fn sigxcpu_exceeds_the_cpu_time_limit() {
+        assert_eq!(
+            BOTH_LIMITS.exceeded(killed_by(libc::SIGXCPU), b""),
+            Some("exceeded CPU time limit of 2s".to_string())
+        );
+    }
+
+    #[test]
+    fn sigkill_is_not_blamed_on_the_cpu_time_limit() {
+        assert_eq!(BOTH_LIMITS.exceeded(killed_by(libc::SIGKILL), b""), None);
+    }
+
+    #[test]
+    fn crashes_are_not_blamed_on_the_memory_limit() {
+        for signal in [libc::SIGSEGV, libc::SIGABRT] {
+            assert_eq!(BOTH_LIMITS.exceeded(killed_by(signal), b"boom\n"), None);
+        }
+    }
+
+    #[test]
+    fn allocation_failures_exceed_the_memory_limit() {
+        let stderr = b"memory allocation of 1048576 bytes failed\n";
+        for status in [killed_by(libc::SIGABRT), exited_with(1)] {
+            assert_eq!(
+                BOTH_LIMITS.exceeded(status, stderr),
+                Some("exceeded memory limit of 64 MB".to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn allocation_failures_need_a_memory_limit() {
+        let limits = ResourceLimits {
+            memory_mb: None,
+            cpu_seconds: Some(2),
+        };
+        assert_eq!(limits.exceeded(exited_with(1), b"Out of memory\n"), None);
+    }
+
+    #[test]
+    fn successful_processes_exceed_no_limit() {
+        assert_eq!(
+            BOTH_LIMITS.exceeded(exited_with(0), b"out of memory\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn signals_of_unlimited_processes_exceed_no_limit() {
+        let limits = ResourceLimits::default();
+        assert_eq!(limits.exceeded(killed_by(libc::SIGXCPU), b""), None);
+    }
+
+    /// A pipe accepting `capacity` bytes at most 100 at a time, then failing with `error`.
+    struct Pipe {
+        capacity: usize,
+        error: std::io::ErrorKind,
+    }
+
+    impl Write for Pipe {
+        fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+            match self.capacity {
+                0 => Err(self.error.into()),
+                _ => {
+                    let count = bytes.len().min(self.capacity).min(100);
+                    self.capacity -= count;
+                    Ok(count)
+                }
+            }
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn closed_pipes_are_no_input_errors() {
+        let pipe = Pipe {
+            capacity: 500,
+            error: std::io::ErrorKind::BrokenPipe,
+        };
+        assert!(write_input(pipe, &[0; 1000]).is_ok());
+    }
+
+    #[test]
+    fn input_errors_tell_how_much_was_written() {
+        let pipe = Pipe {
+            capacity: 500,
+            error: std::io::ErrorKind::PermissionDenied,
+        };
+        let error = write_input(pipe, &[0; 1000]).unwrap_err();
+        assert_eq!((error.written, error.total), (500, 1000));
+        assert!(error
+            .to_string()
+            .starts_with("wrote only 500 of 1000 bytes: "));
+    }
+
+    #[test]
+    fn missing_extensions_are_appended() {
+        let scratch = crate::scratch::ScratchDir::create(None, "resolution").unwrap();
+        let seatbelt = scratch.path().join("Seatbelt");
+        std::fs::write(seatbelt.with_extension("exe"), "").unwrap();
+        assert_eq!(
+            with_missing_extension(seatbelt.clone(), ".exe"),
+            seatbelt.with_extension("exe")
+        );
+        // the path as given wins
+        std::fs::write(&seatbelt, "").unwrap();
+        assert_eq!(with_missing_extension(seatbelt.clone(), ".exe"), seatbelt);
+        scratch.finish(false).unwrap();
+    }
+
+    #[test]
+    fn paths_without_an_executable_are_kept() {
+        let scratch = crate::scratch::ScratchDir::create(None, "resolution").unwrap();
+        let missing = scratch.path().join("backseat_safe_system_2k");
+        assert_eq!(with_missing_extension(missing.clone(), ".exe"), missing);
+        // only files are executables
+        let directory = scratch.path().join("vm");
+        std::fs::create_dir(directory.with_extension("exe")).unwrap();
+        assert_eq!(with_missing_extension(directory.clone(), ".exe"), directory);
+        scratch.finish(false).unwrap();
+    }
+
+    #[test]
+    fn extensions_are_only_appended_on_windows() {
+        let scratch = crate::scratch::ScratchDir::create(None, "resolution").unwrap();
+        let seatbelt = scratch.path().join("Seatbelt");
+        std::fs::write(seatbelt.with_extension("exe"), "").unwrap();
+        assert_eq!(resolve_executable(seatbelt.clone()), seatbelt);
+        scratch.finish(false).unwrap();
+    }
+}
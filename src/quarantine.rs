@@ -0,0 +1,25 @@
+//! The list of known-flaky tests whose failures do not fail the run, see `--quarantine`.
+
+use std::collections::BTreeSet;
+use std::path::Path;
+
+/// Reads the names of the quarantined tests from a file containing one test name per line.
+/// Everything after a `#` is a comment, empty lines are ignored.
+pub(crate) fn read_quarantine(path: &Path) -> anyhow::Result<BTreeSet<String>> {
+    let contents = std::fs::read_to_string(path).map_err(|error| {
+        anyhow::anyhow!(
+            "unable to read the quarantine file {}: {}",
+            path.display(),
+            error
+        )
+    })?;
+    Ok(contents
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(|name| {
+            let name = name.replace('\\', "/");
+            name.strip_prefix("./").unwrap_or(&name).to_string()
+        })
+        .collect())
+}
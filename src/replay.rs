@@ -0,0 +1,214 @@
+//! Recording the processes started for every test and replaying them later without the toolchain,
+//! see `--record` and `--replay`. A recording contains one JSON file per test with the command
+//! lines, inputs and outputs of its processes in the order they were started.
+
+use std::path::PathBuf;
+use std::process::{ExitStatus, Output};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::process::{ProcessOutput, StdinError};
+
+/// What happens to the processes of the tests.
+#[derive(Debug, Clone)]
+pub(crate) enum Recording {
+    /// The processes are started and recorded into the directory.
+    Record(PathBuf),
+    /// The processes are not started, their recorded outputs are read from the directory instead.
+    Replay(PathBuf),
+}
+
+/// A process as it was started and what it produced.
+#[derive(Debug, Serialize, Deserialize)]
+struct RecordedProcess {
+    command_line: String,
+    /// The SHA-256 hash of the stdin of the process, if it received any.
+    stdin_sha256: Option<String>,
+    /// The raw exit status as returned by the operating system.
+    status: i64,
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    timed_out: bool,
+    stdin_error: Option<RecordedStdinError>,
+    duration_us: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RecordedStdinError {
+    written: usize,
+    total: usize,
+    message: String,
+}
+
+/// The processes of a single test: either recorded while they run or replayed one after another.
+pub(crate) struct Tape {
+    recording: Option<Recording>,
+    path: PathBuf,
+    processes: Vec<RecordedProcess>,
+    /// The index of the next process to replay.
+    next: usize,
+}
+
+impl Tape {
+    /// Creates the tape of the test whose files are named `name` within the recording directory.
+    /// When replaying, the recording of the test has to exist.
+    pub(crate) fn new(recording: Option<&Recording>, name: &str) -> anyhow::Result<Self> {
+        let path = match recording {
+            Some(Recording::Record(directory) | Recording::Replay(directory)) => {
+                directory.join(format!("{name}.json"))
+            }
+            None => PathBuf::new(),
+        };
+        let processes = match recording {
+            Some(Recording::Replay(_)) => {
+                let contents = std::fs::read(&path)
+                    .with_context(|| format!("no recording of the test at {}", path.display()))?;
+                serde_json::from_slice(&contents)
+                    .with_context(|| format!("invalid recording {}", path.display()))?
+            }
+            _ => Vec::new(),
+        };
+        Ok(Self {
+            recording: recording.cloned(),
+            path,
+            processes,
+            next: 0,
+        })
+    }
+
+    /// Runs a process via `spawn` with `stdin`, recording it if requested, or replays the next
+    /// recorded process, which has to match `command_line` and `stdin`. Returns the output and
+    /// how long the process ran.
+    pub(crate) fn run(
+        &mut self,
+        command_line: &str,
+        stdin: Option<Vec<u8>>,
+        spawn: impl FnOnce(Option<Vec<u8>>) -> anyhow::Result<ProcessOutput>,
+    ) -> anyhow::Result<(ProcessOutput, Duration)> {
+        let stdin_sha256 = stdin
+            .as_ref()
+            .map(|stdin| format!("{:x}", Sha256::digest(stdin)));
+        match &self.recording {
+            None => timed(|| spawn(stdin)),
+            Some(Recording::Record(_)) => {
+                let (output, duration) = timed(|| spawn(stdin))?;
+                self.processes.push(RecordedProcess {
+                    command_line: command_line.to_string(),
+                    stdin_sha256,
+                    status: raw_status(output.output.status),
+                    stdout: output.output.stdout.clone(),
+                    stderr: output.output.stderr.clone(),
+                    timed_out: output.timed_out,
+                    stdin_error: output.stdin_error.as_ref().map(|error| RecordedStdinError {
+                        written: error.written,
+                        total: error.total,
+                        message: error.error.to_string(),
+                    }),
+                    duration_us: duration.as_micros().try_into().unwrap_or(u64::MAX),
+                });
+                Ok((output, duration))
+            }
+            Some(Recording::Replay(_)) => {
+                let process = self.processes.get_mut(self.next).ok_or_else(|| {
+                    anyhow!(
+                        "{} does not record the process `{command_line}`",
+                        self.path.display()
+                    )
+                })?;
+                self.next += 1;
+                if process.command_line != command_line {
+                    return Err(anyhow!(
+                        "{} records the process `{}` instead of `{command_line}`",
+                        self.path.display(),
+                        process.command_line
+                    ));
+                }
+                if process.stdin_sha256 != stdin_sha256 {
+                    return Err(anyhow!(
+                        "{} records a different input of the process `{command_line}`",
+                        self.path.display()
+                    ));
+                }
+                let output = ProcessOutput {
+                    output: Output {
+                        status: exit_status(process.status)?,
+                        stdout: std::mem::take(&mut process.stdout),
+                        stderr: std::mem::take(&mut process.stderr),
+                    },
+                    timed_out: process.timed_out,
+                    stdin_error: process.stdin_error.take().map(|error| StdinError {
+                        written: error.written,
+                        total: error.total,
+                        error: std::io::Error::other(error.message),
+                    }),
+                };
+                Ok((output, Duration::from_micros(process.duration_us)))
+            }
+        }
+    }
+
+    /// Writes the recorded processes, or checks that all recorded processes have been replayed.
+    pub(crate) fn finish(self) -> anyhow::Result<()> {
+        match &self.recording {
+            None => Ok(()),
+            Some(Recording::Record(_)) => {
+                let write = || -> anyhow::Result<()> {
+                    if let Some(parent) = self.path.parent() {
+                        std::fs::create_dir_all(parent)?;
+                    }
+                    let contents = serde_json::to_vec_pretty(&self.processes)?;
+                    crate::write_atomic(&self.path, &contents)?;
+                    Ok(())
+                };
+                write().with_context(|| {
+                    format!("unable to write the recording {}", self.path.display())
+                })
+            }
+            Some(Recording::Replay(_)) => match self.processes.get(self.next) {
+                Some(process) => Err(anyhow!(
+                    "{} records the process `{}`, which was not started",
+                    self.path.display(),
+                    process.command_line
+                )),
+                None => Ok(()),
+            },
+        }
+    }
+}
+
+fn timed(
+    spawn: impl FnOnce() -> anyhow::Result<ProcessOutput>,
+) -> anyhow::Result<(ProcessOutput, Duration)> {
+    let started = Instant::now();
+    let output = spawn()?;
+    Ok((output, started.elapsed()))
+}
+
+#[cfg(unix)]
+fn raw_status(status: ExitStatus) -> i64 {
+    std::os::unix::process::ExitStatusExt::into_raw(status).into()
+}
+
+#[cfg(windows)]
+fn raw_status(status: ExitStatus) -> i64 {
+    std::os::windows::process::ExitStatusExt::into_raw(status).into()
+}
+
+#[cfg(unix)]
+fn exit_status(raw: i64) -> anyhow::Result<ExitStatus> {
+    let raw = raw
+        .try_into()
+        .context("invalid exit status in the recording")?;
+    Ok(std::os::unix::process::ExitStatusExt::from_raw(raw))
+}
+
+#[cfg(windows)]
+fn exit_status(raw: i64) -> anyhow::Result<ExitStatus> {
+    let raw = raw
+        .try_into()
+        .context("invalid exit status in the recording")?;
+    Ok(std::os::windows::process::ExitStatusExt::from_raw(raw))
+}
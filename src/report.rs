@@ -0,0 +1,623 @@
+use std::io::{stdout, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+use serde::Serialize;
+
+use crate::directives::{self, TestDirectives, TestOutcome};
+use crate::discovery::{self, TestFile, TestRoot};
+use crate::effective_config::EffectiveConfig;
+use crate::events::{EventHandler, RunEvent};
+use crate::failure_code::FailureCode;
+use crate::process;
+use crate::selection::Selection;
+use crate::stats::Stats;
+use crate::summary::Summary;
+use crate::test_log::{Stage, TestLog};
+use crate::timings::RunnerTimings;
+use crate::toolchain::Toolchain;
+use crate::{TestResult, TestResultKind};
+
+/// The format of the results written to stdout.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum Format {
+    /// Human-readable result lines and summary.
+    Human,
+    /// A single JSON document containing all results and the summary, written at the end.
+    Json,
+    /// One JSON object per line for every finished test, followed by one for the summary.
+    Ndjson,
+}
+
+impl Format {
+    pub(crate) fn is_machine_readable(self) -> bool {
+        self != Format::Human
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum Event<'a> {
+    Test(TestEvent<'a>),
+    Summary(Box<SummaryEvent>),
+}
+
+#[derive(Serialize)]
+pub(crate) struct TestEvent<'a> {
+    /// The name of the test, see `--name-format`.
+    name: &'a str,
+    /// The path of the test relative to its root, prefixed with the label of the root, which is
+    /// unique among the tests of a run.
+    path: &'a str,
+    /// The configuration of the matrix the test was run with, see `--config-set`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    configuration: Option<&'a str>,
+    outcome: &'static str,
+    /// The stable code of the failure, see [`crate::failure_code::FailureCode`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<&'a str>,
+    /// The seed passed to the VM, see `--seed-arg`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+    /// The stdout of a failed compilation, if the compiler printed anything.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    compiler_stdout: Option<String>,
+    /// How the test was expected to end, if its directives are valid.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expected_outcome: Option<&'a TestOutcome>,
+    duration_ms: u128,
+    /// The size of the compiled program given to the VM in bytes, if the compilation succeeded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    program_bytes: Option<usize>,
+    /// The stage the test aborted in, if it did.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    aborted_stage: Option<Stage>,
+    /// The stage a failed test failed in, see [`TestLog::failed_stage`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    failed_stage: Option<Stage>,
+    /// The stages of the test whose process was run.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    stages: Vec<StageEvent>,
+}
+
+#[derive(Serialize)]
+struct StageEvent {
+    stage: Stage,
+    duration_ms: u128,
+    /// `None` if the process was killed, e.g. at its timeout.
+    exit_code: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stdout: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stderr: Option<String>,
+}
+
+impl StageEvent {
+    fn from_log(log: &TestLog) -> Vec<Self> {
+        let lossy = |output: Option<&[u8]>| {
+            output
+                .filter(|output| !output.is_empty())
+                .map(|output| String::from_utf8_lossy(output).into_owned())
+        };
+        log.stages
+            .iter()
+            .map(|record| {
+                let (stdout, stderr) = log.stage_output(record.stage);
+                Self {
+                    stage: record.stage,
+                    duration_ms: record.duration.as_millis(),
+                    exit_code: record.exit_code,
+                    stdout: lossy(stdout),
+                    stderr: lossy(stderr),
+                }
+            })
+            .collect()
+    }
+}
+
+impl<'a> From<&'a TestResult> for TestEvent<'a> {
+    fn from(result: &'a TestResult) -> Self {
+        Self {
+            name: &result.report_name,
+            path: &result.test.name,
+            configuration: result.configuration.as_deref(),
+            outcome: outcome(&result.kind),
+            code: result.kind.code().map(FailureCode::as_str),
+            message: message(&result.kind),
+            seed: result.log.seed,
+            compiler_stdout: (result.log.compiler_stdout.as_ref())
+                .map(|stdout| String::from_utf8_lossy(stdout).into_owned()),
+            expected_outcome: result.log.expected_outcome.as_ref(),
+            duration_ms: result.duration.as_millis(),
+            program_bytes: result.log.program_size(),
+            aborted_stage: result.log.aborted_stage,
+            failed_stage: result.log.failed_stage(&result.kind),
+            stages: StageEvent::from_log(&result.log),
+        }
+    }
+}
+
+/// The name of the outcome of a test in the machine-readable formats.
+fn outcome(kind: &TestResultKind) -> &'static str {
+    match kind {
+        TestResultKind::Success => "ok",
+        TestResultKind::Failure(..) => "failed",
+        TestResultKind::Crash(_) => "crashed",
+        TestResultKind::UnexpectedSuccess(_) => "unexpected_success",
+        TestResultKind::Quarantined(..) => "quarantined",
+        TestResultKind::Skipped(_) => "skipped",
+    }
+}
+
+/// The failure message or the skip reason of a test, if there is any.
+fn message(kind: &TestResultKind) -> Option<&str> {
+    match kind {
+        TestResultKind::Success => None,
+        TestResultKind::Failure(_, message)
+        | TestResultKind::Crash(message)
+        | TestResultKind::UnexpectedSuccess(message)
+        | TestResultKind::Quarantined(_, message) => Some(message),
+        TestResultKind::Skipped(reason) => (!reason.is_empty()).then_some(reason),
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct SummaryEvent {
+    run: usize,
+    successful: usize,
+    failed: usize,
+    skipped: usize,
+    quarantined: usize,
+    stale: usize,
+    crashed: usize,
+    aborted_at_compile: usize,
+    aborted_at_run: usize,
+    not_run: usize,
+    pass_percentage: Option<f64>,
+    elapsed_ms: u128,
+    partial: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stats: Option<Stats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    runner_timings: Option<RunnerTimings>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    selection: Option<Selection>,
+}
+
+impl From<&Summary> for SummaryEvent {
+    fn from(summary: &Summary) -> Self {
+        Self {
+            run: summary.run,
+            successful: summary.successful(),
+            failed: summary.failed,
+            skipped: summary.skipped,
+            quarantined: summary.quarantined,
+            stale: summary.stale,
+            crashed: summary.crashed,
+            aborted_at_compile: summary.aborted_at_compile,
+            aborted_at_run: summary.aborted_at_run,
+            not_run: summary.not_run,
+            pass_percentage: summary.pass_percentage(),
+            elapsed_ms: summary.elapsed.as_millis(),
+            partial: summary.partial,
+            stats: summary.stats.clone(),
+            runner_timings: summary.runner_timings.clone(),
+            selection: summary.selection.clone(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    toolchain: &'a Toolchain,
+    config: &'a EffectiveConfig,
+    tests: Vec<TestEvent<'a>>,
+    summary: SummaryEvent,
+}
+
+/// A discovered test as listed by `--list`.
+#[derive(Serialize)]
+pub(crate) struct ManifestEntry<'a> {
+    name: &'a str,
+    /// The source file or the test directory.
+    path: &'a Path,
+    /// The source file that is compiled.
+    source_path: &'a Path,
+    /// The size of the source file in bytes, if it can be read.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size: Option<u64>,
+    /// The modification time of the source file in milliseconds since the Unix epoch.
+    mtime_ms: Option<u128>,
+    quarantined: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    directives: Option<TestDirectives>,
+    /// Why the directives are invalid, if they are.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    directive_error: Option<String>,
+    /// Why the source file cannot be read, if it cannot.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    read_error: Option<String>,
+}
+
+impl<'a> ManifestEntry<'a> {
+    /// Reads the metadata and the leading comment block of the source file of the test. If the
+    /// file cannot be read, the entry says why instead.
+    pub(crate) fn read(test: &'a TestFile) -> Self {
+        let mut read_error = None;
+        let unreadable = |error: std::io::Error| {
+            format!("unable to read {}: {error}", test.source_path.display())
+        };
+        let metadata = std::fs::metadata(&test.source_path)
+            .map_err(|error| read_error = Some(unreadable(error)))
+            .ok();
+        let (directives, directive_error) =
+            match directives::read_leading_comments(&test.source_path) {
+                Ok(leading_comments) => match directives::parse_directives(&leading_comments) {
+                    Ok(directives) => (Some(directives), None),
+                    Err(error) => (None, Some(error.to_string())),
+                },
+                Err(error) if directives::is_invalid_sidecar(&error) => {
+                    (None, Some(error.to_string()))
+                }
+                Err(error) => {
+                    read_error = read_error.or(Some(unreadable(error)));
+                    (None, None)
+                }
+            };
+        Self {
+            name: &test.name,
+            path: &test.path,
+            source_path: &test.source_path,
+            size: metadata.as_ref().map(|metadata| metadata.len()),
+            mtime_ms: metadata
+                .and_then(|metadata| metadata.modified().ok())
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map(|duration| duration.as_millis()),
+            quarantined: test.quarantined,
+            directives,
+            directive_error,
+            read_error,
+        }
+    }
+
+    pub(crate) fn name(&self) -> &str {
+        self.name
+    }
+
+    pub(crate) fn read_error(&self) -> Option<&str> {
+        self.read_error.as_deref()
+    }
+
+    /// How the test is expected to end, `None` if its directives are invalid.
+    pub(crate) fn expected_outcome(&self) -> Option<&TestOutcome> {
+        Some(&self.directives.as_ref()?.expected_outcome)
+    }
+}
+
+/// The logs and reports that could not be written during the run, see `--strict-reports`.
+pub(crate) struct ReportErrors {
+    strict: bool,
+    errors: Mutex<Vec<String>>,
+}
+
+/// The number of errors that are listed after the results, the others are only counted.
+const LISTED_REPORT_ERRORS: usize = 10;
+
+impl ReportErrors {
+    pub(crate) fn new(strict: bool) -> Self {
+        Self {
+            strict,
+            errors: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Records a failure to write a log or a report. Under `--strict-reports`, the run is aborted.
+    pub(crate) fn push(&self, error: String) {
+        if self.strict {
+            process::abort_run();
+        }
+        self.errors
+            .lock()
+            .expect("the report errors are never poisoned")
+            .push(error);
+    }
+
+    /// Lists the errors after the results and returns them.
+    pub(crate) fn finish(self) -> Vec<String> {
+        let errors = self
+            .errors
+            .into_inner()
+            .expect("the report errors are never poisoned");
+        if !errors.is_empty() {
+            eprintln!("The following logs and reports could not be written:");
+            for error in errors.iter().take(LISTED_REPORT_ERRORS) {
+                eprintln!("\t{error}");
+            }
+            if errors.len() > LISTED_REPORT_ERRORS {
+                eprintln!("\t... and {} more", errors.len() - LISTED_REPORT_ERRORS);
+            }
+        }
+        errors
+    }
+}
+
+/// Writes a line to stdout for every finished test and one for the summary, see `--format ndjson`.
+pub(crate) struct NdjsonWriter<'a> {
+    pub(crate) errors: &'a ReportErrors,
+}
+
+impl EventHandler for NdjsonWriter<'_> {
+    fn on_event(&mut self, event: &RunEvent) {
+        let event = match event {
+            RunEvent::Finished { result, .. } => Event::Test(TestEvent::from(*result)),
+            RunEvent::RunFinished { summary } => {
+                Event::Summary(Box::new(SummaryEvent::from(*summary)))
+            }
+            RunEvent::Discovered { .. } | RunEvent::Started { .. } => return,
+        };
+        if let Err(error) = stdout().lock().write_all(ndjson_line(&event).as_bytes()) {
+            self.errors
+                .push(format!("unable to write the NDJSON report: {error}"));
+        }
+    }
+}
+
+/// Serializes a single event as one line of NDJSON, including the line break.
+pub(crate) fn ndjson_line(event: &Event) -> String {
+    let mut line = serde_json::to_string(event).expect("unable to serialize event");
+    line.push('\n');
+    line
+}
+
+/// Serializes the results of all tests, the summary, the used toolchain and the settings of the run
+/// as a single JSON document.
+pub(crate) fn json_report(
+    results: &[TestResult],
+    summary: &Summary,
+    toolchain: &Toolchain,
+    config: &EffectiveConfig,
+) -> String {
+    let report = Report {
+        toolchain,
+        config,
+        tests: results.iter().map(TestEvent::from).collect(),
+        summary: SummaryEvent::from(summary),
+    };
+    let mut document = serde_json::to_string_pretty(&report).expect("unable to serialize report");
+    document.push('\n');
+    document
+}
+
+/// The header row of the CSV report.
+const CSV_HEADER: [&str; 9] = [
+    "path",
+    "configuration",
+    "outcome",
+    "failure_kind",
+    "compile_ms",
+    "run_ms",
+    "program_bytes",
+    "output_bytes",
+    "error",
+];
+
+/// Serializes one row per test with its outcome, timings and the first line of its failure
+/// message as CSV according to RFC 4180, including a header row. The path of a test is relative
+/// to the root it was discovered in, prefixed with the label of the root if there are several.
+pub(crate) fn csv_report(results: &[TestResult], roots: &[TestRoot]) -> String {
+    let mut document = String::new();
+    csv_row(&mut document, CSV_HEADER.map(String::from));
+    for result in results {
+        let failure_kind = match (&result.kind, result.log.failed_stage(&result.kind)) {
+            (_, Some(stage)) => stage.to_string(),
+            (TestResultKind::Success | TestResultKind::Skipped(_), None) => String::new(),
+            // The test failed before any stage ran, e.g. because of its directives.
+            (_, None) => "directives".to_string(),
+        };
+        let millis = |duration: Option<std::time::Duration>| {
+            duration.map_or(String::new(), |duration| duration.as_millis().to_string())
+        };
+        let error = match &result.kind {
+            TestResultKind::Skipped(_) => "",
+            kind => message(kind)
+                .and_then(|message| message.lines().map(str::trim).find(|line| !line.is_empty()))
+                .unwrap_or_default(),
+        };
+        csv_row(
+            &mut document,
+            [
+                relative_path(&result.test, roots),
+                result.configuration.clone().unwrap_or_default(),
+                outcome(&result.kind).to_string(),
+                failure_kind,
+                millis(result.log.compile_duration),
+                millis(result.log.run_duration),
+                (result.log.program_size()).map_or(String::new(), |size| size.to_string()),
+                result
+                    .log
+                    .vm_stdout
+                    .as_ref()
+                    .map_or(String::new(), |stdout| stdout.len().to_string()),
+                error.to_string(),
+            ],
+        );
+    }
+    document
+}
+
+/// The path of the test relative to its root with forward slashes as separators, prefixed with
+/// the label of the root, or the path as given if the test was not discovered below its root.
+fn relative_path(test: &TestFile, roots: &[TestRoot]) -> String {
+    match roots.get(test.root) {
+        Some(root) if test.path.starts_with(&root.path) => discovery::test_name(root, &test.path),
+        _ => test.path.display().to_string(),
+    }
+}
+
+/// Appends a CSV row terminated by CRLF. Fields containing separators, quotes or line breaks are
+/// quoted, with quotes doubled. The last field is always quoted.
+fn csv_row<const N: usize>(document: &mut String, fields: [String; N]) {
+    for (index, field) in fields.iter().enumerate() {
+        if index > 0 {
+            document.push(',');
+        }
+        let is_last = index + 1 == N;
+        if is_last || field.contains([',', '"', '\r', '\n']) {
+            document.push('"');
+            document.push_str(&field.replace('"', "\"\""));
+            document.push('"');
+        } else {
+            document.push_str(field);
+        }
+    }
+    document.push_str("\r\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use super::{csv_report, csv_row};
+    use crate::discovery::{TestFile, TestRoot};
+    use crate::failure_code::FailureCode;
+    use crate::test_log::TestLog;
+    use crate::{TestResult, TestResultKind};
+
+    fn row<const N: usize>(fields: [&str; N]) -> String {
+        let mut document = String::new();
+        csv_row(&mut document, fields.map(String::from));
+        document
+    }
+
+    /// Splits a CSV document into its rows and fields, undoing the quoting of `csv_row`.
+    fn parse(document: &str) -> Vec<Vec<String>> {
+        let mut rows = Vec::new();
+        let mut fields = Vec::new();
+        let mut field = String::new();
+        let mut chars = document.chars().peekable();
+        let mut quoted = false;
+        while let Some(char) = chars.next() {
+            match (quoted, char) {
+                (true, '"') if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                (_, '"') => quoted = !quoted,
+                (false, ',') => fields.push(std::mem::take(&mut field)),
+                (false, '\r') => {
+                    assert_eq!(chars.next(), Some('\n'), "row not terminated by CRLF");
+                    fields.push(std::mem::take(&mut field));
+                    rows.push(std::mem::take(&mut fields));
+                }
+                (_, char) => field.push(char),
+            }
+        }
+        assert!(
+            field.is_empty() && fields.is_empty(),
+            "last row not terminated"
+        );
+        rows
+    }
+
+    fn result(
+        root: &TestRoot,
+        name: &str,
+        configuration: &str,
+        kind: TestResultKind,
+    ) -> TestResult {
+        let path = root.path.join(name);
+        TestResult {
+            filename: name.to_string(),
+            report_name: name.to_string(),
+            test: TestFile {
+                name: name.to_string(),
+                root: 0,
+                source_path: path.clone(),
+                path,
+                quarantined: false,
+                overrides: Default::default(),
+            },
+            configuration: Some(configuration.to_string()),
+            kind,
+            duration: Duration::ZERO,
+            log: TestLog::default(),
+            log_error: None,
+        }
+    }
+
+    #[test]
+    fn plain_fields_are_not_quoted_except_the_last() {
+        assert_eq!(row(["a", "1", "b c"]), "a,1,\"b c\"\r\n");
+    }
+
+    #[test]
+    fn fields_with_separators_are_quoted() {
+        assert_eq!(row(["a,b", "c"]), "\"a,b\",\"c\"\r\n");
+    }
+
+    #[test]
+    fn quotes_are_doubled() {
+        assert_eq!(row(["say \"hi\"", "x"]), "\"say \"\"hi\"\"\",\"x\"\r\n");
+    }
+
+    #[test]
+    fn fields_with_line_breaks_are_quoted() {
+        assert_eq!(
+            row(["a\nb", "c\rd", "e\r\nf"]),
+            "\"a\nb\",\"c\rd\",\"e\r\nf\"\r\n"
+        );
+    }
+
+    #[test]
+    fn an_empty_error_is_an_empty_quoted_field() {
+        assert_eq!(row(["test_ok.bs", "ok", ""]), "test_ok.bs,ok,\"\"\r\n");
+    }
+
+    #[test]
+    fn rows_of_different_configurations_are_distinguished() {
+        let root = TestRoot {
+            label: String::new(),
+            path: PathBuf::from("/suite/tests"),
+        };
+        let failure =
+            || TestResultKind::Failure(FailureCode::WrongOutput, "wrong, \"output\"".into());
+        let results = [
+            result(&root, "test_a.bs", "debug", TestResultKind::Success),
+            result(&root, "test_a.bs", "release", failure()),
+        ];
+        let rows = parse(&csv_report(&results, &[root]));
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0][..3], ["path", "configuration", "outcome"]);
+        assert_eq!(rows[1][..3], ["test_a.bs", "debug", "ok"]);
+        assert_eq!(rows[2][..3], ["test_a.bs", "release", "failed"]);
+        assert_eq!(rows[2].last().unwrap(), "wrong, \"output\"");
+        assert_ne!(rows[1], rows[2]);
+    }
+
+    #[test]
+    fn paths_keep_the_label_of_their_root() {
+        let roots = [
+            TestRoot {
+                label: "unit".to_string(),
+                path: PathBuf::from("/suite/unit"),
+            },
+            TestRoot {
+                label: "regression".to_string(),
+                path: PathBuf::from("/suite/regression"),
+            },
+        ];
+        let mut other = result(&roots[1], "sub/test_a.bs", "debug", TestResultKind::Success);
+        other.test.root = 1;
+        let results = [
+            result(&roots[0], "sub/test_a.bs", "debug", TestResultKind::Success),
+            other,
+        ];
+        let rows = parse(&csv_report(&results, &roots));
+        assert_eq!(rows[1][0], "unit/sub/test_a.bs");
+        assert_eq!(rows[2][0], "regression/sub/test_a.bs");
+    }
+}
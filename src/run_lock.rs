@@ -0,0 +1,89 @@
+//! Advisory locks on the directories a run writes to, so two runs sharing e.g. a `--log-dir` do
+//! not overwrite each other's files, see `--if-locked`.
+
+use std::fs::{File, TryLockError};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// The name of the lock file within a locked directory.
+const LOCK_FILE_NAME: &str = ".test-runner.lock";
+
+/// How often a locked directory is checked again while waiting for it.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// What a run does if another run still holds the lock on one of its output directories once the
+/// grace period is over.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum IfLocked {
+    /// Keep waiting until the other run is done.
+    Wait,
+    /// Run the tests without writing to the locked directory.
+    SkipWrites,
+    /// Fail without running any tests.
+    Error,
+}
+
+/// The exclusive lock on an output directory. It is released when dropped, including while
+/// unwinding from a panic, and by the OS if the test runner exits without unwinding.
+pub(crate) struct DirectoryLock {
+    file: File,
+}
+
+impl Drop for DirectoryLock {
+    fn drop(&mut self) {
+        self.file.unlock().ok();
+    }
+}
+
+/// Locks `directory`, creating it if necessary. If another run holds the lock, waits for up to
+/// `grace_period` and then proceeds according to `if_locked`. Returns `None` if the run has to
+/// skip writing to the directory.
+pub(crate) fn acquire(
+    directory: &Path,
+    if_locked: IfLocked,
+    grace_period: Duration,
+) -> anyhow::Result<Option<DirectoryLock>> {
+    std::fs::create_dir_all(directory)?;
+    let path = directory.join(LOCK_FILE_NAME);
+    let file = File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)
+        .map_err(|error| anyhow::anyhow!("unable to open {}: {error}", path.display()))?;
+    let started = Instant::now();
+    let mut waiting = false;
+    loop {
+        match file.try_lock() {
+            Ok(()) => return Ok(Some(DirectoryLock { file })),
+            Err(TryLockError::WouldBlock) => {}
+            Err(TryLockError::Error(error)) => {
+                anyhow::bail!("unable to lock {}: {error}", path.display())
+            }
+        }
+        if !waiting {
+            eprintln!(
+                "another run is in progress and writes to {}, waiting for it to finish",
+                directory.display()
+            );
+            waiting = true;
+        }
+        let expired = started.elapsed() >= grace_period;
+        match if_locked {
+            IfLocked::SkipWrites if expired => {
+                eprintln!(
+                    "{} is still locked, running without writing to it",
+                    directory.display()
+                );
+                return Ok(None);
+            }
+            IfLocked::Error if expired => anyhow::bail!(
+                "{} is still locked by another run after {:.1}s",
+                directory.display(),
+                grace_period.as_secs_f64()
+            ),
+            _ => {}
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
@@ -0,0 +1,78 @@
+//! Verification that tests do not write to the files below the tests paths, see `--sandbox`. The
+//! sizes and modification times of all files are compared before and after every test.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// The trees that must not change while a test runs.
+#[derive(Debug)]
+pub(crate) struct Sandbox {
+    pub(crate) roots: Vec<PathBuf>,
+    /// Directories below the roots the test runner itself writes to, e.g. the log directory.
+    pub(crate) excluded: Vec<PathBuf>,
+    /// Whether tests run concurrently, so a change cannot be attributed to a single test.
+    pub(crate) parallel: bool,
+}
+
+/// The size and the modification time of every file below the roots.
+pub(crate) struct Snapshot(BTreeMap<PathBuf, (u64, Option<SystemTime>)>);
+
+impl Sandbox {
+    pub(crate) fn snapshot(&self) -> Snapshot {
+        let mut files = BTreeMap::new();
+        for root in &self.roots {
+            self.visit(root, &mut files);
+        }
+        Snapshot(files)
+    }
+
+    /// Records the files below `directory`. Symbolic links are recorded, but not followed.
+    fn visit(&self, directory: &Path, files: &mut BTreeMap<PathBuf, (u64, Option<SystemTime>)>) {
+        let Ok(entries) = std::fs::read_dir(directory) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            if metadata.is_dir() {
+                if !self.excluded.contains(&path) {
+                    self.visit(&path, files);
+                }
+            } else {
+                files.insert(path, (metadata.len(), metadata.modified().ok()));
+            }
+        }
+    }
+
+    /// Returns one line per file that was created, modified or deleted between the snapshots,
+    /// ignoring the given files, which the test runner may write itself.
+    pub(crate) fn changes(
+        &self,
+        before: &Snapshot,
+        after: &Snapshot,
+        ignored: &[PathBuf],
+    ) -> Vec<String> {
+        let mut changes = Vec::new();
+        for (path, metadata) in &after.0 {
+            let change = match before.0.get(path) {
+                None => "created",
+                Some(previous) if previous != metadata => "modified",
+                Some(_) => continue,
+            };
+            changes.push((change, path));
+        }
+        for path in before.0.keys() {
+            if !after.0.contains_key(path) {
+                changes.push(("deleted", path));
+            }
+        }
+        changes
+            .into_iter()
+            .filter(|(_, path)| !ignored.contains(path))
+            .map(|(change, path)| format!("{change} {}", path.display()))
+            .collect()
+    }
+}
@@ -0,0 +1,54 @@
+//! A scratch directory of its own for every test run, exposed to the toolchain as `TEST_TMPDIR`,
+//! so tests running concurrently do not trample each other's files.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The environment variable the scratch directory is passed to the compiler and the VM in.
+pub(crate) const TEST_TMPDIR: &str = "TEST_TMPDIR";
+
+/// Distinguishes the scratch directories of tests with the same name.
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// The directory containing the scratch directories of this run: a directory named after the
+/// process below `work_dir`, or below the system temp directory if it is not given.
+pub(crate) fn run_directory(work_dir: Option<&Path>) -> PathBuf {
+    let base = work_dir.map_or_else(std::env::temp_dir, Path::to_path_buf);
+    base.join(format!("test-runner-{}", std::process::id()))
+}
+
+/// Removes the directory of the run if no scratch directory was kept in it.
+pub(crate) fn remove_run_directory(work_dir: Option<&Path>) {
+    std::fs::remove_dir(run_directory(work_dir)).ok();
+}
+
+pub(crate) struct ScratchDir {
+    path: PathBuf,
+}
+
+impl ScratchDir {
+    /// Creates a new empty scratch directory for the test with the given name.
+    pub(crate) fn create(work_dir: Option<&Path>, name: &str) -> std::io::Result<Self> {
+        let is_safe = |c: char| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_');
+        let name: String = name
+            .chars()
+            .map(|c| if is_safe(c) { c } else { '_' })
+            .collect();
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let path = run_directory(work_dir).join(format!("{id}-{name}"));
+        std::fs::create_dir_all(&path)?;
+        Ok(Self { path })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Removes the directory with everything the test left in it, unless it is to be kept.
+    pub(crate) fn finish(self, keep: bool) -> std::io::Result<()> {
+        match keep {
+            true => Ok(()),
+            false => std::fs::remove_dir_all(&self.path),
+        }
+    }
+}
@@ -0,0 +1,162 @@
+//! Seeds for the random number generator of the Backseater, so failures of programs using random
+//! numbers can be reproduced, see `--seed` and `--seed-arg`.
+
+use std::hash::{BuildHasher, Hasher};
+
+use sha2::{Digest, Sha256};
+
+use crate::shell_words;
+
+/// How the seed of a test is passed to the Backseater.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum SeedArg {
+    /// Extra arguments, in which `{seed}` is replaced with the seed.
+    Args(Vec<String>),
+    /// An environment variable that is set to the seed.
+    Env(String),
+}
+
+/// The placeholder for the seed in the arguments of [`SeedArg::Args`].
+const PLACEHOLDER: &str = "{seed}";
+
+impl SeedArg {
+    /// Parses `env:NAME`, or arguments split like by a shell of which at least one contains
+    /// `{seed}`.
+    pub(crate) fn parse(template: &str) -> Result<Self, String> {
+        if let Some(name) = template.strip_prefix("env:") {
+            let is_valid_name =
+                !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+            return match is_valid_name {
+                true => Ok(Self::Env(name.to_string())),
+                false => Err(format!("invalid environment variable name: {name:?}")),
+            };
+        }
+        let args = shell_words::split(template)?;
+        match args.iter().any(|arg| arg.contains(PLACEHOLDER)) {
+            true => Ok(Self::Args(args)),
+            false => Err(format!(
+                "expected `env:NAME` or arguments containing {PLACEHOLDER}, got {template:?}"
+            )),
+        }
+    }
+
+    /// The extra arguments of the Backseater to pass `seed`.
+    pub(crate) fn args(&self, seed: u64) -> Vec<String> {
+        match self {
+            SeedArg::Args(args) => args
+                .iter()
+                .map(|arg| arg.replace(PLACEHOLDER, &seed.to_string()))
+                .collect(),
+            SeedArg::Env(_) => Vec::new(),
+        }
+    }
+
+    /// The environment variable of the Backseater to pass `seed`, if any.
+    pub(crate) fn env(&self, seed: u64) -> Option<(&str, String)> {
+        match self {
+            SeedArg::Args(_) => None,
+            SeedArg::Env(name) => Some((name, seed.to_string())),
+        }
+    }
+}
+
+/// Parses a seed of `--seed` or the `seed` directive, a non-negative integer that fits in 64 bits.
+pub(crate) fn parse_seed(value: &str) -> Result<u64, String> {
+    Some(value)
+        .filter(|value| !value.is_empty() && value.bytes().all(|c| c.is_ascii_digit()))
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| format!("expected a non-negative integer below 2^64, got `{value}`"))
+}
+
+/// A random seed for a run without `--seed`.
+pub(crate) fn random_run_seed() -> u64 {
+    std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish()
+}
+
+/// The seed of the test with the given name, derived from the seed of the run. It does not depend
+/// on the order the tests are run in, so a test gets the same seed whenever the run has the same
+/// seed.
+pub(crate) fn test_seed(run_seed: u64, name: &str) -> u64 {
+    let hash = Sha256::new()
+        .chain_update(run_seed.to_le_bytes())
+        .chain_update(name.as_bytes())
+        .finalize();
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&hash[..8]);
+    u64::from_le_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_same_run_seed_derives_the_same_test_seeds() {
+        let names = ["a", "b", "dir/c", ""];
+        let seeds = |run_seed| names.map(|name| test_seed(run_seed, name));
+        assert_eq!(seeds(42), seeds(42));
+        assert_ne!(seeds(42), seeds(43));
+        // the seeds are derived from the name, not the position of a test
+        let mut reversed = names;
+        reversed.reverse();
+        let mut reversed_seeds = reversed.map(|name| test_seed(42, name));
+        reversed_seeds.reverse();
+        assert_eq!(reversed_seeds, seeds(42));
+        assert_ne!(test_seed(42, "a"), test_seed(42, "b"));
+    }
+
+    #[test]
+    fn test_seeds_are_stable_across_releases() {
+        const PINNED: u64 = 7586401852616081111;
+        // the seed of a failure has to reproduce it with later versions of the test runner
+        assert_eq!(test_seed(0, "a"), PINNED);
+    }
+
+    #[test]
+    fn seeds_are_parsed() {
+        assert_eq!(parse_seed("0"), Ok(0));
+        assert_eq!(parse_seed("0042"), Ok(42));
+        assert_eq!(parse_seed("18446744073709551615"), Ok(u64::MAX));
+    }
+
+    #[test]
+    fn invalid_seeds_are_rejected() {
+        for value in [
+            "",
+            "-1",
+            "+1",
+            "1.5",
+            "0x10",
+            " 1",
+            "1 ",
+            "seed",
+            "18446744073709551616",
+        ] {
+            assert_eq!(
+                parse_seed(value),
+                Err(format!(
+                    "expected a non-negative integer below 2^64, got `{value}`"
+                )),
+            );
+        }
+    }
+
+    #[test]
+    fn seeds_are_passed_in_arguments_or_the_environment() {
+        let args = SeedArg::parse("--seed={seed} '--check {seed}'").unwrap();
+        assert_eq!(args.args(7), ["--seed=7", "--check 7"]);
+        assert_eq!(args.env(7), None);
+        let env = SeedArg::parse("env:BACKSEAT_SEED").unwrap();
+        assert_eq!(env.args(7), Vec::<String>::new());
+        assert_eq!(env.env(7), Some(("BACKSEAT_SEED", "7".to_string())));
+    }
+
+    #[test]
+    fn invalid_seed_args_are_rejected() {
+        for template in ["env:", "env:A-B", "--seed=7", "", "'{seed}"] {
+            assert!(SeedArg::parse(template).is_err(), "{template:?}");
+        }
+    }
+}
@@ -0,0 +1,372 @@
+//! The selection of the tests to run out of the discovered ones. Before the run, a report of how
+//! many tests every rule excluded is printed and included in the JSON summary, so runs that
+//! select far fewer tests than intended are noticed. With `--explain-selection`, every rule that
+//! applied to a single test is printed instead of running the tests.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::directives::{self, TestDirectives};
+use crate::discovery::{self, TestFile, TEST_FILE_PATTERN};
+
+/// How many tests every selection rule excluded, counted per test, not per configuration.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub(crate) struct Selection {
+    /// The tests found below the tests paths.
+    pub(crate) discovered: usize,
+    /// The tests excluded by a `not_a_test` directive.
+    pub(crate) not_a_test: usize,
+    /// The tests not listed in the `--tests-from` file.
+    pub(crate) not_listed: usize,
+    /// The tests listed in the `--tests-from` file that were not discovered. They are selected
+    /// and fail.
+    pub(crate) listed_missing: usize,
+    /// The tests not matching the `--only-directives` filters.
+    pub(crate) not_matching_directives: usize,
+    /// The selected tests that are skipped in at least one configuration by a `skip` or
+    /// `skip_configs` directive. They are included in `selected`.
+    pub(crate) skipped_by_directive: usize,
+    pub(crate) selected: usize,
+}
+
+impl Selection {
+    /// Renders the report line, e.g. `Selected 12 of 15 discovered tests (excluded: 3 by
+    /// --tests-from)`. Rules that excluded no test are left out.
+    pub(crate) fn render(&self, out: &mut impl Write) -> std::io::Result<()> {
+        write!(
+            out,
+            "Selected {} of {} discovered tests",
+            self.selected, self.discovered
+        )?;
+        let excluded: Vec<_> = [
+            (self.not_a_test, "by `not_a_test`"),
+            (self.not_listed, "by --tests-from"),
+            (self.not_matching_directives, "by --only-directives"),
+        ]
+        .into_iter()
+        .filter(|(count, _)| *count > 0)
+        .map(|(count, rule)| format!("{count} {rule}"))
+        .collect();
+        if !excluded.is_empty() {
+            write!(out, " (excluded: {})", excluded.join(", "))?;
+        }
+        if self.listed_missing > 0 {
+            write!(out, ", {} listed but not discovered", self.listed_missing)?;
+        }
+        if self.skipped_by_directive > 0 {
+            write!(
+                out,
+                ", {} of them skipped by directive",
+                self.skipped_by_directive
+            )?;
+        }
+        writeln!(out)?;
+        out.flush()
+    }
+}
+
+/// Whether the `skip` or the `skip_configs` directive of the test skips it in one of the
+/// configurations.
+pub(crate) fn is_skipped_by_directive(test: &TestFile, configurations: &[&str]) -> bool {
+    parsed_directives(test).is_some_and(|directives| {
+        directives.skip.is_some() || !skipped_configurations(&directives, configurations).is_empty()
+    })
+}
+
+/// The directives of the test. Tests whose directives are unreadable or invalid fail, so they are
+/// never skipped.
+fn parsed_directives(test: &TestFile) -> Option<TestDirectives> {
+    let leading_comments = directives::read_leading_comments(&test.source_path).ok()?;
+    directives::parse_directives(&leading_comments).ok()
+}
+
+/// The configurations the `skip_configs` directive skips the test in.
+fn skipped_configurations<'a>(
+    directives: &TestDirectives,
+    configurations: &[&'a str],
+) -> Vec<&'a str> {
+    configurations
+        .iter()
+        .copied()
+        .filter(|name| {
+            directives
+                .skip_configs
+                .iter()
+                .any(|skipped| skipped == name)
+        })
+        .collect()
+}
+
+/// The rules that applied to the test given via `--explain-selection`, in the order they were
+/// applied. Without a test to explain, nothing is recorded.
+#[derive(Default)]
+pub(crate) struct Explanation {
+    path: Option<PathBuf>,
+    rules: Vec<(String, bool, String)>,
+}
+
+impl Explanation {
+    pub(crate) fn new(path: Option<&Path>) -> Self {
+        Self {
+            path: path.map(|path| {
+                path.canonicalize()
+                    .or_else(|_| std::path::absolute(path))
+                    .unwrap_or(path.to_path_buf())
+            }),
+            rules: Vec::new(),
+        }
+    }
+
+    pub(crate) fn is_active(&self) -> bool {
+        self.path.is_some()
+    }
+
+    /// Whether the test is the one explained, by the path of the test or of its source file.
+    pub(crate) fn is_for(&self, test: &TestFile) -> bool {
+        self.path
+            .as_ref()
+            .is_some_and(|path| *path == test.path || *path == test.source_path)
+    }
+
+    /// Whether the explained test is one of the tests.
+    pub(crate) fn is_among(&self, tests: &[TestFile]) -> bool {
+        tests.iter().any(|test| self.is_for(test))
+    }
+
+    /// Records whether the explained path was discovered as a test.
+    pub(crate) fn discovered(&mut self, tests: &[TestFile], other_files: &[PathBuf]) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let reason = if self.is_among(tests) {
+            format!("matches \"{TEST_FILE_PATTERN}\" or is a test directory below a tests path")
+        } else if other_files.contains(path) {
+            format!("does not match \"{TEST_FILE_PATTERN}\" and is not the `main.bs` of a test directory")
+        } else if discovery::is_precompiled(path) {
+            "not found below any tests path, precompiled tests are only discovered with --include-precompiled".to_string()
+        } else {
+            "not found below any tests path".to_string()
+        };
+        let passed = self.is_among(tests);
+        self.applied("discovery", passed, reason);
+    }
+
+    /// Records the skip directives of the explained test and returns whether they skip it in
+    /// every configuration. Without configurations, only `skip` applies.
+    pub(crate) fn skipped(&mut self, test: &TestFile, configurations: &[&str]) -> bool {
+        let Some(directives) = parsed_directives(test) else {
+            return false;
+        };
+        if let Some(reason) = &directives.skip {
+            let reason = match reason.is_empty() {
+                true => "skipped in every configuration".to_string(),
+                false => format!("skipped in every configuration: {reason}"),
+            };
+            self.applied("`skip`", false, reason);
+            return true;
+        }
+        let skipped = skipped_configurations(&directives, configurations);
+        if skipped.is_empty() {
+            return false;
+        }
+        let everywhere = skipped.len() == configurations.len();
+        self.applied(
+            "`skip_configs`",
+            !everywhere,
+            format!("skipped in the configuration(s) {}", skipped.join(", ")),
+        );
+        everywhere
+    }
+
+    /// Records that the rule applied and whether the test passed it.
+    pub(crate) fn applied(&mut self, rule: &str, passed: bool, reason: String) {
+        if self.is_active() {
+            self.rules.push((rule.to_string(), passed, reason));
+        }
+    }
+
+    /// Lists the rules that applied and whether the test was selected in the end.
+    pub(crate) fn render(&self, out: &mut impl Write, selected: bool) -> std::io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        writeln!(out, "{}:", path.display())?;
+        for (rule, passed, reason) in &self.rules {
+            let verdict = match passed {
+                true => "passed",
+                false => "excluded",
+            };
+            writeln!(out, "  {rule}: {verdict}, {reason}")?;
+        }
+        match selected {
+            true => writeln!(out, "selected")?,
+            false => writeln!(out, "not selected")?,
+        }
+        out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{is_skipped_by_directive, Explanation, Selection};
+    use crate::discovery::TestFile;
+    use crate::scratch::ScratchDir;
+
+    /// Writes a test with the given source to the directory.
+    fn test(directory: &Path, name: &str, source: &str) -> TestFile {
+        let path = directory.join(name);
+        std::fs::write(&path, source).unwrap();
+        let path = path.canonicalize().unwrap();
+        TestFile {
+            name: name.to_string(),
+            root: 0,
+            path: path.clone(),
+            source_path: path,
+            quarantined: false,
+            overrides: Default::default(),
+        }
+    }
+
+    fn render(selection: &Selection) -> String {
+        let mut out = Vec::new();
+        selection.render(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn explain(explanation: &Explanation, selected: bool) -> String {
+        let mut out = Vec::new();
+        explanation.render(&mut out, selected).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn selection_reports_leave_out_rules_that_excluded_nothing() {
+        let selection = Selection {
+            discovered: 15,
+            selected: 15,
+            ..Selection::default()
+        };
+        assert_eq!(render(&selection), "Selected 15 of 15 discovered tests\n");
+    }
+
+    #[test]
+    fn selection_reports_list_every_rule_that_excluded_tests() {
+        let selection = Selection {
+            discovered: 15,
+            not_a_test: 1,
+            not_listed: 3,
+            listed_missing: 2,
+            not_matching_directives: 4,
+            skipped_by_directive: 5,
+            selected: 9,
+        };
+        assert_eq!(
+            render(&selection),
+            "Selected 9 of 15 discovered tests (excluded: 1 by `not_a_test`, 3 by --tests-from, \
+            4 by --only-directives), 2 listed but not discovered, 5 of them skipped by directive\n"
+        );
+    }
+
+    #[test]
+    fn tests_are_skipped_by_directive_in_any_configuration() {
+        let scratch = ScratchDir::create(None, "selection").unwrap();
+        let skipped = test(scratch.path(), "test_skipped.bs", "// test-runner: skip\n");
+        let in_release = test(
+            scratch.path(),
+            "test_in_release.bs",
+            "// test-runner: skip_configs = \"release\"\n",
+        );
+        let invalid = test(
+            scratch.path(),
+            "test_invalid.bs",
+            "// test-runner: skip = 1\n",
+        );
+        let plain = test(scratch.path(), "test_plain.bs", "// a comment\n");
+        assert!(is_skipped_by_directive(&skipped, &[]));
+        assert!(is_skipped_by_directive(&in_release, &["debug", "release"]));
+        assert!(!is_skipped_by_directive(&in_release, &["debug"]));
+        assert!(!is_skipped_by_directive(&in_release, &[]));
+        // tests with invalid directives fail instead of being skipped
+        assert!(!is_skipped_by_directive(&invalid, &["release"]));
+        assert!(!is_skipped_by_directive(&plain, &["release"]));
+        scratch.finish(false).unwrap();
+    }
+
+    #[test]
+    fn explanations_list_the_rules_in_the_order_they_applied() {
+        let scratch = ScratchDir::create(None, "selection").unwrap();
+        let test = test(
+            scratch.path(),
+            "test_a.bs",
+            "// test-runner: skip_configs = \"release\"\n",
+        );
+        let mut explanation = Explanation::new(Some(&test.path));
+        assert!(explanation.is_for(&test));
+        explanation.discovered(std::slice::from_ref(&test), &[]);
+        assert!(!explanation.skipped(&test, &["debug", "release"]));
+        explanation.applied("--tests-from", false, "not listed".to_string());
+        assert_eq!(
+            explain(&explanation, false),
+            format!(
+                "{}:\n  discovery: passed, matches \"test*.bs\" or is a test directory below a \
+                tests path\n  `skip_configs`: passed, skipped in the configuration(s) release\n  \
+                --tests-from: excluded, not listed\nnot selected\n",
+                test.path.display()
+            )
+        );
+        scratch.finish(false).unwrap();
+    }
+
+    #[test]
+    fn explanations_exclude_tests_skipped_in_every_configuration() {
+        let scratch = ScratchDir::create(None, "selection").unwrap();
+        let skipped = test(
+            scratch.path(),
+            "test_a.bs",
+            "// test-runner: skip = \"flaky\"\n",
+        );
+        let mut explanation = Explanation::new(Some(&skipped.path));
+        assert!(explanation.skipped(&skipped, &[]));
+        let in_release = test(
+            scratch.path(),
+            "test_b.bs",
+            "// test-runner: skip_configs = \"release\"\n",
+        );
+        let mut release_only = Explanation::new(Some(&in_release.path));
+        assert!(release_only.skipped(&in_release, &["release"]));
+        assert!(explain(&explanation, false)
+            .contains("  `skip`: excluded, skipped in every configuration: flaky\n"));
+        assert!(explain(&release_only, false)
+            .contains("  `skip_configs`: excluded, skipped in the configuration(s) release\n"));
+        scratch.finish(false).unwrap();
+    }
+
+    #[test]
+    fn files_that_were_not_discovered_are_explained() {
+        let scratch = ScratchDir::create(None, "selection").unwrap();
+        let other = test(scratch.path(), "helper.bs", "");
+        let mut explanation = Explanation::new(Some(&other.path));
+        explanation.discovered(&[], std::slice::from_ref(&other.path));
+        let mut missing = Explanation::new(Some(&scratch.path().join("test_missing.bs")));
+        missing.discovered(&[], &[]);
+        assert!(explain(&explanation, false).contains(
+            "  discovery: excluded, does not match \"test*.bs\" and is not the `main.bs` of a \
+            test directory\n"
+        ));
+        assert!(explain(&missing, false)
+            .contains("  discovery: excluded, not found below any tests path\n"));
+        scratch.finish(false).unwrap();
+    }
+
+    #[test]
+    fn nothing_is_recorded_without_a_test_to_explain() {
+        let mut explanation = Explanation::new(None);
+        explanation.applied("--tests-from", false, "not listed".to_string());
+        assert!(!explanation.is_active());
+        assert_eq!(explain(&explanation, true), "");
+    }
+}
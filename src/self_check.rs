@@ -0,0 +1,157 @@
+use std::io::stdout;
+use std::path::{Path, PathBuf};
+use std::process::Output;
+
+use anyhow::anyhow;
+use crossterm::execute;
+use crossterm::style::{Color, Print, ResetColor, SetForegroundColor};
+use log::debug;
+
+use crate::config::Config;
+use crate::process;
+use crate::text::strip_ansi;
+use crate::{child_with_pipe_args, toolchain_command, validate_error_messages};
+
+/// A program that does nothing and halts regularly.
+const HALT_PROGRAM: &str = "function main() ~> Nothing { }\n";
+
+/// The identifier used by the aborting program. The compiler is expected to mention it in its
+/// error message, which is used to verify the error message matching.
+const UNDEFINED_IDENTIFIER: &str = "self_check_undefined_function";
+
+/// A program that calls an undefined function and therefore has to abort.
+const ABORT_PROGRAM: &str =
+    "function main() ~> Nothing {\n    self_check_undefined_function();\n}\n";
+
+/// Compiles and runs the embedded smoke test programs through the configured toolchain. Returns
+/// an error describing the first stage that did not behave as expected.
+pub(crate) fn run_self_check(config: &Config) -> anyhow::Result<()> {
+    let directory =
+        std::env::temp_dir().join(format!("test-runner-self-check-{}", std::process::id()));
+    std::fs::create_dir_all(&directory)?;
+    let result = run_checks(config, &directory);
+    std::fs::remove_dir_all(&directory).ok();
+    result
+}
+
+/// Compiles and runs the halt program once and discards the result, so the toolchain binaries
+/// are loaded into the page cache before the measured run starts, see `--warm-up`.
+pub(crate) fn warm_up(config: &Config) {
+    let directory =
+        std::env::temp_dir().join(format!("test-runner-warm-up-{}", std::process::id()));
+    let result = std::fs::create_dir_all(&directory)
+        .map_err(anyhow::Error::from)
+        .and_then(|()| {
+            let halt_program = write_program(&directory, "halt.bs", HALT_PROGRAM)?;
+            let compiler_result = compile(config, &halt_program)?;
+            run(config, compiler_result.stdout)
+        });
+    std::fs::remove_dir_all(&directory).ok();
+    if let Err(error) = result {
+        debug!("the warm-up run failed: {error}");
+    }
+}
+
+fn run_checks(config: &Config, directory: &Path) -> anyhow::Result<()> {
+    let halt_program = write_program(directory, "halt.bs", HALT_PROGRAM)?;
+    let compiler_result = compile(config, &halt_program)?;
+    if !compiler_result.status.success() {
+        return Err(stage_failure("halt program", "compile", &compiler_result));
+    }
+    print_check("halt program compiles");
+
+    let backseater_result = run(config, compiler_result.stdout)?;
+    if !backseater_result.status.success() {
+        return Err(stage_failure("halt program", "run", &backseater_result));
+    }
+    print_check("halt program runs");
+
+    let abort_program = write_program(directory, "abort.bs", ABORT_PROGRAM)?;
+    let compiler_result = compile(config, &abort_program)?;
+    let failed_result = if compiler_result.status.success() {
+        let backseater_result = run(config, compiler_result.stdout)?;
+        if backseater_result.status.success() {
+            return Err(anyhow!(
+                "self-check failed: the aborting program compiled and ran without any error"
+            ));
+        }
+        backseater_result
+    } else {
+        compiler_result
+    };
+    print_check("aborting program aborts");
+
+    validate_error_messages(
+        &strip_ansi(&failed_result.stderr),
+        &[UNDEFINED_IDENTIFIER.to_string()],
+    )
+    .map_err(|error| {
+        anyhow!(
+            "self-check failed: error message matching does not work:\n{}",
+            error
+        )
+    })?;
+    print_check("error messages can be matched");
+
+    Ok(())
+}
+
+fn write_program(directory: &Path, filename: &str, source: &str) -> anyhow::Result<PathBuf> {
+    let path = directory.join(filename);
+    std::fs::write(&path, source)?;
+    Ok(path)
+}
+
+fn compile(config: &Config, source_file: &Path) -> anyhow::Result<Output> {
+    let mut command = toolchain_command(config, &config.seatbelt_path);
+    command
+        .arg(source_file.as_os_str())
+        .arg("--lib")
+        .arg(config.lib_path.as_os_str());
+    process::run(&mut command, None, None)
+        .map(|output| output.output)
+        .map_err(|error| {
+            anyhow!(
+                "self-check failed: unable to start the Seatbelt compiler: {}",
+                error
+            )
+        })
+}
+
+fn run(config: &Config, compiler_output: Vec<u8>) -> anyhow::Result<Output> {
+    child_with_pipe_args(
+        toolchain_command(config, &config.backseater_path),
+        compiler_output,
+        config.halt_mode.vm_args(),
+        None,
+        None,
+    )
+    .map(|output| output.output)
+    .map_err(|error| {
+        anyhow!(
+            "self-check failed: unable to start the Backseater: {}",
+            error
+        )
+    })
+}
+
+fn stage_failure(program: &str, stage: &str, output: &Output) -> anyhow::Error {
+    anyhow!(
+        "self-check failed: {} stage of the {} failed ({})\n{}",
+        stage,
+        program,
+        output.status,
+        String::from_utf8_lossy(&output.stderr).trim()
+    )
+}
+
+fn print_check(description: &str) {
+    execute!(
+        stdout().lock(),
+        Print(format!("self-check {description} ... ")),
+        SetForegroundColor(Color::DarkGreen),
+        Print("OK\n"),
+        ResetColor
+    )
+    .expect("unable to print output");
+}
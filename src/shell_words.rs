@@ -0,0 +1,146 @@
+//! Splitting of options that are fragments of a command line into arguments, like a POSIX shell
+//! does, so quoted arguments may contain whitespace. Command lines rendered with
+//! [`crate::text::shell_quote`] are split into the same arguments again.
+
+/// Splits `fragment` at unquoted whitespace. Single quotes preserve everything up to the next
+/// single quote, double quotes everything up to the next unescaped double quote. A backslash
+/// escapes the next character outside of quotes and `"`, `\`, `$` and `` ` `` within double
+/// quotes. Quotes within an argument are removed, `''` is an empty argument.
+pub(crate) fn split(fragment: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    // whether a word was started, which may be empty if it consists of quotes only
+    let mut in_word = false;
+    let mut chars = fragment.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            '\'' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err(error("unterminated single quote", fragment)),
+                    }
+                }
+            }
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => word.push(c),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => return Err(error("unterminated double quote", fragment)),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err(error("unterminated double quote", fragment)),
+                    }
+                }
+            }
+            '\\' => {
+                in_word = true;
+                match chars.next() {
+                    Some(c) => word.push(c),
+                    None => return Err(error("trailing backslash", fragment)),
+                }
+            }
+            c => {
+                in_word = true;
+                word.push(c);
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    Ok(words)
+}
+
+fn error(problem: &str, fragment: &str) -> String {
+    format!("{problem} in the arguments {fragment:?}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::split;
+
+    fn words(fragment: &str) -> Vec<String> {
+        split(fragment).unwrap()
+    }
+
+    #[test]
+    fn unquoted_words_are_split_at_whitespace() {
+        assert_eq!(words("  -O2 \t--seed=3\n x "), ["-O2", "--seed=3", "x"]);
+        assert_eq!(words(""), Vec::<String>::new());
+        assert_eq!(words("   "), Vec::<String>::new());
+    }
+
+    #[test]
+    fn quotes_nest_within_the_other_kind() {
+        assert_eq!(words(r#"'say "hi"' "it's""#), [r#"say "hi""#, "it's"]);
+        assert_eq!(words(r#""a 'b' c"'d "e" f'"#), [r#"a 'b' cd "e" f"#]);
+        assert_eq!(words(r#"--name="a b"'c d'"#), ["--name=a bc d"]);
+    }
+
+    #[test]
+    fn escaped_quotes_are_kept() {
+        assert_eq!(words(r#""a \"b\"" \'c\' d\ e"#), [r#"a "b""#, "'c'", "d e"]);
+        assert_eq!(words(r#""\$x \`y\` \\ \n""#), [r"$x `y` \ \n"]);
+        assert_eq!(words(r"'a\'"), [r"a\"]);
+    }
+
+    #[test]
+    fn empty_quotes_are_empty_arguments() {
+        assert_eq!(words(r#"'' "" a''"#), ["", "", "a"]);
+        assert_eq!(words(r#"a "" b"#), ["a", "", "b"]);
+    }
+
+    #[test]
+    fn unterminated_quotes_are_errors() {
+        assert_eq!(
+            split("a 'b").unwrap_err(),
+            r#"unterminated single quote in the arguments "a 'b""#
+        );
+        assert_eq!(
+            split(r#""a\""#).unwrap_err(),
+            r#"unterminated double quote in the arguments "\"a\\\"""#
+        );
+        assert_eq!(
+            split(r"a\").unwrap_err(),
+            r#"trailing backslash in the arguments "a\\""#
+        );
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn shell_quoted_words_are_split_into_the_same_words() {
+        let argv = [
+            "plain",
+            "",
+            "with space",
+            "it's",
+            r#"say "hi""#,
+            r"back\slash",
+            "$HOME `id` *",
+            "'",
+            "tab\tand\nnewline",
+            "ünïcode",
+        ];
+        let command_line: Vec<String> = argv
+            .iter()
+            .map(|word| crate::text::shell_quote(word))
+            .collect();
+        assert_eq!(words(&command_line.join(" ")), argv);
+    }
+}
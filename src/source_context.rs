@@ -0,0 +1,78 @@
+//! Excerpts of the source of a test around the line a failure refers to, so the failing code is
+//! shown next to the failure without opening the test, see `--no-source-context`.
+
+use std::path::Path;
+
+use crate::text::strip_ansi;
+
+/// The number of lines shown before and after the referenced line.
+const CONTEXT_LINES: usize = 3;
+
+/// Renders the lines of the test around the first line the stderr refers to, with the referenced
+/// line marked by carets. Returns `None` if the stderr refers to no line of the test. Every line
+/// is indented with a tab, so the excerpt can be embedded into a failure message.
+pub(crate) fn excerpt(source_file: &Path, stderr: &[u8]) -> Option<String> {
+    let stderr = String::from_utf8_lossy(&strip_ansi(stderr)).into_owned();
+    let file_name = source_file.file_name()?.to_string_lossy();
+    let line_number = referenced_line(&stderr, &file_name)?;
+    let source = std::fs::read_to_string(source_file).ok()?;
+    let lines: Vec<&str> = source.lines().collect();
+    let index = line_number
+        .checked_sub(1)
+        .filter(|index| *index < lines.len())?;
+    let first = index.saturating_sub(CONTEXT_LINES);
+    let last = (index + CONTEXT_LINES).min(lines.len() - 1);
+    let width = (last + 1).to_string().len();
+
+    let mut excerpt = format!(
+        "\tsource of {} around line {line_number}:\n",
+        source_file.display()
+    );
+    for (index, line) in lines.iter().enumerate().take(last + 1).skip(first) {
+        excerpt += &format!("\t{:>width$} | {line}\n", index + 1);
+        if index + 1 == line_number {
+            let code = line.trim_start();
+            let indentation = &line[..line.len() - code.len()];
+            excerpt += &format!(
+                "\t{:width$}   {indentation}{}\n",
+                "",
+                "^".repeat(code.chars().count().max(1))
+            );
+        }
+    }
+    Some(excerpt)
+}
+
+/// The first line number of the test the stderr refers to, either as `line <N>` or as
+/// `<file name>:<N>:`.
+fn referenced_line(stderr: &str, file_name: &str) -> Option<usize> {
+    // the number at the start of `text` and its length
+    let number = |text: &str| {
+        let length = text.len() - text.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        Some((text[..length].parse::<usize>().ok()?, length))
+    };
+    let line_references = stderr
+        .match_indices("line ")
+        .filter(|(start, _)| {
+            !stderr[..*start]
+                .chars()
+                .next_back()
+                .is_some_and(char::is_alphanumeric)
+        })
+        .filter_map(|(start, pattern)| {
+            let (line, _) = number(&stderr[start + pattern.len()..])?;
+            Some((start, line))
+        });
+    let file_pattern = format!("{file_name}:");
+    let file_references = stderr
+        .match_indices(&file_pattern)
+        .filter_map(|(start, pattern)| {
+            let rest = &stderr[start + pattern.len()..];
+            let (line, length) = number(rest)?;
+            rest[length..].starts_with(':').then_some((start, line))
+        });
+    line_references
+        .chain(file_references)
+        .min_by_key(|(start, _)| *start)
+        .map(|(_, line)| line)
+}
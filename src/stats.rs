@@ -0,0 +1,193 @@
+//! The distribution of the compile and run durations of a run, the sizes of the compiled programs
+//! and the CPU time spent in the toolchain, see `--stats`.
+
+use std::io::Write;
+use std::time::Duration;
+
+use serde::{Serialize, Serializer};
+
+use crate::{process, TestResult};
+
+/// The distribution of the durations of one stage of the tests.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct DurationStats {
+    count: usize,
+    #[serde(rename = "min_ms", serialize_with = "milliseconds")]
+    min: Duration,
+    #[serde(rename = "median_ms", serialize_with = "milliseconds")]
+    median: Duration,
+    #[serde(rename = "p90_ms", serialize_with = "milliseconds")]
+    p90: Duration,
+    #[serde(rename = "max_ms", serialize_with = "milliseconds")]
+    max: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct Stats {
+    /// `None` if no test was compiled.
+    compile: Option<DurationStats>,
+    /// `None` if no test was run in the VM.
+    run: Option<DurationStats>,
+    /// The CPU time of all child processes, `None` if it cannot be determined on this platform.
+    #[serde(rename = "children_cpu_ms", serialize_with = "optional_milliseconds")]
+    children_cpu: Option<Duration>,
+    /// How often spawning a process was retried because the OS was temporarily out of resources.
+    spawn_retries: usize,
+    /// `None` if no test was compiled successfully.
+    program_size: Option<SizeStats>,
+}
+
+/// The distribution of the sizes of the compiled programs given to the VM, to notice code
+/// generation bloat.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct SizeStats {
+    count: usize,
+    total_bytes: usize,
+    median_bytes: usize,
+    max_bytes: usize,
+    /// The name of the test with the largest program.
+    largest: String,
+}
+
+impl SizeStats {
+    /// Returns `None` if no result has a compiled program.
+    fn from_results(results: &[TestResult]) -> Option<Self> {
+        let mut sizes: Vec<(usize, &str)> = results
+            .iter()
+            .filter_map(|result| {
+                let size = result.log.program_size()?;
+                Some((size, result.report_name.as_str()))
+            })
+            .collect();
+        sizes.sort();
+        let (max_bytes, largest) = *sizes.last()?;
+        Some(Self {
+            count: sizes.len(),
+            total_bytes: sizes.iter().map(|(size, _)| size).sum(),
+            // nearest-rank median, like the durations
+            median_bytes: sizes[sizes.len().div_ceil(2).max(1) - 1].0,
+            max_bytes,
+            largest: largest.to_string(),
+        })
+    }
+}
+
+impl DurationStats {
+    /// Returns `None` if there are no durations.
+    fn from_durations(mut durations: Vec<Duration>) -> Option<Self> {
+        durations.sort();
+        // nearest-rank percentiles
+        let percentile = |percent: usize| {
+            let rank = (durations.len() * percent).div_ceil(100).max(1);
+            durations[rank - 1]
+        };
+        Some(Self {
+            count: durations.len(),
+            min: *durations.first()?,
+            median: percentile(50),
+            p90: percentile(90),
+            max: *durations.last()?,
+        })
+    }
+}
+
+impl Stats {
+    /// Collects the durations of the results. Has to be called after all tests finished, so the
+    /// CPU time of all child processes is included.
+    pub(crate) fn collect(results: &[TestResult]) -> Self {
+        let durations = |duration: fn(&TestResult) -> Option<Duration>| {
+            DurationStats::from_durations(results.iter().filter_map(duration).collect())
+        };
+        Self {
+            compile: durations(|result| result.log.compile_duration),
+            run: durations(|result| result.log.run_duration),
+            children_cpu: children_cpu_time(),
+            spawn_retries: process::spawn_retries(),
+            program_size: SizeStats::from_results(results),
+        }
+    }
+
+    /// Renders the distributions and the CPU time compared to the wall-clock time of the run.
+    pub(crate) fn render(&self, out: &mut impl Write, elapsed: Duration) -> std::io::Result<()> {
+        writeln!(out, "Durations (min / median / p90 / max):")?;
+        for (label, stats) in [("compile:", &self.compile), ("run:", &self.run)] {
+            match stats {
+                Some(stats) => writeln!(
+                    out,
+                    "  {label:<8} {:.3}s / {:.3}s / {:.3}s / {:.3}s ({} tests)",
+                    stats.min.as_secs_f64(),
+                    stats.median.as_secs_f64(),
+                    stats.p90.as_secs_f64(),
+                    stats.max.as_secs_f64(),
+                    stats.count
+                )?,
+                None => writeln!(out, "  {label:<8} n/a")?,
+            }
+        }
+        match &self.program_size {
+            Some(sizes) => writeln!(
+                out,
+                "Compiled programs ({} tests): {} bytes in total, median {} bytes, largest {} bytes ({})",
+                sizes.count, sizes.total_bytes, sizes.median_bytes, sizes.max_bytes, sizes.largest
+            )?,
+            None => writeln!(out, "Compiled programs: n/a")?,
+        }
+        match self.children_cpu {
+            Some(cpu) => writeln!(
+                out,
+                "CPU time of the toolchain: {:.2}s, wall-clock time: {:.2}s ({:.1}x)",
+                cpu.as_secs_f64(),
+                elapsed.as_secs_f64(),
+                cpu.as_secs_f64() / elapsed.as_secs_f64().max(f64::EPSILON)
+            )?,
+            None => writeln!(out, "CPU time of the toolchain: n/a")?,
+        }
+        if self.spawn_retries > 0 {
+            writeln!(
+                out,
+                "Retried spawning processes {} times because the OS was out of resources",
+                self.spawn_retries
+            )?;
+        }
+        out.flush()
+    }
+}
+
+/// The user and system CPU time of all child processes that have been waited for.
+#[cfg(unix)]
+fn children_cpu_time() -> Option<Duration> {
+    // SAFETY: `getrusage` only writes to the given struct.
+    let usage = unsafe {
+        let mut usage = std::mem::zeroed::<libc::rusage>();
+        if libc::getrusage(libc::RUSAGE_CHILDREN, &mut usage) != 0 {
+            return None;
+        }
+        usage
+    };
+    let duration = |time: libc::timeval| {
+        Duration::from_secs(time.tv_sec as u64) + Duration::from_micros(time.tv_usec as u64)
+    };
+    Some(duration(usage.ru_utime) + duration(usage.ru_stime))
+}
+
+#[cfg(not(unix))]
+fn children_cpu_time() -> Option<Duration> {
+    None
+}
+
+pub(crate) fn milliseconds<S: Serializer>(
+    duration: &Duration,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u128(duration.as_millis())
+}
+
+fn optional_milliseconds<S: Serializer>(
+    duration: &Option<Duration>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match duration {
+        Some(duration) => milliseconds(duration, serializer),
+        None => serializer.serialize_none(),
+    }
+}
@@ -0,0 +1,166 @@
+//! The live status lines below the result lines, one per worker, showing the test every worker is
+//! running and for how long. They are redrawn in place while the result lines of finished tests
+//! scroll above them, see `--no-status`.
+
+use std::io::{stderr, stdout, Write};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crossterm::cursor::MoveToPreviousLine;
+use crossterm::queue;
+use crossterm::style::{Color, Print, ResetColor, SetForegroundColor};
+use crossterm::terminal::{Clear, ClearType};
+
+/// How often the elapsed times are updated.
+const REFRESH_INTERVAL: Duration = Duration::from_millis(250);
+
+pub(crate) struct StatusBoard {
+    to_stderr: bool,
+    color: bool,
+    state: Mutex<State>,
+    /// The thread redrawing the board periodically, stopped by dropping the sender.
+    ticker: Mutex<Option<(Sender<()>, JoinHandle<()>)>>,
+}
+
+struct State {
+    /// The test every worker is running and since when, `None` if it is idle.
+    slots: Vec<Option<(String, Instant)>>,
+    /// The number of characters of every line that is currently drawn.
+    drawn: Vec<usize>,
+    stopped: bool,
+}
+
+impl StatusBoard {
+    /// Starts redrawing a board with a line for each of `workers` workers on stdout, or on
+    /// stderr if `to_stderr` is set. The output has to be a terminal.
+    pub(crate) fn start(workers: usize, to_stderr: bool, color: bool) -> Arc<Self> {
+        let board = Arc::new(Self {
+            to_stderr,
+            color,
+            state: Mutex::new(State {
+                slots: vec![None; workers.max(1)],
+                drawn: Vec::new(),
+                stopped: false,
+            }),
+            ticker: Mutex::new(None),
+        });
+        let (sender, receiver) = mpsc::channel::<()>();
+        let ticking = Arc::clone(&board);
+        let ticker = std::thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = receiver.recv_timeout(REFRESH_INTERVAL) {
+                ticking.write_above(&[]);
+            }
+        });
+        *board.ticker.lock().expect("the ticker is never poisoned") = Some((sender, ticker));
+        board
+    }
+
+    /// Shows that the worker with the given index started running the test.
+    pub(crate) fn started(&self, worker: usize, name: &str) {
+        self.set_slot(worker, Some((name.to_string(), Instant::now())));
+    }
+
+    /// Shows that the worker with the given index is idle.
+    pub(crate) fn finished(&self, worker: usize) {
+        self.set_slot(worker, None);
+    }
+
+    fn set_slot(&self, slot: usize, test: Option<(String, Instant)>) {
+        let mut state = self
+            .state
+            .lock()
+            .expect("the status board is never poisoned");
+        if let Some(entry) = state.slots.get_mut(slot) {
+            *entry = test;
+        }
+    }
+
+    /// Writes `bytes`, which have to consist of whole lines, above the board and redraws it.
+    pub(crate) fn write_above(&self, bytes: &[u8]) {
+        let mut state = self
+            .state
+            .lock()
+            .expect("the status board is never poisoned");
+        let written = match self.to_stderr {
+            true => self.redraw(&mut stderr().lock(), &mut state, bytes),
+            false => self.redraw(&mut stdout().lock(), &mut state, bytes),
+        };
+        written.expect("unable to print output");
+    }
+
+    /// Stops redrawing the board and removes it, so later output is written where it was.
+    pub(crate) fn stop(&self) {
+        let ticker = self
+            .ticker
+            .lock()
+            .expect("the ticker is never poisoned")
+            .take();
+        if let Some((sender, ticker)) = ticker {
+            drop(sender);
+            ticker.join().expect("the ticker never panics");
+        }
+        self.state
+            .lock()
+            .expect("the status board is never poisoned")
+            .stopped = true;
+        self.write_above(&[]);
+    }
+
+    fn redraw(&self, out: &mut impl Write, state: &mut State, bytes: &[u8]) -> std::io::Result<()> {
+        // the width may have changed since the board was drawn, so lines may have been wrapped
+        let width = crossterm::terminal::size()
+            .map_or(80, |(columns, _)| usize::from(columns))
+            .max(1);
+        let rows: usize = state
+            .drawn
+            .iter()
+            .map(|length| length.div_ceil(width).max(1))
+            .sum();
+        if rows > 0 {
+            let rows = u16::try_from(rows).unwrap_or(u16::MAX);
+            queue!(
+                out,
+                MoveToPreviousLine(rows),
+                Clear(ClearType::FromCursorDown)
+            )?;
+        }
+        out.write_all(bytes)?;
+        state.drawn.clear();
+        if !state.stopped {
+            let now = Instant::now();
+            for (worker, slot) in state.slots.iter().enumerate() {
+                let label = format!("  #{:<2} ", worker + 1);
+                let (text, color) = match slot {
+                    Some((name, started)) => (
+                        format!(
+                            "{name} ({:.1}s)",
+                            now.duration_since(*started).as_secs_f64()
+                        ),
+                        Color::DarkCyan,
+                    ),
+                    None => ("idle".to_string(), Color::DarkGrey),
+                };
+                // a line never wraps, so the cursor can be moved back to the start of the board
+                let text: String = text
+                    .chars()
+                    .take(width.saturating_sub(label.len() + 1))
+                    .collect();
+                state.drawn.push(label.len() + text.chars().count());
+                match self.color {
+                    true => queue!(
+                        out,
+                        Print(label),
+                        SetForegroundColor(color),
+                        Print(text),
+                        ResetColor,
+                        Print("\n")
+                    )?,
+                    false => queue!(out, Print(label), Print(text), Print("\n"))?,
+                }
+            }
+        }
+        out.flush()
+    }
+}
@@ -0,0 +1,161 @@
+//! Lines of the stderr of the toolchain that carry no information about a test, e.g. a banner the
+//! Backseater prints before every error. They are removed before the stderr is matched against
+//! the expectations and included in failure messages, see `--ignore-stderr-pattern`. The logs
+//! keep the stderr as it was written.
+
+use std::fmt;
+
+use regex::Regex;
+
+use crate::text::strip_ansi;
+
+/// A regular expression matching the lines to remove. A line matches if the expression matches
+/// any part of it.
+#[derive(Clone)]
+pub(crate) struct NoisePattern(Regex);
+
+impl NoisePattern {
+    pub(crate) fn parse(pattern: &str) -> Result<Self, String> {
+        Regex::new(pattern)
+            .map(Self)
+            .map_err(|error| format!("invalid regular expression {pattern:?}: {error}"))
+    }
+
+    pub(crate) fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+}
+
+impl PartialEq for NoisePattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl fmt::Debug for NoisePattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+/// Removes the lines of `stderr` that match one of the patterns. Lines are matched without their
+/// line break and ANSI escape sequences, but kept as they are otherwise.
+pub(crate) fn remove<'a>(
+    stderr: &[u8],
+    patterns: impl Iterator<Item = &'a NoisePattern> + Clone,
+) -> Vec<u8> {
+    if patterns.clone().next().is_none() {
+        return stderr.to_vec();
+    }
+    stderr
+        .split_inclusive(|byte| *byte == b'\n')
+        .filter(|line| {
+            let line = String::from_utf8_lossy(&strip_ansi(line)).into_owned();
+            let line = line.trim_end_matches(['\n', '\r']);
+            !patterns.clone().any(|pattern| pattern.0.is_match(line))
+        })
+        .flatten()
+        .copied()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn remove_matching(patterns: &[&str], stderr: &str) -> String {
+        let patterns: Vec<_> = patterns
+            .iter()
+            .map(|pattern| NoisePattern::parse(pattern).unwrap())
+            .collect();
+        String::from_utf8(remove(stderr.as_bytes(), patterns.iter())).unwrap()
+    }
+
+    #[test]
+    fn invalid_regular_expressions_are_rejected() {
+        for pattern in ["(", "[a-", "a{2,1}", "\\p{Unknown}"] {
+            let error = NoisePattern::parse(pattern).unwrap_err();
+            assert!(
+                error.starts_with(&format!("invalid regular expression {pattern:?}: ")),
+                "{error}"
+            );
+        }
+        assert_eq!(
+            NoisePattern::parse("^banner$").unwrap().as_str(),
+            "^banner$"
+        );
+    }
+
+    #[test]
+    fn patterns_match_any_part_of_a_line() {
+        let stderr = "Backseater 1.0\nerror: Backseater crashed\nerror: out of memory\n";
+        assert_eq!(
+            remove_matching(&["Backseater"], stderr),
+            "error: out of memory\n"
+        );
+    }
+
+    #[test]
+    fn anchors_match_at_the_ends_of_each_line() {
+        let stderr = "banner\nnot a banner\nbanner line\n";
+        assert_eq!(
+            remove_matching(&["^banner$"], stderr),
+            "not a banner\nbanner line\n"
+        );
+        assert_eq!(remove_matching(&["^banner"], stderr), "not a banner\n");
+        assert_eq!(remove_matching(&["banner$"], stderr), "banner line\n");
+    }
+
+    #[test]
+    fn crlf_line_breaks_are_not_matched_but_kept() {
+        let stderr = "banner\r\nerror\r\n";
+        assert_eq!(remove_matching(&["^banner$"], stderr), "error\r\n");
+        assert_eq!(remove_matching(&["^error$"], stderr), "banner\r\n");
+    }
+
+    #[test]
+    fn lines_are_matched_without_ansi_escape_sequences() {
+        let stderr = "\x1b[1mbanner\x1b[0m\nerror\n";
+        assert_eq!(remove_matching(&["^banner$"], stderr), "error\n");
+        assert_eq!(
+            remove_matching(&["^error$"], stderr),
+            "\x1b[1mbanner\x1b[0m\n"
+        );
+    }
+
+    #[test]
+    fn trailing_whitespace_is_part_of_the_line() {
+        assert_eq!(remove_matching(&["^banner$"], "banner \n"), "banner \n");
+    }
+
+    #[test]
+    fn a_last_line_without_a_line_break_is_matched() {
+        assert_eq!(remove_matching(&["^banner$"], "error\nbanner"), "error\n");
+        assert_eq!(remove_matching(&["^error$"], "error\nbanner"), "banner");
+    }
+
+    #[test]
+    fn lines_matching_any_pattern_are_removed() {
+        let stderr = "a\nb\nc\n";
+        assert_eq!(remove_matching(&["^a$", "^c$"], stderr), "b\n");
+        assert_eq!(remove_matching(&[], stderr), stderr);
+        // an empty pattern matches every line
+        assert_eq!(remove_matching(&[""], stderr), "");
+    }
+
+    #[test]
+    fn patterns_are_compared_by_their_source() {
+        assert_eq!(
+            NoisePattern::parse("a+").unwrap(),
+            NoisePattern::parse("a+").unwrap()
+        );
+        assert_ne!(
+            NoisePattern::parse("a+").unwrap(),
+            NoisePattern::parse("aa*").unwrap()
+        );
+        assert_eq!(
+            format!("{:?}", NoisePattern::parse("a+").unwrap()),
+            "\"a+\""
+        );
+    }
+}
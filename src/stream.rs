@@ -0,0 +1,46 @@
+//! Live output of the Backseater while a test runs, see `--stream-output`. Only one test streams at
+//! a time, so the lines of concurrently running tests do not interleave. The others are captured
+//! as usual.
+
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::process::LineSink;
+
+/// Whether a test is currently streaming its output.
+static STREAMING: AtomicBool = AtomicBool::new(false);
+
+/// The permission to stream, held by one test at a time until it is dropped.
+pub(crate) struct StreamSlot(());
+
+impl StreamSlot {
+    /// Claims the slot. Returns `None` if another test is already streaming.
+    pub(crate) fn claim() -> Option<Self> {
+        STREAMING
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+            .ok()
+            .map(|_| Self(()))
+    }
+
+    /// A sink printing every line to stderr, prefixed with the name of the test. The slot is
+    /// released once the sink, and thereby the process, is done.
+    pub(crate) fn sink(self, name: &str) -> LineSink {
+        let prefix = format!("{name} | ");
+        Box::new(move |line: &[u8]| {
+            // the slot is owned by the sink, so it is released when the sink is dropped
+            let _slot = &self;
+            let mut stderr = std::io::stderr().lock();
+            stderr.write_all(prefix.as_bytes()).ok();
+            stderr.write_all(line).ok();
+            if !line.ends_with(b"\n") {
+                stderr.write_all(b"\n").ok();
+            }
+        })
+    }
+}
+
+impl Drop for StreamSlot {
+    fn drop(&mut self) {
+        STREAMING.store(false, Ordering::SeqCst);
+    }
+}
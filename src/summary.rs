@@ -0,0 +1,444 @@
+use std::io::Write;
+use std::time::Duration;
+
+use crossterm::queue;
+use crossterm::style::{Color, Print, ResetColor, SetForegroundColor};
+
+use crate::selection::Selection;
+use crate::stats::Stats;
+use crate::test_log::Stage;
+use crate::timings::RunnerTimings;
+use crate::{TestResult, TestResultKind};
+
+/// The aggregated outcome of a test run.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Summary {
+    /// The number of tests that were executed, i.e. all tests that were not skipped.
+    pub(crate) run: usize,
+    pub(crate) failed: usize,
+    pub(crate) skipped: usize,
+    /// The number of executed quarantined tests that failed, which are not counted as failed.
+    pub(crate) quarantined: usize,
+    /// The number of failed tests that finished although they were expected to abort. They are
+    /// included in `failed`.
+    pub(crate) stale: usize,
+    /// The number of failed tests whose compiler or VM crashed. They are included in `failed`.
+    pub(crate) crashed: usize,
+    /// The number of successful tests with a failure directive that aborted at compile time.
+    pub(crate) aborted_at_compile: usize,
+    /// The number of successful tests with a failure directive that aborted at runtime.
+    pub(crate) aborted_at_run: usize,
+    /// The number of tests that were neither run nor skipped because the run was stopped early.
+    pub(crate) not_run: usize,
+    /// The wall-clock time of the whole run.
+    pub(crate) elapsed: Duration,
+    /// Whether the run was stopped early via `--fail-fast`, `--total-timeout` or Ctrl-C, so not
+    /// all tests were run.
+    pub(crate) partial: bool,
+    /// The distribution of the durations, only collected under `--stats`.
+    pub(crate) stats: Option<Stats>,
+    /// The time the test runner itself spent in the phases of the run, only collected under
+    /// `--verbose` and `--stats`.
+    pub(crate) runner_timings: Option<RunnerTimings>,
+    /// How many of the discovered tests were selected and why the others were not, only set for
+    /// whole runs.
+    pub(crate) selection: Option<Selection>,
+}
+
+impl Summary {
+    /// Counts the outcomes of the given results. The elapsed time is left at zero.
+    pub(crate) fn from_results<'a>(results: impl IntoIterator<Item = &'a TestResult>) -> Self {
+        let mut summary = Self::default();
+        for result in results {
+            summary.record(result);
+        }
+        summary
+    }
+
+    /// Counts the outcome of a single result. This is the only place the counters are updated,
+    /// so they cannot disagree with each other.
+    pub(crate) fn record(&mut self, result: &TestResult) {
+        match result.kind {
+            TestResultKind::Success => {
+                self.run += 1;
+                match result.log.aborted_stage {
+                    Some(Stage::Compile) => self.aborted_at_compile += 1,
+                    Some(Stage::Run) => self.aborted_at_run += 1,
+                    None => {}
+                }
+            }
+            TestResultKind::Failure(..) => {
+                self.run += 1;
+                self.failed += 1;
+            }
+            TestResultKind::Crash(_) => {
+                self.run += 1;
+                self.failed += 1;
+                self.crashed += 1;
+            }
+            TestResultKind::UnexpectedSuccess(_) => {
+                self.run += 1;
+                self.failed += 1;
+                self.stale += 1;
+            }
+            TestResultKind::Skipped(_) => self.skipped += 1,
+            TestResultKind::Quarantined(..) => {
+                self.run += 1;
+                self.quarantined += 1;
+            }
+        }
+    }
+
+    pub(crate) fn successful(&self) -> usize {
+        self.run - self.failed - self.quarantined
+    }
+
+    /// The percentage of executed tests that succeeded, or `None` if no test was executed.
+    pub(crate) fn pass_percentage(&self) -> Option<f64> {
+        (self.run > 0).then(|| self.successful() as f64 * 100.0 / self.run as f64)
+    }
+
+    pub(crate) fn succeeded(&self) -> bool {
+        self.failed == 0
+    }
+
+    /// Renders the summary line. The line is green if no test failed and red otherwise, the counts
+    /// of the individual categories are colored on their own. Nothing is colored unless `color`
+    /// is set, see [`crate::output::use_color`].
+    pub(crate) fn render(&self, out: &mut impl Write, color: bool) -> std::io::Result<()> {
+        let line_color = if self.succeeded() {
+            Color::DarkGreen
+        } else {
+            Color::DarkRed
+        };
+        let colors = Colors {
+            enabled: color,
+            line: line_color,
+        };
+        colors.set(out, line_color)?;
+        queue!(out, Print(format!("Tests run: {}, ", self.run)))?;
+        render_count(
+            out,
+            "Tests successful",
+            self.successful(),
+            Color::DarkGreen,
+            &colors,
+        )?;
+        render_count(out, "Tests failed", self.failed, Color::DarkRed, &colors)?;
+        if self.crashed > 0 {
+            render_count(out, "Crashes", self.crashed, Color::DarkRed, &colors)?;
+        }
+        if self.stale > 0 {
+            render_count(
+                out,
+                "Stale failure directives",
+                self.stale,
+                Color::DarkYellow,
+                &colors,
+            )?;
+        }
+        if self.quarantined > 0 {
+            render_count(
+                out,
+                "Tests quarantined",
+                self.quarantined,
+                Color::DarkYellow,
+                &colors,
+            )?;
+        }
+        if self.skipped > 0 {
+            render_count(
+                out,
+                "Tests skipped",
+                self.skipped,
+                Color::DarkYellow,
+                &colors,
+            )?;
+        }
+        if self.not_run > 0 {
+            render_count(
+                out,
+                "Tests not run",
+                self.not_run,
+                Color::DarkYellow,
+                &colors,
+            )?;
+        }
+        if self.aborted_at_compile + self.aborted_at_run > 0 {
+            queue!(
+                out,
+                Print(format!(
+                    "Expected aborts at compile/run time: {}/{}, ",
+                    self.aborted_at_compile, self.aborted_at_run
+                ))
+            )?;
+        }
+        let pass_percentage = match self.pass_percentage() {
+            Some(percentage) => format!("{percentage:.1}%"),
+            None => String::from("n/a"),
+        };
+        queue!(
+            out,
+            Print(format!(
+                "Pass rate: {}, Time: {:.2}s",
+                pass_percentage,
+                self.elapsed.as_secs_f64()
+            ))
+        )?;
+        colors.reset(out)?;
+        queue!(out, Print("\n"))?;
+        out.flush()
+    }
+}
+
+/// Renders one indented line per group of tests, e.g. the tests below one tests path, with the
+/// counts of the group. The lines are only colored if `color` is set.
+pub(crate) fn render_breakdown(
+    out: &mut impl Write,
+    groups: &[(&str, Summary)],
+    color: bool,
+) -> std::io::Result<()> {
+    for (label, summary) in groups {
+        let line_color = if summary.succeeded() {
+            Color::DarkGreen
+        } else {
+            Color::DarkRed
+        };
+        let colors = Colors {
+            enabled: color,
+            line: line_color,
+        };
+        let mut line = format!(
+            "  {}: run: {}, successful: {}, failed: {}",
+            label,
+            summary.run,
+            summary.successful(),
+            summary.failed
+        );
+        if summary.quarantined > 0 {
+            line += &format!(", quarantined: {}", summary.quarantined);
+        }
+        if summary.skipped > 0 {
+            line += &format!(", skipped: {}", summary.skipped);
+        }
+        colors.set(out, line_color)?;
+        queue!(out, Print(line))?;
+        colors.reset(out)?;
+        queue!(out, Print("\n"))?;
+    }
+    out.flush()
+}
+
+/// The colors of a rendered line, which are only written if they are enabled.
+struct Colors {
+    enabled: bool,
+    /// The color of the line outside of highlighted counts.
+    line: Color,
+}
+
+impl Colors {
+    fn set(&self, out: &mut impl Write, color: Color) -> std::io::Result<()> {
+        match self.enabled {
+            true => queue!(out, SetForegroundColor(color)),
+            false => Ok(()),
+        }
+    }
+
+    fn reset(&self, out: &mut impl Write) -> std::io::Result<()> {
+        match self.enabled {
+            true => queue!(out, ResetColor),
+            false => Ok(()),
+        }
+    }
+}
+
+/// Renders `label: count, ` with the count in `count_color` if it is nonzero and everything else
+/// in the color of the line.
+fn render_count(
+    out: &mut impl Write,
+    label: &str,
+    count: usize,
+    count_color: Color,
+    colors: &Colors,
+) -> std::io::Result<()> {
+    let count_color = if count > 0 { count_color } else { colors.line };
+    queue!(out, Print(format!("{label}: ")))?;
+    colors.set(out, count_color)?;
+    queue!(out, Print(count))?;
+    colors.set(out, colors.line)?;
+    queue!(out, Print(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{render_breakdown, Summary};
+    use crate::discovery::TestFile;
+    use crate::failure_code::FailureCode;
+    use crate::test_log::{Stage, TestLog};
+    use crate::{TestResult, TestResultKind};
+
+    fn result(kind: TestResultKind, aborted_stage: Option<Stage>) -> TestResult {
+        TestResult {
+            filename: "test_a.bs".to_string(),
+            report_name: "test_a.bs".to_string(),
+            test: TestFile {
+                name: "test_a.bs".to_string(),
+                root: 0,
+                path: "test_a.bs".into(),
+                source_path: "test_a.bs".into(),
+                quarantined: false,
+                overrides: Default::default(),
+            },
+            configuration: None,
+            kind,
+            duration: Duration::ZERO,
+            log: TestLog {
+                aborted_stage,
+                ..TestLog::default()
+            },
+            log_error: None,
+        }
+    }
+
+    fn rendered(summary: &Summary, color: bool) -> String {
+        let mut out = Vec::new();
+        summary.render(&mut out, color).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn failed_run() -> Summary {
+        Summary {
+            run: 10,
+            failed: 3,
+            skipped: 2,
+            quarantined: 1,
+            stale: 1,
+            crashed: 1,
+            aborted_at_compile: 2,
+            aborted_at_run: 1,
+            not_run: 4,
+            elapsed: Duration::from_millis(1234),
+            ..Summary::default()
+        }
+    }
+
+    #[test]
+    fn every_count_is_rendered() {
+        assert_eq!(
+            rendered(&failed_run(), false),
+            "Tests run: 10, Tests successful: 6, Tests failed: 3, Crashes: 1, \
+             Stale failure directives: 1, Tests quarantined: 1, Tests skipped: 2, \
+             Tests not run: 4, Expected aborts at compile/run time: 2/1, Pass rate: 60.0%, \
+             Time: 1.23s\n"
+        );
+    }
+
+    #[test]
+    fn empty_categories_are_left_out() {
+        let summary = Summary {
+            run: 2,
+            elapsed: Duration::from_millis(5),
+            ..Summary::default()
+        };
+        assert_eq!(
+            rendered(&summary, false),
+            "Tests run: 2, Tests successful: 2, Tests failed: 0, Pass rate: 100.0%, Time: 0.01s\n"
+        );
+        assert_eq!(
+            rendered(&Summary::default(), false),
+            "Tests run: 0, Tests successful: 0, Tests failed: 0, Pass rate: n/a, Time: 0.00s\n"
+        );
+    }
+
+    #[test]
+    fn colors_are_only_rendered_if_enabled() {
+        let colored = rendered(&failed_run(), true);
+        assert!(colored.starts_with("\x1b["), "{colored:?}");
+        assert!(colored.ends_with("\x1b[0m\n"), "{colored:?}");
+        assert!(!rendered(&failed_run(), false).contains('\x1b'));
+
+        let mut out = Vec::new();
+        render_breakdown(&mut out, &[("tests", failed_run())], false).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "  tests: run: 10, successful: 6, failed: 3, quarantined: 1, skipped: 2\n"
+        );
+    }
+
+    #[test]
+    fn every_outcome_is_counted() {
+        let failure = || TestResultKind::Failure(FailureCode::WrongMessage, String::new());
+        let results = [
+            result(TestResultKind::Success, None),
+            result(TestResultKind::Success, Some(Stage::Compile)),
+            result(TestResultKind::Success, Some(Stage::Run)),
+            result(TestResultKind::Success, Some(Stage::Run)),
+            result(failure(), None),
+            result(failure(), Some(Stage::Run)),
+            result(TestResultKind::Crash(String::new()), None),
+            result(TestResultKind::UnexpectedSuccess(String::new()), None),
+            result(TestResultKind::Skipped(String::new()), None),
+            result(
+                TestResultKind::Quarantined(FailureCode::Timeout, String::new()),
+                None,
+            ),
+        ];
+        let summary = Summary::from_results(&results);
+        assert_eq!(
+            summary,
+            Summary {
+                run: 9,
+                failed: 4,
+                skipped: 1,
+                quarantined: 1,
+                stale: 1,
+                crashed: 1,
+                aborted_at_compile: 1,
+                aborted_at_run: 2,
+                ..Summary::default()
+            }
+        );
+        assert_eq!(summary.successful(), 4);
+        assert!(!summary.succeeded());
+    }
+
+    #[test]
+    fn recording_agrees_with_counting_all_results() {
+        let results = [
+            result(TestResultKind::Success, Some(Stage::Compile)),
+            result(TestResultKind::Crash(String::new()), None),
+            result(TestResultKind::Skipped(String::new()), None),
+        ];
+        let mut summary = Summary::default();
+        for result in &results {
+            summary.record(result);
+        }
+        assert_eq!(summary, Summary::from_results(&results));
+    }
+
+    #[test]
+    fn only_failures_fail_the_run() {
+        let passing = [
+            result(TestResultKind::Success, None),
+            result(TestResultKind::Skipped(String::new()), None),
+            result(
+                TestResultKind::Quarantined(FailureCode::Timeout, String::new()),
+                None,
+            ),
+        ];
+        let summary = Summary::from_results(&passing);
+        assert!(summary.succeeded());
+        assert_eq!(summary.pass_percentage(), Some(50.0));
+        assert!(Summary::from_results(&[]).succeeded());
+        assert_eq!(Summary::from_results(&[]).pass_percentage(), None);
+        for kind in [
+            TestResultKind::Failure(FailureCode::Infra, String::new()),
+            TestResultKind::Crash(String::new()),
+            TestResultKind::UnexpectedSuccess(String::new()),
+        ] {
+            assert!(!Summary::from_results(&[result(kind, None)]).succeeded());
+        }
+    }
+}
@@ -0,0 +1,196 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::directives::TestOutcome;
+use crate::TestResultKind;
+
+/// The commands and outputs of the toolchain recorded while running a single test.
+#[derive(Debug, Default)]
+pub(crate) struct TestLog {
+    pub(crate) commands: Vec<String>,
+    pub(crate) compiler_stderr: Option<Vec<u8>>,
+    /// The stdout of the compiler if the compilation failed and it printed anything.
+    pub(crate) compiler_stdout: Option<Vec<u8>>,
+    /// The size of the compiled program and its start as rendered according to
+    /// `--stdin-encoding`, if the compilation succeeded.
+    pub(crate) compiled_program: Option<(usize, String)>,
+    pub(crate) vm_stdout: Option<Vec<u8>>,
+    pub(crate) vm_stderr: Option<Vec<u8>>,
+    /// How long the compiler ran, if it was started.
+    pub(crate) compile_duration: Option<Duration>,
+    /// How long the VM ran, if it was started.
+    pub(crate) run_duration: Option<Duration>,
+    /// The seed passed to the VM, see `--seed-arg`.
+    pub(crate) seed: Option<u64>,
+    /// How the test was expected to end, if its directives are valid.
+    pub(crate) expected_outcome: Option<TestOutcome>,
+    /// The timeout of the VM, given via `--timeout` or a `timeout` directive.
+    pub(crate) timeout: Option<Duration>,
+    /// The scratch directory of the test, see `TEST_TMPDIR`.
+    pub(crate) tmp_dir: Option<PathBuf>,
+    /// Why the successful test looks like it did not test anything, see [`crate::suspicion`].
+    pub(crate) suspicion: Option<String>,
+    /// The stages of the test whose process was run, in the order they were run.
+    pub(crate) stages: Vec<StageRecord>,
+    /// The stage the test aborted in, if it did.
+    pub(crate) aborted_stage: Option<Stage>,
+}
+
+/// A stage of a test that runs a process of the toolchain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Stage {
+    Compile,
+    Run,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stage::Compile => write!(f, "compile"),
+            Stage::Run => write!(f, "run"),
+        }
+    }
+}
+
+/// How the process of a stage ended. Its outputs are recorded in the [`TestLog`].
+#[derive(Debug)]
+pub(crate) struct StageRecord {
+    pub(crate) stage: Stage,
+    pub(crate) duration: Duration,
+    /// The exit code of the process, `None` if it was killed, e.g. at its timeout.
+    pub(crate) exit_code: Option<i32>,
+}
+
+/// The path of the log of the test with the given file stem, see [`crate::file_stem`].
+pub(crate) fn log_path(log_dir: &Path, file_stem: &str) -> PathBuf {
+    log_dir.join(format!("{file_stem}.log"))
+}
+
+/// Removes the logs below `log_dir` that are not among the given ones, e.g. those of deleted
+/// tests, and the directories that become empty. Files that are not logs are kept. Returns the
+/// number of removed logs.
+pub(crate) fn prune(log_dir: &Path, logs: &HashSet<PathBuf>) -> std::io::Result<usize> {
+    let mut pruned = 0;
+    for entry in std::fs::read_dir(log_dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            pruned += prune(&path, logs)?;
+            // fails if the directory still contains something, which is fine
+            std::fs::remove_dir(&path).ok();
+        } else if path.extension().is_some_and(|extension| extension == "log")
+            && !logs.contains(&path)
+        {
+            std::fs::remove_file(&path)?;
+            pruned += 1;
+        }
+    }
+    Ok(pruned)
+}
+
+impl TestLog {
+    /// The size of the program the VM was given in bytes, if the compilation succeeded.
+    pub(crate) fn program_size(&self) -> Option<usize> {
+        self.compiled_program.as_ref().map(|(size, _)| *size)
+    }
+
+    /// The stage a failed test failed in, i.e. the last one that was run. `None` if the test did
+    /// not fail or failed before any stage was run, e.g. because of invalid directives.
+    pub(crate) fn failed_stage(&self, kind: &TestResultKind) -> Option<Stage> {
+        let failed = matches!(
+            kind,
+            TestResultKind::Failure(..)
+                | TestResultKind::Quarantined(..)
+                | TestResultKind::UnexpectedSuccess(_)
+                | TestResultKind::Crash(_)
+        );
+        self.stages
+            .last()
+            .filter(|_| failed)
+            .map(|record| record.stage)
+    }
+
+    /// The stdout and the stderr recorded for the stage.
+    pub(crate) fn stage_output(&self, stage: Stage) -> (Option<&[u8]>, Option<&[u8]>) {
+        match stage {
+            Stage::Compile => (
+                self.compiler_stdout.as_deref(),
+                self.compiler_stderr.as_deref(),
+            ),
+            Stage::Run => (self.vm_stdout.as_deref(), self.vm_stderr.as_deref()),
+        }
+    }
+
+    /// Writes the log to `path` atomically, creating the parent directories if necessary.
+    pub(crate) fn write(
+        &self,
+        path: &Path,
+        duration: Duration,
+        kind: &TestResultKind,
+    ) -> anyhow::Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        crate::write_atomic(path, self.render(duration, kind).as_bytes())?;
+        Ok(())
+    }
+
+    /// Renders the commands, the result and the outputs of the test as text.
+    pub(crate) fn render(&self, duration: Duration, kind: &TestResultKind) -> String {
+        let mut contents = String::new();
+        for command in &self.commands {
+            contents += &format!("command: {command}\n");
+        }
+        if let Some(seed) = self.seed {
+            contents += &format!("seed: {seed}\n");
+        }
+        if let Some(timeout) = self.timeout {
+            contents += &format!("timeout: {}s\n", timeout.as_secs_f64());
+        }
+        if let Some(tmp_dir) = &self.tmp_dir {
+            contents += &format!("{}: {}\n", crate::scratch::TEST_TMPDIR, tmp_dir.display());
+        }
+        contents += &format!("duration: {:.3}s\n", duration.as_secs_f64());
+        if let Some(expected_outcome) = &self.expected_outcome {
+            contents += &format!("expected: {expected_outcome}\n");
+        }
+        contents += &format!("outcome: {}\n", kind.status());
+        if let Some(stage) = self.failed_stage(kind) {
+            contents += &format!("failed stage: {stage}\n");
+        }
+        if let Some(suspicion) = &self.suspicion {
+            contents += &format!("suspicious: {suspicion}\n");
+        }
+        if let TestResultKind::Failure(_, message)
+        | TestResultKind::Skipped(message)
+        | TestResultKind::Quarantined(_, message)
+        | TestResultKind::UnexpectedSuccess(message)
+        | TestResultKind::Crash(message) = kind
+        {
+            if !message.is_empty() {
+                contents += &format!("{}\n", message.trim_end());
+            }
+        }
+
+        if let Some((size, program)) = &self.compiled_program {
+            contents += &format!("\n===== compiled program ({size} bytes) =====\n{program}");
+        }
+        let sections = [
+            ("compiler stdout", &self.compiler_stdout),
+            ("compiler stderr", &self.compiler_stderr),
+            ("VM stdout", &self.vm_stdout),
+            ("VM stderr", &self.vm_stderr),
+        ];
+        for (label, output) in sections {
+            if let Some(output) = output {
+                contents += &format!("\n===== {label} =====\n");
+                contents += &String::from_utf8_lossy(output);
+            }
+        }
+        contents
+    }
+}
@@ -0,0 +1,186 @@
+//! Helpers for processing the text captured from the toolchain.
+
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+
+const ESCAPE: u8 = 0x1b;
+const BELL: u8 = 0x07;
+
+/// Removes ANSI escape sequences (e.g. colors and cursor movement) from `bytes`.
+pub(crate) fn strip_ansi(bytes: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        if bytes[index] != ESCAPE {
+            result.push(bytes[index]);
+            index += 1;
+            continue;
+        }
+        index += 1;
+        match bytes.get(index) {
+            // control sequence: parameters and intermediate bytes followed by a final byte
+            Some(b'[') => {
+                index += 1;
+                while index < bytes.len() && !(0x40..=0x7e).contains(&bytes[index]) {
+                    index += 1;
+                }
+                index += 1;
+            }
+            // operating system command: terminated by BEL or ESC \
+            Some(b']') => {
+                index += 1;
+                while index < bytes.len() {
+                    if bytes[index] == BELL {
+                        index += 1;
+                        break;
+                    }
+                    if bytes[index] == ESCAPE && bytes.get(index + 1) == Some(&b'\\') {
+                        index += 2;
+                        break;
+                    }
+                    index += 1;
+                }
+            }
+            // two-byte escape sequence
+            Some(_) => index += 1,
+            None => {}
+        }
+    }
+    result
+}
+
+/// Replaces every occurrence of the given paths in `text` with their placeholders. Longer paths
+/// are replaced first, so no path is replaced partially because it starts with another one. On
+/// Windows, backslashes are collapsed to forward slashes in the text and the paths beforehand.
+pub(crate) fn normalize_paths(text: &[u8], paths: &[(PathBuf, &str)]) -> Vec<u8> {
+    let normalize_separators = |text: String| match cfg!(windows) {
+        true => text.replace('\\', "/"),
+        false => text,
+    };
+    let mut paths: Vec<_> = paths
+        .iter()
+        .map(|(path, placeholder)| {
+            let path = normalize_separators(path.display().to_string());
+            (path, *placeholder)
+        })
+        .filter(|(path, _)| !path.is_empty())
+        .collect();
+    paths.sort_by_key(|(path, _)| std::cmp::Reverse(path.len()));
+
+    let mut text = normalize_separators(String::from_utf8_lossy(text).into_owned());
+    for (path, placeholder) in paths {
+        text = text.replace(&path, placeholder);
+    }
+    text.into_bytes()
+}
+
+/// Quotes a word for a POSIX shell if it contains characters the shell would interpret.
+#[cfg(not(windows))]
+pub(crate) fn shell_quote(word: &str) -> String {
+    let is_plain = !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "/._-+=:,@".contains(c));
+    if is_plain {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', "'\\''"))
+    }
+}
+
+/// Quotes a word for PowerShell if it contains characters PowerShell would interpret.
+#[cfg(windows)]
+pub(crate) fn shell_quote(word: &str) -> String {
+    let is_plain = !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "/\\._-+=:".contains(c));
+    if is_plain {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', "''"))
+    }
+}
+
+/// Quotes the executable at the start of a shell command. PowerShell only runs a quoted executable
+/// with the call operator `&`.
+pub(crate) fn shell_executable(path: &Path) -> String {
+    let quoted = shell_quote(&path.display().to_string());
+    match cfg!(windows) && quoted.starts_with('\'') {
+        true => format!("& {quoted}"),
+        false => quoted,
+    }
+}
+
+/// Renders the assignments of environment variables in front of a shell command. PowerShell does
+/// not support assignments that only apply to a single command, so they become statements of their
+/// own there, which also apply to the commands after them.
+pub(crate) fn shell_env(variables: &[(&str, String)]) -> String {
+    variables
+        .iter()
+        .map(|(name, value)| match cfg!(windows) {
+            true => format!("$env:{name} = {}; ", shell_quote(value)),
+            false => format!("{name}={} ", shell_quote(value)),
+        })
+        .collect()
+}
+
+/// Keeps the first and the last `context` lines of `text` and replaces the lines in between with
+/// a marker stating how many lines were elided. Texts of at most `2 * context + 1` lines are
+/// returned as is, since the marker would not make them any shorter.
+pub(crate) fn elide_middle_lines(text: &str, context: usize) -> Cow<'_, str> {
+    let lines: Vec<_> = text.split_inclusive('\n').collect();
+    if lines.len() <= 2 * context + 1 {
+        return Cow::Borrowed(text);
+    }
+    let elided = lines.len() - 2 * context;
+    let mut truncated = lines[..context].concat();
+    truncated += &format!("\t... {elided} lines elided, run with --full-output to see them ...\n");
+    truncated += &lines[lines.len() - context..].concat();
+    Cow::Owned(truncated)
+}
+
+/// Renders at most the first `limit` bytes of `bytes` as hex with 16 bytes per line, each line
+/// starting with its offset. The number of omitted bytes is noted on an extra line.
+pub(crate) fn hex_dump(bytes: &[u8], limit: usize) -> String {
+    let mut dump = String::new();
+    for (index, line) in bytes[..bytes.len().min(limit)].chunks(16).enumerate() {
+        let hex: Vec<_> = line.iter().map(|byte| format!("{byte:02x}")).collect();
+        dump += &format!("{:08x}  {}\n", index * 16, hex.join(" "));
+    }
+    if bytes.len() > limit {
+        dump += &format!("... {} more bytes\n", bytes.len() - limit);
+    }
+    dump
+}
+
+#[cfg(test)]
+mod tests {
+    use super::strip_ansi;
+
+    fn stripped(bytes: &[u8]) -> String {
+        String::from_utf8(strip_ansi(bytes)).unwrap()
+    }
+
+    #[test]
+    fn colors_and_cursor_movement_are_stripped() {
+        assert_eq!(
+            stripped(b"\x1b[1;31merror\x1b[0m: \x1b[2Kunused \x1b[38;5;208mvariable\x1b[m\n"),
+            "error: unused variable\n"
+        );
+    }
+
+    #[test]
+    fn operating_system_commands_are_stripped() {
+        assert_eq!(
+            stripped(b"\x1b]8;;file:///a.bs\x07a.bs\x1b]8;;\x1b\\:1: \x1bMok"),
+            "a.bs:1: ok"
+        );
+    }
+
+    #[test]
+    fn text_without_escapes_is_unchanged() {
+        assert_eq!(stripped("plain [text] ü\n".as_bytes()), "plain [text] ü\n");
+        assert_eq!(stripped(b"cut off \x1b[1;3"), "cut off ");
+    }
+}
@@ -0,0 +1,81 @@
+//! The time the test runner itself spends in the phases of a run, independent of the time spent in
+//! the toolchain, see `--verbose` and `--stats`. The phases that run concurrently for multiple
+//! tests are summed up over all threads.
+
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::stats::milliseconds;
+
+#[derive(Clone, Copy)]
+pub(crate) enum Phase {
+    /// Walking the tests paths.
+    Discovery,
+    /// Reading the leading comments of source files.
+    Directives,
+    /// Starting child processes.
+    Spawning,
+    /// Printing results and writing logs and reports.
+    Reporting,
+    /// Writing batched result lines to the terminal, see [`crate::output::Printer`].
+    Printing,
+}
+
+/// The nanoseconds spent in every phase so far.
+static NANOS: [AtomicU64; 5] = [const { AtomicU64::new(0) }; 5];
+
+/// Runs `f` and adds the time it takes to the given phase.
+pub(crate) fn measure<T>(phase: Phase, f: impl FnOnce() -> T) -> T {
+    let started = Instant::now();
+    let result = f();
+    let nanos = started.elapsed().as_nanos().try_into().unwrap_or(u64::MAX);
+    NANOS[phase as usize].fetch_add(nanos, Ordering::Relaxed);
+    result
+}
+
+fn total(phase: Phase) -> Duration {
+    Duration::from_nanos(NANOS[phase as usize].load(Ordering::Relaxed))
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct RunnerTimings {
+    #[serde(rename = "discovery_ms", serialize_with = "milliseconds")]
+    discovery: Duration,
+    #[serde(rename = "directives_ms", serialize_with = "milliseconds")]
+    directives: Duration,
+    #[serde(rename = "spawning_ms", serialize_with = "milliseconds")]
+    spawning: Duration,
+    #[serde(rename = "reporting_ms", serialize_with = "milliseconds")]
+    reporting: Duration,
+    #[serde(rename = "printing_ms", serialize_with = "milliseconds")]
+    printing: Duration,
+}
+
+impl RunnerTimings {
+    /// The time spent in every phase so far.
+    pub(crate) fn collect() -> Self {
+        Self {
+            discovery: total(Phase::Discovery),
+            directives: total(Phase::Directives),
+            spawning: total(Phase::Spawning),
+            reporting: total(Phase::Reporting),
+            printing: total(Phase::Printing),
+        }
+    }
+
+    pub(crate) fn render(&self, out: &mut impl Write) -> std::io::Result<()> {
+        writeln!(
+            out,
+            "Runner time: discovery {:.3}s, reading directives {:.3}s, spawning processes {:.3}s, reporting {:.3}s, printing batched results {:.3}s",
+            self.discovery.as_secs_f64(),
+            self.directives.as_secs_f64(),
+            self.spawning.as_secs_f64(),
+            self.reporting.as_secs_f64(),
+            self.printing.as_secs_f64()
+        )?;
+        out.flush()
+    }
+}
@@ -0,0 +1,261 @@
+//! Identification of the toolchain binaries and the standard library a run uses, so reports tell
+//! which compiler, virtual machine and library produced the results.
+
+use std::fs::File;
+use std::io::{stderr, Read};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::config::Config;
+use crate::output::print_warning;
+use crate::version::Version;
+use crate::{process, toolchain_command};
+
+/// Printed for every property of a binary that cannot be determined.
+const UNKNOWN: &str = "unknown";
+
+/// How long a binary may take to print its version.
+const VERSION_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Serialize)]
+pub(crate) struct Toolchain {
+    pub(crate) seatbelt: BinaryInfo,
+    pub(crate) backseater: BinaryInfo,
+    pub(crate) std_library: LibraryInfo,
+}
+
+impl Toolchain {
+    /// Inspects the binaries configured on the command line.
+    pub(crate) fn inspect(config: &Config) -> Self {
+        Self {
+            seatbelt: BinaryInfo::inspect(&config.seatbelt_path),
+            backseater: BinaryInfo::inspect(&config.backseater_path),
+            std_library: LibraryInfo::inspect(&config.lib_path.join("std")),
+        }
+    }
+
+    /// Renders a small header listing the binaries, one per line.
+    pub(crate) fn header(&self) -> String {
+        format!(
+            "toolchain:\n  seatbelt:   {}\n  backseater: {}\n  std:        {}\n",
+            self.seatbelt, self.backseater, self.std_library
+        )
+    }
+}
+
+/// The versions of the binaries, `None` if a version cannot be determined.
+#[derive(Debug, Default)]
+pub(crate) struct ToolchainVersions {
+    pub(crate) seatbelt: Option<Version>,
+    pub(crate) backseater: Option<Version>,
+}
+
+impl ToolchainVersions {
+    /// Queries the versions of the binaries via `--version`. Warns about every version that
+    /// cannot be determined, the requirements of the tests on it are ignored.
+    pub(crate) fn query(config: &Config) -> Self {
+        let query = |name: &str, key: &str, executable: &Path| {
+            let version = query_version(config, executable);
+            if let Err(error) = &version {
+                print_warning(&mut stderr(), &format!(
+                    "unable to determine the version of {name}, ignoring `{key}` directives: {error}\n"
+                ));
+            }
+            version.ok()
+        };
+        Self {
+            seatbelt: query("Seatbelt", "requires_seatbelt", &config.seatbelt_path),
+            backseater: query(
+                "the Backseater",
+                "requires_backseater",
+                &config.backseater_path,
+            ),
+        }
+    }
+}
+
+/// Runs `executable --version` and finds the version in its output.
+fn query_version(config: &Config, executable: &Path) -> anyhow::Result<Version> {
+    let mut command = toolchain_command(config, executable);
+    command.arg("--version");
+    let output = process::run(&mut command, None, Some(VERSION_TIMEOUT))?.output;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    match Version::find(&stdout) {
+        Some(version) if output.status.success() => Ok(version),
+        _ => Err(anyhow::anyhow!(
+            "unexpected output of `{} --version`: {:?}",
+            executable.display(),
+            stdout.trim()
+        )),
+    }
+}
+
+/// The properties of a single binary. Everything that cannot be determined is `None`.
+#[derive(Serialize)]
+pub(crate) struct BinaryInfo {
+    /// The canonicalized path, or the configured one if it cannot be resolved.
+    path: String,
+    size: Option<u64>,
+    /// The modification time in RFC 3339 format.
+    modified: Option<String>,
+    sha256: Option<String>,
+}
+
+impl BinaryInfo {
+    fn inspect(path: &Path) -> Self {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let metadata = path.metadata().ok();
+        Self {
+            path: path.display().to_string(),
+            size: metadata.as_ref().map(|metadata| metadata.len()),
+            modified: metadata
+                .and_then(|metadata| metadata.modified().ok())
+                .map(|modified| humantime::format_rfc3339_seconds(modified).to_string()),
+            sha256: sha256(&path).ok(),
+        }
+    }
+}
+
+impl std::fmt::Display for BinaryInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let size = match self.size {
+            Some(size) => format!("{size} bytes"),
+            None => UNKNOWN.to_string(),
+        };
+        write!(
+            f,
+            "{} (size: {}, modified: {}, sha256: {})",
+            self.path,
+            size,
+            self.modified.as_deref().unwrap_or(UNKNOWN),
+            self.sha256.as_deref().unwrap_or(UNKNOWN)
+        )
+    }
+}
+
+/// The fingerprint of the source files of the standard library.
+#[derive(Serialize)]
+pub(crate) struct LibraryInfo {
+    /// The canonicalized path of the `std` directory, or the configured one if it cannot be
+    /// resolved.
+    path: String,
+    /// The number of `.bs` files, `None` if the directory cannot be read.
+    files: Option<usize>,
+    /// A hash over the relative paths and the contents of all `.bs` files, `None` if any of them
+    /// cannot be read.
+    sha256: Option<String>,
+}
+
+impl LibraryInfo {
+    fn inspect(path: &Path) -> Self {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let files = source_files(&path);
+        let sha256 = files.as_ref().ok().and_then(|files| {
+            // the files are hashed in parallel and combined in the order of their paths
+            let hashes: Vec<_> = files.par_iter().map(|file| sha256(file).ok()).collect();
+            let mut hasher = Sha256::new();
+            for (file, hash) in files.iter().zip(hashes) {
+                let relative_path = file.strip_prefix(&path).unwrap_or(file);
+                hasher.update(relative_path.to_string_lossy().as_bytes());
+                hasher.update([0]);
+                hasher.update(hash?.as_bytes());
+                hasher.update([b'\n']);
+            }
+            Some(hex(&hasher.finalize()))
+        });
+        Self {
+            path: path.display().to_string(),
+            files: files.map(|files| files.len()).ok(),
+            sha256,
+        }
+    }
+}
+
+impl std::fmt::Display for LibraryInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let files = match self.files {
+            Some(files) => files.to_string(),
+            None => UNKNOWN.to_string(),
+        };
+        write!(
+            f,
+            "{} (files: {}, sha256: {})",
+            self.path,
+            files,
+            self.sha256.as_deref().unwrap_or(UNKNOWN)
+        )
+    }
+}
+
+/// Checks that `lib_path` is the parent of a `std` directory, as `--lib-path` has to be. Returns
+/// the problem otherwise, suggesting the parent directory if `lib_path` looks like the `std`
+/// directory itself: it is named `std` or directly contains Backseat source files.
+pub(crate) fn check_lib_path(lib_path: &Path) -> Option<String> {
+    if lib_path.join("std").is_dir() {
+        return None;
+    }
+    let lib_path = lib_path
+        .canonicalize()
+        .unwrap_or_else(|_| lib_path.to_path_buf());
+    let looks_like_std = lib_path.file_name().is_some_and(|name| name == "std")
+        || std::fs::read_dir(&lib_path).is_ok_and(|entries| {
+            entries.flatten().any(|entry| {
+                let path = entry.path();
+                path.is_file() && path.extension().is_some_and(|extension| extension == "bs")
+            })
+        });
+    Some(match lib_path.parent().filter(|_| looks_like_std) {
+        Some(parent) => format!(
+            "the lib path {} contains no `std` directory, but looks like the `std` directory itself; the lib path has to be its parent directory, pass `--lib-path {}` instead\n",
+            lib_path.display(),
+            parent.display()
+        ),
+        None => format!(
+            "the lib path {} contains no `std` directory, so the tests cannot import the standard library\n",
+            lib_path.display()
+        ),
+    })
+}
+
+/// Returns the paths of all `.bs` files below `directory`, sorted.
+fn source_files(directory: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    if !directory.is_dir() {
+        return Err(anyhow::anyhow!(
+            "{} is not a directory",
+            directory.display()
+        ));
+    }
+    let mut files = Vec::new();
+    for entry in globwalk::GlobWalkerBuilder::new(directory, "*.bs").build()? {
+        let entry = entry?;
+        if entry.file_type().is_file() {
+            files.push(entry.into_path());
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Hashes the contents of a file and returns the hash as lowercase hex digits.
+fn sha256(path: &Path) -> std::io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hex(&hasher.finalize()))
+}
+
+/// Renders bytes as lowercase hex digits.
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
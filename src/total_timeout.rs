@@ -0,0 +1,59 @@
+//! The deadline of the whole run, see `--total-timeout`. Shortly before the deadline no new tests
+//! are started anymore, and the tests that are still running at the deadline are killed, so there
+//! is time left to print the summary and write the reports.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use crate::process;
+
+/// The longest time the running tests get to finish after no new tests are started anymore.
+const MAX_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+/// The exit status of a run that exceeded its total timeout.
+pub(crate) const EXIT_STATUS: i32 = 124;
+
+/// Whether no new tests are started anymore because the deadline is near.
+static WINDING_DOWN: AtomicBool = AtomicBool::new(false);
+/// Whether the deadline passed and the running tests have been killed.
+static EXPIRED: AtomicBool = AtomicBool::new(false);
+/// Whether all tests are done, so the deadline does not matter anymore.
+static FINISHED: AtomicBool = AtomicBool::new(false);
+
+/// Starts watching the deadline, which is `timeout` from now. The grace period for the running
+/// tests is a tenth of the timeout, but at most [`MAX_GRACE_PERIOD`].
+pub(crate) fn start(timeout: Duration) {
+    let grace_period = MAX_GRACE_PERIOD.min(timeout / 10);
+    std::thread::spawn(move || {
+        std::thread::sleep(timeout - grace_period);
+        if FINISHED.load(Ordering::SeqCst) {
+            return;
+        }
+        eprintln!(
+            "the total timeout of {:.2}s is near, not starting any further tests",
+            timeout.as_secs_f64()
+        );
+        WINDING_DOWN.store(true, Ordering::SeqCst);
+        std::thread::sleep(grace_period);
+        if FINISHED.load(Ordering::SeqCst) {
+            return;
+        }
+        EXPIRED.store(true, Ordering::SeqCst);
+        process::abort_run();
+    });
+}
+
+/// Stops watching the deadline once all tests are done.
+pub(crate) fn finish() {
+    FINISHED.store(true, Ordering::SeqCst);
+}
+
+/// Whether no new tests may be started anymore.
+pub(crate) fn is_winding_down() -> bool {
+    WINDING_DOWN.load(Ordering::SeqCst)
+}
+
+/// Whether the tests that were still running at the deadline have been killed.
+pub(crate) fn is_expired() -> bool {
+    EXPIRED.load(Ordering::SeqCst)
+}
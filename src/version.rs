@@ -0,0 +1,156 @@
+//! Versions of the toolchain binaries, which tests can require a minimum of, see the
+//! `requires_seatbelt` and `requires_backseater` directives.
+
+use std::cmp::Ordering;
+use std::fmt;
+
+use serde::{Serialize, Serializer};
+
+/// A version made of dot-separated numbers like `0.4.1`. Missing trailing components compare as
+/// zero, so `0.4` and `0.4.0` are equal.
+#[derive(Debug, Clone, Eq)]
+pub(crate) struct Version(Vec<u64>);
+
+impl Version {
+    /// Parses a version that consists of dot-separated numbers only.
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        value
+            .split('.')
+            .map(|component| {
+                Some(component)
+                    .filter(|component| component.bytes().all(|c| c.is_ascii_digit()))
+                    .and_then(|component| component.parse().ok())
+            })
+            .collect::<Option<Vec<_>>>()
+            .map(Self)
+    }
+
+    /// Finds the first version in the output of `--version`, e.g. `0.3.1` in `Seatbelt 0.3.1-dev`.
+    /// Suffixes like pre-release tags are ignored.
+    pub(crate) fn find(output: &str) -> Option<Self> {
+        output
+            .split(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .map(|word| word.trim_matches('.'))
+            .find(|word| word.contains('.'))
+            .and_then(Self::parse)
+    }
+
+    /// Why a test that requires at least this version of the binary called `name` has to be
+    /// skipped, `None` if the `available` version meets the requirement. Tests are not skipped if
+    /// the available version is unknown.
+    pub(crate) fn unmet_by(&self, name: &str, available: Option<&Version>) -> Option<String> {
+        available
+            .filter(|available| *available < self)
+            .map(|available| format!("requires {name} >= {self}, have {available}"))
+    }
+
+    fn component(&self, index: usize) -> u64 {
+        self.0.get(index).copied().unwrap_or_default()
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (0..self.0.len().max(other.0.len()))
+            .map(|index| self.component(index).cmp(&other.component(index)))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Serialize for Version {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let components: Vec<_> = self.0.iter().map(u64::to_string).collect();
+        write!(f, "{}", components.join("."))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(value: &str) -> Version {
+        Version::parse(value).unwrap()
+    }
+
+    #[test]
+    fn malformed_requirements_are_rejected() {
+        for value in [
+            "", ".", "1.", ".4", "1..2", "a.b", "0.4a", "+1.2", "-1.2", "1.2 ", "v0.4",
+        ] {
+            assert_eq!(Version::parse(value), None, "{value:?}");
+        }
+        assert_eq!(Version::parse("18446744073709551616"), None);
+    }
+
+    #[test]
+    fn requirements_do_not_take_pre_release_tags() {
+        for value in ["0.4.0-dev", "1.0-rc.1", "1.0.0+build"] {
+            assert_eq!(Version::parse(value), None, "{value:?}");
+        }
+    }
+
+    #[test]
+    fn missing_trailing_components_compare_as_zero() {
+        assert_eq!(version("0.4"), version("0.4.0"));
+        assert!(version("0.4") < version("0.4.1"));
+        assert!(version("0.10") > version("0.9.9"));
+        assert_eq!(version("0.4.0").to_string(), "0.4.0");
+    }
+
+    #[test]
+    fn pre_release_tags_of_toolchain_versions_are_ignored() {
+        let cases = [
+            ("Seatbelt 0.3.1-dev", "0.3.1"),
+            ("backseater 1.2.0-rc.1 (2024-05-01)", "1.2.0"),
+            ("v0.4+build.7", "0.4"),
+            ("Seatbelt 2 version 0.5.\n", "0.5"),
+        ];
+        for (output, expected) in cases {
+            assert_eq!(Version::find(output), Some(version(expected)), "{output:?}");
+        }
+    }
+
+    #[test]
+    fn output_without_a_version_has_none() {
+        for output in ["", "Seatbelt", "Seatbelt 3", "build 2024-05-01", "..."] {
+            assert_eq!(Version::find(output), None, "{output:?}");
+        }
+    }
+
+    #[test]
+    fn pre_releases_meet_the_requirement_of_their_release() {
+        let available = Version::find("Seatbelt 0.4.0-dev");
+        assert_eq!(
+            version("0.4").unmet_by("Seatbelt", available.as_ref()),
+            None
+        );
+        assert_eq!(
+            version("0.4.1").unmet_by("Seatbelt", available.as_ref()),
+            Some("requires Seatbelt >= 0.4.1, have 0.4.0".to_string())
+        );
+    }
+
+    #[test]
+    fn unknown_toolchain_versions_meet_every_requirement() {
+        assert_eq!(version("99").unmet_by("Backseater", None), None);
+    }
+}
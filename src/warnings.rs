@@ -0,0 +1,167 @@
+//! The limit on how often the same warning is printed, so a systemic problem that causes the same
+//! warning for thousands of files does not bury the other output, see `--max-repeated-warnings`.
+//! Every warning is recorded nevertheless and written to the `--log-dir`.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+
+use crossterm::queue;
+use crossterm::style::{Color, Print, ResetColor, SetForegroundColor};
+
+/// How often the same warning is printed by default.
+pub(crate) const DEFAULT_MAX_REPEATED: usize = 5;
+
+/// The file in the `--log-dir` that every warning of the last run is written to.
+const WARNINGS_FILE: &str = "warnings.txt";
+
+static WARNINGS: Mutex<Warnings> = Mutex::new(Warnings::new(Some(DEFAULT_MAX_REPEATED)));
+
+/// The warnings of the run, counted by their text.
+struct Warnings {
+    /// How often the same warning is printed, `None` if it is always printed.
+    limit: Option<usize>,
+    counts: BTreeMap<String, usize>,
+    /// Every warning in the order it was emitted, including the repeated ones.
+    all: Vec<String>,
+}
+
+impl Warnings {
+    const fn new(limit: Option<usize>) -> Self {
+        Self {
+            limit,
+            counts: BTreeMap::new(),
+            all: Vec::new(),
+        }
+    }
+
+    /// Counts the warning and returns whether it is printed.
+    fn record(&mut self, message: &str) -> bool {
+        let count = self.counts.entry(message.to_string()).or_default();
+        *count += 1;
+        self.all.push(message.to_string());
+        self.limit.is_none_or(|limit| *count <= limit)
+    }
+
+    /// Every warning that was not printed every time, with the number of times it was not.
+    fn suppressed(&self) -> Vec<(&str, usize)> {
+        let Some(limit) = self.limit else {
+            return Vec::new();
+        };
+        self.counts
+            .iter()
+            .filter(|(_, count)| **count > limit)
+            .map(|(message, count)| (message.as_str(), count - limit))
+            .collect()
+    }
+
+    fn print_suppressed(&self, out: &mut impl Write) -> std::io::Result<()> {
+        for (message, count) in self.suppressed() {
+            queue!(
+                out,
+                SetForegroundColor(Color::DarkYellow),
+                Print("warning: "),
+                ResetColor,
+                Print(format!(
+                    "…and {count} more like this: {}\n",
+                    message.lines().next().unwrap_or_default()
+                ))
+            )?;
+        }
+        out.flush()
+    }
+}
+
+fn warnings() -> std::sync::MutexGuard<'static, Warnings> {
+    WARNINGS.lock().expect("the warnings are never poisoned")
+}
+
+/// Sets how often the same warning is printed, `None` to print it every time.
+pub(crate) fn set_limit(limit: Option<usize>) {
+    warnings().limit = limit;
+}
+
+/// Counts the warning and returns whether it is printed, see [`crate::output::print_warning`].
+pub(crate) fn record(message: &str) -> bool {
+    warnings().record(message)
+}
+
+/// Prints how often every warning that was printed the maximum number of times was not printed,
+/// e.g. `…and 412 more like this`, followed by its first line.
+pub(crate) fn print_suppressed(out: &mut impl Write) -> std::io::Result<()> {
+    warnings().print_suppressed(out)
+}
+
+/// Writes every warning of the run to the `--log-dir`, or removes the file of a previous run if
+/// there were none.
+pub(crate) fn write_all(log_dir: &Path) -> std::io::Result<()> {
+    let path = log_dir.join(WARNINGS_FILE);
+    let warnings = warnings();
+    if warnings.all.is_empty() {
+        return match std::fs::remove_file(&path) {
+            Err(error) if error.kind() != std::io::ErrorKind::NotFound => Err(error),
+            _ => Ok(()),
+        };
+    }
+    let mut contents = String::new();
+    for message in &warnings.all {
+        contents += "warning: ";
+        contents += message;
+        if !message.ends_with('\n') {
+            contents.push('\n');
+        }
+    }
+    crate::write_atomic(&path, contents.as_bytes())
+}
+
+/// Prints the suppressed warnings when it is dropped, so they are printed however `main` returns.
+pub(crate) struct PrintSuppressedOnDrop;
+
+impl Drop for PrintSuppressedOnDrop {
+    fn drop(&mut self) {
+        print_suppressed(&mut std::io::stderr().lock()).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Warnings;
+
+    #[test]
+    fn warnings_are_printed_up_to_the_limit() {
+        let mut warnings = Warnings::new(Some(2));
+        assert!(warnings.record("a"));
+        assert!(warnings.record("b"));
+        assert!(warnings.record("a"));
+        assert!(!warnings.record("a"));
+        assert!(!warnings.record("a"));
+        assert!(warnings.record("b"));
+        assert_eq!(warnings.suppressed(), [("a", 2)]);
+        assert_eq!(warnings.all, ["a", "b", "a", "a", "a", "b"]);
+    }
+
+    #[test]
+    fn without_a_limit_every_warning_is_printed() {
+        let mut warnings = Warnings::new(None);
+        assert!((0..100).all(|_| warnings.record("a")));
+        assert_eq!(warnings.suppressed(), []);
+    }
+
+    #[test]
+    fn the_suppressed_count_is_printed_with_the_first_line() {
+        let mut warnings = Warnings::new(Some(1));
+        for _ in 0..4 {
+            warnings.record("unable to read foo\n\tcaused by: bar");
+        }
+        warnings.record("once");
+        let mut out = Vec::new();
+        warnings.print_suppressed(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.matches("warning: ").count(), 1);
+        assert!(
+            out.ends_with("…and 3 more like this: unable to read foo\n"),
+            "{out:?}"
+        );
+    }
+}
@@ -0,0 +1,197 @@
+//! A suite of tests in a temporary directory, run by the test runner binary with stub versions
+//! of the Seatbelt and the Backseater. The stubs are shell scripts, so the integration tests only
+//! run on Unix.
+
+#![allow(dead_code)]
+
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use serde_json::Value;
+
+/// A compiler that "compiles" a test by printing its source. Sources containing `COMPILE_ERROR`
+/// fail to compile.
+pub const SEATBELT: &str = r#"#!/bin/sh
+if grep -q COMPILE_ERROR "$1"; then
+    echo "error: compile error in $1" >&2
+    exit 1
+fi
+cat "$1"
+"#;
+
+/// A VM that prints the program it receives on stdin, unless the program contains one of the
+/// markers: `ABORT` aborts with a runtime error, `SLEEP` sleeps for half a minute, `NAP` for a
+/// second before printing the program, `HANG` never exits and ignores `SIGTERM`, `HUGE_OUTPUT`
+/// prints 32 MiB.
+pub const BACKSEATER: &str = r#"#!/bin/sh
+program=$(cat)
+case "$program" in
+    *ABORT*)
+        echo "runtime error: boom" >&2
+        exit 1;;
+    *SLEEP*)
+        sleep 30;;
+    *NAP*)
+        sleep 1;;
+    *HANG*)
+        trap '' TERM
+        while :; do sleep 1; done;;
+    *HUGE_OUTPUT*)
+        head -c 33554432 /dev/zero | tr '\0' x
+        exit 0;;
+esac
+printf '%s\n' "$program"
+"#;
+
+static SUITES: AtomicUsize = AtomicUsize::new(0);
+
+/// A temporary directory with the stubs, a standard library in `lib` and the tests in `tests`.
+/// It is removed when the suite is dropped.
+pub struct Suite {
+    root: PathBuf,
+    pub seatbelt: PathBuf,
+    pub backseater: PathBuf,
+}
+
+impl Suite {
+    pub fn new() -> Self {
+        let root = std::env::temp_dir().join(format!(
+            "test-runner-suite-{}-{}",
+            std::process::id(),
+            SUITES.fetch_add(1, Ordering::Relaxed)
+        ));
+        if root.exists() {
+            std::fs::remove_dir_all(&root).unwrap();
+        }
+        std::fs::create_dir_all(root.join("lib/std")).unwrap();
+        std::fs::create_dir_all(root.join("tests")).unwrap();
+        std::fs::write(root.join("lib/std/core.bs"), "// the standard library\n").unwrap();
+        let mut suite = Self {
+            seatbelt: PathBuf::new(),
+            backseater: PathBuf::new(),
+            root,
+        };
+        suite.seatbelt = suite.stub("seatbelt", SEATBELT);
+        suite.backseater = suite.stub("backseater", BACKSEATER);
+        suite
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn tests_path(&self) -> PathBuf {
+        self.root.join("tests")
+    }
+
+    /// Writes a test, or any other file, relative to the tests path.
+    pub fn test(&self, name: &str, contents: impl AsRef<[u8]>) -> &Self {
+        self.file(&format!("tests/{name}"), contents)
+    }
+
+    /// Writes a file relative to the root of the suite, creating its parent directories.
+    pub fn file(&self, name: &str, contents: impl AsRef<[u8]>) -> &Self {
+        let path = self.root.join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+        self
+    }
+
+    /// Writes an executable script to the root of the suite and returns its path.
+    pub fn stub(&self, name: &str, script: &str) -> PathBuf {
+        let path = self.root.join(name);
+        std::fs::write(&path, script).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    /// The test runner with the stubs, the library and the tests path of the suite, run from the
+    /// root of the suite without colors. It runs four tests at once, also on machines with a
+    /// single CPU.
+    pub fn command(&self) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_test-runner"));
+        command
+            .current_dir(&self.root)
+            .env("NO_COLOR", "1")
+            .env_remove("RUST_LOG")
+            .env("RAYON_NUM_THREADS", "4")
+            .arg("--seatbelt-path")
+            .arg(&self.seatbelt)
+            .arg("--backseater-path")
+            .arg(&self.backseater)
+            .arg("--lib-path")
+            .arg(self.root.join("lib"))
+            .arg("--tests-path")
+            .arg(self.tests_path());
+        command
+    }
+
+    pub fn run(&self, args: &[&str]) -> Output {
+        self.command().args(args).output().unwrap()
+    }
+
+    /// Runs the tests with `--format json` and returns the report.
+    pub fn run_json(&self, args: &[&str]) -> Report {
+        let output = self.run(&[&["--format", "json"], args].concat());
+        let document = serde_json::from_slice(&output.stdout).unwrap_or_else(|error| {
+            panic!(
+                "invalid JSON report ({error}):\n{}\nstderr:\n{}",
+                String::from_utf8_lossy(&output.stdout),
+                String::from_utf8_lossy(&output.stderr)
+            )
+        });
+        Report { output, document }
+    }
+}
+
+impl Drop for Suite {
+    fn drop(&mut self) {
+        std::fs::remove_dir_all(&self.root).ok();
+    }
+}
+
+pub struct Report {
+    pub output: Output,
+    pub document: Value,
+}
+
+impl Report {
+    /// The result of the test with the given name.
+    pub fn test(&self, name: &str) -> &Value {
+        self.document["tests"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|test| test["name"] == name)
+            .unwrap_or_else(|| panic!("no result of {name} in {:#}", self.document))
+    }
+
+    /// The outcome of the test and its failure code, if any, e.g. `("failed", "E-TIMEOUT")`.
+    pub fn outcome(&self, name: &str) -> (String, String) {
+        let test = self.test(name);
+        (
+            test["outcome"].as_str().unwrap().to_string(),
+            test["code"].as_str().unwrap_or_default().to_string(),
+        )
+    }
+
+    /// The failure message of the test.
+    pub fn message(&self, name: &str) -> String {
+        self.test(name)["message"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string()
+    }
+}
+
+/// The stdout of the process as text.
+pub fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+/// The stderr of the process as text.
+pub fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
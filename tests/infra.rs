@@ -0,0 +1,209 @@
+//! Errors of the test runner itself, which fail only the affected tests with `E-INFRA`.
+
+#![cfg(unix)]
+
+mod common;
+
+use std::os::unix::fs::PermissionsExt;
+
+use common::Suite;
+
+fn infra_failure() -> (String, String) {
+    ("failed".into(), "E-INFRA".into())
+}
+
+#[test]
+fn missing_compilers_fail_every_compiled_test() {
+    let mut suite = Suite::new();
+    suite.seatbelt = suite.path().join("missing-seatbelt");
+    suite.test("test_a.bs", "print(1)\n");
+    suite.test("test_b.bs", "print(2)\n");
+    let report = suite.run_json(&[]);
+    for name in ["test_a.bs", "test_b.bs"] {
+        assert_eq!(report.outcome(name), infra_failure());
+        assert!(report.message(name).contains("unable to run the compiler"));
+    }
+    assert!(!report.output.status.success());
+}
+
+#[test]
+fn missing_vms_fail_the_tests_that_are_run() {
+    let mut suite = Suite::new();
+    suite.backseater = suite.path().join("missing-backseater");
+    suite.test("test_run.bs", "print(1)\n");
+    suite.test(
+        "test_compile.bs",
+        "// test-runner: fails_with = \"compile error\"\nCOMPILE_ERROR\n",
+    );
+    let report = suite.run_json(&[]);
+    assert_eq!(report.outcome("test_run.bs"), infra_failure());
+    assert!(report
+        .message("test_run.bs")
+        .contains("unable to run the VM"));
+    assert_eq!(report.outcome("test_compile.bs").0, "ok");
+}
+
+#[test]
+fn uncreatable_scratch_directories_fail_the_tests() {
+    let suite = Suite::new();
+    suite.file("work", "not a directory\n");
+    suite.test("test_a.bs", "print(1)\n");
+    let report = suite.run_json(&["--work-dir", "work"]);
+    assert_eq!(report.outcome("test_a.bs"), infra_failure());
+    assert!(report
+        .message("test_a.bs")
+        .contains("unable to create the scratch directory"));
+}
+
+#[test]
+fn inaccessible_work_directories_fail_the_tests() {
+    let suite = Suite::new();
+    let work_dir = suite.path().join("work");
+    std::fs::create_dir(&work_dir).unwrap();
+    std::fs::set_permissions(&work_dir, std::fs::Permissions::from_mode(0o000)).unwrap();
+    // the permissions do not apply to root
+    let accessible = std::fs::create_dir(work_dir.join("probe")).is_ok();
+    suite.test("test_a.bs", "print(1)\n");
+    let report = suite.run_json(&["--work-dir", "work"]);
+    std::fs::set_permissions(&work_dir, std::fs::Permissions::from_mode(0o755)).unwrap();
+    if accessible {
+        return;
+    }
+    assert_eq!(report.outcome("test_a.bs"), infra_failure());
+}
+
+#[test]
+fn unremovable_stale_snapshots_fail_the_test_when_blessing() {
+    let suite = Suite::new();
+    suite.test("test_a.bs", "print(1)\n");
+    suite.test("test_b.bs", "print(2)\n");
+    // a directory cannot be removed like a file
+    std::fs::create_dir(suite.tests_path().join("test_a.bs.stderr")).unwrap();
+    let report = suite.run_json(&["--bless"]);
+    assert_eq!(report.outcome("test_a.bs"), infra_failure());
+    assert!(report
+        .message("test_a.bs")
+        .contains("unable to remove the stale snapshot"));
+    assert_eq!(report.outcome("test_b.bs").0, "ok");
+}
+
+#[test]
+fn unwritable_snapshots_fail_the_test_when_blessing() {
+    let suite = Suite::new();
+    suite.test("test_a.bs", "COMPILE_ERROR\n");
+    // the snapshot is written to `<snapshot>.tmp` first
+    std::fs::create_dir(suite.tests_path().join("test_a.bs.stderr.tmp")).unwrap();
+    let report = suite.run_json(&["--bless"]);
+    assert_eq!(report.outcome("test_a.bs"), infra_failure());
+    assert!(report.message("test_a.bs").contains("unable to bless"));
+    assert!(!suite.tests_path().join("test_a.bs.stderr").exists());
+}
+
+/// Asserts that only the unreadable test failed, with a message naming its path.
+fn assert_only_unreadable_test_failed(suite: &Suite, report: &common::Report, name: &str) {
+    assert_eq!(report.outcome(name), infra_failure());
+    let path = suite.tests_path().join(name);
+    let message = report.message(name);
+    assert!(
+        message.contains(&format!("unable to read {}", path.display())),
+        "{message}"
+    );
+    assert_eq!(report.outcome("test_ok.bs").0, "ok");
+    assert_eq!(report.document["summary"]["failed"], 1);
+    assert_eq!(report.output.status.code(), Some(1));
+}
+
+#[test]
+fn unreadable_tests_fail_alone() {
+    let suite = Suite::new();
+    suite.test("test_ok.bs", "print(1)\n");
+    suite.test("test_locked.bs", "print(2)\n");
+    // files that are no tests are skipped with a warning when looking for misnamed tests
+    suite.test("helper.bs", "// test-runner: skip\n");
+    let locked = suite.tests_path().join("test_locked.bs");
+    let helper = suite.tests_path().join("helper.bs");
+    for path in [&locked, &helper] {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o000)).unwrap();
+    }
+    // the permissions do not apply to root
+    let readable = std::fs::read(&locked).is_ok();
+    let report = suite.run_json(&[]);
+    let check = suite.run(&["--check"]);
+    let list = suite.run(&["--list"]);
+    for path in [&locked, &helper] {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o644)).unwrap();
+    }
+    if readable {
+        return;
+    }
+    assert_only_unreadable_test_failed(&suite, &report, "test_locked.bs");
+    let stderr = common::stderr(&report.output);
+    assert!(
+        stderr.contains(&format!("unable to read {}", helper.display())),
+        "{stderr}"
+    );
+    for output in [check, list] {
+        assert_eq!(output.status.code(), Some(1));
+        let stdout = common::stdout(&output);
+        assert!(stdout.contains("test_locked.bs"), "{stdout}");
+        assert!(stdout.contains("Permission denied"), "{stdout}");
+    }
+}
+
+#[test]
+fn tests_that_are_no_utf8_fail_alone() {
+    let suite = Suite::new();
+    suite.test("test_ok.bs", "print(1)\n");
+    suite.test("test_latin1.bs", b"// caf\xe9\nprint(2)\n");
+    // files that are no tests are skipped when looking for misnamed tests
+    suite.test("helper.bs", b"\xff\xfe\n");
+    let report = suite.run_json(&[]);
+    assert_only_unreadable_test_failed(&suite, &report, "test_latin1.bs");
+
+    let check = suite.run(&["--check"]);
+    assert_eq!(check.status.code(), Some(1));
+    let stdout = common::stdout(&check);
+    assert!(
+        stdout.contains("test_latin1.bs: unreadable (unable to read"),
+        "{stdout}"
+    );
+    assert!(stdout.contains("Tests checked: 2"), "{stdout}");
+
+    let list = suite.run(&["--list"]);
+    assert_eq!(list.status.code(), Some(1));
+    let stdout = common::stdout(&list);
+    assert!(stdout.contains("test_ok.bs\n"), "{stdout}");
+    assert!(
+        stdout.contains("test_latin1.bs (unable to read"),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn failed_compilations_ahead_fail_only_their_test() {
+    let suite = Suite::new();
+    // environment variables cannot contain NUL bytes, so the compiler cannot be started
+    suite.test("broken/test_broken.bs", "print(1)\n");
+    suite.test("broken/test-runner.toml", "[env]\nBROKEN = \"a\\u0000b\"\n");
+    suite.test("test_ok.bs", "print(1)\n");
+    let report = suite.run_json(&["--phases", "compile-first"]);
+    assert_eq!(report.outcome("broken/test_broken.bs"), infra_failure());
+    assert!(report
+        .message("broken/test_broken.bs")
+        .contains("unable to run the compiler"));
+    assert_eq!(report.outcome("test_ok.bs"), ("ok".into(), "".into()));
+    assert_eq!(report.document["summary"]["failed"], 1);
+}
+
+#[test]
+fn missing_compilers_fail_every_test_compiled_ahead() {
+    let mut suite = Suite::new();
+    suite.seatbelt = suite.path().join("missing-seatbelt");
+    suite.test("test_a.bs", "print(1)\n");
+    suite.test("test_b.bs", "print(2)\n");
+    let report = suite.run_json(&["--phases", "compile-first"]);
+    for name in ["test_a.bs", "test_b.bs"] {
+        assert_eq!(report.outcome(name), infra_failure());
+        assert!(report.message(name).contains("unable to run the compiler"));
+    }
+}
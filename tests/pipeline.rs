@@ -0,0 +1,432 @@
+//! The outcomes of tests run through the whole pipeline of compiling and running them.
+
+#![cfg(unix)]
+
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::Suite;
+
+#[test]
+fn passing_tests_succeed() {
+    let suite = Suite::new();
+    suite.test("test_pass.bs", "print(1)\n");
+    let report = suite.run_json(&[]);
+    assert_eq!(report.outcome("test_pass.bs"), ("ok".into(), "".into()));
+    assert!(report.output.status.success());
+}
+
+#[test]
+fn expected_compile_failures_succeed() {
+    let suite = Suite::new();
+    suite.test(
+        "test_compile.bs",
+        "// test-runner: fails_with = \"compile error\"\nCOMPILE_ERROR\n",
+    );
+    let report = suite.run_json(&[]);
+    assert_eq!(report.outcome("test_compile.bs"), ("ok".into(), "".into()));
+    assert_eq!(report.test("test_compile.bs")["aborted_stage"], "compile");
+}
+
+#[test]
+fn unexpected_compile_failures_fail() {
+    let suite = Suite::new();
+    suite.test("test_compile.bs", "COMPILE_ERROR\n");
+    let report = suite.run_json(&[]);
+    assert_eq!(
+        report.outcome("test_compile.bs"),
+        ("failed".into(), "E-COMPILE-UNEXPECTED".into())
+    );
+    assert!(report
+        .message("test_compile.bs")
+        .contains("error: compile error in"));
+    assert!(!report.output.status.success());
+}
+
+#[test]
+fn expected_runtime_failures_succeed() {
+    let suite = Suite::new();
+    suite.test(
+        "test_abort.bs",
+        "// test-runner: fails_with = \"boom\"\nABORT\n",
+    );
+    let report = suite.run_json(&[]);
+    assert_eq!(report.outcome("test_abort.bs"), ("ok".into(), "".into()));
+    assert_eq!(report.test("test_abort.bs")["aborted_stage"], "run");
+}
+
+#[test]
+fn directives_after_a_header_comment_apply() {
+    let suite = Suite::new();
+    suite.test(
+        "test_second.bs",
+        "// AUTOGENERATED - DO NOT EDIT\n// test-runner: fails_with = \"boom\"\nABORT\n",
+    );
+    suite.test(
+        "test_third.bs",
+        "#!/usr/bin/env backseat\n// AUTOGENERATED - DO NOT EDIT\n// test-runner: fails_with = \"boom\"\nABORT\n",
+    );
+    let report = suite.run_json(&[]);
+    assert_eq!(report.outcome("test_second.bs"), ("ok".into(), "".into()));
+    assert_eq!(report.outcome("test_third.bs"), ("ok".into(), "".into()));
+}
+
+#[test]
+fn unexpected_runtime_failures_fail() {
+    let suite = Suite::new();
+    suite.test("test_abort.bs", "ABORT\n");
+    let report = suite.run_json(&[]);
+    assert_eq!(
+        report.outcome("test_abort.bs"),
+        ("failed".into(), "E-RUNTIME-UNEXPECTED".into())
+    );
+    assert!(report
+        .message("test_abort.bs")
+        .contains("runtime error: boom"));
+}
+
+#[test]
+fn runtime_failures_with_another_message_fail() {
+    let suite = Suite::new();
+    suite.test(
+        "test_abort.bs",
+        "// test-runner: fails_with = \"bust\"\nABORT\n",
+    );
+    let report = suite.run_json(&[]);
+    assert_eq!(
+        report.outcome("test_abort.bs"),
+        ("failed".into(), "E-WRONG-MESSAGE".into())
+    );
+}
+
+#[test]
+fn invalid_directives_fail_before_compiling() {
+    let suite = Suite::new();
+    suite.test("test_unknown.bs", "// test-runner: bogus = 1\nprint(1)\n");
+    suite.test(
+        "test_quoting.bs",
+        "// test-runner: fails_with = \"boom\nABORT\n",
+    );
+    let report = suite.run_json(&[]);
+    for name in ["test_unknown.bs", "test_quoting.bs"] {
+        assert_eq!(
+            report.outcome(name),
+            ("failed".into(), "E-DIRECTIVES".into())
+        );
+        assert!(report.test(name)["stages"].is_null(), "{name} was run");
+    }
+    assert!(report.message("test_unknown.bs").contains("line 1"));
+}
+
+#[test]
+fn tests_exceeding_their_timeout_are_killed() {
+    let suite = Suite::new();
+    suite.test("test_sleep.bs", "// test-runner: timeout = 500ms\nSLEEP\n");
+    let started = Instant::now();
+    let report = suite.run_json(&[]);
+    assert!(started.elapsed() < Duration::from_secs(20));
+    assert_eq!(
+        report.outcome("test_sleep.bs"),
+        ("failed".into(), "E-TIMEOUT".into())
+    );
+}
+
+#[test]
+fn processes_started_by_the_vm_are_killed_with_it() {
+    let suite = Suite::new();
+    let pid_file = suite.path().join("grandchild.pid");
+    suite.stub(
+        "backseater",
+        &format!(
+            "#!/bin/sh\nsleep 30 &\necho $! > '{pid}.tmp'\nmv '{pid}.tmp' '{pid}'\nwait\n",
+            pid = pid_file.display()
+        ),
+    );
+    suite.test(
+        "test_fork.bs",
+        "// test-runner: timeout = 500ms\nprint(1)\n",
+    );
+    let started = Instant::now();
+    let report = suite.run_json(&[]);
+    assert!(started.elapsed() < Duration::from_secs(20));
+    assert_eq!(
+        report.outcome("test_fork.bs"),
+        ("failed".into(), "E-TIMEOUT".into())
+    );
+    let pid = std::fs::read_to_string(&pid_file).unwrap();
+    assert!(
+        !is_running(pid.trim()),
+        "the grandchild {pid} is still running"
+    );
+}
+
+#[test]
+fn hanging_tests_ignoring_sigterm_are_killed() {
+    let suite = Suite::new();
+    suite.test("test_hang.bs", "HANG\n");
+    let started = Instant::now();
+    let report = suite.run_json(&["--timeout", "1"]);
+    assert!(started.elapsed() < Duration::from_secs(20));
+    assert_eq!(
+        report.outcome("test_hang.bs"),
+        ("failed".into(), "E-TIMEOUT".into())
+    );
+}
+
+#[test]
+fn huge_outputs_are_captured() {
+    let suite = Suite::new();
+    suite.test("test_huge.bs", "HUGE_OUTPUT\n");
+    let report = suite.run_json(&[]);
+    assert_eq!(report.outcome("test_huge.bs"), ("ok".into(), "".into()));
+    let stages = report.test("test_huge.bs")["stages"].as_array().unwrap();
+    assert_eq!(stages.len(), 2);
+}
+
+#[test]
+fn one_failure_does_not_stop_the_other_tests() {
+    let suite = Suite::new();
+    suite.test("test_a.bs", "print(1)\n");
+    suite.test("test_b.bs", "ABORT\n");
+    suite.test("test_c.bs", "print(3)\n");
+    let report = suite.run_json(&[]);
+    assert_eq!(report.outcome("test_a.bs").0, "ok");
+    assert_eq!(report.outcome("test_b.bs").0, "failed");
+    assert_eq!(report.outcome("test_c.bs").0, "ok");
+    assert_eq!(report.document["summary"]["failed"], 1);
+}
+
+#[test]
+fn compiled_programs_reach_the_vm_byte_for_byte() {
+    let mut suite = Suite::new();
+    let bytes: Vec<u8> = (0..=255).collect();
+    suite.file("program.bin", &bytes);
+    let program = suite.path().join("program.bin");
+    suite.seatbelt = suite.stub(
+        "seatbelt",
+        &format!("#!/bin/sh\ncat '{}'\n", program.display()),
+    );
+    suite.backseater = suite.stub(
+        "backseater",
+        &format!(
+            "#!/bin/sh\ncat > received.bin\ncmp -s received.bin '{}' || {{ echo 'the program differs' >&2; exit 1; }}\n",
+            program.display()
+        ),
+    );
+    suite.test("test_bytes.bs", "print(1)\n");
+    let report = suite.run_json(&[]);
+    assert_eq!(
+        report.outcome("test_bytes.bs"),
+        ("ok".into(), "".into()),
+        "{}",
+        report.message("test_bytes.bs")
+    );
+    assert_eq!(report.test("test_bytes.bs")["program_bytes"], 256);
+}
+
+#[test]
+fn stderr_that_is_no_utf8_is_reported() {
+    let mut suite = Suite::new();
+    suite.seatbelt = suite.stub(
+        "seatbelt",
+        "#!/bin/sh\nprintf 'error: \\377\\376 in the source\\n' >&2\nexit 1\n",
+    );
+    suite.test("test_compile.bs", "print(1)\n");
+    let report = suite.run_json(&[]);
+    assert_eq!(
+        report.outcome("test_compile.bs"),
+        ("failed".into(), "E-COMPILE-UNEXPECTED".into())
+    );
+    assert!(report
+        .message("test_compile.bs")
+        .contains("error: \u{FFFD}\u{FFFD} in the source"));
+}
+
+#[test]
+fn ndjson_stdout_contains_nothing_else() {
+    let suite = Suite::new();
+    suite.test("test_pass.bs", "print(1)\n");
+    suite.test("test_abort.bs", "ABORT\n");
+    suite.file("quarantine.txt", "test_missing.bs\n");
+    let output = suite.run(&["--format", "ndjson", "--quarantine", "quarantine.txt"]);
+    let stdout = common::stdout(&output);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert!(lines.len() >= 3, "{stdout}");
+    for line in lines {
+        let event: serde_json::Value = serde_json::from_str(line)
+            .unwrap_or_else(|error| panic!("{error} in the line {line:?}"));
+        assert!(event.is_object(), "{line}");
+    }
+    let stderr = common::stderr(&output);
+    assert!(stderr.contains("warning: the quarantined test"), "{stderr}");
+}
+
+#[test]
+fn results_are_reported_in_the_order_of_the_tests() {
+    let suite = Suite::new();
+    suite.test("test_a.bs", "NAP\n");
+    for name in ["test_b.bs", "test_c.bs", "test_d.bs"] {
+        suite.test(name, "print(1)\n");
+    }
+    let names = |report: &common::Report| -> Vec<String> {
+        let tests = report.document["tests"].as_array().unwrap();
+        tests.iter().map(|test| test["name"].to_string()).collect()
+    };
+    // the tests are run in the order they are discovered in
+    let sequential = names(&suite.run_json(&["--no-parallel"]));
+    assert_eq!(sequential.len(), 4);
+    assert_eq!(names(&suite.run_json(&[])), sequential);
+}
+
+#[test]
+fn ndjson_has_a_line_per_finished_test_and_ends_with_the_summary() {
+    let suite = Suite::new();
+    suite.test("test_a.bs", "NAP\n");
+    suite.test("test_b.bs", "ABORT\n");
+    suite.test("test_c.bs", "print(1)\n");
+    for parallel in [true, false] {
+        let output = match parallel {
+            true => suite.run(&["--format", "ndjson"]),
+            false => suite.run(&["--format", "ndjson", "--no-parallel"]),
+        };
+        let events: Vec<serde_json::Value> = common::stdout(&output)
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let (summary, tests) = events.split_last().unwrap();
+        assert_eq!(summary["type"], "summary");
+        assert_eq!(summary["run"], 3);
+        assert_eq!(summary["failed"], 1);
+        let mut names: Vec<&str> = tests
+            .iter()
+            .inspect(|event| assert_eq!(event["type"], "test"))
+            .map(|event| event["name"].as_str().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["test_a.bs", "test_b.bs", "test_c.bs"]);
+    }
+}
+
+/// A compiler that reports colored errors, as if it wrote to a terminal.
+const COLORED_SEATBELT: &str = r#"#!/bin/sh
+if grep -q COMPILE_ERROR "$1"; then
+    printf '\033[1;31merror\033[0m: compile \033[1merror\033[0m in the test\n' >&2
+    exit 1
+fi
+cat "$1"
+"#;
+
+#[test]
+fn colored_errors_match_failure_directives() {
+    let suite = Suite::new();
+    suite.stub("seatbelt", COLORED_SEATBELT);
+    suite.test(
+        "test_compile.bs",
+        "// test-runner: fails_with = \"error: compile error in the test\"\nCOMPILE_ERROR\n",
+    );
+    suite.test("test_unexpected.bs", "COMPILE_ERROR\n");
+    let report = suite.run_json(&[]);
+    assert_eq!(report.outcome("test_compile.bs"), ("ok".into(), "".into()));
+    let message = report.message("test_unexpected.bs");
+    assert!(
+        message.contains("error: compile error in the test"),
+        "{message:?}"
+    );
+    assert!(!message.contains('\x1b'), "{message:?}");
+}
+
+#[test]
+fn colored_errors_are_kept_with_keep_ansi() {
+    let suite = Suite::new();
+    suite.stub("seatbelt", COLORED_SEATBELT);
+    suite.test("test_unexpected.bs", "COMPILE_ERROR\n");
+    let report = suite.run_json(&["--keep-ansi"]);
+    let message = report.message("test_unexpected.bs");
+    assert!(
+        message.contains("\x1b[1;31merror\x1b[0m: compile \x1b[1merror\x1b[0m"),
+        "{message:?}"
+    );
+}
+
+/// Whether the process with the given PID is still running. Killed processes whose parent exited
+/// may not be reaped in containers, so zombies are not running.
+fn is_running(pid: &str) -> bool {
+    if cfg!(target_os = "linux") {
+        return std::fs::read_to_string(format!("/proc/{pid}/stat")).is_ok_and(|stat| {
+            !stat
+                .rsplit_once(") ")
+                .is_some_and(|(_, rest)| rest.starts_with('Z'))
+        });
+    }
+    std::process::Command::new("kill")
+        .args(["-0", pid])
+        .stderr(std::process::Stdio::null())
+        .status()
+        .unwrap()
+        .success()
+}
+
+#[test]
+fn fail_fast_kills_the_running_tests() {
+    let suite = Suite::new();
+    let pid_file = suite.path().join("sleeper.pid");
+    suite.stub(
+        "backseater",
+        &format!(
+            r#"#!/bin/sh
+program=$(cat)
+case "$program" in
+    *SLEEPER*)
+        echo $$ > '{pid}.tmp'
+        mv '{pid}.tmp' '{pid}'
+        exec sleep 30;;
+    *FAIL_LATER*)
+        # fail once the sleeper runs
+        for _ in $(seq 100); do
+            [ -e '{pid}' ] && break
+            sleep 0.1
+        done
+        echo "runtime error: boom" >&2
+        exit 1;;
+esac
+printf '%s\n' "$program"
+"#,
+            pid = pid_file.display()
+        ),
+    );
+    suite.test("test_sleeper.bs", "SLEEPER\n");
+    suite.test("test_fail.bs", "FAIL_LATER\n");
+    let started = Instant::now();
+    let report = suite.run_json(&["--fail-fast"]);
+    assert!(started.elapsed() < Duration::from_secs(20));
+    assert!(!report.output.status.success());
+    assert_eq!(
+        report.outcome("test_fail.bs"),
+        ("failed".into(), "E-RUNTIME-UNEXPECTED".into())
+    );
+    let pid = std::fs::read_to_string(&pid_file).unwrap();
+    assert!(
+        !is_running(pid.trim()),
+        "the sleeper {pid} is still running"
+    );
+}
+
+#[test]
+fn vms_reading_only_half_of_the_program_do_not_fail_the_test() {
+    let suite = Suite::new();
+    // exits after reading half of the program, while the rest is still being written
+    suite.stub(
+        "backseater",
+        "#!/bin/sh\nhead -c 1048576 > /dev/null\necho read half\n",
+    );
+    suite.test("test_half.bs", "x".repeat(2 * 1024 * 1024));
+    let started = Instant::now();
+    let report = suite.run_json(&[]);
+    assert!(started.elapsed() < Duration::from_secs(20));
+    assert_eq!(report.outcome("test_half.bs"), ("ok".into(), "".into()));
+    assert_eq!(
+        report.test("test_half.bs")["program_bytes"],
+        2 * 1024 * 1024
+    );
+}
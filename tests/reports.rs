@@ -0,0 +1,120 @@
+//! Logs and reports: the names tests are reported with, and logs and reports that cannot be
+//! written, which never hide the outcomes of the tests.
+
+#![cfg(unix)]
+
+mod common;
+
+use common::{stderr, stdout, Suite};
+
+/// A suite whose output directory `out` cannot be written to, since it is a file.
+fn suite_with_unwritable_output() -> Suite {
+    let suite = Suite::new();
+    suite.file("out", "not a directory\n");
+    suite
+}
+
+#[test]
+fn unwritable_reports_fail_green_runs_after_the_results() {
+    let suite = suite_with_unwritable_output();
+    suite.test("test_pass.bs", "print(1)\n");
+    let output = suite.run(&["--csv", "out/report.csv", "--log-dir", "out/logs"]);
+    assert!(!output.status.success());
+    let stdout = stdout(&output);
+    assert!(stdout.contains("test_pass.bs ... OK"), "{stdout}");
+    assert!(stdout.contains("Tests failed: 0"), "{stdout}");
+    let stderr = stderr(&output);
+    assert!(
+        stderr.contains("The following logs and reports could not be written:"),
+        "{stderr}"
+    );
+    assert!(
+        stderr.contains("unable to write out/report.csv"),
+        "{stderr}"
+    );
+    assert!(
+        stderr.contains("all tests succeeded, but not all logs and reports could be written"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn unwritable_reports_do_not_hide_failed_tests() {
+    let suite = suite_with_unwritable_output();
+    suite.test("test_pass.bs", "print(1)\n");
+    suite.test("test_abort.bs", "ABORT\n");
+    let output = suite.run(&["--csv", "out/report.csv", "--log-dir", "out/logs"]);
+    assert!(!output.status.success());
+    let stdout = stdout(&output);
+    assert!(stdout.contains("test_pass.bs  ... OK"), "{stdout}");
+    assert!(
+        stdout.contains("test_abort.bs ... FAILED [E-RUNTIME-UNEXPECTED]"),
+        "{stdout}"
+    );
+    let stderr = stderr(&output);
+    assert!(
+        stderr.contains("unable to write out/report.csv"),
+        "{stderr}"
+    );
+    assert!(stderr.contains("not all tests succeeded"), "{stderr}");
+    assert!(!stderr.contains("all tests succeeded, but"), "{stderr}");
+}
+
+#[test]
+fn unwritable_reports_are_fatal_with_strict_reports() {
+    let suite = suite_with_unwritable_output();
+    suite.test("test_pass.bs", "print(1)\n");
+    let output = suite.run(&["--csv", "out/report.csv", "--strict-reports"]);
+    assert!(!output.status.success());
+    let stderr = stderr(&output);
+    assert!(
+        stderr.contains("unable to write out/report.csv"),
+        "{stderr}"
+    );
+    assert!(
+        stderr.contains("not all logs and reports could be written"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn writable_reports_keep_green_runs_green() {
+    let suite = Suite::new();
+    suite.test("test_pass.bs", "print(1)\n");
+    let output = suite.run(&["--csv", "report.csv", "--log-dir", "logs"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(suite.path().join("report.csv").is_file());
+}
+
+#[test]
+fn tests_sharing_a_file_name_are_told_apart_by_their_path() {
+    let suite = Suite::new();
+    suite.test("test_ok.bs", "print(1)\n");
+    suite.test("sub/test_ok.bs", "print(1)\n");
+    let report = suite.run_json(&["--config-set", "plain:", "--deny-duplicates"]);
+    assert!(report.output.status.success(), "{}", stderr(&report.output));
+    assert!(!stderr(&report.output).contains("multiple tests are named"));
+    let top = report.test("test_ok.bs [plain]");
+    assert_eq!(top["path"], "test_ok.bs");
+    assert_eq!(top["configuration"], "plain");
+    assert_eq!(
+        report.test("sub/test_ok.bs [plain]")["path"],
+        "sub/test_ok.bs"
+    );
+}
+
+#[test]
+fn tests_sharing_a_report_name_are_reported_as_duplicates() {
+    let suite = Suite::new();
+    suite.test("test_ok.bs", "print(1)\n");
+    suite.test("sub/test_ok.bs", "print(1)\n");
+    let output = suite.run(&["--name-format", "{stem}"]);
+    assert!(output.status.success());
+    let warnings = stderr(&output);
+    assert!(warnings.contains("multiple tests are named \"test_ok\""));
+    assert!(warnings.contains("sub/test_ok.bs"), "{warnings}");
+
+    let output = suite.run(&["--name-format", "{stem}", "--deny-duplicates"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("duplicate test names found"));
+}